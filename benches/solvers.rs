@@ -0,0 +1,60 @@
+//! Timing comparison of the pairwise gravity solvers. Run with `cargo bench`; it uses
+//! no harness, so it just prints the time per call of each solver and the speedup
+//! over the serial direct sum.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use grav_sim::{Body, GravitySolver};
+
+/// Bodies scattered over the unit square by a fixed linear congruential sequence.
+fn scattered_bodies(count: usize) -> Vec<Body> {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = || {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        (state >> 40) as f32 / (1u64 << 24) as f32
+    };
+    (0..count)
+        .map(|_| Body::new(0.5 + next(), (next(), next()), (0.0, 0.0)).unwrap())
+        .collect()
+}
+
+/// Mean time per call of `f`, over enough calls to fill about half a second.
+fn time_per_call(mut f: impl FnMut()) -> Duration {
+    f();
+    let mut calls = 1u32;
+    loop {
+        let start = Instant::now();
+        for _ in 0..calls {
+            f();
+        }
+        let elapsed = start.elapsed();
+        if elapsed > Duration::from_millis(500) {
+            return elapsed / calls;
+        }
+        calls *= 2;
+    }
+}
+
+fn main() {
+    for count in [256, 1024, 4096] {
+        let bodies = scattered_bodies(count);
+        let time = |solver: &GravitySolver| {
+            time_per_call(|| {
+                black_box(solver.accelerations(black_box(&bodies), 1.0, 1e-4));
+            })
+        };
+        let reference = time(&GravitySolver::DirectSum);
+        println!("{count} bodies: direct_sum {reference:?}");
+        for (name, solver) in [
+            ("simd", GravitySolver::Simd),
+            ("parallel", GravitySolver::Parallel { threads: 0 }),
+        ] {
+            let elapsed = time(&solver);
+            let speedup = reference.as_secs_f64() / elapsed.as_secs_f64();
+            println!("{count} bodies: {name} {elapsed:?} ({speedup:.1}x)");
+        }
+    }
+}
@@ -6,21 +6,26 @@ use winit::event_loop::ActiveEventLoop;
 use winit::keyboard::{KeyCode, PhysicalKey};
 use winit::window::{Window, WindowAttributes, WindowId};
 
+use crate::scenario::Scenario;
 use crate::state::State;
 
 pub struct App {
     pub state: Option<State<'static>>,
+    pub scenario: Scenario,
 }
 
 impl App {
-    pub fn new() -> Self {
-        Self { state: None }
+    pub fn new(scenario: Scenario) -> Self {
+        Self {
+            state: None,
+            scenario,
+        }
     }
 }
 
 impl Default for App {
     fn default() -> Self {
-        Self::new()
+        Self::new(Scenario::default())
     }
 }
 
@@ -52,7 +57,15 @@ impl ApplicationHandler for App {
                 .expect("Couldn't append canvas to document body.");
         }
 
-        self.state = Some(pollster::block_on(State::new(window)).unwrap());
+        self.state = Some(pollster::block_on(State::new(window, &self.scenario)).unwrap());
+    }
+
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        if let Some(state) = &mut self.state
+            && let Err(e) = state.simulation.flush_strain()
+        {
+            log::error!("Couldn't write the strain: {e}");
+        }
     }
 
     fn window_event(&mut self, event_loop: &ActiveEventLoop, _id: WindowId, event: WindowEvent) {
@@ -0,0 +1,401 @@
+use anyhow::{Result, bail};
+use serde::Deserialize;
+
+use crate::body::Body;
+use crate::force_law::ForceLaw;
+use crate::solver::ewald;
+
+/// What happens at the edge of the simulated region.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Boundary {
+    /// Unbounded space.
+    #[default]
+    Open,
+    /// Square box of side `size` centred on the origin, repeated infinitely in both
+    /// directions. Bodies leaving one side re-enter on the opposite one, and feel the
+    /// gravity of every periodic image of the others: through `forces`, or on the
+    /// mesh of a periodic particle-mesh solver with the same box, which is the only
+    /// solver that applies.
+    Periodic {
+        size: f32,
+        #[serde(default)]
+        forces: PeriodicForces,
+    },
+    /// Square box of side `size` centred on the origin with elastic walls: a body
+    /// crossing a wall is mirrored back inside and its normal velocity reversed.
+    Reflecting {
+        #[serde(default = "default_region_size")]
+        size: f32,
+    },
+    /// Square box of side `size` centred on the origin; bodies that leave it are
+    /// deleted.
+    Absorbing {
+        #[serde(default = "default_region_size")]
+        size: f32,
+    },
+    /// Open space, except that bodies further than `r_cut` from the centre of mass
+    /// and gravitationally unbound from the rest of the system are deleted.
+    Escape { r_cut: f32 },
+}
+
+/// Side of the region shown by the default camera.
+fn default_region_size() -> f32 {
+    2.0
+}
+
+/// Why a body was removed from the simulation.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RemovalKind {
+    /// Left an [`Boundary::Absorbing`] box.
+    Absorbed,
+    /// Unbound and beyond the [`Boundary::Escape`] radius.
+    Escaped,
+}
+
+/// Record of a body removed by the boundary, so that mass and energy diagnostics
+/// can account for what left the system.
+#[derive(Copy, Clone, Debug)]
+pub struct BoundaryEvent {
+    pub kind: RemovalKind,
+    /// Simulated time at the end of the step that removed the body.
+    pub time: f64,
+    /// Index the body had just before it was removed.
+    pub index: usize,
+    /// The body as it was when removed.
+    pub body: Body,
+    /// Energy taken out of the system: the body's kinetic energy plus its softened
+    /// potential energy with the bodies that remain, under the simulation's force law.
+    pub energy: f32,
+}
+
+/// How gravity is summed over the periodic images.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PeriodicForces {
+    /// Ewald summation over every image, against a uniform neutralising background.
+    #[default]
+    Ewald,
+    /// Only the nearest image of each body. Cheap, and adequate when the softened
+    /// interactions that matter are much shorter than the box.
+    MinimumImage,
+}
+
+impl PeriodicForces {
+    pub fn accelerations(
+        self,
+        bodies: &[Body],
+        g_constant: f32,
+        eps2: f32,
+        size: f32,
+    ) -> Vec<(f32, f32)> {
+        match self {
+            PeriodicForces::Ewald => ewald::accelerations(bodies, g_constant, eps2, size),
+            PeriodicForces::MinimumImage => {
+                ewald::minimum_image_accelerations(bodies, g_constant, eps2, size)
+            }
+        }
+    }
+
+    pub fn potential_energy(self, bodies: &[Body], g_constant: f32, eps2: f32, size: f32) -> f32 {
+        match self {
+            PeriodicForces::Ewald => ewald::potential_energy(bodies, g_constant, eps2, size),
+            PeriodicForces::MinimumImage => {
+                ewald::minimum_image_potential_energy(bodies, g_constant, eps2, size)
+            }
+        }
+    }
+}
+
+impl Boundary {
+    pub fn validate(&self) -> Result<()> {
+        match *self {
+            Boundary::Open => {}
+            Boundary::Periodic { size, .. }
+            | Boundary::Reflecting { size }
+            | Boundary::Absorbing { size } => {
+                if size.is_nan() || size <= 0.0 {
+                    bail!("the boundary box size must be positive, got {size}");
+                }
+            }
+            Boundary::Escape { r_cut } => {
+                if r_cut.is_nan() || r_cut < 0.0 {
+                    bail!("the escape radius r_cut can't be negative, got {r_cut}");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the boundary to bodies that have just moved.
+    pub fn apply(&self, bodies: &mut [Body]) {
+        match *self {
+            Boundary::Open | Boundary::Absorbing { .. } | Boundary::Escape { .. } => {}
+            Boundary::Periodic { size, .. } => {
+                for body in bodies {
+                    body.position.0 = wrap(body.position.0, size);
+                    body.position.1 = wrap(body.position.1, size);
+                }
+            }
+            Boundary::Reflecting { size } => {
+                for body in bodies {
+                    reflect(&mut body.position.0, &mut body.velocity.0, size);
+                    reflect(&mut body.position.1, &mut body.velocity.1, size);
+                }
+            }
+        }
+    }
+
+    /// Deletes the bodies this boundary removes at the end of a step, returning one
+    /// event per body in the order they were removed. Energies and escape are judged
+    /// with `force_law`, the law the bodies actually move under.
+    pub fn remove(
+        &self,
+        bodies: &mut Vec<Body>,
+        force_law: &ForceLaw,
+        g_constant: f32,
+        eps2: f32,
+        time: f64,
+    ) -> Vec<BoundaryEvent> {
+        let (kind, leaving): (RemovalKind, Vec<bool>) = match *self {
+            Boundary::Absorbing { size } => {
+                let half = 0.5 * size;
+                let outside = |x: f32| !(-half..=half).contains(&x);
+                (
+                    RemovalKind::Absorbed,
+                    bodies
+                        .iter()
+                        .map(|b| outside(b.position.0) || outside(b.position.1))
+                        .collect(),
+                )
+            }
+            Boundary::Escape { r_cut } => (
+                RemovalKind::Escaped,
+                escaping(bodies, force_law, g_constant, eps2, r_cut),
+            ),
+            _ => return Vec::new(),
+        };
+
+        // Remove from the back so earlier indices stay valid, charging each body
+        // with its energy against whatever is still left
+        let mut events = Vec::new();
+        for index in (0..bodies.len()).rev().filter(|&i| leaving[i]) {
+            let body = bodies.remove(index);
+            let potential: f32 = bodies
+                .iter()
+                .filter(|other| !other.is_test_particle())
+                .map(|other| force_law.potential_energy(&body, other, g_constant, eps2))
+                .sum();
+            events.push(BoundaryEvent {
+                kind,
+                time,
+                index,
+                body,
+                energy: body.get_kinetic_energy() + potential,
+            });
+        }
+        events
+    }
+}
+
+/// Mirrors one coordinate back into `[-size / 2, size / 2]`, reversing the velocity
+/// component if it crossed a wall.
+fn reflect(x: &mut f32, v: &mut f32, size: f32) {
+    let half = 0.5 * size;
+    if *x > half {
+        *x = (2.0 * half - *x).max(-half);
+        *v = -v.abs();
+    } else if *x < -half {
+        *x = (-2.0 * half - *x).min(half);
+        *v = v.abs();
+    }
+}
+
+/// Flags bodies beyond `r_cut` from the centre of mass whose specific kinetic energy
+/// in the barycentric frame exceeds their specific binding energy to everything else.
+fn escaping(
+    bodies: &[Body],
+    force_law: &ForceLaw,
+    g_constant: f32,
+    eps2: f32,
+    r_cut: f32,
+) -> Vec<bool> {
+    let total_mass: f32 = bodies.iter().map(|b| b.mass).sum();
+    if total_mass == 0.0 {
+        return vec![false; bodies.len()];
+    }
+    let weighted = |f: fn(&Body) -> (f32, f32)| {
+        let (x, y) = bodies.iter().fold((0.0, 0.0), |(x, y), b| {
+            let v = f(b);
+            (x + b.mass * v.0, y + b.mass * v.1)
+        });
+        (x / total_mass, y / total_mass)
+    };
+    let com = weighted(|b| b.position);
+    let com_velocity = weighted(|b| b.velocity);
+
+    bodies
+        .iter()
+        .enumerate()
+        .map(|(i, body)| {
+            let dx = body.position.0 - com.0;
+            let dy = body.position.1 - com.1;
+            if dx * dx + dy * dy <= r_cut * r_cut {
+                return false;
+            }
+            let vx = body.velocity.0 - com_velocity.0;
+            let vy = body.velocity.1 - com_velocity.1;
+            // Per unit mass, so massless test particles can escape too
+            let kinetic = 0.5 * (vx * vx + vy * vy);
+            let potential: f32 = bodies
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .map(|(_, other)| {
+                    force_law.specific_potential_energy(body, other, g_constant, eps2)
+                })
+                .sum();
+            kinetic + potential > 0.0
+        })
+        .collect()
+}
+
+/// Maps `x` into `[-size / 2, size / 2)`.
+pub fn wrap(x: f32, size: f32) -> f32 {
+    let half = 0.5 * size;
+    if (-half..half).contains(&x) {
+        x
+    } else {
+        (x + half).rem_euclid(size) - half
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(wrap(0.3, 2.0), 0.3);
+        assert_eq!(wrap(1.25, 2.0), -0.75);
+        assert_eq!(wrap(-1.5, 2.0), 0.5);
+        assert_eq!(wrap(5.0, 2.0), -1.0);
+    }
+
+    #[test]
+    fn test_periodic_boundary_wraps_positions() {
+        let boundary = Boundary::Periodic {
+            size: 2.0,
+            forces: PeriodicForces::Ewald,
+        };
+        let mut bodies = vec![Body::new(1.0, (1.5, -0.25), (1.0, 2.0)).unwrap()];
+
+        boundary.apply(&mut bodies);
+
+        assert_eq!(bodies[0].position, (-0.5, -0.25));
+        assert_eq!(bodies[0].velocity, (1.0, 2.0));
+    }
+
+    #[test]
+    fn test_reflecting_boundary_mirrors_position_and_velocity() {
+        let boundary = Boundary::Reflecting { size: 2.0 };
+        let mut bodies = vec![
+            Body::new(1.0, (1.25, 0.5), (3.0, 2.0)).unwrap(),
+            Body::new(1.0, (0.5, -1.5), (-1.0, -4.0)).unwrap(),
+        ];
+
+        boundary.apply(&mut bodies);
+
+        assert_eq!(bodies[0].position, (0.75, 0.5));
+        assert_eq!(bodies[0].velocity, (-3.0, 2.0));
+        assert_eq!(bodies[1].position, (0.5, -0.5));
+        assert_eq!(bodies[1].velocity, (-1.0, 4.0));
+    }
+
+    #[test]
+    fn test_absorbing_boundary_removes_bodies_outside() {
+        let boundary = Boundary::Absorbing { size: 2.0 };
+        let mut bodies = vec![
+            Body::new(1.0, (0.0, 0.0), (0.0, 0.0)).unwrap(),
+            Body::new(2.0, (1.5, 0.0), (1.0, 0.0)).unwrap(),
+            Body::new(3.0, (0.5, 0.5), (0.0, 0.0)).unwrap(),
+        ];
+
+        let events = boundary.remove(&mut bodies, &ForceLaw::Newtonian, 1.0, 0.0, 4.0);
+
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(events.len(), 1);
+        let event = events[0];
+        assert_eq!(event.kind, RemovalKind::Absorbed);
+        assert_eq!(event.time, 4.0);
+        assert_eq!(event.index, 1);
+        assert_eq!(event.body.mass, 2.0);
+        let expected = 1.0 - 2.0 * 1.0 / 1.5 - 2.0 * 3.0 / 1.25f32.sqrt();
+        assert!((event.energy - expected).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_escape_boundary_removes_only_unbound_distant_bodies() {
+        let boundary = Boundary::Escape { r_cut: 5.0 };
+        let mut bodies = vec![
+            Body::new(1.0, (0.0, 0.0), (0.0, 0.0)).unwrap(),
+            // Far out but bound: v^2 / 2 < G M / r
+            Body::new(1e-3, (10.0, 0.0), (0.0, 0.1)).unwrap(),
+            // Far out and unbound
+            Body::new(1e-3, (-10.0, 0.0), (0.0, 1.0)).unwrap(),
+            // Unbound but still inside the cut
+            Body::new(1e-3, (0.0, 1.0), (5.0, 0.0)).unwrap(),
+        ];
+
+        let events = boundary.remove(&mut bodies, &ForceLaw::Newtonian, 1.0, 0.0, 0.0);
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, RemovalKind::Escaped);
+        assert_eq!(events[0].index, 2);
+        assert_eq!(bodies.len(), 3);
+    }
+
+    #[test]
+    fn test_escape_uses_the_force_law() {
+        // Bound by Newtonian gravity, but screening at this range leaves almost no well
+        let boundary = Boundary::Escape { r_cut: 5.0 };
+        let bodies = vec![
+            Body::new(1.0, (0.0, 0.0), (0.0, 0.0)).unwrap(),
+            Body::new(1e-3, (10.0, 0.0), (0.0, 0.1)).unwrap(),
+        ];
+
+        let mut newtonian = bodies.clone();
+        assert!(
+            boundary
+                .remove(&mut newtonian, &ForceLaw::Newtonian, 1.0, 0.0, 0.0)
+                .is_empty()
+        );
+        let mut screened = bodies;
+        let events = boundary.remove(
+            &mut screened,
+            &ForceLaw::Yukawa { range: 1.0 },
+            1.0,
+            0.0,
+            0.0,
+        );
+        assert_eq!(events.len(), 1);
+        let expected = 0.5e-3 * 0.01 - 1e-3 * (-10.0f32).exp() / 10.0;
+        assert!((events[0].energy - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_unbound_test_particle_escapes() {
+        let boundary = Boundary::Escape { r_cut: 5.0 };
+        let mut bodies = vec![
+            Body::new(1.0, (0.0, 0.0), (0.0, 0.0)).unwrap(),
+            Body::test_particle((10.0, 0.0), (0.0, 0.1)).unwrap(),
+            Body::test_particle((0.0, 10.0), (1.0, 0.0)).unwrap(),
+        ];
+
+        let events = boundary.remove(&mut bodies, &ForceLaw::Newtonian, 1.0, 0.0, 0.0);
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].index, 2);
+        assert_eq!(events[0].energy, 0.0);
+    }
+}
@@ -0,0 +1,445 @@
+use anyhow::{Result, bail};
+use rustfft::FftPlanner;
+use rustfft::num_complex::Complex;
+use serde::Deserialize;
+use std::f64::consts::PI;
+
+use crate::body::Body;
+use crate::boundary::wrap;
+use crate::solver::particle_mesh::fft2;
+
+/// Friedmann model of an expanding universe of matter, cosmological constant and
+/// curvature, whose scale factor `a(t)` sets the comoving coordinates of a periodic
+/// box. Time is cosmic time in the simulation's units.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Cosmology {
+    /// Matter density parameter today, `a = 1`.
+    pub omega_m: f64,
+    /// Cosmological constant density parameter today. Whatever `omega_m` and
+    /// `omega_lambda` leave of 1 is curvature.
+    pub omega_lambda: f64,
+    /// Hubble rate today, `H0`.
+    pub hubble: f64,
+    /// Scale factor at the start of the run.
+    pub initial_scale_factor: f64,
+}
+
+/// The expansion over one interval of cosmic time.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Expansion {
+    /// Scale factor at the end of the interval.
+    pub scale_factor: f64,
+    /// Drift factor, the integral of `dt / a^2`.
+    pub drift: f64,
+    /// Kick factor, the integral of `dt / a`.
+    pub kick: f64,
+}
+
+/// Fourth-order Runge-Kutta steps taken per call to [`Cosmology::advance`].
+const EXPANSION_SUBSTEPS: usize = 16;
+
+impl Cosmology {
+    pub fn omega_k(&self) -> f64 {
+        1.0 - self.omega_m - self.omega_lambda
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.hubble <= 0.0 {
+            bail!("the Hubble rate must be positive");
+        }
+        if self.omega_m <= 0.0 || self.omega_lambda < 0.0 {
+            bail!("omega_m must be positive and omega_lambda non-negative");
+        }
+        if self.initial_scale_factor <= 0.0 {
+            bail!("the initial scale factor must be positive");
+        }
+        if self.hubble_rate_squared(self.initial_scale_factor) <= 0.0 {
+            bail!("the universe isn't expanding at the initial scale factor");
+        }
+        Ok(())
+    }
+
+    fn hubble_rate_squared(&self, a: f64) -> f64 {
+        let h0 = self.hubble;
+        h0 * h0 * (self.omega_m / (a * a * a) + self.omega_k() / (a * a) + self.omega_lambda)
+    }
+
+    /// Hubble rate `H = da/dt / a` at scale factor `a`.
+    pub fn hubble_rate(&self, a: f64) -> f64 {
+        self.hubble_rate_squared(a).max(0.0).sqrt()
+    }
+
+    /// Integrates the Friedmann equation for `dt` from scale factor `a`, along with the
+    /// drift and kick factors over the interval.
+    pub fn advance(&self, a: f64, dt: f64) -> Expansion {
+        // State (a, drift, kick); the factors don't feed back into the rates
+        let rates = |a: f64| [a * self.hubble_rate(a), 1.0 / (a * a), 1.0 / a];
+        let h = dt / EXPANSION_SUBSTEPS as f64;
+        let mut state = [a, 0.0, 0.0];
+        for _ in 0..EXPANSION_SUBSTEPS {
+            let k1 = rates(state[0]);
+            let k2 = rates(state[0] + 0.5 * h * k1[0]);
+            let k3 = rates(state[0] + 0.5 * h * k2[0]);
+            let k4 = rates(state[0] + h * k3[0]);
+            for i in 0..3 {
+                state[i] += h / 6.0 * (k1[i] + 2.0 * k2[i] + 2.0 * k3[i] + k4[i]);
+            }
+        }
+        Expansion {
+            scale_factor: state[0],
+            drift: state[1],
+            kick: state[2],
+        }
+    }
+
+    /// Linear growth rate of density perturbations, `f = d ln D / d ln a`, from the
+    /// growing mode `D ∝ H ∫ da / (a H)^3` of a universe of matter and a cosmological
+    /// constant.
+    pub fn growth_rate(&self, a: f64) -> f64 {
+        // Simpson's rule; the integrand goes as a^(3/2) near a = 0
+        let intervals = 1000;
+        let da = a / intervals as f64;
+        let integrand = |x: f64| {
+            if x == 0.0 {
+                0.0
+            } else {
+                (x * self.hubble_rate(x)).powi(-3)
+            }
+        };
+        let integral = (0..=intervals)
+            .map(|i| {
+                let weight = match i {
+                    0 => 1.0,
+                    i if i == intervals => 1.0,
+                    i if i % 2 == 1 => 4.0,
+                    _ => 2.0,
+                };
+                weight * integrand(i as f64 * da)
+            })
+            .sum::<f64>()
+            * da
+            / 3.0;
+        let h0 = self.hubble;
+        let log_slope = -h0 * h0 * (1.5 * self.omega_m / a.powi(3) + self.omega_k() / (a * a))
+            / self.hubble_rate_squared(a);
+        log_slope + a * integrand(a) / integral
+    }
+}
+
+/// Power spectrum `P(k)` of the initial density contrast, per unit area of the box.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum PowerSpectrum {
+    /// `P(k) = amplitude k^index`.
+    PowerLaw { amplitude: f64, index: f64 },
+    /// `[k, P]` points in increasing `k`, interpolated log-log and zero outside.
+    Table { points: Vec<[f64; 2]> },
+}
+
+impl PowerSpectrum {
+    pub fn power(&self, k: f64) -> f64 {
+        match self {
+            PowerSpectrum::PowerLaw { amplitude, index } => amplitude * k.powf(*index),
+            PowerSpectrum::Table { points } => {
+                let Some(i) = points.iter().position(|p| p[0] >= k) else {
+                    return 0.0;
+                };
+                if points[i][0] == k {
+                    return points[i][1];
+                }
+                if i == 0 {
+                    return 0.0;
+                }
+                let ([k0, p0], [k1, p1]) = (points[i - 1], points[i]);
+                if p0 <= 0.0 || p1 <= 0.0 {
+                    return 0.0;
+                }
+                let t = (k / k0).ln() / (k1 / k0).ln();
+                (p0.ln() + t * (p1 / p0).ln()).exp()
+            }
+        }
+    }
+}
+
+/// Zel'dovich initial conditions: a square lattice of equal particles filling the
+/// periodic box, displaced along a Gaussian random displacement field whose
+/// divergence has the given power spectrum at the initial scale factor, and moving
+/// with the growing mode's peculiar velocities `a H f ψ`.
+///
+/// The bodies attract with the `1 / r^2` law in the plane, so the box is a thin slice
+/// rather than a 2D universe, and its perturbations grow at a rate that depends on
+/// their wavelength. The velocities follow the 3D growth rate of the background
+/// model, which starts the growing mode only approximately.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Zeldovich {
+    /// Particles per side of the lattice.
+    pub grid: usize,
+    /// Total mass of the particles.
+    pub mass: f32,
+    pub power_spectrum: PowerSpectrum,
+    /// Seed of the random phases.
+    #[serde(default)]
+    pub seed: u64,
+}
+
+impl Zeldovich {
+    /// The particles in a periodic box of side `size` centred on the origin, with
+    /// peculiar velocities at the cosmology's initial scale factor.
+    pub fn bodies(&self, cosmology: &Cosmology, size: f32) -> Result<Vec<Body>> {
+        if self.grid == 0 {
+            bail!("the Zel'dovich lattice needs at least one particle per side");
+        }
+        if self.mass <= 0.0 {
+            bail!("the Zel'dovich particles need a positive mass");
+        }
+        let n = self.grid;
+        let (dx, dy) = self.displacements(size as f64);
+        let a = cosmology.initial_scale_factor;
+        let velocity_factor = (a * cosmology.hubble_rate(a) * cosmology.growth_rate(a)) as f32;
+        let spacing = size / n as f32;
+        let mass = self.mass / (n * n) as f32;
+        (0..n * n)
+            .map(|k| {
+                let q = (
+                    ((k % n) as f32 + 0.5) * spacing - 0.5 * size,
+                    ((k / n) as f32 + 0.5) * spacing - 0.5 * size,
+                );
+                Body::new(
+                    mass,
+                    (wrap(q.0 + dx[k], size), wrap(q.1 + dy[k], size)),
+                    (velocity_factor * dx[k], velocity_factor * dy[k]),
+                )
+            })
+            .collect()
+    }
+
+    /// Displacement field on the lattice, row-major, as x and y components.
+    fn displacements(&self, size: f64) -> (Vec<f32>, Vec<f32>) {
+        let n = self.grid;
+        let mut random = SplitMix64(self.seed);
+        let mut delta: Vec<Complex<f32>> = (0..n * n)
+            .map(|_| Complex::new(random.normal() as f32, 0.0))
+            .collect();
+        let mut planner = FftPlanner::new();
+        fft2(&mut delta, n, &mut planner, false);
+
+        // White noise has |W|^2 = n^2 on average, and each mode of the contrast needs
+        // a variance of P(k) / size^2
+        let wavenumber = |m: usize| {
+            let m = if m <= n / 2 {
+                m as f64
+            } else {
+                m as f64 - n as f64
+            };
+            2.0 * PI * m / size
+        };
+        let mut psi_x = vec![Complex::new(0.0, 0.0); n * n];
+        let mut psi_y = vec![Complex::new(0.0, 0.0); n * n];
+        for row in 0..n {
+            for col in 0..n {
+                // The mean and the unpaired Nyquist modes stay zero
+                let nyquist = n.is_multiple_of(2) && (row == n / 2 || col == n / 2);
+                if (row == 0 && col == 0) || nyquist {
+                    continue;
+                }
+                let (kx, ky) = (wavenumber(col), wavenumber(row));
+                let k2 = kx * kx + ky * ky;
+                let amplitude =
+                    (self.power_spectrum.power(k2.sqrt())).max(0.0).sqrt() / (size * n as f64);
+                // psi = i k / k^2 delta, so that delta = -div psi
+                let mode = delta[row * n + col] * Complex::new(0.0, (amplitude / k2) as f32);
+                psi_x[row * n + col] = mode * kx as f32;
+                psi_y[row * n + col] = mode * ky as f32;
+            }
+        }
+        fft2(&mut psi_x, n, &mut planner, true);
+        fft2(&mut psi_y, n, &mut planner, true);
+        (
+            psi_x.iter().map(|c| c.re).collect(),
+            psi_y.iter().map(|c| c.re).collect(),
+        )
+    }
+}
+
+/// Small seeded generator for the random phases, so initial conditions are
+/// reproducible without a dependency.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in (0, 1].
+    fn uniform(&mut self) -> f64 {
+        ((self.next() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Standard normal, by the Box-Muller transform.
+    fn normal(&mut self) -> f64 {
+        let (u, v) = (self.uniform(), self.uniform());
+        (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn einstein_de_sitter() -> Cosmology {
+        Cosmology {
+            omega_m: 1.0,
+            omega_lambda: 0.0,
+            hubble: 2.0,
+            initial_scale_factor: 0.1,
+        }
+    }
+
+    /// Advances in `steps` equal intervals, as a simulation would, summing the factors.
+    fn advance_in_steps(cosmology: &Cosmology, a: f64, t: f64, steps: usize) -> Expansion {
+        let mut total = Expansion {
+            scale_factor: a,
+            drift: 0.0,
+            kick: 0.0,
+        };
+        for _ in 0..steps {
+            let step = cosmology.advance(total.scale_factor, t / steps as f64);
+            total.scale_factor = step.scale_factor;
+            total.drift += step.drift;
+            total.kick += step.kick;
+        }
+        total
+    }
+
+    #[test]
+    fn test_einstein_de_sitter_expansion() {
+        // a = (a0^(3/2) + 3 H0 t / 2)^(2/3), so the drift factor integrates to
+        // 2 (a0^(-1/2) - a^(-1/2)) / H0 and the kick factor to 2 (a^(1/2) - a0^(1/2)) / H0
+        let cosmology = einstein_de_sitter();
+        let (a0, h0, t) = (0.1f64, 2.0, 0.3);
+        let expansion = advance_in_steps(&cosmology, a0, t, 30);
+        let a = (a0.powf(1.5) + 1.5 * h0 * t).powf(2.0 / 3.0);
+        assert!((expansion.scale_factor - a).abs() < 1e-6 * a);
+        let drift = 2.0 * (a0.powf(-0.5) - a.powf(-0.5)) / h0;
+        let kick = 2.0 * (a.sqrt() - a0.sqrt()) / h0;
+        assert!((expansion.drift - drift).abs() < 1e-6 * drift);
+        assert!((expansion.kick - kick).abs() < 1e-6 * kick);
+        // Perturbations grow as a
+        assert!((cosmology.growth_rate(0.5) - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_flat_lambda_expansion() {
+        // a = (Om / OL)^(1/3) sinh^(2/3)(3 sqrt(OL) H0 t / 2)
+        let cosmology = Cosmology {
+            omega_m: 0.3,
+            omega_lambda: 0.7,
+            hubble: 1.0,
+            initial_scale_factor: 0.2,
+        };
+        let scale = (0.3f64 / 0.7).powf(1.0 / 3.0);
+        let rate = 1.5 * 0.7f64.sqrt();
+        let age = |a: f64| ((a / scale).powf(1.5)).asinh() / rate;
+        let t0 = age(0.2);
+        let a = advance_in_steps(&cosmology, 0.2, 1.0, 100).scale_factor;
+        let expected = scale * (rate * (t0 + 1.0)).sinh().powf(2.0 / 3.0);
+        assert!((a - expected).abs() < 1e-6 * expected, "{a} {expected}");
+        // Today H = H0, and f is close to the usual Om^0.55
+        assert!((cosmology.hubble_rate(1.0) - 1.0).abs() < 1e-12);
+        assert!((cosmology.growth_rate(1.0) - 0.3f64.powf(0.55)).abs() < 0.01);
+
+        let closed = Cosmology {
+            omega_m: 3.0,
+            omega_lambda: 0.0,
+            initial_scale_factor: 2.0,
+            ..cosmology
+        };
+        assert!(closed.validate().is_err());
+        assert!(cosmology.validate().is_ok());
+    }
+
+    #[test]
+    fn test_power_spectrum_table() {
+        let table = PowerSpectrum::Table {
+            points: vec![[1.0, 4.0], [4.0, 1.0]],
+        };
+        assert_eq!(table.power(0.5), 0.0);
+        assert_eq!(table.power(1.0), 4.0);
+        assert!((table.power(2.0) - 2.0).abs() < 1e-12);
+        assert_eq!(table.power(5.0), 0.0);
+    }
+
+    #[test]
+    fn test_zeldovich_displacements_follow_the_spectrum() {
+        let (n, size) = (64, 2.0);
+        let zeldovich = Zeldovich {
+            grid: n,
+            mass: 1.0,
+            power_spectrum: PowerSpectrum::PowerLaw {
+                amplitude: 1e-6,
+                index: 1.0,
+            },
+            seed: 7,
+        };
+        let cosmology = einstein_de_sitter();
+        let bodies = zeldovich.bodies(&cosmology, size as f32).unwrap();
+        assert_eq!(bodies.len(), n * n);
+
+        // The expected variance of |psi| is the sum of P(k) / (k size)^2 over the modes
+        let mut expected = 0.0;
+        let step = 2.0 * PI / size;
+        for row in 0..n {
+            for col in 0..n {
+                let signed = |m: usize| {
+                    if m <= n / 2 {
+                        m as f64
+                    } else {
+                        m as f64 - n as f64
+                    }
+                };
+                let (mx, my) = (signed(col), signed(row));
+                if (mx == 0.0 && my == 0.0) || col == n / 2 || row == n / 2 {
+                    continue;
+                }
+                let k = step * mx.hypot(my);
+                expected += zeldovich.power_spectrum.power(k) / (k * size).powi(2);
+            }
+        }
+        let (psi_x, psi_y) = zeldovich.displacements(size);
+        let variance: f64 = psi_x
+            .iter()
+            .zip(&psi_y)
+            .map(|(x, y)| (x * x + y * y) as f64)
+            .sum::<f64>()
+            / (n * n) as f64;
+        assert!(
+            (variance / expected - 1.0).abs() < 0.1,
+            "{variance} {expected}"
+        );
+
+        // No net displacement or momentum, and velocities along the displacements
+        let mean = psi_x.iter().sum::<f32>() / (n * n) as f32;
+        assert!(mean.abs() < 1e-3 * variance.sqrt() as f32);
+        let factor = (0.1 * cosmology.hubble_rate(0.1)) as f32;
+        let momentum = bodies.iter().fold((0.0, 0.0), |(x, y), b| {
+            (x + b.mass * b.velocity.0, y + b.mass * b.velocity.1)
+        });
+        assert!(momentum.0.hypot(momentum.1) < 1e-3 * factor * variance.sqrt() as f32);
+        assert!((bodies[5].velocity.0 - factor * psi_x[5]).abs() < 1e-3 * factor * psi_x[5].abs());
+
+        // The same seed gives the same box
+        let again = zeldovich.bodies(&cosmology, size as f32).unwrap();
+        assert!(
+            again
+                .iter()
+                .zip(&bodies)
+                .all(|(a, b)| a.position == b.position)
+        );
+    }
+}
@@ -0,0 +1,460 @@
+use anyhow::{Result, bail};
+use serde::Deserialize;
+
+use crate::body::Body;
+use crate::force_law::ForceLaw;
+use crate::neighbours;
+use crate::regularization;
+
+/// Bisection steps for the time of an event, enough to pin it to f64 precision within
+/// a step.
+const BISECTION_STEPS: usize = 60;
+
+/// What an event detector watches for. Each event is a sign change of some function of
+/// the bodies' states, checked at either end of a step and then root-found within it,
+/// so a step should be short enough to hold at most one of each.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum DetectorKind {
+    /// Two bodies coming within `distance` of each other, even if they are apart again
+    /// by the end of the step.
+    CloseApproach { distance: f32 },
+    /// Two bodies touching: coming within the sum of their radii.
+    Collision {},
+    /// The second body passing periapsis about the first, where their separation
+    /// stops shrinking.
+    Periapsis {},
+    /// The second body passing apoapsis about the first, where their separation stops
+    /// growing.
+    Apoapsis {},
+    /// A body becoming unbound: its energy relative to the rest of the bodies under the
+    /// force law, in the frame of their centre of mass, turning positive. External
+    /// fields aren't counted, so it can't be used with them.
+    Escape {},
+    /// The relative error of the conserved energy growing past `tolerance`. Energy is
+    /// only known at the ends of the step, so its time is interpolated linearly.
+    EnergyError { tolerance: f32 },
+}
+
+/// An event detector on bodies given by index into the simulation's bodies.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Detector {
+    pub kind: DetectorKind,
+    /// The pair watched by the kinds between two bodies, primary first for periapsis
+    /// and apoapsis, or the one body watched for an escape. Empty watches every pair or
+    /// body.
+    pub bodies: Vec<usize>,
+}
+
+/// An event found during a step.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Detection {
+    /// Index of the detector that found it.
+    pub detector: usize,
+    pub kind: DetectorKind,
+    /// Simulated time of the event, within the step that found it.
+    pub time: f64,
+    /// The bodies involved, by the indices they had during the step: the pair, or the
+    /// escaping body. Empty for an energy error.
+    pub bodies: Vec<usize>,
+}
+
+impl Detector {
+    pub fn new(kind: DetectorKind, bodies: Vec<usize>) -> Result<Self> {
+        let (counts, expected): (&[usize], &str) = match kind {
+            DetectorKind::CloseApproach { distance } => {
+                if distance <= 0.0 {
+                    bail!("close approach distance must be positive");
+                }
+                (&[0, 2], "no bodies or two")
+            }
+            DetectorKind::Collision {} => (&[0, 2], "no bodies or two"),
+            DetectorKind::Periapsis {} | DetectorKind::Apoapsis {} => (&[2], "two bodies"),
+            DetectorKind::Escape {} => (&[0, 1], "no bodies or one"),
+            DetectorKind::EnergyError { tolerance } => {
+                if tolerance <= 0.0 {
+                    bail!("energy error tolerance must be positive");
+                }
+                (&[0], "no bodies")
+            }
+        };
+        if !counts.contains(&bodies.len()) {
+            bail!("{kind:?} detector watches {expected}, not {}", bodies.len());
+        }
+        if bodies.len() == 2 && bodies[0] == bodies[1] {
+            bail!("{kind:?} detector can't pair a body with itself");
+        }
+        Ok(Self { kind, bodies })
+    }
+}
+
+/// The bodies at either end of a step, between which detectors look for events.
+pub struct StepRecord<'a> {
+    pub before: &'a [Body],
+    pub after: &'a [Body],
+    /// Simulated time at the start of the step.
+    pub time: f64,
+    pub dt: f64,
+    pub g_constant: f32,
+    pub eps2: f32,
+    /// Interaction the bodies move under, which decides whether they are bound.
+    pub force_law: ForceLaw,
+    /// Pairs whose relative motion was drifted along its Kepler orbit, which is
+    /// interpolated the same way.
+    pub regularized_pairs: &'a [(usize, usize)],
+    /// Relative error of the conserved energy at either end of the step, if any
+    /// detector watches it.
+    pub energy_errors: Option<(f32, f32)>,
+}
+
+type Vector = (f64, f64);
+
+impl StepRecord<'_> {
+    /// Events the detectors find during the step, in time order.
+    pub fn detect(&self, detectors: &[Detector]) -> Vec<Detection> {
+        let mut detections = Vec::new();
+        for (index, detector) in detectors.iter().enumerate() {
+            // An empty list watches everything
+            let every = detector.bodies.is_empty();
+            let pair = || (detector.bodies[0], detector.bodies[1]);
+            let found: Vec<(f64, Vec<usize>)> = match detector.kind {
+                DetectorKind::CloseApproach { distance } => {
+                    let pairs = if every {
+                        self.nearby_pairs(distance as f64)
+                    } else {
+                        vec![pair()]
+                    };
+                    pairs
+                        .into_iter()
+                        .filter_map(|(i, j)| {
+                            Some((self.approach(i, j, distance as f64)?, vec![i, j]))
+                        })
+                        .collect()
+                }
+                DetectorKind::Collision {} => {
+                    let pairs = if every {
+                        let largest = self.before.iter().map(|b| b.radius).fold(0.0, f32::max);
+                        self.nearby_pairs(2.0 * largest as f64)
+                    } else {
+                        vec![pair()]
+                    };
+                    pairs
+                        .into_iter()
+                        .filter_map(|(i, j)| {
+                            let reach = (self.before[i].radius + self.before[j].radius) as f64;
+                            Some((self.approach(i, j, reach)?, vec![i, j]))
+                        })
+                        .collect()
+                }
+                DetectorKind::Periapsis {} | DetectorKind::Apoapsis {} => {
+                    let (i, j) = pair();
+                    let closing = detector.kind == DetectorKind::Periapsis {};
+                    self.turning(i, j, closing)
+                        .map(|fraction| (fraction, vec![i, j]))
+                        .into_iter()
+                        .collect()
+                }
+                DetectorKind::Escape {} => {
+                    let bodies = if every {
+                        (0..self.before.len()).collect()
+                    } else {
+                        detector.bodies.clone()
+                    };
+                    bodies
+                        .into_iter()
+                        .filter_map(|k| Some((self.escape(k)?, vec![k])))
+                        .collect()
+                }
+                DetectorKind::EnergyError { tolerance } => self
+                    .energy_errors
+                    .and_then(|(start, end)| {
+                        (start < tolerance && end >= tolerance)
+                            .then(|| ((tolerance - start) / (end - start)) as f64)
+                    })
+                    .map(|fraction| (fraction, Vec::new()))
+                    .into_iter()
+                    .collect(),
+            };
+            detections.extend(found.into_iter().map(|(fraction, bodies)| Detection {
+                detector: index,
+                kind: detector.kind,
+                time: self.time + fraction * self.dt,
+                bodies,
+            }));
+        }
+        detections.sort_by(|a, b| a.time.total_cmp(&b.time));
+        detections
+    }
+
+    /// Position and velocity of body `k` a `fraction` of the way through the step, by
+    /// cubic Hermite interpolation between its states at either end.
+    fn state(&self, k: usize, fraction: f64) -> (Vector, Vector) {
+        let (a, b) = (&self.before[k], &self.after[k]);
+        let (s, h) = (fraction, self.dt);
+        if h == 0.0 {
+            return (
+                (a.position.0 as f64, a.position.1 as f64),
+                (a.velocity.0 as f64, a.velocity.1 as f64),
+            );
+        }
+        let (s2, s3) = (s * s, s * s * s);
+        let weights = [
+            2.0 * s3 - 3.0 * s2 + 1.0,
+            s3 - 2.0 * s2 + s,
+            3.0 * s2 - 2.0 * s3,
+            s3 - s2,
+        ];
+        let rates = [
+            6.0 * s2 - 6.0 * s,
+            3.0 * s2 - 4.0 * s + 1.0,
+            6.0 * s - 6.0 * s2,
+            3.0 * s2 - 2.0 * s,
+        ];
+        let component = |p0: f32, v0: f32, p1: f32, v1: f32| {
+            let (p0, v0, p1, v1) = (p0 as f64, v0 as f64, p1 as f64, v1 as f64);
+            (
+                weights[0] * p0 + weights[1] * h * v0 + weights[2] * p1 + weights[3] * h * v1,
+                (rates[0] * p0 + rates[2] * p1) / h + rates[1] * v0 + rates[3] * v1,
+            )
+        };
+        let x = component(a.position.0, a.velocity.0, b.position.0, b.velocity.0);
+        let y = component(a.position.1, a.velocity.1, b.position.1, b.velocity.1);
+        ((x.0, y.0), (x.1, y.1))
+    }
+
+    /// Position and velocity of body `j` relative to body `i` a `fraction` of the way
+    /// through the step. A regularized pair follows its Kepler orbit from the start of
+    /// the step, which a coarse step can't interpolate.
+    fn relative(&self, i: usize, j: usize, fraction: f64) -> (Vector, Vector) {
+        let (a, b) = (&self.before[i], &self.before[j]);
+        if self.regularized_pairs.contains(&(i.min(j), i.max(j))) {
+            let r = (
+                (b.position.0 - a.position.0) as f64,
+                (b.position.1 - a.position.1) as f64,
+            );
+            let v = (
+                (b.velocity.0 - a.velocity.0) as f64,
+                (b.velocity.1 - a.velocity.1) as f64,
+            );
+            let mu = self.g_constant as f64 * (a.mass + b.mass) as f64;
+            return regularization::kepler_drift(r, v, mu, fraction * self.dt);
+        }
+        let ((xi, vi), (xj, vj)) = (self.state(i, fraction), self.state(j, fraction));
+        ((xj.0 - xi.0, xj.1 - xi.1), (vj.0 - vi.0, vj.1 - vi.1))
+    }
+
+    /// Pairs that might come within `reach` during the step: those ending no farther
+    /// apart than `reach` plus a generous bound on how far both could have strayed.
+    fn nearby_pairs(&self, reach: f64) -> Vec<(usize, usize)> {
+        let travel = self
+            .before
+            .iter()
+            .zip(self.after)
+            .map(|(a, b)| {
+                let moved = (b.position.0 - a.position.0).hypot(b.position.1 - a.position.1);
+                let speed = a
+                    .velocity
+                    .0
+                    .hypot(a.velocity.1)
+                    .max(b.velocity.0.hypot(b.velocity.1));
+                moved as f64 + speed as f64 * self.dt.abs()
+            })
+            .fold(0.0, f64::max);
+        let points: Vec<(f32, f32)> = self.after.iter().map(|b| b.position).collect();
+        neighbours::pairs_within(&points, (reach + 2.0 * travel) as f32)
+    }
+
+    /// Fraction of the step at which the pair first comes within `reach`, whether it
+    /// ends the step within it or only dips within it around a closest approach.
+    fn approach(&self, i: usize, j: usize, reach: f64) -> Option<f64> {
+        let gap = |fraction: f64| {
+            let (r, _) = self.relative(i, j, fraction);
+            r.0.hypot(r.1) - reach
+        };
+        if gap(0.0) <= 0.0 {
+            return None;
+        }
+        let end = if gap(1.0) <= 0.0 {
+            1.0
+        } else {
+            let closest = self.turning(i, j, true)?;
+            if gap(closest) > 0.0 {
+                return None;
+            }
+            closest
+        };
+        Some(bisect(gap, 0.0, end))
+    }
+
+    /// Fraction of the step at which the pair's separation stops shrinking if
+    /// `closing`, or stops growing if not.
+    fn turning(&self, i: usize, j: usize, closing: bool) -> Option<f64> {
+        let radial = |fraction: f64| {
+            let (r, v) = self.relative(i, j, fraction);
+            let rate = r.0 * v.0 + r.1 * v.1;
+            if closing { rate } else { -rate }
+        };
+        (radial(0.0) < 0.0 && radial(1.0) >= 0.0).then(|| bisect(radial, 0.0, 1.0))
+    }
+
+    /// Fraction of the step at which body `k` becomes unbound from the rest.
+    fn escape(&self, k: usize) -> Option<f64> {
+        let (start, end) = (self.binding_energy(k, 0.0)?, self.binding_energy(k, 1.0)?);
+        (start < 0.0 && end >= 0.0).then(|| {
+            bisect(
+                |fraction| self.binding_energy(k, fraction).unwrap_or(0.0),
+                0.0,
+                1.0,
+            )
+        })
+    }
+
+    /// Energy per unit mass of body `k` relative to the other bodies a `fraction` of the
+    /// way through the step: its kinetic energy relative to their centre of mass and
+    /// its softened potential energy with them under the force law. `None` if the
+    /// others have no mass.
+    fn binding_energy(&self, k: usize, fraction: f64) -> Option<f64> {
+        let (position, velocity) = self.state(k, fraction);
+        let at = |index: usize, x: Vector| Body {
+            position: (x.0 as f32, x.1 as f32),
+            ..self.before[index]
+        };
+        let (mut mass, mut momentum, mut potential) = (0.0, (0.0, 0.0), 0.0);
+        for other in (0..self.before.len()).filter(|&i| i != k) {
+            let m = self.before[other].mass as f64;
+            if m == 0.0 {
+                continue;
+            }
+            let (x, v) = self.state(other, fraction);
+            mass += m;
+            momentum = (momentum.0 + m * v.0, momentum.1 + m * v.1);
+            potential += if self.force_law == ForceLaw::Newtonian {
+                // In double precision, as the sign change is found to a fine tolerance
+                let (dx, dy) = (x.0 - position.0, x.1 - position.1);
+                -self.g_constant as f64 * m / (dx * dx + dy * dy + self.eps2 as f64).sqrt()
+            } else {
+                self.force_law.specific_potential_energy(
+                    &at(k, position),
+                    &at(other, x),
+                    self.g_constant,
+                    self.eps2,
+                ) as f64
+            };
+        }
+        if mass == 0.0 {
+            return None;
+        }
+        let (dvx, dvy) = (
+            velocity.0 - momentum.0 / mass,
+            velocity.1 - momentum.1 / mass,
+        );
+        Some(0.5 * (dvx * dvx + dvy * dvy) + potential)
+    }
+}
+
+/// Point in `[low, high]` just past where `f` changes sign, given that it does.
+fn bisect(f: impl Fn(f64) -> f64, mut low: f64, mut high: f64) -> f64 {
+    let negative = f(low) < 0.0;
+    for _ in 0..BISECTION_STEPS {
+        let middle = 0.5 * (low + high);
+        if (f(middle) < 0.0) == negative {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    high
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record<'a>(before: &'a [Body], after: &'a [Body], dt: f64) -> StepRecord<'a> {
+        StepRecord {
+            before,
+            after,
+            time: 1.0,
+            dt,
+            g_constant: 1.0,
+            eps2: 0.0,
+            force_law: ForceLaw::Newtonian,
+            regularized_pairs: &[],
+            energy_errors: None,
+        }
+    }
+
+    #[test]
+    fn test_close_approach_found_between_steps() {
+        // Two bodies passing 0.1 apart in a straight line, level at t = 1.5: they are
+        // 1 apart at either end of the step, and within 0.2 for 0.0866 either side
+        let body = |x: f32, y: f32, vx: f32| Body::test_particle((x, y), (vx, 0.0)).unwrap();
+        let before = [body(-0.5, 0.0, 1.0), body(0.5, 0.1, -1.0)];
+        let after = [body(0.5, 0.0, 1.0), body(-0.5, 0.1, -1.0)];
+        let step = record(&before, &after, 1.0);
+        let detectors = [
+            Detector::new(DetectorKind::CloseApproach { distance: 0.2 }, vec![0, 1]).unwrap(),
+            Detector::new(DetectorKind::CloseApproach { distance: 0.2 }, Vec::new()).unwrap(),
+            Detector::new(DetectorKind::CloseApproach { distance: 0.05 }, Vec::new()).unwrap(),
+            Detector::new(DetectorKind::Periapsis {}, vec![0, 1]).unwrap(),
+            Detector::new(DetectorKind::Apoapsis {}, vec![0, 1]).unwrap(),
+        ];
+        let detections = step.detect(&detectors);
+        let entry = 1.5 - 0.03f64.sqrt() / 2.0;
+        assert_eq!(detections.len(), 3, "{detections:?}");
+        for detection in &detections[..2] {
+            assert!((detection.time - entry).abs() < 1e-6, "{detection:?}");
+            assert_eq!(detection.bodies, vec![0, 1]);
+        }
+        assert_eq!(detections[2].kind, DetectorKind::Periapsis {});
+        assert!((detections[2].time - 1.5).abs() < 1e-9);
+
+        assert!(Detector::new(DetectorKind::Periapsis {}, Vec::new()).is_err());
+        assert!(Detector::new(DetectorKind::Collision {}, vec![1, 1]).is_err());
+        assert!(Detector::new(DetectorKind::EnergyError { tolerance: 0.0 }, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_escape_and_energy_error() {
+        // A particle kicked past the escape speed sqrt(2) of a unit mass at unit
+        // distance within a short step
+        let sun = Body::new(1.0, (0.0, 0.0), (0.0, 0.0)).unwrap();
+        let particle = |y: f32, vy: f32| Body::test_particle((1.0, y), (0.0, vy)).unwrap();
+        let (before, after) = ([sun, particle(0.0, 1.4)], [sun, particle(0.00145, 1.5)]);
+        let mut step = record(&before, &after, 0.001);
+        step.energy_errors = Some((1e-4, 3e-4));
+        let detectors = [
+            Detector::new(DetectorKind::Escape {}, Vec::new()).unwrap(),
+            Detector::new(DetectorKind::EnergyError { tolerance: 2e-4 }, Vec::new()).unwrap(),
+            Detector::new(DetectorKind::EnergyError { tolerance: 1e-3 }, Vec::new()).unwrap(),
+        ];
+        let detections = step.detect(&detectors);
+        assert_eq!(detections.len(), 2, "{detections:?}");
+        assert_eq!(detections[0].bodies, vec![1]);
+        // The speed grows linearly through the step and reaches sqrt(2) at 0.142 of it
+        let escape = 1.0 + 0.001 * (2.0f64.sqrt() - 1.4) / 0.1;
+        assert!((detections[0].time - escape).abs() < 1e-6, "{detections:?}");
+        assert_eq!(detections[1].bodies, Vec::<usize>::new());
+        assert_eq!(detections[1].detector, 1);
+    }
+
+    #[test]
+    fn test_escape_follows_the_force_law() {
+        // With a 1 / r^3 attraction the escape speed at unit distance from a unit mass
+        // is 1, which the particle passes halfway through the step; under Newtonian
+        // gravity it stays bound
+        let sun = Body::new(1.0, (0.0, 0.0), (0.0, 0.0)).unwrap();
+        let particle = |y: f32, vy: f32| Body::test_particle((1.0, y), (0.0, vy)).unwrap();
+        let (before, after) = ([sun, particle(0.0, 0.95)], [sun, particle(0.001, 1.05)]);
+        let detectors = [Detector::new(DetectorKind::Escape {}, Vec::new()).unwrap()];
+
+        let mut step = record(&before, &after, 0.001);
+        assert!(step.detect(&detectors).is_empty());
+
+        step.force_law = ForceLaw::PowerLaw { exponent: 3.0 };
+        let detections = step.detect(&detectors);
+        assert_eq!(detections.len(), 1, "{detections:?}");
+        assert_eq!(detections[0].bodies, vec![1]);
+        assert!((detections[0].time - 1.0005).abs() < 1e-6, "{detections:?}");
+    }
+}
@@ -0,0 +1,288 @@
+use serde::Deserialize;
+
+use crate::body::Body;
+
+/// An external acceleration acting on every body on top of their mutual gravity.
+///
+/// Fields stack: the simulation sums the accelerations of all of them, and adds the
+/// potential of each to the potential energy.
+pub trait ForceField: Send + Sync {
+    /// Acceleration of `body` at simulated time `time`.
+    fn acceleration(&self, body: &Body, time: f64, g_constant: f32) -> (f32, f32);
+
+    /// Potential per unit mass at `position`. Fields that are not conservative, such
+    /// as drag, have none and leave it at zero.
+    fn potential(&self, _position: (f32, f32), _time: f64, _g_constant: f32) -> f32 {
+        0.0
+    }
+}
+
+/// The built-in fields, as listed in scenario files. Halos are centred on the origin,
+/// and their potentials are those of the 3D profiles evaluated in the plane.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ExternalField {
+    /// A fixed point mass, softened over `softening`.
+    PointMass {
+        mass: f32,
+        #[serde(default)]
+        position: (f32, f32),
+        #[serde(default)]
+        softening: f32,
+    },
+    /// `0.5 v0^2 ln(r^2 + core_radius^2)`: a flat rotation curve of speed `v0` outside
+    /// the core.
+    LogarithmicHalo { v0: f32, core_radius: f32 },
+    /// Navarro-Frenk-White profile, `-G mass ln(1 + r / scale_radius) / r`, where
+    /// `mass` is the characteristic `4 pi rho_0 scale_radius^3`.
+    NfwHalo { mass: f32, scale_radius: f32 },
+    /// Plummer sphere, `-G mass / sqrt(r^2 + scale_radius^2)`.
+    PlummerHalo { mass: f32, scale_radius: f32 },
+    /// Constant acceleration, as near the surface of a planet.
+    Uniform { acceleration: (f32, f32) },
+    /// Linear drag `-coefficient v`.
+    Drag { coefficient: f32 },
+    /// Quadrupole bar rotating at `pattern_speed` about the origin, with potential
+    /// `-strength b^2 R^2 / (R^2 + b^2)^(5/2) cos 2(phi - angle - pattern_speed t)`
+    /// for `b = scale_radius`, starting along `angle`.
+    RotatingBar {
+        strength: f32,
+        scale_radius: f32,
+        pattern_speed: f32,
+        #[serde(default)]
+        angle: f32,
+    },
+}
+
+impl ExternalField {
+    /// Rotation taking the bar's frame to the inertial frame at `time`.
+    fn bar_rotation(angle: f32, pattern_speed: f32, time: f64) -> (f32, f32) {
+        let theta = angle as f64 + pattern_speed as f64 * time;
+        (theta.cos() as f32, theta.sin() as f32)
+    }
+}
+
+impl ForceField for ExternalField {
+    fn acceleration(&self, body: &Body, time: f64, g_constant: f32) -> (f32, f32) {
+        let (x, y) = body.position;
+        let r2 = x * x + y * y;
+        match *self {
+            ExternalField::PointMass {
+                mass,
+                position,
+                softening,
+            } => {
+                let (dx, dy) = (x - position.0, y - position.1);
+                let d2 = dx * dx + dy * dy + softening * softening;
+                if d2 == 0.0 {
+                    return (0.0, 0.0);
+                }
+                let a = -g_constant * mass / (d2 * d2.sqrt());
+                (a * dx, a * dy)
+            }
+            ExternalField::LogarithmicHalo { v0, core_radius } => {
+                let a = -v0 * v0 / (r2 + core_radius * core_radius);
+                (a * x, a * y)
+            }
+            ExternalField::NfwHalo { mass, scale_radius } => {
+                if r2 == 0.0 {
+                    return (0.0, 0.0);
+                }
+                // d(phi)/dr = G M / rs^2 [ln(1 + u) / u^2 - 1 / (u (1 + u))], with the
+                // bracket expanded near the centre where it cancels
+                let r = (r2 as f64).sqrt();
+                let rs = scale_radius as f64;
+                let u = r / rs;
+                let bracket = if u < 1e-3 {
+                    0.5 - 2.0 * u / 3.0 + 0.75 * u * u
+                } else {
+                    (1.0 + u).ln() / (u * u) - 1.0 / (u * (1.0 + u))
+                };
+                let a = (-(g_constant * mass) as f64 * bracket / (rs * rs * r)) as f32;
+                (a * x, a * y)
+            }
+            ExternalField::PlummerHalo { mass, scale_radius } => {
+                let d2 = r2 + scale_radius * scale_radius;
+                let a = -g_constant * mass / (d2 * d2.sqrt());
+                (a * x, a * y)
+            }
+            ExternalField::Uniform { acceleration } => acceleration,
+            ExternalField::Drag { coefficient } => (
+                -coefficient * body.velocity.0,
+                -coefficient * body.velocity.1,
+            ),
+            ExternalField::RotatingBar {
+                strength,
+                scale_radius,
+                pattern_speed,
+                angle,
+            } => {
+                // Work in the bar's frame, where phi = -strength b^2 (x^2 - y^2) / D^(5/2)
+                let (cos, sin) = Self::bar_rotation(angle, pattern_speed, time);
+                let (bx, by) = (cos * x + sin * y, -sin * x + cos * y);
+                let b2 = scale_radius * scale_radius;
+                let d = r2 + b2;
+                let d52 = d * d * d.sqrt();
+                let q = bx * bx - by * by;
+                let ax = strength * b2 * (2.0 * bx - 5.0 * q * bx / d) / d52;
+                let ay = strength * b2 * (-2.0 * by - 5.0 * q * by / d) / d52;
+                (cos * ax - sin * ay, sin * ax + cos * ay)
+            }
+        }
+    }
+
+    fn potential(&self, position: (f32, f32), time: f64, g_constant: f32) -> f32 {
+        let (x, y) = position;
+        let r2 = x * x + y * y;
+        match *self {
+            ExternalField::PointMass {
+                mass,
+                position,
+                softening,
+            } => {
+                let (dx, dy) = (x - position.0, y - position.1);
+                let d2 = dx * dx + dy * dy + softening * softening;
+                if d2 == 0.0 {
+                    return 0.0;
+                }
+                -g_constant * mass / d2.sqrt()
+            }
+            ExternalField::LogarithmicHalo { v0, core_radius } => {
+                0.5 * v0 * v0 * (r2 + core_radius * core_radius).ln()
+            }
+            ExternalField::NfwHalo { mass, scale_radius } => {
+                let u = (r2 as f64).sqrt() / scale_radius as f64;
+                let shape = if u < 1e-6 {
+                    1.0 - 0.5 * u
+                } else {
+                    (1.0 + u).ln() / u
+                };
+                (-(g_constant * mass) as f64 * shape / scale_radius as f64) as f32
+            }
+            ExternalField::PlummerHalo { mass, scale_radius } => {
+                -g_constant * mass / (r2 + scale_radius * scale_radius).sqrt()
+            }
+            ExternalField::Uniform { acceleration } => -(acceleration.0 * x + acceleration.1 * y),
+            ExternalField::Drag { .. } => 0.0,
+            ExternalField::RotatingBar {
+                strength,
+                scale_radius,
+                pattern_speed,
+                angle,
+            } => {
+                let (cos, sin) = Self::bar_rotation(angle, pattern_speed, time);
+                let (bx, by) = (cos * x + sin * y, -sin * x + cos * y);
+                let b2 = scale_radius * scale_radius;
+                let d = r2 + b2;
+                -strength * b2 * (bx * bx - by * by) / (d * d * d.sqrt())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_conservative_fields() -> Vec<ExternalField> {
+        vec![
+            ExternalField::PointMass {
+                mass: 2.0,
+                position: (0.3, -0.2),
+                softening: 0.05,
+            },
+            ExternalField::LogarithmicHalo {
+                v0: 1.5,
+                core_radius: 0.2,
+            },
+            ExternalField::NfwHalo {
+                mass: 3.0,
+                scale_radius: 0.5,
+            },
+            ExternalField::PlummerHalo {
+                mass: 3.0,
+                scale_radius: 0.5,
+            },
+            ExternalField::Uniform {
+                acceleration: (0.5, -2.0),
+            },
+            ExternalField::RotatingBar {
+                strength: 1.2,
+                scale_radius: 0.4,
+                pattern_speed: 0.8,
+                angle: 0.3,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_accelerations_are_potential_gradients() {
+        let (g, time, h) = (1.3, 0.7, 1e-3);
+        for field in all_conservative_fields() {
+            for position in [(0.5, 0.2), (-0.7, 0.9), (0.01, -0.02), (2.0, -1.5)] {
+                let body = Body::new(1.0, position, (0.0, 0.0)).unwrap();
+                let a = field.acceleration(&body, time, g);
+                let phi =
+                    |dx: f32, dy: f32| field.potential((position.0 + dx, position.1 + dy), time, g);
+                let expected = (
+                    -(phi(h, 0.0) - phi(-h, 0.0)) / (2.0 * h),
+                    -(phi(0.0, h) - phi(0.0, -h)) / (2.0 * h),
+                );
+                let error = (a.0 - expected.0).hypot(a.1 - expected.1);
+                assert!(
+                    error < 1e-2 * expected.0.hypot(expected.1).max(1.0),
+                    "{field:?} at {position:?}: {a:?} vs {expected:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_halo_profiles() {
+        // The logarithmic halo has a flat rotation curve far outside its core
+        let halo = ExternalField::LogarithmicHalo {
+            v0: 2.0,
+            core_radius: 0.01,
+        };
+        let body = Body::new(1.0, (5.0, 0.0), (0.0, 0.0)).unwrap();
+        let a = halo.acceleration(&body, 0.0, 1.0);
+        assert!(((-a.0 * 5.0).sqrt() - 2.0).abs() < 1e-4);
+
+        // NFW and Plummer halos look like point masses from far away
+        let body = Body::new(1.0, (1e3, 0.0), (0.0, 0.0)).unwrap();
+        let plummer = ExternalField::PlummerHalo {
+            mass: 4.0,
+            scale_radius: 0.5,
+        };
+        assert!((plummer.acceleration(&body, 0.0, 1.0).0 + 4e-6).abs() < 1e-9);
+        let nfw = ExternalField::NfwHalo {
+            mass: 4.0,
+            scale_radius: 0.5,
+        };
+        let enclosed = 4.0 * ((1.0f32 + 2e3).ln() - 2e3 / (1.0 + 2e3));
+        assert!((nfw.acceleration(&body, 0.0, 1.0).0 + enclosed * 1e-6).abs() < 1e-8);
+    }
+
+    #[test]
+    fn test_drag_opposes_velocity() {
+        let drag = ExternalField::Drag { coefficient: 0.5 };
+        let body = Body::new(1.0, (1.0, 1.0), (2.0, -4.0)).unwrap();
+        assert_eq!(drag.acceleration(&body, 0.0, 1.0), (-1.0, 2.0));
+        assert_eq!(drag.potential(body.position, 0.0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn test_bar_rotates_with_pattern_speed() {
+        let bar = ExternalField::RotatingBar {
+            strength: 1.0,
+            scale_radius: 0.5,
+            pattern_speed: 2.0,
+            angle: 0.0,
+        };
+        // After a quarter turn the bar lies along y
+        let time = std::f64::consts::FRAC_PI_4;
+        let along = bar.potential((0.0, 0.4), time, 1.0);
+        assert!((along - bar.potential((0.4, 0.0), 0.0, 1.0)).abs() < 1e-6);
+        assert!(along < 0.0);
+    }
+}
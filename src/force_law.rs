@@ -0,0 +1,329 @@
+use anyhow::{Result, bail};
+use serde::Deserialize;
+
+use crate::body::Body;
+
+/// Pairwise interaction between bodies. Every law is softened by evaluating it at
+/// `s = sqrt(r^2 + eps2)`, so forces stay the exact gradients of the pair potentials.
+///
+/// Only Newtonian gravity goes through the [`GravitySolver`](crate::GravitySolver);
+/// the other laws are summed over every pair directly, so scenarios must leave the
+/// solver at `direct_sum` for them. Test particles feel the other bodies but, as with
+/// gravity, exert nothing.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ForceLaw {
+    /// `U = -G m1 m2 / r`.
+    #[default]
+    Newtonian,
+    /// Electrostatics between the bodies' signed charges, `U = k q1 q2 / r`: like
+    /// charges repel and opposite ones attract. Masses only set the inertia, and
+    /// massless bodies feel nothing.
+    Coulomb {
+        #[serde(default = "default_coulomb_constant")]
+        coulomb_constant: f32,
+    },
+    /// Screened gravity, `U = -G m1 m2 exp(-r / range) / r`.
+    Yukawa { range: f32 },
+    /// Milgromian dynamics with the "simple" interpolating function: each pair moves
+    /// under `a = g / 2 + sqrt(g^2 / 4 + g a0)` for the Newtonian relative acceleration
+    /// `g = G (m1 + m2) / r^2`, split between the two bodies by the reduced mass. This
+    /// two-body approximation conserves momentum and energy, but is not the full
+    /// non-linear MOND field of many bodies.
+    Mond { a0: f32 },
+    /// Attraction falling as `1 / r^exponent`, `U = -G m1 m2 / ((n - 1) r^(n - 1))`, or
+    /// `G m1 m2 ln r` for `n = 1`. `exponent = 2` is Newtonian gravity.
+    PowerLaw { exponent: f32 },
+}
+
+fn default_coulomb_constant() -> f32 {
+    1.0
+}
+
+impl ForceLaw {
+    /// Rejects parameters that make the law singular or meaningless.
+    pub fn validate(&self) -> Result<()> {
+        match *self {
+            ForceLaw::Yukawa { range } if range.is_nan() || range <= 0.0 => {
+                bail!("the yukawa range must be positive, got {range}")
+            }
+            ForceLaw::Mond { a0 } if a0.is_nan() || a0 < 0.0 => {
+                bail!("the mond a0 can't be negative, got {a0}")
+            }
+            ForceLaw::PowerLaw { exponent } if exponent.is_nan() || exponent <= 0.0 => {
+                bail!("the power law exponent must be positive, got {exponent}")
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Acceleration of `body` due to `other`.
+    pub fn acceleration(
+        &self,
+        body: &Body,
+        other: &Body,
+        g_constant: f32,
+        eps2: f32,
+    ) -> (f32, f32) {
+        let dx = other.position.0 - body.position.0;
+        let dy = other.position.1 - body.position.1;
+        let s = (dx * dx + dy * dy + eps2).sqrt();
+        if s == 0.0 {
+            return (0.0, 0.0);
+        }
+        let a = self.radial_acceleration(body, other, s, g_constant) / s;
+        (a * dx, a * dy)
+    }
+
+    /// Acceleration towards `other` at softened separation `s`; negative for repulsion.
+    fn radial_acceleration(&self, body: &Body, other: &Body, s: f32, g_constant: f32) -> f32 {
+        match *self {
+            ForceLaw::Newtonian => g_constant * other.mass / (s * s),
+            ForceLaw::Coulomb { coulomb_constant } => {
+                if body.mass == 0.0 {
+                    0.0
+                } else {
+                    -coulomb_constant * body.charge * other.charge / (body.mass * s * s)
+                }
+            }
+            ForceLaw::Yukawa { range } => {
+                g_constant * other.mass * (-s / range).exp() * (1.0 / (s * s) + 1.0 / (range * s))
+            }
+            ForceLaw::Mond { a0 } => {
+                let total_mass = body.mass + other.mass;
+                if total_mass == 0.0 {
+                    return 0.0;
+                }
+                let g = g_constant * total_mass / (s * s);
+                other.mass / total_mass * (0.5 * g + (0.25 * g * g + g * a0).sqrt())
+            }
+            ForceLaw::PowerLaw { exponent } => g_constant * other.mass / s.powf(exponent),
+        }
+    }
+
+    /// Potential energy of the pair `a`, `b`.
+    pub fn potential_energy(&self, a: &Body, b: &Body, g_constant: f32, eps2: f32) -> f32 {
+        let dx = b.position.0 - a.position.0;
+        let dy = b.position.1 - a.position.1;
+        let s = (dx * dx + dy * dy + eps2).sqrt();
+        if s == 0.0 {
+            return 0.0;
+        }
+        let gmm = g_constant * a.mass * b.mass;
+        match *self {
+            ForceLaw::Newtonian => -gmm / s,
+            ForceLaw::Coulomb { coulomb_constant } => {
+                if a.mass == 0.0 || b.mass == 0.0 {
+                    0.0
+                } else {
+                    coulomb_constant * a.charge * b.charge / s
+                }
+            }
+            ForceLaw::Yukawa { range } => -gmm * (-s / range).exp() / s,
+            ForceLaw::Mond { a0 } => {
+                let total_mass = a.mass + b.mass;
+                if total_mass == 0.0 {
+                    return 0.0;
+                }
+                // Integral of the relative acceleration, which tends to -G M / s at
+                // high accelerations and grows as sqrt(G M a0) ln s at low ones
+                let half_gm = 0.5 * g_constant * total_mass;
+                let c = g_constant * total_mass * a0;
+                let w = (half_gm * half_gm + c * s * s).sqrt();
+                let relative = -half_gm / s - w / s + c.sqrt() * (c.sqrt() * s / half_gm).asinh();
+                a.mass * b.mass / total_mass * relative
+            }
+            ForceLaw::PowerLaw { exponent } => {
+                if exponent == 1.0 {
+                    gmm * s.ln()
+                } else {
+                    -gmm / ((exponent - 1.0) * s.powf(exponent - 1.0))
+                }
+            }
+        }
+    }
+
+    /// Potential energy per unit mass of `body` in the field of `other`, for telling
+    /// whether it is bound. A massless body is weighed as a vanishingly light tracer,
+    /// since MOND's pair potential isn't linear in the masses, except under Coulomb's
+    /// law, which doesn't act on it at all.
+    pub fn specific_potential_energy(
+        &self,
+        body: &Body,
+        other: &Body,
+        g_constant: f32,
+        eps2: f32,
+    ) -> f32 {
+        let mass = if !body.is_test_particle() {
+            body.mass
+        } else if matches!(self, ForceLaw::Coulomb { .. }) || other.is_test_particle() {
+            return 0.0;
+        } else {
+            1e-6 * other.mass
+        };
+        let tracer = Body { mass, ..*body };
+        self.potential_energy(&tracer, other, g_constant, eps2) / mass
+    }
+
+    /// Acceleration of every body due to all the others, by direct summation.
+    pub fn accelerations(&self, bodies: &[Body], g_constant: f32, eps2: f32) -> Vec<(f32, f32)> {
+        bodies
+            .iter()
+            .enumerate()
+            .map(|(i, body)| {
+                bodies
+                    .iter()
+                    .enumerate()
+                    .filter(|&(j, other)| j != i && !other.is_test_particle())
+                    .fold((0.0, 0.0), |(ax, ay), (_, other)| {
+                        let a = self.acceleration(body, other, g_constant, eps2);
+                        (ax + a.0, ay + a.1)
+                    })
+            })
+            .collect()
+    }
+
+    /// Potential energy summed over every pair of massive bodies.
+    pub fn total_potential_energy(&self, bodies: &[Body], g_constant: f32, eps2: f32) -> f32 {
+        let massive: Vec<&Body> = bodies.iter().filter(|b| !b.is_test_particle()).collect();
+        let mut energy = 0.0;
+        for (i, a) in massive.iter().enumerate() {
+            for b in &massive[i + 1..] {
+                energy += self.potential_energy(a, b, g_constant, eps2);
+            }
+        }
+        energy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::direct_sum;
+    use crate::solver::tests::{max_relative_error, random_bodies};
+
+    fn all_laws() -> Vec<ForceLaw> {
+        vec![
+            ForceLaw::Newtonian,
+            ForceLaw::Coulomb {
+                coulomb_constant: 2.0,
+            },
+            ForceLaw::Yukawa { range: 0.4 },
+            ForceLaw::Mond { a0: 0.5 },
+            ForceLaw::PowerLaw { exponent: 1.0 },
+            ForceLaw::PowerLaw { exponent: 3.0 },
+        ]
+    }
+
+    fn charged_pair() -> (Body, Body) {
+        let mut a = Body::new(2.0, (0.1, -0.2), (0.0, 0.0)).unwrap();
+        let mut b = Body::new(0.5, (0.7, 0.3), (0.0, 0.0)).unwrap();
+        a.charge = 1.5;
+        b.charge = -0.5;
+        (a, b)
+    }
+
+    #[test]
+    fn test_forces_are_potential_gradients_and_balance() {
+        let (g, eps2, h) = (1.3, 0.01, 1e-3);
+        let (a, b) = charged_pair();
+        for law in all_laws() {
+            let acceleration = law.acceleration(&a, &b, g, eps2);
+            let energy = |dx: f32, dy: f32| {
+                let moved = Body {
+                    position: (a.position.0 + dx, a.position.1 + dy),
+                    ..a
+                };
+                law.potential_energy(&moved, &b, g, eps2)
+            };
+            let force = (
+                -(energy(h, 0.0) - energy(-h, 0.0)) / (2.0 * h),
+                -(energy(0.0, h) - energy(0.0, -h)) / (2.0 * h),
+            );
+            let scale = force.0.hypot(force.1);
+            assert!(
+                (a.mass * acceleration.0 - force.0).hypot(a.mass * acceleration.1 - force.1)
+                    < 1e-2 * scale,
+                "{law:?}: {acceleration:?} vs {force:?}"
+            );
+
+            // Newton's third law
+            let reaction = law.acceleration(&b, &a, g, eps2);
+            assert!(
+                (a.mass * acceleration.0 + b.mass * reaction.0).abs() < 1e-5 * scale,
+                "{law:?}"
+            );
+            assert_eq!(
+                law.potential_energy(&a, &b, g, eps2),
+                law.potential_energy(&b, &a, g, eps2)
+            );
+        }
+    }
+
+    #[test]
+    fn test_coulomb_signs() {
+        let law = ForceLaw::Coulomb {
+            coulomb_constant: 1.0,
+        };
+        let (mut a, mut b) = charged_pair();
+        // Opposite charges attract
+        assert!(law.acceleration(&a, &b, 1.0, 0.0).0 > 0.0);
+        b.charge = 0.5;
+        assert!(law.acceleration(&a, &b, 1.0, 0.0).0 < 0.0);
+        a.charge = 0.0;
+        assert_eq!(law.acceleration(&a, &b, 1.0, 0.0), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_limits_reduce_to_newtonian() {
+        let bodies = random_bodies(30, 7);
+        let newtonian = direct_sum(&bodies, 1.0, 0.01);
+        let newtonian_energy = ForceLaw::Newtonian.total_potential_energy(&bodies, 1.0, 0.01);
+        for law in [
+            ForceLaw::Newtonian,
+            ForceLaw::Yukawa { range: 1e6 },
+            ForceLaw::Mond { a0: 0.0 },
+            ForceLaw::PowerLaw { exponent: 2.0 },
+        ] {
+            let accelerations = law.accelerations(&bodies, 1.0, 0.01);
+            assert!(
+                max_relative_error(&accelerations, &newtonian) < 1e-4,
+                "{law:?}"
+            );
+            let energy = law.total_potential_energy(&bodies, 1.0, 0.01);
+            assert!(
+                ((energy - newtonian_energy) / newtonian_energy).abs() < 1e-4,
+                "{law:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_deep_mond_rotation_is_flat() {
+        // Far from a mass M the circular speed tends to (G M a0)^(1/4)
+        let law = ForceLaw::Mond { a0: 1.0 };
+        let star = Body::new(1.0, (0.0, 0.0), (0.0, 0.0)).unwrap();
+        for r in [100.0, 1000.0] {
+            let tracer = Body::test_particle((r, 0.0), (0.0, 0.0)).unwrap();
+            let a = law.acceleration(&tracer, &star, 1.0, 0.0);
+            assert!(((-a.0 * r).sqrt() - 1.0).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn test_parameters_validated() {
+        for law in all_laws() {
+            assert!(law.validate().is_ok(), "{law:?}");
+        }
+        for law in [
+            ForceLaw::Yukawa { range: 0.0 },
+            ForceLaw::Yukawa { range: -1.0 },
+            ForceLaw::Mond { a0: -0.1 },
+            ForceLaw::PowerLaw { exponent: 0.0 },
+            ForceLaw::PowerLaw { exponent: f32::NAN },
+        ] {
+            assert!(law.validate().is_err(), "{law:?}");
+        }
+        assert!(ForceLaw::Mond { a0: 0.0 }.validate().is_ok());
+    }
+}
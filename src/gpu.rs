@@ -0,0 +1,377 @@
+use wgpu::{Device, Queue, util::DeviceExt};
+
+use crate::body::Body;
+use crate::simulation::Simulation;
+
+/// Invocations per workgroup, and the number of bodies staged per tile. Must match
+/// `TILE` in `shaders/nbody.wgsl`.
+const WORKGROUP_SIZE: u32 = 64;
+
+/// Uniform block shared by the compute entry points.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Params {
+    num_bodies: u32,
+    g_constant: f32,
+    eps2: f32,
+    dt: f32,
+}
+
+/// N-body state resident on the GPU, advanced by compute passes. The position buffer
+/// doubles as the instance buffer for the body render pipeline, so stepping and
+/// drawing never round-trip through the CPU.
+pub struct GpuSimulation {
+    pub positions: wgpu::Buffer,
+    velocities: wgpu::Buffer,
+    accelerations: wgpu::Buffer,
+    /// One bind group per integrator substep, differing only in the time step.
+    substeps: Vec<wgpu::BindGroup>,
+    kick_drift_pipeline: wgpu::ComputePipeline,
+    accelerate_pipeline: wgpu::ComputePipeline,
+    kick_pipeline: wgpu::ComputePipeline,
+    pub num_bodies: u32,
+}
+
+impl GpuSimulation {
+    /// Uploads the bodies of `simulation` and evaluates their initial accelerations.
+    pub fn new(device: &Device, queue: &Queue, simulation: &Simulation) -> Self {
+        let positions: Vec<[f32; 2]> = simulation
+            .bodies
+            .iter()
+            .map(|b| [b.position.0, b.position.1])
+            .collect();
+        let velocities: Vec<[f32; 2]> = simulation
+            .bodies
+            .iter()
+            .map(|b| [b.velocity.0, b.velocity.1])
+            .collect();
+        let masses: Vec<f32> = simulation.bodies.iter().map(|b| b.mass).collect();
+        let num_bodies = masses.len() as u32;
+
+        // Zero-sized bindings are invalid, so an empty system still gets one slot
+        let storage = |label, contents: &[u8], usage| {
+            let padding = [0u8; 8];
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents: if contents.is_empty() {
+                    &padding
+                } else {
+                    contents
+                },
+                usage: wgpu::BufferUsages::STORAGE | usage,
+            })
+        };
+        let copy = wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST;
+        let positions = storage(
+            "Body Positions",
+            bytemuck::cast_slice(&positions),
+            copy | wgpu::BufferUsages::VERTEX,
+        );
+        let velocities = storage("Body Velocities", bytemuck::cast_slice(&velocities), copy);
+        let masses = storage(
+            "Body Masses",
+            bytemuck::cast_slice(&masses),
+            wgpu::BufferUsages::empty(),
+        );
+        let accelerations = storage(
+            "Body Accelerations",
+            &vec![0u8; 8 * num_bodies as usize],
+            copy,
+        );
+
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("N-Body Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1, false),
+                storage_entry(2, false),
+                storage_entry(3, true),
+                storage_entry(4, false),
+            ],
+        });
+
+        let substeps = simulation
+            .integrator
+            .substeps()
+            .iter()
+            .map(|&weight| {
+                let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("N-Body Params"),
+                    contents: bytemuck::bytes_of(&Params {
+                        num_bodies,
+                        g_constant: simulation.g_constant,
+                        eps2: simulation.eps2,
+                        dt: weight * simulation.time_step,
+                    }),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("N-Body Bind Group"),
+                    layout: &bind_group_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: params.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: positions.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: velocities.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: masses.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 4,
+                            resource: accelerations.as_entire_binding(),
+                        },
+                    ],
+                })
+            })
+            .collect();
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("N-Body Compute Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("shaders/nbody.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("N-Body Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+
+        let gpu = Self {
+            positions,
+            velocities,
+            accelerations,
+            substeps,
+            kick_drift_pipeline: pipeline("kick_drift"),
+            accelerate_pipeline: pipeline("accelerate"),
+            kick_pipeline: pipeline("kick"),
+            num_bodies,
+        };
+
+        // a(t) for the first half kick
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("N-Body Initial Accelerations"),
+        });
+        gpu.dispatch(&mut encoder, &gpu.accelerate_pipeline, &gpu.substeps[0]);
+        queue.submit(std::iter::once(encoder.finish()));
+        gpu
+    }
+
+    /// Encodes one full time step (every integrator substep) and submits it.
+    pub fn step(&self, device: &Device, queue: &Queue) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("N-Body Step"),
+        });
+        for bind_group in &self.substeps {
+            self.dispatch(&mut encoder, &self.kick_drift_pipeline, bind_group);
+            self.dispatch(&mut encoder, &self.accelerate_pipeline, bind_group);
+            self.dispatch(&mut encoder, &self.kick_pipeline, bind_group);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    fn dispatch(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pipeline: &wgpu::ComputePipeline,
+        bind_group: &wgpu::BindGroup,
+    ) {
+        if self.num_bodies == 0 {
+            return;
+        }
+        // Separate passes so each stage sees the previous one's writes
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.dispatch_workgroups(self.num_bodies.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+
+    /// Copies the positions and velocities back into `bodies`, which must be the
+    /// bodies this simulation was created from. Blocks until the GPU has finished.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read_bodies(&self, device: &Device, queue: &Queue, bodies: &mut [Body]) {
+        let positions = read_vec2s(device, queue, &self.positions, self.num_bodies);
+        let velocities = read_vec2s(device, queue, &self.velocities, self.num_bodies);
+        for ((body, p), v) in bodies.iter_mut().zip(positions).zip(velocities) {
+            body.position = p;
+            body.velocity = v;
+        }
+    }
+
+    /// Accelerations from the most recent force evaluation. Blocks until the GPU has
+    /// finished.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn read_accelerations(&self, device: &Device, queue: &Queue) -> Vec<(f32, f32)> {
+        read_vec2s(device, queue, &self.accelerations, self.num_bodies)
+    }
+}
+
+/// Reads the first `count` `vec2f`s of a `COPY_SRC` buffer through a staging buffer.
+#[cfg(not(target_arch = "wasm32"))]
+fn read_vec2s(
+    device: &Device,
+    queue: &Queue,
+    buffer: &wgpu::Buffer,
+    count: u32,
+) -> Vec<(f32, f32)> {
+    let size = 8 * count as u64;
+    if size == 0 {
+        return Vec::new();
+    }
+    let staging = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("N-Body Readback"),
+        size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("N-Body Readback"),
+    });
+    encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, size);
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = staging.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |result| {
+        result.expect("map readback buffer");
+    });
+    device.poll(wgpu::Maintain::Wait);
+    let values = bytemuck::cast_slice::<u8, [f32; 2]>(&slice.get_mapped_range())
+        .iter()
+        .map(|v| (v[0], v[1]))
+        .collect();
+    staging.unmap();
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::Integrator;
+    use crate::solver::tests::{max_relative_error, random_bodies};
+
+    /// Device on a software (fallback) adapter, or any adapter if there is no
+    /// fallback, or `None` when the machine has no adapter at all. Panics if the
+    /// adapter found can't run compute shaders.
+    fn test_device() -> Option<(Device, Queue)> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let request = |force_fallback_adapter| {
+            pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::LowPower,
+                compatible_surface: None,
+                force_fallback_adapter,
+            }))
+        };
+        let adapter = request(true).or_else(|| request(false))?;
+        assert!(
+            adapter
+                .get_downlevel_capabilities()
+                .flags
+                .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS),
+            "adapter {:?} has no compute shaders",
+            adapter.get_info().name
+        );
+        let device = adapter.request_device(&wgpu::DeviceDescriptor::default(), None);
+        Some(pollster::block_on(device).expect("request a device"))
+    }
+
+    // Skips the rest of the test, with a note, on a machine with no adapter at all
+    macro_rules! require_device {
+        () => {
+            match test_device() {
+                Some(device) => device,
+                None => {
+                    eprintln!("skipped: no graphics adapter");
+                    return;
+                }
+            }
+        };
+    }
+
+    #[test]
+    fn test_initial_accelerations_match_direct_sum() {
+        let (device, queue) = require_device!();
+        // Not a multiple of the tile size, so the last tile is padded
+        let simulation = Simulation::new(random_bodies(203, 5), 1.5, 0.001, 0.01);
+        let gpu = GpuSimulation::new(&device, &queue, &simulation);
+
+        let accelerations = gpu.read_accelerations(&device, &queue);
+
+        assert!(max_relative_error(&accelerations, &simulation.accelerations()) < 1e-4);
+    }
+
+    #[test]
+    fn test_steps_match_cpu_reference() {
+        let (device, queue) = require_device!();
+        for integrator in [Integrator::VelocityVerlet, Integrator::Yoshida4] {
+            let mut simulation = Simulation::new(random_bodies(100, 9), 1.0, 0.0001, 0.01);
+            simulation.integrator = integrator;
+            let gpu = GpuSimulation::new(&device, &queue, &simulation);
+
+            for _ in 0..20 {
+                gpu.step(&device, &queue);
+            }
+            let mut bodies = simulation.bodies.clone();
+            gpu.read_bodies(&device, &queue, &mut bodies);
+            for _ in 0..20 {
+                simulation.step();
+            }
+
+            for (gpu_body, cpu_body) in bodies.iter().zip(&simulation.bodies) {
+                let dx = gpu_body.position.0 - cpu_body.position.0;
+                let dy = gpu_body.position.1 - cpu_body.position.1;
+                let dvx = gpu_body.velocity.0 - cpu_body.velocity.0;
+                let dvy = gpu_body.velocity.1 - cpu_body.velocity.1;
+                assert!(dx.hypot(dy) < 1e-5, "{integrator:?} position error");
+                assert!(dvx.hypot(dvy) < 1e-3, "{integrator:?} velocity error");
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty_system() {
+        let (device, queue) = require_device!();
+        let simulation = Simulation::new(Vec::new(), 1.0, 0.001, 0.0);
+        let gpu = GpuSimulation::new(&device, &queue, &simulation);
+        gpu.step(&device, &queue);
+        assert_eq!(gpu.num_bodies, 0);
+    }
+}
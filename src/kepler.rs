@@ -0,0 +1,628 @@
+use anyhow::{Result, bail};
+use std::f64::consts::{PI, TAU};
+
+use crate::body::Body;
+
+/// Below this eccentricity an orbit is treated as circular and the argument of
+/// periapsis is fixed at zero.
+const CIRCULAR_TOLERANCE: f64 = 1e-9;
+/// Below this inclination (or above `PI` minus it) the node is undefined and the
+/// longitude of the ascending node is fixed at zero.
+const EQUATORIAL_TOLERANCE: f64 = 1e-9;
+/// Orbits with `|e - 1|` below this are propagated with Barker's equation.
+const PARABOLIC_TOLERANCE: f64 = 1e-9;
+
+pub type Vec3 = [f64; 3];
+
+/// The kind of conic section an orbit follows.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Conic {
+    Elliptic,
+    Parabolic,
+    Hyperbolic,
+}
+
+/// Classical Keplerian elements of a two-body orbit.
+///
+/// The size of the orbit is stored as the periapsis distance so that parabolic
+/// orbits (where the semi-major axis is infinite) are representable. All angles are
+/// in radians. Planar orbits in the x-y plane have an inclination of `0` (prograde)
+/// or `PI` (retrograde) and a zero longitude of the ascending node, in which case the
+/// argument of periapsis is measured from the x-axis.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OrbitalElements {
+    pub periapsis: f64,
+    pub eccentricity: f64,
+    pub inclination: f64,
+    pub longitude_of_ascending_node: f64,
+    pub argument_of_periapsis: f64,
+    pub true_anomaly: f64,
+}
+
+impl OrbitalElements {
+    /// Planar orbit described by its semi-major axis, which must be negative for
+    /// hyperbolic orbits (`e > 1`). Parabolic orbits can't be described this way; use
+    /// [`OrbitalElements::from_periapsis`] instead.
+    pub fn from_semi_major_axis(
+        semi_major_axis: f64,
+        eccentricity: f64,
+        argument_of_periapsis: f64,
+        true_anomaly: f64,
+    ) -> Result<Self> {
+        if eccentricity < 0.0 {
+            bail!("eccentricity must be non-negative, got {eccentricity}");
+        }
+        if (eccentricity - 1.0).abs() < PARABOLIC_TOLERANCE {
+            bail!("parabolic orbits must be given by their periapsis distance");
+        }
+        if semi_major_axis == 0.0 || (semi_major_axis > 0.0) != (eccentricity < 1.0) {
+            bail!(
+                "semi-major axis {semi_major_axis} is inconsistent with eccentricity {eccentricity}"
+            );
+        }
+        Self::from_periapsis(
+            semi_major_axis * (1.0 - eccentricity),
+            eccentricity,
+            argument_of_periapsis,
+            true_anomaly,
+        )
+    }
+
+    /// Planar orbit described by its periapsis distance.
+    pub fn from_periapsis(
+        periapsis: f64,
+        eccentricity: f64,
+        argument_of_periapsis: f64,
+        true_anomaly: f64,
+    ) -> Result<Self> {
+        if periapsis <= 0.0 {
+            bail!("periapsis distance must be positive, got {periapsis}");
+        }
+        if eccentricity < 0.0 {
+            bail!("eccentricity must be non-negative, got {eccentricity}");
+        }
+        let elements = Self {
+            periapsis,
+            eccentricity,
+            inclination: 0.0,
+            longitude_of_ascending_node: 0.0,
+            argument_of_periapsis: wrap_angle(argument_of_periapsis),
+            true_anomaly: wrap_angle(true_anomaly),
+        };
+        if let Some(limit) = elements.max_true_anomaly() {
+            let nu = signed_angle(elements.true_anomaly);
+            if nu.abs() >= limit {
+                bail!("true anomaly {true_anomaly} is beyond the asymptote of an open orbit");
+            }
+        }
+        Ok(elements)
+    }
+
+    /// Returns a copy of these elements with the orbit tilted out of the x-y plane.
+    pub fn with_orientation(mut self, inclination: f64, longitude_of_ascending_node: f64) -> Self {
+        self.inclination = inclination;
+        self.longitude_of_ascending_node = wrap_angle(longitude_of_ascending_node);
+        self
+    }
+
+    /// Returns a copy of these elements with the body moved to the given mean anomaly.
+    pub fn with_mean_anomaly(mut self, mean_anomaly: f64) -> Self {
+        self.true_anomaly = wrap_angle(true_from_mean(mean_anomaly, self.eccentricity));
+        self
+    }
+
+    /// Recovers the elements of the relative orbit with position `r` and velocity
+    /// `v` about a centre with gravitational parameter `mu = G (m1 + m2)`.
+    pub fn from_state_vectors(r: Vec3, v: Vec3, mu: f64) -> Result<Self> {
+        let r_mag = norm(r);
+        if r_mag == 0.0 {
+            bail!("relative position must be non-zero");
+        }
+        if mu <= 0.0 {
+            bail!("gravitational parameter must be positive, got {mu}");
+        }
+        let h = cross(r, v);
+        let h_mag = norm(h);
+        if h_mag == 0.0 {
+            bail!("radial trajectories have no Keplerian elements");
+        }
+        let h_hat = scale(h, 1.0 / h_mag);
+
+        let rv = dot(r, v);
+        let v2 = dot(v, v);
+        let e_vec = scale(sub(scale(r, v2 - mu / r_mag), scale(v, rv)), 1.0 / mu);
+        let eccentricity = norm(e_vec);
+        let semi_latus_rectum = h_mag * h_mag / mu;
+        let periapsis = semi_latus_rectum / (1.0 + eccentricity);
+
+        let inclination = h_hat[2].clamp(-1.0, 1.0).acos();
+        let node = [-h[1], h[0], 0.0];
+        let node_mag = norm(node);
+        let (longitude_of_ascending_node, reference) = if node_mag > EQUATORIAL_TOLERANCE * h_mag {
+            (node[1].atan2(node[0]), scale(node, 1.0 / node_mag))
+        } else {
+            (0.0, [1.0, 0.0, 0.0])
+        };
+        // Measures the angle from `reference` to `x` in the plane of the orbit
+        let in_plane_angle = |x: Vec3| dot(cross(h_hat, reference), x).atan2(dot(reference, x));
+
+        let (argument_of_periapsis, true_anomaly) = if eccentricity > CIRCULAR_TOLERANCE {
+            let omega = in_plane_angle(e_vec);
+            let nu = dot(cross(h_hat, e_vec), r).atan2(dot(e_vec, r));
+            (omega, nu)
+        } else {
+            (0.0, in_plane_angle(r))
+        };
+
+        Ok(Self {
+            periapsis,
+            eccentricity,
+            inclination,
+            longitude_of_ascending_node: wrap_angle(longitude_of_ascending_node),
+            argument_of_periapsis: wrap_angle(argument_of_periapsis),
+            true_anomaly: wrap_angle(true_anomaly),
+        })
+    }
+
+    /// Position and velocity relative to the central body.
+    pub fn to_state_vectors(&self, mu: f64) -> (Vec3, Vec3) {
+        let e = self.eccentricity;
+        let p = self.semi_latus_rectum();
+        let (sin_nu, cos_nu) = self.true_anomaly.sin_cos();
+        let r = p / (1.0 + e * cos_nu);
+        let speed = (mu / p).sqrt();
+
+        let r_pf = [r * cos_nu, r * sin_nu, 0.0];
+        let v_pf = [-speed * sin_nu, speed * (e + cos_nu), 0.0];
+        (
+            self.perifocal_to_inertial(r_pf),
+            self.perifocal_to_inertial(v_pf),
+        )
+    }
+
+    pub fn conic(&self) -> Conic {
+        if (self.eccentricity - 1.0).abs() < PARABOLIC_TOLERANCE {
+            Conic::Parabolic
+        } else if self.eccentricity < 1.0 {
+            Conic::Elliptic
+        } else {
+            Conic::Hyperbolic
+        }
+    }
+
+    /// Semi-major axis; negative for hyperbolic orbits and infinite for parabolic ones.
+    pub fn semi_major_axis(&self) -> f64 {
+        match self.conic() {
+            Conic::Parabolic => f64::INFINITY,
+            _ => self.periapsis / (1.0 - self.eccentricity),
+        }
+    }
+
+    pub fn semi_latus_rectum(&self) -> f64 {
+        self.periapsis * (1.0 + self.eccentricity)
+    }
+
+    /// Orbital period, or `None` for open orbits.
+    pub fn period(&self, mu: f64) -> Option<f64> {
+        match self.conic() {
+            Conic::Elliptic => Some(TAU / self.mean_motion(mu)),
+            _ => None,
+        }
+    }
+
+    /// Rate of change of the mean anomaly. For parabolic orbits this is the rate
+    /// appearing in Barker's equation, `sqrt(mu / (2 q^3))`.
+    pub fn mean_motion(&self, mu: f64) -> f64 {
+        match self.conic() {
+            Conic::Parabolic => (mu / (2.0 * self.periapsis.powi(3))).sqrt(),
+            _ => (mu / self.semi_major_axis().abs().powi(3)).sqrt(),
+        }
+    }
+
+    /// Secular rates of the longitude of the ascending node (nodal regression) and of
+    /// the argument of periapsis (apsidal precession) of a bound orbit around an oblate
+    /// body with zonal harmonic `j2` and equatorial radius `radius`, to first order in
+    /// `j2`. Inclinations are measured from the body's equator.
+    pub fn j2_rates(&self, mu: f64, j2: f64, radius: f64) -> (f64, f64) {
+        let factor = self.mean_motion(mu) * j2 * (radius / self.semi_latus_rectum()).powi(2);
+        let cos_i = self.inclination.cos();
+        (
+            -1.5 * factor * cos_i,
+            0.75 * factor * (5.0 * cos_i * cos_i - 1.0),
+        )
+    }
+
+    pub fn mean_anomaly(&self) -> f64 {
+        mean_from_true(signed_angle(self.true_anomaly), self.eccentricity)
+    }
+
+    /// Analytically advances the body along its orbit by `dt`.
+    pub fn propagate(&self, mu: f64, dt: f64) -> Self {
+        let mean_anomaly = self.mean_anomaly() + self.mean_motion(mu) * dt;
+        self.with_mean_anomaly(mean_anomaly)
+    }
+
+    /// Largest true anomaly reachable on an open orbit, or `None` for closed ones.
+    fn max_true_anomaly(&self) -> Option<f64> {
+        match self.conic() {
+            Conic::Elliptic => None,
+            Conic::Parabolic => Some(PI),
+            Conic::Hyperbolic => Some((-1.0 / self.eccentricity).acos()),
+        }
+    }
+
+    /// Applies the rotation `R3(Omega) R1(i) R3(omega)`.
+    fn perifocal_to_inertial(&self, x: Vec3) -> Vec3 {
+        let (sin_w, cos_w) = self.argument_of_periapsis.sin_cos();
+        let (sin_i, cos_i) = self.inclination.sin_cos();
+        let (sin_o, cos_o) = self.longitude_of_ascending_node.sin_cos();
+
+        let x1 = [
+            x[0] * cos_w - x[1] * sin_w,
+            x[0] * sin_w + x[1] * cos_w,
+            x[2],
+        ];
+        let x2 = [
+            x1[0],
+            x1[1] * cos_i - x1[2] * sin_i,
+            x1[1] * sin_i + x1[2] * cos_i,
+        ];
+        [
+            x2[0] * cos_o - x2[1] * sin_o,
+            x2[0] * sin_o + x2[1] * cos_o,
+            x2[2],
+        ]
+    }
+}
+
+/// Analytically propagates the relative state `(r, v)` about a centre with
+/// gravitational parameter `mu` forward by `dt`.
+pub fn propagate_state(r: Vec3, v: Vec3, mu: f64, dt: f64) -> Result<(Vec3, Vec3)> {
+    Ok(OrbitalElements::from_state_vectors(r, v, mu)?
+        .propagate(mu, dt)
+        .to_state_vectors(mu))
+}
+
+/// Solves Kepler's equation `M = E - e sin E` for the eccentric anomaly.
+pub fn eccentric_from_mean(mean_anomaly: f64, eccentricity: f64) -> f64 {
+    let m = signed_angle(mean_anomaly);
+    let mut e_anom = if eccentricity < 0.8 {
+        m
+    } else {
+        PI.copysign(m)
+    };
+    for _ in 0..50 {
+        let f = e_anom - eccentricity * e_anom.sin() - m;
+        let delta = f / (1.0 - eccentricity * e_anom.cos());
+        e_anom -= delta;
+        if delta.abs() < 1e-15 {
+            break;
+        }
+    }
+    e_anom + (mean_anomaly - m)
+}
+
+/// Solves the hyperbolic Kepler equation `M = e sinh H - H`.
+pub fn hyperbolic_from_mean(mean_anomaly: f64, eccentricity: f64) -> f64 {
+    let m = mean_anomaly;
+    let mut h = (m / eccentricity).asinh();
+    for _ in 0..100 {
+        let f = eccentricity * h.sinh() - h - m;
+        let delta = f / (eccentricity * h.cosh() - 1.0);
+        h -= delta;
+        if delta.abs() < 1e-15 * h.abs().max(1.0) {
+            break;
+        }
+    }
+    h
+}
+
+/// Solves Barker's equation `M = D + D^3 / 3` for `D = tan(nu / 2)`.
+pub fn parabolic_from_mean(mean_anomaly: f64) -> f64 {
+    let a = 1.5 * mean_anomaly;
+    let b = (a + (a * a + 1.0).sqrt()).cbrt();
+    b - 1.0 / b
+}
+
+/// Converts a true anomaly into the mean anomaly of the matching conic.
+pub fn mean_from_true(true_anomaly: f64, eccentricity: f64) -> f64 {
+    let e = eccentricity;
+    let half = 0.5 * true_anomaly;
+    if (e - 1.0).abs() < PARABOLIC_TOLERANCE {
+        let d = half.tan();
+        d + d * d * d / 3.0
+    } else if e < 1.0 {
+        let turns = (true_anomaly / TAU).round() * TAU;
+        let half = 0.5 * (true_anomaly - turns);
+        let e_anom = 2.0 * ((1.0 - e).sqrt() * half.sin()).atan2((1.0 + e).sqrt() * half.cos());
+        e_anom - e * e_anom.sin() + turns
+    } else {
+        let h = 2.0 * (((e - 1.0) / (e + 1.0)).sqrt() * half.tan()).atanh();
+        e * h.sinh() - h
+    }
+}
+
+/// Converts a mean anomaly into the true anomaly of the matching conic.
+pub fn true_from_mean(mean_anomaly: f64, eccentricity: f64) -> f64 {
+    let e = eccentricity;
+    if (e - 1.0).abs() < PARABOLIC_TOLERANCE {
+        2.0 * parabolic_from_mean(mean_anomaly).atan()
+    } else if e < 1.0 {
+        let half = 0.5 * eccentric_from_mean(mean_anomaly, e);
+        2.0 * ((1.0 + e).sqrt() * half.sin()).atan2((1.0 - e).sqrt() * half.cos())
+    } else {
+        let h = hyperbolic_from_mean(mean_anomaly, e);
+        2.0 * (((e + 1.0) / (e - 1.0)).sqrt() * (0.5 * h).tanh()).atan()
+    }
+}
+
+/// Elements of `body`'s orbit relative to `primary`.
+pub fn elements_of(body: &Body, primary: &Body, g_constant: f32) -> Result<OrbitalElements> {
+    let (r, v) = relative_state(body, primary);
+    OrbitalElements::from_state_vectors(r, v, two_body_mu(body.mass, primary.mass, g_constant))
+}
+
+/// Creates a body of the given mass moving on `elements` around `primary`. The orbit
+/// must lie in the simulation plane.
+pub fn body_on_orbit(
+    mass: f32,
+    primary: &Body,
+    elements: &OrbitalElements,
+    g_constant: f32,
+) -> Result<Body> {
+    if elements.inclination.sin().abs() > EQUATORIAL_TOLERANCE {
+        bail!(
+            "orbit with inclination {} does not lie in the simulation plane",
+            elements.inclination
+        );
+    }
+    let mu = two_body_mu(mass, primary.mass, g_constant);
+    let (r, v) = elements.to_state_vectors(mu);
+    Body::new(
+        mass,
+        (
+            primary.position.0 + r[0] as f32,
+            primary.position.1 + r[1] as f32,
+        ),
+        (
+            primary.velocity.0 + v[0] as f32,
+            primary.velocity.1 + v[1] as f32,
+        ),
+    )
+}
+
+fn relative_state(body: &Body, primary: &Body) -> (Vec3, Vec3) {
+    (
+        [
+            (body.position.0 - primary.position.0) as f64,
+            (body.position.1 - primary.position.1) as f64,
+            0.0,
+        ],
+        [
+            (body.velocity.0 - primary.velocity.0) as f64,
+            (body.velocity.1 - primary.velocity.1) as f64,
+            0.0,
+        ],
+    )
+}
+
+fn two_body_mu(m1: f32, m2: f32, g_constant: f32) -> f64 {
+    g_constant as f64 * (m1 as f64 + m2 as f64)
+}
+
+/// Wraps an angle into `[0, 2 PI)`.
+fn wrap_angle(angle: f64) -> f64 {
+    let wrapped = angle.rem_euclid(TAU);
+    if wrapped >= TAU { 0.0 } else { wrapped }
+}
+
+/// Wraps an angle into `(-PI, PI]`.
+fn signed_angle(angle: f64) -> f64 {
+    let wrapped = wrap_angle(angle);
+    if wrapped > PI { wrapped - TAU } else { wrapped }
+}
+
+fn dot(a: Vec3, b: Vec3) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: Vec3, b: Vec3) -> Vec3 {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn norm(a: Vec3) -> f64 {
+    dot(a, a).sqrt()
+}
+
+fn scale(a: Vec3, s: f64) -> Vec3 {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+fn sub(a: Vec3, b: Vec3) -> Vec3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_vec_close(a: Vec3, b: Vec3, tol: f64) {
+        for k in 0..3 {
+            assert!((a[k] - b[k]).abs() < tol, "{a:?} != {b:?}");
+        }
+    }
+
+    fn assert_angle_close(a: f64, b: f64, tol: f64) {
+        assert!(signed_angle(a - b).abs() < tol, "{a} != {b}");
+    }
+
+    #[test]
+    fn test_kepler_equation_solvers() {
+        for &e in &[0.0, 0.1, 0.5, 0.9, 0.99] {
+            for &m in &[-3.0, -0.5, 0.0, 0.3, 2.0, 3.1] {
+                let e_anom = eccentric_from_mean(m, e);
+                assert!((e_anom - e * e_anom.sin() - m).abs() < 1e-12);
+            }
+        }
+        for &e in &[1.01, 1.5, 5.0] {
+            for &m in &[-20.0, -1.0, 0.0, 0.5, 50.0] {
+                let h = hyperbolic_from_mean(m, e);
+                assert!((e * h.sinh() - h - m).abs() < 1e-9 * m.abs().max(1.0));
+            }
+        }
+        for &m in &[-10.0, -0.2, 0.0, 1.0, 100.0] {
+            let d = parabolic_from_mean(m);
+            assert!((d + d * d * d / 3.0 - m).abs() < 1e-9 * m.abs().max(1.0));
+        }
+    }
+
+    #[test]
+    fn test_anomaly_round_trip() {
+        for &e in &[0.0, 0.3, 0.95, 1.0, 1.2, 3.0] {
+            for &nu in &[-1.5, -0.4, 0.0, 0.7, 1.5] {
+                let m = mean_from_true(nu, e);
+                assert_angle_close(true_from_mean(m, e), nu, 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn test_state_vector_round_trip_3d() {
+        let mu = 3.5;
+        let cases = [
+            OrbitalElements::from_semi_major_axis(2.0, 0.3, 1.0, 2.5).unwrap(),
+            OrbitalElements::from_semi_major_axis(-1.5, 1.8, 4.0, 0.4).unwrap(),
+            OrbitalElements::from_periapsis(0.7, 1.0, 0.2, -1.2).unwrap(),
+        ];
+        for elements in cases {
+            let elements = elements.with_orientation(0.6, 2.2);
+            let (r, v) = elements.to_state_vectors(mu);
+            let recovered = OrbitalElements::from_state_vectors(r, v, mu).unwrap();
+
+            assert!((recovered.periapsis - elements.periapsis).abs() < 1e-9);
+            assert!((recovered.eccentricity - elements.eccentricity).abs() < 1e-9);
+            assert!((recovered.inclination - elements.inclination).abs() < 1e-9);
+            assert_angle_close(
+                recovered.longitude_of_ascending_node,
+                elements.longitude_of_ascending_node,
+                1e-9,
+            );
+            assert_angle_close(
+                recovered.argument_of_periapsis,
+                elements.argument_of_periapsis,
+                1e-9,
+            );
+            assert_angle_close(recovered.true_anomaly, elements.true_anomaly, 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_planar_retrograde_orbit() {
+        let mu = 1.0;
+        // Clockwise circular orbit of radius 2 starting on the +y axis
+        let r = [0.0, 2.0, 0.0];
+        let v = [0.5_f64.sqrt(), 0.0, 0.0];
+        let elements = OrbitalElements::from_state_vectors(r, v, mu).unwrap();
+
+        assert!((elements.inclination - PI).abs() < 1e-12);
+        assert!(elements.eccentricity < 1e-12);
+        let (r2, v2) = elements.to_state_vectors(mu);
+        assert_vec_close(r2, r, 1e-12);
+        assert_vec_close(v2, v, 1e-12);
+    }
+
+    #[test]
+    fn test_propagate_full_period_returns_to_start() {
+        let mu = 2.0;
+        let elements = OrbitalElements::from_semi_major_axis(1.3, 0.6, 0.4, 0.9)
+            .unwrap()
+            .with_orientation(0.3, 1.1);
+        let period = elements.period(mu).unwrap();
+        let (r0, v0) = elements.to_state_vectors(mu);
+        let (r1, v1) = propagate_state(r0, v0, mu, 3.0 * period).unwrap();
+
+        assert_vec_close(r1, r0, 1e-9);
+        assert_vec_close(v1, v0, 1e-9);
+    }
+
+    #[test]
+    fn test_propagate_conserves_energy_and_momentum() {
+        let mu = 1.0;
+        let cases = [
+            OrbitalElements::from_semi_major_axis(1.0, 0.5, 0.0, 0.0).unwrap(),
+            OrbitalElements::from_semi_major_axis(-2.0, 1.5, 0.0, -1.0).unwrap(),
+            OrbitalElements::from_periapsis(0.5, 1.0, 0.0, -1.0).unwrap(),
+        ];
+        for elements in cases {
+            let (r0, v0) = elements.to_state_vectors(mu);
+            let (r1, v1) = propagate_state(r0, v0, mu, 0.75).unwrap();
+            let energy = |r: Vec3, v: Vec3| 0.5 * dot(v, v) - mu / norm(r);
+
+            assert!((energy(r1, v1) - energy(r0, v0)).abs() < 1e-9);
+            assert_vec_close(cross(r1, v1), cross(r0, v0), 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_propagate_matches_parabolic_time_of_flight() {
+        // From periapsis to nu = 90 degrees takes (4/3) sqrt(2 q^3 / mu) on a parabola
+        let mu = 1.0;
+        let q = 0.5;
+        let elements = OrbitalElements::from_periapsis(q, 1.0, 0.0, 0.0).unwrap();
+        let dt = (4.0 / 3.0) * (2.0 * q * q * q / mu).sqrt();
+
+        assert_angle_close(elements.propagate(mu, dt).true_anomaly, PI / 2.0, 1e-12);
+    }
+
+    #[test]
+    fn test_open_orbit_rejects_true_anomaly_beyond_asymptote() {
+        assert!(OrbitalElements::from_semi_major_axis(-1.0, 2.0, 0.0, 2.5).is_err());
+        assert!(OrbitalElements::from_semi_major_axis(1.0, 1.5, 0.0, 0.0).is_err());
+    }
+
+    #[test]
+    fn test_j2_rates() {
+        let orbit = OrbitalElements::from_semi_major_axis(2.0, 0.5, 0.0, 0.0).unwrap();
+        let n = orbit.mean_motion(3.0);
+        let factor = n * 1e-3 * (0.5f64 / 1.5).powi(2);
+        let (node, periapsis) = orbit.j2_rates(3.0, 1e-3, 0.5);
+        assert!((node + 1.5 * factor).abs() < 1e-15);
+        assert!((periapsis - 3.0 * factor).abs() < 1e-15);
+
+        // Polar orbits keep their node, and the critical inclination its periapsis
+        let polar = orbit.with_orientation(PI / 2.0, 0.0);
+        assert!(polar.j2_rates(3.0, 1e-3, 0.5).0.abs() < 1e-15);
+        let critical = orbit.with_orientation((1.0f64 / 5.0).sqrt().acos(), 0.0);
+        assert!(critical.j2_rates(3.0, 1e-3, 0.5).1.abs() < 1e-15);
+    }
+
+    #[test]
+    fn test_body_on_circular_orbit() {
+        let primary = Body::new(100.0, (0.1, -0.2), (0.5, 0.0)).unwrap();
+        let elements = OrbitalElements::from_semi_major_axis(0.5, 0.0, 0.0, 0.0).unwrap();
+        let body = body_on_orbit(1.0, &primary, &elements, 1.0).unwrap();
+
+        assert!((body.distance_to(&primary) - 0.5).abs() < 1e-6);
+        // Circular speed relative to the primary is sqrt(G (M + m) / r)
+        let expected_speed = (101.0_f32 / 0.5).sqrt();
+        assert!((body.velocity.0 - 0.5).abs() < 1e-5);
+        assert!((body.velocity.1 - expected_speed).abs() < 1e-4);
+
+        let recovered = elements_of(&body, &primary, 1.0).unwrap();
+        assert!((recovered.semi_major_axis() - 0.5).abs() < 1e-5);
+        assert!(recovered.eccentricity < 1e-5);
+    }
+
+    #[test]
+    fn test_body_on_orbit_rejects_inclined_orbit() {
+        let primary = Body::new(1.0, (0.0, 0.0), (0.0, 0.0)).unwrap();
+        let elements = OrbitalElements::from_semi_major_axis(1.0, 0.1, 0.0, 0.0)
+            .unwrap()
+            .with_orientation(0.5, 0.0);
+        assert!(body_on_orbit(1.0, &primary, &elements, 1.0).is_err());
+    }
+}
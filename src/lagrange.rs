@@ -0,0 +1,196 @@
+use anyhow::{Result, bail};
+
+use crate::body::Body;
+
+/// Bisection steps for the collinear points, enough to reach f64 precision.
+const BISECTION_STEPS: usize = 100;
+
+/// Lagrange points L1 to L5 for mass ratio `mu = m2 / (m1 + m2)`, in units of the
+/// separation in the frame turning with the primaries: barycentre at the origin,
+/// primary at `(-mu, 0)` and secondary at `(1 - mu, 0)`, turning anticlockwise. L1 lies
+/// between the primaries, L2 beyond the secondary, L3 beyond the primary, and L4 leads
+/// the secondary by 60 degrees, with L5 trailing it.
+pub fn normalized_lagrange_points(mu: f64) -> [(f64, f64); 5] {
+    // The x-axis force balance, positive outwards from the barycentre
+    let balance = |x: f64| {
+        let (r1, r2) = (x + mu, x - 1.0 + mu);
+        x - (1.0 - mu) * r1 / r1.abs().powi(3) - mu * r2 / r2.abs().powi(3)
+    };
+    // It rises from minus to plus infinity across each interval between the poles
+    let root = |mut low: f64, mut high: f64| {
+        for _ in 0..BISECTION_STEPS {
+            let middle = 0.5 * (low + high);
+            if balance(middle) < 0.0 {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+        0.5 * (low + high)
+    };
+    let half_height = 0.75f64.sqrt();
+    [
+        (root(-mu, 1.0 - mu), 0.0),
+        (root(1.0 - mu, 2.0), 0.0),
+        (root(-2.0, -mu), 0.0),
+        (0.5 - mu, half_height),
+        (0.5 - mu, -half_height),
+    ]
+}
+
+/// Lagrange points L1 to L5 of `secondary`'s orbit about `primary`, for the bodies'
+/// current separation and orientation. L4 leads the secondary along its orbit, which
+/// may turn either way. The points are equilibria only for a circular orbit.
+pub fn lagrange_points(primary: &Body, secondary: &Body) -> Result<[(f32, f32); 5]> {
+    let frame = Frame::new(primary, secondary)?;
+    Ok(normalized_lagrange_points(frame.mu).map(|point| frame.to_position(point)))
+}
+
+/// Velocity of a point at `position` turning rigidly with the two bodies about their
+/// barycentre, as a particle held at one of their Lagrange points moves.
+pub fn corotating_velocity(
+    primary: &Body,
+    secondary: &Body,
+    position: (f32, f32),
+) -> Result<(f32, f32)> {
+    let frame = Frame::new(primary, secondary)?;
+    let (dx, dy) = (
+        position.0 as f64 - frame.centre.0,
+        position.1 as f64 - frame.centre.1,
+    );
+    Ok((
+        (frame.velocity.0 - frame.angular_velocity * dy) as f32,
+        (frame.velocity.1 + frame.angular_velocity * dx) as f32,
+    ))
+}
+
+/// Radius of `secondary`'s Hill sphere, `d (m2 / 3 (m1 + m2))^(1/3)` at separation `d`,
+/// within which its gravity dominates the primary's tide.
+pub fn hill_radius(primary: &Body, secondary: &Body) -> Result<f32> {
+    let frame = Frame::new(primary, secondary)?;
+    Ok((frame.separation * (frame.mu / 3.0).cbrt()) as f32)
+}
+
+/// Radius of the sphere with the volume of `secondary`'s Roche lobe, by Eggleton's
+/// fit `0.49 q^(2/3) / (0.6 q^(2/3) + ln(1 + q^(1/3)))` of the separation, with
+/// `q = m2 / m1`. Good to 1% for any mass ratio.
+pub fn roche_lobe_radius(primary: &Body, secondary: &Body) -> Result<f32> {
+    let frame = Frame::new(primary, secondary)?;
+    let q = (secondary.mass / primary.mass) as f64;
+    let q23 = q.powf(2.0 / 3.0);
+    Ok((frame.separation * 0.49 * q23 / (0.6 * q23 + (1.0 + q.cbrt()).ln())) as f32)
+}
+
+/// The frame turning with a pair of bodies about their barycentre.
+struct Frame {
+    mu: f64,
+    separation: f64,
+    centre: (f64, f64),
+    velocity: (f64, f64),
+    /// Unit vector from the primary to the secondary.
+    axis: (f64, f64),
+    /// Instantaneous rate at which the pair turns, positive anticlockwise.
+    angular_velocity: f64,
+}
+
+impl Frame {
+    fn new(primary: &Body, secondary: &Body) -> Result<Self> {
+        if primary.mass <= 0.0 || secondary.mass <= 0.0 {
+            bail!("Lagrange points need two massive bodies");
+        }
+        let (m1, m2) = (primary.mass as f64, secondary.mass as f64);
+        let r = (
+            (secondary.position.0 - primary.position.0) as f64,
+            (secondary.position.1 - primary.position.1) as f64,
+        );
+        let v = (
+            (secondary.velocity.0 - primary.velocity.0) as f64,
+            (secondary.velocity.1 - primary.velocity.1) as f64,
+        );
+        let separation = r.0.hypot(r.1);
+        if separation == 0.0 {
+            bail!("Lagrange points need the bodies apart");
+        }
+        let weighted = |a: f32, b: f32| (m1 * a as f64 + m2 * b as f64) / (m1 + m2);
+        Ok(Self {
+            mu: m2 / (m1 + m2),
+            separation,
+            centre: (
+                weighted(primary.position.0, secondary.position.0),
+                weighted(primary.position.1, secondary.position.1),
+            ),
+            velocity: (
+                weighted(primary.velocity.0, secondary.velocity.0),
+                weighted(primary.velocity.1, secondary.velocity.1),
+            ),
+            axis: (r.0 / separation, r.1 / separation),
+            angular_velocity: (r.0 * v.1 - r.1 * v.0) / (separation * separation),
+        })
+    }
+
+    /// Position of a point given in units of the separation in the turning frame,
+    /// mirrored when the pair turns clockwise so that L4 still leads.
+    fn to_position(&self, (x, y): (f64, f64)) -> (f32, f32) {
+        let y = if self.angular_velocity < 0.0 { -y } else { y };
+        let normal = (-self.axis.1, self.axis.0);
+        (
+            (self.centre.0 + self.separation * (x * self.axis.0 + y * normal.0)) as f32,
+            (self.centre.1 + self.separation * (x * self.axis.1 + y * normal.1)) as f32,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collinear_points_balance_and_match_hill_limit() {
+        let mu = 1e-3;
+        let points = normalized_lagrange_points(mu);
+        // L1 and L2 sit about a Hill radius either side of a light secondary
+        let hill = (mu / 3.0).cbrt();
+        assert!((1.0 - mu - points[0].0 - hill).abs() < 0.1 * hill);
+        assert!((points[1].0 - (1.0 - mu) - hill).abs() < 0.1 * hill);
+        // L3 is just beyond the primary's far side, at about 1 + 5 mu / 12
+        assert!((points[2].0 + 1.0 + 5.0 * mu / 12.0).abs() < 1e-5);
+        // Earth-Moon values
+        let earth_moon = normalized_lagrange_points(0.012_15);
+        for (point, expected) in earth_moon.iter().zip([0.8369, 1.1557, -1.0051]) {
+            assert!((point.0 - expected).abs() < 1e-3, "{point:?} {expected}");
+        }
+    }
+
+    #[test]
+    fn test_points_follow_the_pair() {
+        // A pair on a clockwise circular orbit, turned a quarter: the secondary is above
+        let primary = Body::new(9.0, (1.0, 0.0), (0.0, 0.0)).unwrap();
+        let secondary = Body::new(1.0, (1.0, 2.0), (1.5, 0.0)).unwrap();
+        let points = lagrange_points(&primary, &secondary).unwrap();
+        let expected = normalized_lagrange_points(0.1);
+        let centre = (1.0, 0.2);
+        // L1 to L3 lie along the pair's axis
+        for (point, normalized) in points.iter().zip(&expected).take(3) {
+            assert!((point.0 - 1.0).abs() < 1e-6);
+            assert!((point.1 - (centre.1 + 2.0 * normalized.0 as f32)).abs() < 1e-5);
+        }
+        // Turning clockwise, L4 leads the secondary to the right
+        assert!(points[3].0 > 1.0 && points[4].0 < 1.0);
+        assert!((points[3].0 - 1.0 - 3.0f32.sqrt()).abs() < 1e-5);
+
+        // Held at L4, a particle turns with the pair: here at -0.75 about the barycentre
+        let velocity = corotating_velocity(&primary, &secondary, points[3]).unwrap();
+        let barycentre_velocity = (0.15, 0.0);
+        let (dx, dy) = (points[3].0 - centre.0, points[3].1 - centre.1);
+        assert!((velocity.0 - (barycentre_velocity.0 + 0.75 * dy)).abs() < 1e-5);
+        assert!((velocity.1 - (barycentre_velocity.1 - 0.75 * dx)).abs() < 1e-5);
+
+        let hill = hill_radius(&primary, &secondary).unwrap();
+        assert!((hill - 2.0 * (0.1f32 / 3.0).cbrt()).abs() < 1e-5);
+        // For q = 1/9 Eggleton gives about 0.213 of the separation
+        let roche = roche_lobe_radius(&primary, &secondary).unwrap();
+        assert!((roche / 2.0 - 0.2132).abs() < 1e-3, "{roche}");
+        let particle = Body::test_particle((0.0, 0.0), (0.0, 0.0)).unwrap();
+        assert!(lagrange_points(&primary, &particle).is_err());
+    }
+}
@@ -1,10 +1,48 @@
 use log::info;
 
 pub mod app;
+pub mod body;
+pub mod boundary;
+pub mod cosmology;
+pub mod detector;
+pub mod field;
+pub mod force_law;
+pub mod gpu;
+pub mod kepler;
+pub mod lagrange;
+pub mod link;
+pub mod mass_flow;
+pub mod neighbours;
+pub mod oblateness;
+pub mod post_newtonian;
+pub mod regularization;
+pub mod restricted;
+pub mod rubble;
+pub mod scenario;
+pub mod simulation;
+pub mod solver;
+pub mod sph;
 pub mod state;
 
 pub use app::App;
-pub use state::{Body, State};
+pub use body::{Body, BodyStore};
+pub use boundary::{Boundary, BoundaryEvent, PeriodicForces, RemovalKind};
+pub use cosmology::{Cosmology, PowerSpectrum, Zeldovich};
+pub use detector::{Detection, Detector, DetectorKind};
+pub use field::{ExternalField, ForceField};
+pub use force_law::ForceLaw;
+pub use gpu::GpuSimulation;
+pub use link::{Link, LinkKind};
+pub use mass_flow::{MassSchedule, Thrust, ThrustDirection};
+pub use post_newtonian::{PostNewtonian, StrainObserver, StrainSample};
+pub use regularization::Regularization;
+pub use restricted::RestrictedThreeBody;
+pub use rubble::{Aggregate, SoftSphere};
+pub use scenario::Scenario;
+pub use simulation::{Integrator, Simulation};
+pub use solver::{GravitySolver, MeshBoundary};
+pub use sph::{GasParticle, Sph};
+pub use state::State;
 
 use winit::event_loop::EventLoop;
 
@@ -22,7 +60,16 @@ pub fn run() {
         }
     }
 
+    // Desktop builds take an optional scenario file as the first argument
+    #[cfg(not(target_arch = "wasm32"))]
+    let scenario = match std::env::args().nth(1) {
+        Some(path) => Scenario::load(path).expect("load scenario"),
+        None => Scenario::default(),
+    };
+    #[cfg(target_arch = "wasm32")]
+    let scenario = Scenario::default();
+
     let event_loop = EventLoop::new().unwrap();
-    let mut app = App::new();
+    let mut app = App::new(scenario);
     let _ = event_loop.run_app(&mut app);
 }
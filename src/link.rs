@@ -0,0 +1,283 @@
+use serde::Deserialize;
+
+use crate::body::Body;
+
+/// Iterations allowed for the rod constraints to converge in each stage.
+const MAX_ITERATIONS: usize = 100;
+/// Relative error in length, or in relative speed along the rod, that the rod
+/// constraints settle for.
+const TOLERANCE: f32 = 1e-6;
+
+/// A non-gravitational connection between two bodies, by index into the simulation's
+/// bodies.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Link {
+    pub bodies: (usize, usize),
+    /// Rest length of a spring, length at which a tether goes taut, or a rod's length.
+    pub length: f32,
+    pub kind: LinkKind,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LinkKind {
+    /// Spring-damper: pulls when stretched past its length and pushes when compressed.
+    Spring {
+        /// Force per unit extension.
+        stiffness: f32,
+        /// Force per unit speed along the spring.
+        #[serde(default)]
+        damping: f32,
+    },
+    /// A spring-damper that only ever pulls, and goes slack shorter than its length.
+    Tether {
+        stiffness: f32,
+        #[serde(default)]
+        damping: f32,
+    },
+    /// Rigid rod holding the bodies exactly its length apart, as a constraint on the
+    /// integrator rather than a force.
+    Rod {},
+}
+
+impl Link {
+    /// Force on the second body, along the unit vector from the first, or `None` for a
+    /// rod or a link whose bodies coincide.
+    fn tension(&self, bodies: &[Body]) -> Option<(f32, (f32, f32))> {
+        let (a, b) = (&bodies[self.bodies.0], &bodies[self.bodies.1]);
+        let (dx, dy) = (b.position.0 - a.position.0, b.position.1 - a.position.1);
+        let distance = dx.hypot(dy);
+        if distance == 0.0 {
+            return None;
+        }
+        let normal = (dx / distance, dy / distance);
+        let extension = distance - self.length;
+        let separating =
+            (b.velocity.0 - a.velocity.0) * normal.0 + (b.velocity.1 - a.velocity.1) * normal.1;
+        match self.kind {
+            LinkKind::Spring { stiffness, damping } => {
+                Some((-stiffness * extension - damping * separating, normal))
+            }
+            LinkKind::Tether { stiffness, damping } if extension > 0.0 => {
+                // Damping can slow a taut tether's recoil but never push
+                Some((
+                    (-stiffness * extension - damping * separating).min(0.0),
+                    normal,
+                ))
+            }
+            LinkKind::Tether { .. } | LinkKind::Rod {} => None,
+        }
+    }
+
+    /// Elastic energy stored in a spring or stretched tether.
+    fn potential_energy(&self, bodies: &[Body]) -> f32 {
+        let extension = bodies[self.bodies.0].distance_to(&bodies[self.bodies.1]) - self.length;
+        match self.kind {
+            LinkKind::Spring { stiffness, .. } => 0.5 * stiffness * extension * extension,
+            LinkKind::Tether { stiffness, .. } if extension > 0.0 => {
+                0.5 * stiffness * extension * extension
+            }
+            LinkKind::Tether { .. } | LinkKind::Rod {} => 0.0,
+        }
+    }
+
+    pub fn is_rod(&self) -> bool {
+        self.kind == LinkKind::Rod {}
+    }
+}
+
+/// Spring and tether accelerations, added to `accelerations`.
+pub fn add_accelerations(links: &[Link], bodies: &[Body], accelerations: &mut [(f32, f32)]) {
+    for link in links {
+        let Some((force, normal)) = link.tension(bodies) else {
+            continue;
+        };
+        let (i, j) = link.bodies;
+        accelerations[i].0 -= force * normal.0 / bodies[i].mass;
+        accelerations[i].1 -= force * normal.1 / bodies[i].mass;
+        accelerations[j].0 += force * normal.0 / bodies[j].mass;
+        accelerations[j].1 += force * normal.1 / bodies[j].mass;
+    }
+}
+
+/// Elastic energy of the springs and tethers.
+pub fn potential_energy(links: &[Link], bodies: &[Body]) -> f32 {
+    links.iter().map(|link| link.potential_energy(bodies)).sum()
+}
+
+/// SHAKE: after a drift over `dt` from `previous` positions, moves the bodies joined by
+/// rods back to the rods' lengths along the rods' directions before the drift, and
+/// gives their velocities the impulse that moves them there. The corrections are equal
+/// and opposite, so momentum is conserved.
+pub fn constrain_positions(links: &[Link], bodies: &mut [Body], previous: &[(f32, f32)], dt: f32) {
+    for _ in 0..MAX_ITERATIONS {
+        let mut converged = true;
+        for link in links.iter().filter(|link| link.is_rod()) {
+            let (i, j) = link.bodies;
+            let r = (
+                bodies[i].position.0 - bodies[j].position.0,
+                bodies[i].position.1 - bodies[j].position.1,
+            );
+            let error = r.0 * r.0 + r.1 * r.1 - link.length * link.length;
+            if error.abs() <= 2.0 * TOLERANCE * link.length * link.length {
+                continue;
+            }
+            converged = false;
+            let s = (previous[i].0 - previous[j].0, previous[i].1 - previous[j].1);
+            let (wi, wj) = (1.0 / bodies[i].mass, 1.0 / bodies[j].mass);
+            let g = error / (2.0 * (s.0 * r.0 + s.1 * r.1) * (wi + wj));
+            for (k, w, sign) in [(i, wi, -1.0), (j, wj, 1.0)] {
+                let shift = (sign * g * w * s.0, sign * g * w * s.1);
+                bodies[k].position.0 += shift.0;
+                bodies[k].position.1 += shift.1;
+                bodies[k].velocity.0 += shift.0 / dt;
+                bodies[k].velocity.1 += shift.1 / dt;
+            }
+        }
+        if converged {
+            return;
+        }
+    }
+}
+
+/// RATTLE's velocity stage: removes the relative velocity along each rod, so the
+/// bodies it joins only turn about each other.
+pub fn constrain_velocities(links: &[Link], bodies: &mut [Body]) {
+    for _ in 0..MAX_ITERATIONS {
+        let mut converged = true;
+        for link in links.iter().filter(|link| link.is_rod()) {
+            let (i, j) = link.bodies;
+            let r = (
+                bodies[i].position.0 - bodies[j].position.0,
+                bodies[i].position.1 - bodies[j].position.1,
+            );
+            let v = (
+                bodies[i].velocity.0 - bodies[j].velocity.0,
+                bodies[i].velocity.1 - bodies[j].velocity.1,
+            );
+            let r2 = r.0 * r.0 + r.1 * r.1;
+            let along = r.0 * v.0 + r.1 * v.1;
+            let speed = v.0.hypot(v.1);
+            if r2 == 0.0 || along.abs() <= TOLERANCE * r2.sqrt() * speed {
+                continue;
+            }
+            converged = false;
+            let (wi, wj) = (1.0 / bodies[i].mass, 1.0 / bodies[j].mass);
+            let k = along / (r2 * (wi + wj));
+            bodies[i].velocity.0 -= k * wi * r.0;
+            bodies[i].velocity.1 -= k * wi * r.1;
+            bodies[j].velocity.0 += k * wj * r.0;
+            bodies[j].velocity.1 += k * wj * r.1;
+        }
+        if converged {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair() -> Vec<Body> {
+        vec![
+            Body::new(1.0, (0.0, 0.0), (0.0, 1.0)).unwrap(),
+            Body::new(3.0, (0.5, 0.0), (0.25, 0.0)).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn test_spring_forces_balance_and_match_energy() {
+        let bodies = pair();
+        let spring = Link {
+            bodies: (0, 1),
+            length: 0.25,
+            kind: LinkKind::Spring {
+                stiffness: 8.0,
+                damping: 0.0,
+            },
+        };
+        let mut accelerations = vec![(0.0, 0.0); 2];
+        add_accelerations(&[spring], &bodies, &mut accelerations);
+        // Stretched by 0.25, so each body is pulled towards the other with a force of 2
+        assert_eq!(accelerations, vec![(2.0, 0.0), (-2.0 / 3.0, 0.0)]);
+        assert_eq!(potential_energy(&[spring], &bodies), 0.25);
+
+        // Damping resists the bodies separating
+        let damped = Link {
+            kind: LinkKind::Spring {
+                stiffness: 8.0,
+                damping: 4.0,
+            },
+            ..spring
+        };
+        let mut accelerations = vec![(0.0, 0.0); 2];
+        add_accelerations(&[damped], &bodies, &mut accelerations);
+        assert_eq!(accelerations[0], (3.0, 0.0));
+    }
+
+    #[test]
+    fn test_tether_only_pulls() {
+        let mut bodies = pair();
+        let tether = Link {
+            bodies: (0, 1),
+            length: 1.0,
+            kind: LinkKind::Tether {
+                stiffness: 8.0,
+                damping: 100.0,
+            },
+        };
+        let mut accelerations = vec![(0.0, 0.0); 2];
+        add_accelerations(&[tether], &bodies, &mut accelerations);
+        assert_eq!(accelerations, vec![(0.0, 0.0); 2]);
+        assert_eq!(potential_energy(&[tether], &bodies), 0.0);
+
+        // Taut but recoiling fast, the damping can't make it push
+        bodies[1].position.0 = 1.25;
+        bodies[1].velocity.0 = -1.0;
+        add_accelerations(&[tether], &bodies, &mut accelerations);
+        assert_eq!(accelerations, vec![(0.0, 0.0); 2]);
+        bodies[1].velocity.0 = 0.0;
+        add_accelerations(&[tether], &bodies, &mut accelerations);
+        assert_eq!(accelerations, vec![(2.0, 0.0), (-2.0 / 3.0, 0.0)]);
+    }
+
+    #[test]
+    fn test_rod_constraints_hold_length_and_momentum() {
+        let mut bodies = pair();
+        let rod = Link {
+            bodies: (0, 1),
+            length: 0.5,
+            kind: LinkKind::Rod {},
+        };
+        let momentum = |bodies: &[Body]| {
+            bodies.iter().fold((0.0, 0.0), |(x, y), b| {
+                (x + b.mass * b.velocity.0, y + b.mass * b.velocity.1)
+            })
+        };
+        let initial = momentum(&bodies);
+
+        // Drift freely, then pull the bodies back onto the rod
+        let dt = 0.1;
+        let previous: Vec<(f32, f32)> = bodies.iter().map(|b| b.position).collect();
+        for body in &mut bodies {
+            body.position.0 += body.velocity.0 * dt;
+            body.position.1 += body.velocity.1 * dt;
+        }
+        constrain_positions(&[rod], &mut bodies, &previous, dt);
+        assert!((bodies[0].distance_to(&bodies[1]) - 0.5).abs() < 1e-5);
+
+        constrain_velocities(&[rod], &mut bodies);
+        let r = (
+            bodies[1].position.0 - bodies[0].position.0,
+            bodies[1].position.1 - bodies[0].position.1,
+        );
+        let v = (
+            bodies[1].velocity.0 - bodies[0].velocity.0,
+            bodies[1].velocity.1 - bodies[0].velocity.1,
+        );
+        assert!((r.0 * v.0 + r.1 * v.1).abs() < 1e-5);
+        let p = momentum(&bodies);
+        assert!((p.0 - initial.0).abs() < 1e-5 && (p.1 - initial.1).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,171 @@
+use serde::Deserialize;
+
+use crate::body::Body;
+
+/// How a body's mass changes with time. Material leaves or arrives moving with the
+/// body, as for an isotropic stellar wind or co-moving accretion, so the body's
+/// velocity is unchanged and the momentum exchanged is `dm v`.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum MassSchedule {
+    /// `dm/dt = rate`: negative for mass loss, positive for accretion.
+    Constant { rate: f32 },
+    /// `dm/dt = m / timescale`: exponential growth, or decay for a negative
+    /// timescale.
+    Exponential { timescale: f32 },
+    /// Mass interpolated linearly through `[time, mass]` points in time order, and
+    /// held at the end values outside them.
+    Table { points: Vec<[f64; 2]> },
+}
+
+impl MassSchedule {
+    /// Mass at `time + dt` of a body with mass `mass` at `time`. Never negative.
+    pub fn mass_after(&self, mass: f32, time: f64, dt: f64) -> f32 {
+        let mass = match self {
+            MassSchedule::Constant { rate } => mass + rate * dt as f32,
+            MassSchedule::Exponential { timescale } => mass * (dt / *timescale as f64).exp() as f32,
+            MassSchedule::Table { points } => {
+                let t = time + dt;
+                match points.iter().position(|p| p[0] > t) {
+                    None => points.last().map_or(mass, |p| p[1] as f32),
+                    Some(0) => points[0][1] as f32,
+                    Some(i) => {
+                        let ([t0, m0], [t1, m1]) = (points[i - 1], points[i]);
+                        (m0 + (m1 - m0) * (t - t0) / (t1 - t0)) as f32
+                    }
+                }
+            }
+        };
+        mass.max(0.0)
+    }
+}
+
+/// Constant-acceleration engine burn, as for a spacecraft.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Thrust {
+    /// Magnitude of the thrust acceleration.
+    pub acceleration: f32,
+    #[serde(default)]
+    pub direction: ThrustDirection,
+    /// Simulated time at which the burn starts.
+    #[serde(default)]
+    pub start: f64,
+    /// Length of the burn; it never stops if not given.
+    pub duration: Option<f64>,
+    /// Speed of the exhaust relative to the body. When given, propellant is burnt
+    /// following the rocket equation, `dm/dt = -m acceleration / exhaust_velocity`;
+    /// otherwise the mass is constant.
+    pub exhaust_velocity: Option<f32>,
+}
+
+/// Which way a [`Thrust`] pushes.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ThrustDirection {
+    /// Along the body's velocity.
+    #[default]
+    Prograde,
+    /// Against the body's velocity.
+    Retrograde,
+    /// At a fixed angle, in radians anticlockwise from the x axis.
+    Fixed { angle: f32 },
+}
+
+impl Thrust {
+    /// Thrust acceleration of `body` at `time`, zero outside the burn.
+    pub fn acceleration(&self, body: &Body, time: f64) -> (f32, f32) {
+        if !self.is_burning(time) {
+            return (0.0, 0.0);
+        }
+        let (vx, vy) = body.velocity;
+        let speed = vx.hypot(vy);
+        let direction = match self.direction {
+            ThrustDirection::Prograde if speed > 0.0 => (vx / speed, vy / speed),
+            ThrustDirection::Retrograde if speed > 0.0 => (-vx / speed, -vy / speed),
+            ThrustDirection::Prograde | ThrustDirection::Retrograde => (0.0, 0.0),
+            ThrustDirection::Fixed { angle } => (angle.cos(), angle.sin()),
+        };
+        (
+            self.acceleration * direction.0,
+            self.acceleration * direction.1,
+        )
+    }
+
+    fn is_burning(&self, time: f64) -> bool {
+        time >= self.start && self.duration.is_none_or(|d| time < self.start + d)
+    }
+
+    /// Mass at `time + dt` of a body burning propellant from mass `mass` at `time`.
+    pub fn mass_after(&self, mass: f32, time: f64, dt: f64) -> f32 {
+        let Some(exhaust_velocity) = self.exhaust_velocity else {
+            return mass;
+        };
+        // Time spent burning within the step
+        let end = self.duration.map_or(f64::INFINITY, |d| self.start + d);
+        let burn = ((time + dt).min(end) - time.max(self.start)).max(0.0);
+        mass * (-(self.acceleration / exhaust_velocity) as f64 * burn).exp() as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mass_schedules() {
+        let wind = MassSchedule::Constant { rate: -0.5 };
+        assert_eq!(wind.mass_after(2.0, 0.0, 1.0), 1.5);
+        assert_eq!(wind.mass_after(0.25, 0.0, 1.0), 0.0);
+
+        let growth = MassSchedule::Exponential { timescale: 2.0 };
+        assert!((growth.mass_after(1.0, 5.0, 2.0) - std::f32::consts::E).abs() < 1e-6);
+
+        let table = MassSchedule::Table {
+            points: vec![[1.0, 4.0], [3.0, 2.0], [4.0, 3.0]],
+        };
+        assert_eq!(table.mass_after(9.0, 0.0, 0.5), 4.0);
+        assert_eq!(table.mass_after(9.0, 1.5, 0.5), 3.0);
+        assert_eq!(table.mass_after(9.0, 3.0, 0.5), 2.5);
+        assert_eq!(table.mass_after(9.0, 4.0, 1.0), 3.0);
+    }
+
+    #[test]
+    fn test_thrust_directions_and_window() {
+        let body = Body::new(1.0, (0.0, 0.0), (3.0, 4.0)).unwrap();
+        let mut thrust = Thrust {
+            acceleration: 2.0,
+            direction: ThrustDirection::Prograde,
+            start: 1.0,
+            duration: Some(2.0),
+            exhaust_velocity: None,
+        };
+        assert_eq!(thrust.acceleration(&body, 0.5), (0.0, 0.0));
+        assert_eq!(thrust.acceleration(&body, 1.0), (1.2, 1.6));
+        assert_eq!(thrust.acceleration(&body, 3.0), (0.0, 0.0));
+
+        thrust.direction = ThrustDirection::Retrograde;
+        assert_eq!(thrust.acceleration(&body, 2.0), (-1.2, -1.6));
+        thrust.direction = ThrustDirection::Fixed {
+            angle: std::f32::consts::FRAC_PI_2,
+        };
+        let a = thrust.acceleration(&body, 2.0);
+        assert!(a.0.abs() < 1e-6 && (a.1 - 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_rocket_burns_propellant_only_while_thrusting() {
+        let thrust = Thrust {
+            acceleration: 2.0,
+            direction: ThrustDirection::Prograde,
+            start: 1.0,
+            duration: Some(1.0),
+            exhaust_velocity: Some(4.0),
+        };
+        // Half a time unit of burn in each of these steps
+        let expected = 10.0 * (-0.25f32).exp();
+        assert!((thrust.mass_after(10.0, 0.5, 1.0) - expected).abs() < 1e-5);
+        assert!((thrust.mass_after(10.0, 1.5, 1.0) - expected).abs() < 1e-5);
+        assert_eq!(thrust.mass_after(10.0, 2.5, 1.0), 10.0);
+    }
+}
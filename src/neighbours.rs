@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+
+/// Pairs `(i, j)`, `i < j`, of points closer than `distance`, in order. The points are
+/// binned on a grid of cells `distance` wide, so only neighbouring cells are compared
+/// and the search takes time proportional to the number of points.
+pub fn pairs_within(points: &[(f32, f32)], distance: f32) -> Vec<(usize, usize)> {
+    if distance <= 0.0 {
+        return Vec::new();
+    }
+    let cell = |(x, y): (f32, f32)| ((x / distance).floor() as i64, (y / distance).floor() as i64);
+    let mut grid: HashMap<(i64, i64), Vec<usize>> = HashMap::new();
+    for (i, &point) in points.iter().enumerate() {
+        grid.entry(cell(point)).or_default().push(i);
+    }
+
+    let distance2 = distance * distance;
+    let mut pairs = Vec::new();
+    for (i, &(x, y)) in points.iter().enumerate() {
+        let (cx, cy) = cell((x, y));
+        for gx in cx - 1..=cx + 1 {
+            for gy in cy - 1..=cy + 1 {
+                for &j in grid.get(&(gx, gy)).into_iter().flatten() {
+                    let (dx, dy) = (points[j].0 - x, points[j].1 - y);
+                    if j > i && dx * dx + dy * dy < distance2 {
+                        pairs.push((i, j));
+                    }
+                }
+            }
+        }
+    }
+    pairs.sort_unstable();
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pairs_match_brute_force() {
+        let mut seed = 12345u32;
+        let mut random = || {
+            seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+            (seed >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+        };
+        let points: Vec<(f32, f32)> = (0..300).map(|_| (random(), random())).collect();
+        let mut expected = Vec::new();
+        for i in 0..points.len() {
+            for j in i + 1..points.len() {
+                let (dx, dy) = (points[j].0 - points[i].0, points[j].1 - points[i].1);
+                if dx * dx + dy * dy < 0.01 {
+                    expected.push((i, j));
+                }
+            }
+        }
+        assert!(!expected.is_empty());
+        assert_eq!(pairs_within(&points, 0.1), expected);
+        assert!(pairs_within(&points, 0.0).is_empty());
+    }
+}
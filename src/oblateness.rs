@@ -0,0 +1,138 @@
+use crate::body::Body;
+
+/// `J2 R^2` of a body, zero for test particles.
+fn quadrupole(body: &Body) -> f32 {
+    if body.is_test_particle() {
+        0.0
+    } else {
+        body.j2 * body.radius * body.radius
+    }
+}
+
+/// Whether any body is oblate.
+pub fn any_oblate(bodies: &[Body]) -> bool {
+    bodies.iter().any(|b| quadrupole(b) != 0.0)
+}
+
+/// Accelerations due to the J2 terms of the oblate bodies, added to `accelerations`.
+///
+/// The simulation is planar, so spin axes are normal to the plane and orbits lie in
+/// the equatorial planes of the bodies they circle. There a body's J2 term adds
+/// `-G m J2 R^2 / (2 r^3)` to its potential: an extra attraction that makes orbits
+/// precess but exerts no torque. Each oblate body feels the reaction of the pull it
+/// exerts, so momentum is conserved.
+pub fn add_accelerations(
+    bodies: &[Body],
+    g_constant: f32,
+    eps2: f32,
+    accelerations: &mut [(f32, f32)],
+) {
+    for (k, oblate) in bodies.iter().enumerate() {
+        let q = quadrupole(oblate);
+        if q == 0.0 {
+            continue;
+        }
+        for (j, body) in bodies.iter().enumerate() {
+            if j == k {
+                continue;
+            }
+            let dx = body.position.0 - oblate.position.0;
+            let dy = body.position.1 - oblate.position.1;
+            let s2 = dx * dx + dy * dy + eps2;
+            if s2 == 0.0 {
+                continue;
+            }
+            // Acceleration per unit source mass, -3 G J2 R^2 d / (2 s^5)
+            let f = -1.5 * g_constant * q / (s2 * s2 * s2.sqrt());
+            accelerations[j].0 += oblate.mass * f * dx;
+            accelerations[j].1 += oblate.mass * f * dy;
+            accelerations[k].0 -= body.mass * f * dx;
+            accelerations[k].1 -= body.mass * f * dy;
+        }
+    }
+}
+
+/// Potential energy of the J2 terms, summed over every oblate body and every other
+/// massive body.
+pub fn potential_energy(bodies: &[Body], g_constant: f32, eps2: f32) -> f32 {
+    let mut energy = 0.0;
+    for (k, oblate) in bodies.iter().enumerate() {
+        let q = quadrupole(oblate);
+        if q == 0.0 {
+            continue;
+        }
+        for (j, body) in bodies.iter().enumerate() {
+            let dx = body.position.0 - oblate.position.0;
+            let dy = body.position.1 - oblate.position.1;
+            let s2 = dx * dx + dy * dy + eps2;
+            if j != k && s2 > 0.0 {
+                energy -= 0.5 * g_constant * oblate.mass * body.mass * q / (s2 * s2.sqrt());
+            }
+        }
+    }
+    energy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn oblate_planet() -> Body {
+        let mut planet = Body::new(2.0, (0.1, -0.2), (0.0, 0.0)).unwrap();
+        planet.radius = 0.3;
+        planet.j2 = 0.05;
+        planet
+    }
+
+    #[test]
+    fn test_accelerations_are_potential_gradients_and_balance() {
+        let (g, eps2, h) = (1.3, 0.001, 1e-3);
+        let mut moon = Body::new(0.5, (0.7, 0.3), (0.0, 0.0)).unwrap();
+        moon.radius = 0.1;
+        moon.j2 = 0.02;
+        let bodies = [oblate_planet(), moon];
+        let mut accelerations = vec![(0.0, 0.0); 2];
+        add_accelerations(&bodies, g, eps2, &mut accelerations);
+
+        let energy = |dx: f32, dy: f32| {
+            let mut moved = bodies;
+            moved[1].position.0 += dx;
+            moved[1].position.1 += dy;
+            potential_energy(&moved, g, eps2)
+        };
+        let force = (
+            -(energy(h, 0.0) - energy(-h, 0.0)) / (2.0 * h),
+            -(energy(0.0, h) - energy(0.0, -h)) / (2.0 * h),
+        );
+        let scale = force.0.hypot(force.1);
+        let a = accelerations[1];
+        assert!((moon.mass * a.0 - force.0).hypot(moon.mass * a.1 - force.1) < 1e-2 * scale);
+
+        // Newton's third law
+        let total = (
+            bodies[0].mass * accelerations[0].0 + moon.mass * a.0,
+            bodies[0].mass * accelerations[0].1 + moon.mass * a.1,
+        );
+        assert!(total.0.hypot(total.1) < 1e-5 * scale);
+    }
+
+    #[test]
+    fn test_spherical_and_massless_bodies_have_no_field() {
+        let mut planet = oblate_planet();
+        let mut tracer = Body::test_particle((1.0, 0.0), (0.0, 0.0)).unwrap();
+        tracer.j2 = 0.1;
+        tracer.radius = 1.0;
+        let mut accelerations = vec![(0.0, 0.0); 2];
+
+        planet.j2 = 0.0;
+        add_accelerations(&[planet, tracer], 1.0, 0.0, &mut accelerations);
+        assert!(!any_oblate(&[planet, tracer]));
+        assert_eq!(accelerations, vec![(0.0, 0.0); 2]);
+
+        // A test particle is pulled harder towards an oblate body
+        planet.j2 = 0.05;
+        add_accelerations(&[planet, tracer], 1.0, 0.0, &mut accelerations);
+        assert!(accelerations[1].0 < 0.0);
+        assert_eq!(accelerations[0], (0.0, 0.0));
+    }
+}
@@ -0,0 +1,409 @@
+use anyhow::{Result, bail};
+use serde::Deserialize;
+use std::io::{self, Write};
+
+use crate::body::Body;
+
+/// Post-Newtonian corrections to the mutual gravity of the bodies, in harmonic
+/// coordinates. They are added on top of the Newtonian accelerations from the
+/// solver and vanish as `speed_of_light` goes to infinity.
+///
+/// The energy diagnostics stay Newtonian, so they wander at order `v^2 / c^2` and
+/// don't include the energy radiated away.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PostNewtonian {
+    /// Speed of light in simulation units.
+    pub speed_of_light: f32,
+    /// Include the conservative first post-Newtonian terms.
+    #[serde(default = "default_first_order")]
+    pub first_order: bool,
+    /// Include the 2.5PN radiation-reaction damping that drives binaries to inspiral.
+    #[serde(default)]
+    pub radiation_reaction: bool,
+    /// Record the gravitational-wave strain seen by this observer after every step.
+    #[serde(default)]
+    pub observer: Option<StrainObserver>,
+}
+
+fn default_first_order() -> bool {
+    true
+}
+
+/// Distant observer of the quadrupole gravitational-wave strain. The orbits lie in
+/// the x-y plane; the observer sits `distance` away, `inclination` radians from the
+/// z axis towards the x axis, so zero is face-on.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StrainObserver {
+    pub distance: f32,
+    #[serde(default)]
+    pub inclination: f32,
+}
+
+/// The two polarizations of the strain at one instant.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StrainSample {
+    pub time: f64,
+    pub plus: f32,
+    pub cross: f32,
+}
+
+/// Header line of the strain CSV.
+pub const STRAIN_CSV_HEADER: &str = "time,plus,cross";
+
+impl StrainSample {
+    /// Writes the sample as one row of the strain CSV.
+    pub fn write_csv(&self, mut out: impl Write) -> io::Result<()> {
+        writeln!(out, "{},{:e},{:e}", self.time, self.plus, self.cross)
+    }
+}
+
+/// Writes strain samples as CSV with a `time,plus,cross` header.
+pub fn write_strain_csv<'a>(
+    samples: impl IntoIterator<Item = &'a StrainSample>,
+    mut out: impl Write,
+) -> io::Result<()> {
+    writeln!(out, "{STRAIN_CSV_HEADER}")?;
+    for sample in samples {
+        sample.write_csv(&mut out)?;
+    }
+    Ok(())
+}
+
+impl PostNewtonian {
+    /// First post-Newtonian corrections only, with no radiation and no observer.
+    pub fn new(speed_of_light: f32) -> Self {
+        Self {
+            speed_of_light,
+            first_order: true,
+            radiation_reaction: false,
+            observer: None,
+        }
+    }
+
+    /// Rejects a speed of light or observer distance that isn't positive.
+    pub fn validate(&self) -> Result<()> {
+        let c = self.speed_of_light;
+        if c.is_nan() || c <= 0.0 {
+            bail!("the speed of light must be positive, got {c}");
+        }
+        if let Some(observer) = self.observer
+            && (observer.distance.is_nan() || observer.distance <= 0.0)
+        {
+            bail!(
+                "the strain observer distance must be positive, got {}",
+                observer.distance
+            );
+        }
+        Ok(())
+    }
+
+    /// The enabled post-Newtonian parts of each body's acceleration.
+    pub fn accelerations(&self, bodies: &[Body], g_constant: f32, eps2: f32) -> Vec<(f32, f32)> {
+        let mut accelerations = if self.first_order {
+            self.first_order_accelerations(bodies, g_constant, eps2)
+        } else {
+            vec![(0.0, 0.0); bodies.len()]
+        };
+        if self.radiation_reaction {
+            let damping = self.radiation_reaction_accelerations(bodies, g_constant, eps2);
+            for (a, d) in accelerations.iter_mut().zip(damping) {
+                a.0 += d.0;
+                a.1 += d.1;
+            }
+        }
+        accelerations
+    }
+
+    /// 2.5PN radiation reaction, the terms of order `1 / c^5`, applied to every pair
+    /// as if it were an isolated binary. In harmonic coordinates the relative
+    /// acceleration picks up `8/5 eta (GM)^2 / (c^5 r^3) [A rdot n - B v]` with
+    /// `A = 18 v^2 + 2/3 GM/r - 25 rdot^2` and `B = 6 v^2 - 2 GM/r - 15 rdot^2`,
+    /// shared between the two bodies in inverse proportion to their masses so
+    /// momentum is conserved.
+    pub fn radiation_reaction_accelerations(
+        &self,
+        bodies: &[Body],
+        g_constant: f32,
+        eps2: f32,
+    ) -> Vec<(f32, f32)> {
+        let c5 = (self.speed_of_light as f64).powi(5);
+        let mut accelerations = vec![[0.0f64; 2]; bodies.len()];
+        for (i, a) in bodies.iter().enumerate() {
+            for (j, b) in bodies.iter().enumerate().skip(i + 1) {
+                let total_mass = (a.mass + b.mass) as f64;
+                if a.is_test_particle() || b.is_test_particle() {
+                    continue;
+                }
+                let x = [
+                    (a.position.0 - b.position.0) as f64,
+                    (a.position.1 - b.position.1) as f64,
+                ];
+                let v = [
+                    (a.velocity.0 - b.velocity.0) as f64,
+                    (a.velocity.1 - b.velocity.1) as f64,
+                ];
+                let r = (x[0] * x[0] + x[1] * x[1] + eps2 as f64).sqrt();
+                let n = [x[0] / r, x[1] / r];
+                let v2 = v[0] * v[0] + v[1] * v[1];
+                let rdot = n[0] * v[0] + n[1] * v[1];
+                let gm = g_constant as f64 * total_mass;
+                let eta = a.mass as f64 * b.mass as f64 / (total_mass * total_mass);
+
+                let scale = 1.6 * eta * gm * gm / (c5 * r * r * r);
+                let radial = (18.0 * v2 + 2.0 / 3.0 * gm / r - 25.0 * rdot * rdot) * rdot;
+                let tangential = 6.0 * v2 - 2.0 * gm / r - 15.0 * rdot * rdot;
+                for k in 0..2 {
+                    let relative = scale * (radial * n[k] - tangential * v[k]);
+                    accelerations[i][k] += b.mass as f64 / total_mass * relative;
+                    accelerations[j][k] -= a.mass as f64 / total_mass * relative;
+                }
+            }
+        }
+        accelerations
+            .into_iter()
+            .map(|a| (a[0] as f32, a[1] as f32))
+            .collect()
+    }
+
+    /// Strain seen by `observer` from the quadrupole formula,
+    /// `h_jk = 2 G / (c^4 D) d^2/dt^2 (sum m x_j x_k)` projected transverse-traceless.
+    /// `accelerations` are the bodies' current accelerations; the formula is of
+    /// Newtonian order, so their mutual Newtonian gravity is enough.
+    pub fn strain(
+        &self,
+        observer: &StrainObserver,
+        bodies: &[Body],
+        accelerations: &[(f32, f32)],
+        g_constant: f32,
+    ) -> (f32, f32) {
+        // Second time derivative of the mass quadrupole, m (a_j x_k + 2 v_j v_k + x_j a_k)
+        let (mut xx, mut yy, mut xy) = (0.0f64, 0.0f64, 0.0f64);
+        for (body, a) in bodies.iter().zip(accelerations) {
+            let m = body.mass as f64;
+            let (x, y) = (body.position.0 as f64, body.position.1 as f64);
+            let (vx, vy) = (body.velocity.0 as f64, body.velocity.1 as f64);
+            let (ax, ay) = (a.0 as f64, a.1 as f64);
+            xx += m * (2.0 * ax * x + 2.0 * vx * vx);
+            yy += m * (2.0 * ay * y + 2.0 * vy * vy);
+            xy += m * (ax * y + 2.0 * vx * vy + x * ay);
+        }
+        // Polarization axes (cos i, 0, -sin i) and (0, 1, 0) on the sky
+        let cos_i = (observer.inclination as f64).cos();
+        let scale =
+            g_constant as f64 / ((self.speed_of_light as f64).powi(4) * observer.distance as f64);
+        let plus = scale * (cos_i * cos_i * xx - yy);
+        let cross = scale * 2.0 * cos_i * xy;
+        (plus as f32, cross as f32)
+    }
+
+    /// First post-Newtonian (Einstein-Infeld-Hoffmann) part of each body's
+    /// acceleration, the terms of order `1 / c^2`.
+    pub fn first_order_accelerations(
+        &self,
+        bodies: &[Body],
+        g_constant: f32,
+        eps2: f32,
+    ) -> Vec<(f32, f32)> {
+        let g = g_constant as f64;
+        let eps2 = eps2 as f64;
+        let position = |b: &Body| [b.position.0 as f64, b.position.1 as f64];
+        let velocity = |b: &Body| [b.velocity.0 as f64, b.velocity.1 as f64];
+        let dot = |a: [f64; 2], b: [f64; 2]| a[0] * b[0] + a[1] * b[1];
+        // Separation from `a` to `b` and its softened length
+        let separation = |a: &Body, b: &Body| {
+            let (pa, pb) = (position(a), position(b));
+            let d = [pb[0] - pa[0], pb[1] - pa[1]];
+            (d, (dot(d, d) + eps2).sqrt())
+        };
+
+        // Newtonian potential (as a positive sum of G m / r) and acceleration of each
+        // body, which appear inside the 1PN terms of the others
+        let mut potential = vec![0.0; bodies.len()];
+        let mut newtonian = vec![[0.0; 2]; bodies.len()];
+        for (a, body) in bodies.iter().enumerate() {
+            for (b, other) in bodies.iter().enumerate() {
+                let (d, s) = separation(body, other);
+                if a == b || s == 0.0 {
+                    continue;
+                }
+                let gm = g * other.mass as f64;
+                potential[a] += gm / s;
+                newtonian[a][0] += gm * d[0] / (s * s * s);
+                newtonian[a][1] += gm * d[1] / (s * s * s);
+            }
+        }
+
+        let inv_c2 = 1.0 / (self.speed_of_light as f64).powi(2);
+        bodies
+            .iter()
+            .enumerate()
+            .map(|(a, body)| {
+                let va = velocity(body);
+                let mut acceleration = [0.0; 2];
+                for (b, other) in bodies.iter().enumerate() {
+                    let (d, s) = separation(body, other);
+                    if a == b || s == 0.0 || other.is_test_particle() {
+                        continue;
+                    }
+                    let vb = velocity(other);
+                    // Unit vector from b to a
+                    let n = [-d[0] / s, -d[1] / s];
+                    let gm = g * other.mass as f64;
+
+                    let along = dot(va, va) + 2.0 * dot(vb, vb)
+                        - 4.0 * dot(va, vb)
+                        - 1.5 * dot(n, vb).powi(2)
+                        - 4.0 * potential[a]
+                        - potential[b]
+                        + 0.5 * dot(d, newtonian[b]);
+                    let relative = dot(n, [4.0 * va[0] - 3.0 * vb[0], 4.0 * va[1] - 3.0 * vb[1]]);
+                    for k in 0..2 {
+                        acceleration[k] += gm / (s * s)
+                            * (-n[k] * along + relative * (va[k] - vb[k]))
+                            + 3.5 * gm / s * newtonian[b][k];
+                    }
+                }
+                (
+                    (acceleration[0] * inv_c2) as f32,
+                    (acceleration[1] * inv_c2) as f32,
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_test_particle_matches_schwarzschild() {
+        // Around a fixed mass M in harmonic coordinates the 1PN acceleration of a test
+        // particle is GM / (c^2 r^2) [(4 GM / r - v^2) n + 4 (n . v) v]
+        let pn = PostNewtonian::new(10.0);
+        let star = Body::new(2.0, (0.0, 0.0), (0.0, 0.0)).unwrap();
+        let tracer = Body::test_particle((0.6, 0.8), (-0.9, 0.5)).unwrap();
+
+        let a = pn.accelerations(&[star, tracer], 1.5, 0.0)[1];
+
+        let (gm, r) = (3.0, 1.0);
+        let (n, v) = ((0.6, 0.8), (-0.9, 0.5));
+        let v2 = v.0 * v.0 + v.1 * v.1;
+        let n_dot_v = n.0 * v.0 + n.1 * v.1;
+        let scale = gm / (100.0 * r * r);
+        let expected = (
+            scale * ((4.0 * gm / r - v2) * n.0 + 4.0 * n_dot_v * v.0),
+            scale * ((4.0 * gm / r - v2) * n.1 + 4.0 * n_dot_v * v.1),
+        );
+        assert!((a.0 - expected.0).abs() < 1e-6 && (a.1 - expected.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_corrections_vanish_without_relativity() {
+        let pn = PostNewtonian::new(1e6);
+        let bodies = [
+            Body::new(1.0, (0.0, 0.0), (0.1, 0.0)).unwrap(),
+            Body::new(2.0, (1.0, 0.0), (0.0, 0.5)).unwrap(),
+        ];
+        for a in pn.accelerations(&bodies, 1.0, 0.0) {
+            assert!(a.0.abs() < 1e-10 && a.1.abs() < 1e-10);
+        }
+    }
+
+    /// Equal-mass circular binary with separation 1 and G M = 1, orbiting anticlockwise.
+    fn circular_binary() -> [Body; 2] {
+        [
+            Body::new(0.5, (-0.5, 0.0), (0.0, -0.5)).unwrap(),
+            Body::new(0.5, (0.5, 0.0), (0.0, 0.5)).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn test_write_strain_csv() {
+        let samples = [
+            StrainSample {
+                time: 0.0,
+                plus: 1.5e-6,
+                cross: 0.0,
+            },
+            StrainSample {
+                time: 0.25,
+                plus: -2e-7,
+                cross: 3e-6,
+            },
+        ];
+        let mut out = Vec::new();
+        write_strain_csv(&samples, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "time,plus,cross\n0,1.5e-6,0e0\n0.25,-2e-7,3e-6\n"
+        );
+    }
+
+    #[test]
+    fn test_radiation_reaction_drains_orbital_energy() {
+        let mut pn = PostNewtonian::new(10.0);
+        pn.first_order = false;
+        pn.radiation_reaction = true;
+        let bodies = circular_binary();
+
+        let a = pn.accelerations(&bodies, 1.0, 0.0);
+
+        // Peters-Mathews: dE/dt = -32/5 G^4 m1^2 m2^2 M / (c^5 r^5)
+        let power: f32 = bodies
+            .iter()
+            .zip(&a)
+            .map(|(b, a)| b.mass * (b.velocity.0 * a.0 + b.velocity.1 * a.1))
+            .sum();
+        let expected = -6.4 * 0.0625 / 1e5;
+        assert!(((power - expected) / expected).abs() < 1e-5);
+        assert!((0.5 * (a[0].0 + a[1].0)).abs() < 1e-12);
+        assert!((0.5 * (a[0].1 + a[1].1)).abs() < 1e-12);
+    }
+
+    /// The circular binary rotated to orbital phase `phase`, with the Newtonian
+    /// centripetal accelerations of its bodies.
+    fn binary_at(phase: f32) -> ([Body; 2], [(f32, f32); 2]) {
+        let (sin, cos) = phase.sin_cos();
+        let rotate = |(x, y): (f32, f32)| (cos * x - sin * y, sin * x + cos * y);
+        let bodies = circular_binary()
+            .map(|b| Body::new(b.mass, rotate(b.position), rotate(b.velocity)).unwrap());
+        // G m_other / r^2 towards the centre
+        let accelerations = bodies.map(|b| (-b.position.0, -b.position.1));
+        (bodies, accelerations)
+    }
+
+    #[test]
+    fn test_circular_binary_strain_amplitude() {
+        let pn = PostNewtonian::new(10.0);
+        let distance = 50.0;
+        // h0 = 4 G^2 m1 m2 / (c^4 a D)
+        let h0 = 4.0 * 0.25 / (1e4 * distance);
+        let strain = |inclination: f32, phase: f32| {
+            let (bodies, accelerations) = binary_at(phase);
+            let observer = StrainObserver {
+                distance,
+                inclination,
+            };
+            pn.strain(&observer, &bodies, &accelerations, 1.0)
+        };
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-4 * h0;
+
+        // Face-on both polarizations have amplitude h0, a quarter cycle apart, and
+        // oscillate at twice the orbital frequency
+        let (plus, cross) = strain(0.0, 0.0);
+        assert!(close(plus, -h0) && close(cross, 0.0));
+        let (plus, cross) = strain(0.0, std::f32::consts::FRAC_PI_4);
+        assert!(close(plus, 0.0) && close(cross, -h0));
+        let (plus, cross) = strain(0.0, std::f32::consts::FRAC_PI_2);
+        assert!(close(plus, h0) && close(cross, 0.0));
+
+        // Edge-on only a plus polarization of half the amplitude remains
+        let (plus, cross) = strain(std::f32::consts::FRAC_PI_2, std::f32::consts::FRAC_PI_2);
+        assert!(close(plus, 0.5 * h0) && close(cross, 0.0));
+        let (plus, cross) = strain(std::f32::consts::FRAC_PI_2, std::f32::consts::FRAC_PI_4);
+        assert!(close(plus, 0.0) && close(cross, 0.0));
+    }
+}
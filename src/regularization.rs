@@ -0,0 +1,222 @@
+use anyhow::{Result, bail};
+use serde::Deserialize;
+
+use crate::body::Body;
+use crate::neighbours;
+
+/// Iterations allowed for finding the fictitious time that spans a step.
+const MAX_ITERATIONS: usize = 200;
+
+/// Two-body regularization of close encounters. Each step, massive bodies closer than
+/// `radius` are paired off, closest first, and each pair's relative orbit is drifted
+/// exactly in Levi-Civita coordinates (the plane's Kustaanheimo-Stiefel transform)
+/// rather than by straight lines, while the rest of the forces on its members kick
+/// them as usual. Close approaches, even head-on collisions, then cost nothing in time
+/// step, so gravity can go unsoftened.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Regularization {
+    pub radius: f32,
+}
+
+impl Regularization {
+    pub fn validate(&self) -> Result<()> {
+        if self.radius <= 0.0 {
+            bail!("regularization radius must be positive");
+        }
+        Ok(())
+    }
+
+    /// Pairs of massive bodies within `radius` of each other, taken closest first so no
+    /// body is in more than one pair, in index order.
+    pub fn pairs(&self, bodies: &[Body]) -> Vec<(usize, usize)> {
+        let massive: Vec<usize> = (0..bodies.len())
+            .filter(|&i| !bodies[i].is_test_particle())
+            .collect();
+        let points: Vec<(f32, f32)> = massive.iter().map(|&i| bodies[i].position).collect();
+        let mut candidates: Vec<(f32, usize, usize)> =
+            neighbours::pairs_within(&points, self.radius)
+                .into_iter()
+                .map(|(a, b)| (massive[a], massive[b]))
+                .map(|(i, j)| (bodies[i].distance_to(&bodies[j]), i, j))
+                .filter(|(distance, _, _)| *distance > 0.0)
+                .collect();
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut paired = vec![false; bodies.len()];
+        let mut pairs = Vec::new();
+        for (_, i, j) in candidates {
+            if !paired[i] && !paired[j] {
+                paired[i] = true;
+                paired[j] = true;
+                pairs.push((i, j));
+            }
+        }
+        pairs.sort_unstable();
+        pairs
+    }
+}
+
+/// Advances the relative position `r` and velocity `v` of an unperturbed pair with
+/// gravitational parameter `mu = G (m1 + m2)` by `dt`, which may be negative.
+///
+/// With `r = u^2` in complex numbers and the fictitious time `ds = dt / |r|`, the
+/// Kepler problem becomes the harmonic oscillator `u'' = (h / 2) u` at energy `h`,
+/// regular even through a collision. It is solved in closed form, and the physical
+/// time `t(s)`, the integral of `|u|^2`, is inverted by safeguarded Newton iteration.
+pub fn kepler_drift(r: (f64, f64), v: (f64, f64), mu: f64, dt: f64) -> ((f64, f64), (f64, f64)) {
+    let radius = r.0.hypot(r.1);
+    if dt == 0.0 || radius == 0.0 {
+        return (r, v);
+    }
+    let u0 = complex_sqrt(r);
+    // dr/ds = |r| v = 2 u u'
+    let du0 = scale(multiply(conjugate(u0), v), 0.5);
+    let energy = 0.5 * (v.0 * v.0 + v.1 * v.1) - mu / radius;
+    let k = -0.5 * energy;
+
+    let squared_speed = du0.0 * du0.0 + du0.1 * du0.1;
+    let cross = u0.0 * du0.0 + u0.1 * du0.1;
+    let state = |s: f64| {
+        let (c, sn, _) = universal_functions(k, s);
+        let u = add(scale(u0, c), scale(du0, sn));
+        let du = add(scale(u0, -k * sn), scale(du0, c));
+        (u, du)
+    };
+    // t(s) = |u0|^2 int C^2 + |u0'|^2 int S^2 + 2 (u0 . u0') int C S, with C^2 = 1 - k S^2
+    // and int C S = S^2 / 2
+    let time = |s: f64| {
+        let (_, sn, integral) = universal_functions(k, s);
+        radius * (s - k * integral) + squared_speed * integral + cross * sn * sn
+    };
+    let rate = |s: f64| {
+        let (u, _) = state(s);
+        u.0 * u.0 + u.1 * u.1
+    };
+
+    // Bracket the fictitious time, which runs the same way as dt
+    let mut end = dt / radius;
+    for _ in 0..MAX_ITERATIONS {
+        if (time(end) - dt) * dt.signum() >= 0.0 {
+            break;
+        }
+        end *= 2.0;
+    }
+    let (mut low, mut high) = if dt > 0.0 { (0.0, end) } else { (end, 0.0) };
+    let mut s = 0.5 * (low + high);
+    for _ in 0..MAX_ITERATIONS {
+        let error = time(s) - dt;
+        if error.abs() <= 1e-14 * dt.abs() {
+            break;
+        }
+        if error < 0.0 {
+            low = s;
+        } else {
+            high = s;
+        }
+        let newton = s - error / rate(s);
+        s = if newton > low && newton < high {
+            newton
+        } else {
+            0.5 * (low + high)
+        };
+        if high - low <= f64::EPSILON * s.abs() {
+            break;
+        }
+    }
+
+    let (u, du) = state(s);
+    // v = dr/dt = 2 u u' / |u|^2
+    let speed_scale = 2.0 / (u.0 * u.0 + u.1 * u.1);
+    (multiply(u, u), scale(multiply(u, du), speed_scale))
+}
+
+/// `C(s)` and `S(s)` solving `C'' = -k C` with `C(0) = 1`, `S = int C` with `S(0) = 0`,
+/// and the integral of `S^2` from zero, for an energy `k` of either sign. Near `k s^2 = 0`
+/// they are summed as series to avoid cancellation.
+fn universal_functions(k: f64, s: f64) -> (f64, f64, f64) {
+    let x = k * s * s;
+    if x.abs() < 0.1 {
+        let c = 1.0 - x / 2.0 + x * x / 24.0 - x.powi(3) / 720.0 + x.powi(4) / 40_320.0;
+        let sn = s * (1.0 - x / 6.0 + x * x / 120.0 - x.powi(3) / 5_040.0 + x.powi(4) / 362_880.0);
+        let integral =
+            s.powi(3) * (1.0 / 3.0 - x / 15.0 + 2.0 * x * x / 315.0 - x.powi(3) / 2_835.0);
+        return (c, sn, integral);
+    }
+    let (c, sn) = if k > 0.0 {
+        let w = k.sqrt();
+        ((w * s).cos(), (w * s).sin() / w)
+    } else {
+        let w = (-k).sqrt();
+        ((w * s).cosh(), (w * s).sinh() / w)
+    };
+    (c, sn, (s - c * sn) / (2.0 * k))
+}
+
+fn complex_sqrt((x, y): (f64, f64)) -> (f64, f64) {
+    let modulus = x.hypot(y).sqrt();
+    let half_angle = 0.5 * y.atan2(x);
+    (modulus * half_angle.cos(), modulus * half_angle.sin())
+}
+
+fn multiply(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    (a.0 * b.0 - a.1 * b.1, a.0 * b.1 + a.1 * b.0)
+}
+
+fn conjugate(a: (f64, f64)) -> (f64, f64) {
+    (a.0, -a.1)
+}
+
+fn add(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    (a.0 + b.0, a.1 + b.1)
+}
+
+fn scale(a: (f64, f64), factor: f64) -> (f64, f64) {
+    (a.0 * factor, a.1 * factor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kepler;
+
+    #[test]
+    fn test_drift_matches_kepler_propagation() {
+        let mu = 2.0;
+        // A very eccentric ellipse from near apocentre, and a hyperbola
+        for (r, v) in [((1.0, 0.2), (0.05, 0.3)), ((0.3, -0.1), (2.5, 3.0))] {
+            for dt in [0.7, -0.4, 3.1] {
+                let (r1, v1) = kepler_drift(r, v, mu, dt);
+                let (expected_r, expected_v) =
+                    kepler::propagate_state([r.0, r.1, 0.0], [v.0, v.1, 0.0], mu, dt).unwrap();
+                assert!((r1.0 - expected_r[0]).hypot(r1.1 - expected_r[1]) < 1e-9);
+                assert!((v1.0 - expected_v[0]).hypot(v1.1 - expected_v[1]) < 1e-8);
+            }
+        }
+    }
+
+    #[test]
+    fn test_head_on_collision_bounces_back() {
+        // Released from rest, the pair falls in, bounces through the collision, and is
+        // back at rest where it started after the period of the degenerate ellipse
+        let (mu, start): (f64, f64) = (1.0, 1.0);
+        let period = std::f64::consts::TAU * (0.5 * start).powf(1.5) / mu.sqrt();
+        let (r, v) = kepler_drift((start, 0.0), (0.0, 0.0), mu, 0.49 * period);
+        assert!(r.0 > 0.0 && r.0 < 0.2 * start && v.0 < 0.0, "{r:?} {v:?}");
+        let (r, v) = kepler_drift((start, 0.0), (0.0, 0.0), mu, 0.51 * period);
+        assert!(r.0 > 0.0 && r.0 < 0.2 * start && v.0 > 0.0, "{r:?} {v:?}");
+        let (r, v) = kepler_drift((start, 0.0), (0.0, 0.0), mu, period);
+        assert!((r.0 - start).abs() < 1e-9 && r.1.abs() < 1e-9, "{r:?}");
+        assert!(v.0.hypot(v.1) < 1e-6, "{v:?}");
+    }
+
+    #[test]
+    fn test_pairs_closest_first() {
+        let body = |x: f32| Body::new(1.0, (x, 0.0), (0.0, 0.0)).unwrap();
+        let mut bodies = vec![body(0.0), body(0.03), body(0.05), body(0.5), body(0.52)];
+        bodies.push(Body::test_particle((0.51, 0.0), (0.0, 0.0)).unwrap());
+        let regularization = Regularization { radius: 0.1 };
+        // 1 and 2 are closest, which leaves 0 alone; the test particle is never paired
+        assert_eq!(regularization.pairs(&bodies), vec![(1, 2), (3, 4)]);
+    }
+}
@@ -0,0 +1,267 @@
+use anyhow::{Result, bail};
+use serde::Deserialize;
+
+use crate::body::Body;
+use crate::field::ForceField;
+use crate::lagrange;
+
+/// Circular restricted three-body problem: two primaries on circular orbits about
+/// their barycentre, and massless particles integrated in the frame turning with them.
+/// The barycentre is at the origin and the primaries lie on the x-axis, heavier on
+/// the left, so they stay put and the particles feel their gravity plus the
+/// centrifugal and Coriolis accelerations of the rotating frame. As a field it gives
+/// the gravity and centrifugal terms; the Coriolis term only turns the velocities, and
+/// is left to the integrator.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RestrictedThreeBody {
+    pub primary_mass: f32,
+    pub secondary_mass: f32,
+    /// Distance between the primaries.
+    #[serde(default = "default_separation")]
+    pub separation: f32,
+    /// Softening length of the primaries' gravity.
+    #[serde(default)]
+    pub softening: f32,
+    /// Jacobi constants whose zero-velocity curves are drawn.
+    #[serde(default)]
+    pub zero_velocity_curves: Vec<f32>,
+}
+
+fn default_separation() -> f32 {
+    1.0
+}
+
+impl RestrictedThreeBody {
+    pub fn validate(&self) -> Result<()> {
+        if self.primary_mass <= 0.0 || self.secondary_mass <= 0.0 {
+            bail!("both primaries need a positive mass");
+        }
+        if self.secondary_mass > self.primary_mass {
+            bail!("the secondary can't be heavier than the primary");
+        }
+        if self.separation <= 0.0 {
+            bail!("the primaries need a positive separation");
+        }
+        Ok(())
+    }
+
+    /// `mu = m2 / (m1 + m2)`.
+    pub fn mass_ratio(&self) -> f32 {
+        self.secondary_mass / (self.primary_mass + self.secondary_mass)
+    }
+
+    /// Angular velocity of the primaries, and so of the frame.
+    pub fn angular_velocity(&self, g_constant: f32) -> f32 {
+        (g_constant * (self.primary_mass + self.secondary_mass) / self.separation.powi(3)).sqrt()
+    }
+
+    /// Positions of the primary and the secondary in the rotating frame.
+    pub fn primary_positions(&self) -> [(f32, f32); 2] {
+        let mu = self.mass_ratio();
+        [
+            (-mu * self.separation, 0.0),
+            ((1.0 - mu) * self.separation, 0.0),
+        ]
+    }
+
+    /// Lagrange points L1 to L5 in the rotating frame.
+    pub fn lagrange_points(&self) -> [(f32, f32); 5] {
+        let separation = self.separation as f64;
+        lagrange::normalized_lagrange_points(self.mass_ratio() as f64)
+            .map(|(x, y)| ((x * separation) as f32, (y * separation) as f32))
+    }
+
+    /// Gravity of the primaries at `position`, per unit mass.
+    fn gravity(&self, position: (f32, f32), g_constant: f32) -> (f32, f32) {
+        let masses = [self.primary_mass, self.secondary_mass];
+        let eps2 = self.softening * self.softening;
+        self.primary_positions()
+            .iter()
+            .zip(masses)
+            .fold((0.0, 0.0), |(ax, ay), (p, mass)| {
+                let (dx, dy) = (position.0 - p.0, position.1 - p.1);
+                let d2 = dx * dx + dy * dy + eps2;
+                if d2 == 0.0 {
+                    return (ax, ay);
+                }
+                let a = -g_constant * mass / (d2 * d2.sqrt());
+                (ax + a * dx, ay + a * dy)
+            })
+    }
+
+    /// Effective potential per unit mass of the rotating frame: the primaries' gravity
+    /// and the centrifugal term `-Omega^2 r^2 / 2`.
+    pub fn effective_potential(&self, position: (f32, f32), g_constant: f32) -> f32 {
+        let masses = [self.primary_mass, self.secondary_mass];
+        let eps2 = self.softening * self.softening;
+        let gravity: f32 = self
+            .primary_positions()
+            .iter()
+            .zip(masses)
+            .map(|(p, mass)| {
+                let (dx, dy) = (position.0 - p.0, position.1 - p.1);
+                -g_constant * mass / (dx * dx + dy * dy + eps2).sqrt()
+            })
+            .sum();
+        let omega = self.angular_velocity(g_constant);
+        gravity - 0.5 * omega * omega * (position.0 * position.0 + position.1 * position.1)
+    }
+
+    /// Jacobi constant `C = -2 Phi_eff - v^2` of a particle, conserved along its path
+    /// in the rotating frame.
+    pub fn jacobi_constant(&self, body: &Body, g_constant: f32) -> f32 {
+        let (vx, vy) = body.velocity;
+        -2.0 * self.effective_potential(body.position, g_constant) - (vx * vx + vy * vy)
+    }
+
+    /// Zero-velocity curve of Jacobi constant `jacobi`, which particles with that
+    /// constant can't cross, as line segments traced by marching squares over a
+    /// `resolution` by `resolution` grid on the square of half-width `half_width`
+    /// centred on the barycentre.
+    pub fn zero_velocity_curve(
+        &self,
+        jacobi: f32,
+        g_constant: f32,
+        half_width: f32,
+        resolution: usize,
+    ) -> Vec<[(f32, f32); 2]> {
+        let n = resolution.max(1);
+        let spacing = 2.0 * half_width / n as f32;
+        let node = |i: usize, j: usize| {
+            (
+                -half_width + i as f32 * spacing,
+                -half_width + j as f32 * spacing,
+            )
+        };
+        // Positive where a particle of this constant can move
+        let allowed = |p: (f32, f32)| -2.0 * self.effective_potential(p, g_constant) - jacobi;
+        let values: Vec<f32> = (0..=n)
+            .flat_map(|j| (0..=n).map(move |i| (i, j)))
+            .map(|(i, j)| allowed(node(i, j)))
+            .collect();
+        let value = |i: usize, j: usize| values[j * (n + 1) + i];
+
+        let mut segments = Vec::new();
+        for j in 0..n {
+            for i in 0..n {
+                // Corners anticlockwise from the bottom left, and the edges between them
+                let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+                let mut crossings = Vec::with_capacity(4);
+                for k in 0..4 {
+                    let (a, b) = (corners[k], corners[(k + 1) % 4]);
+                    let (fa, fb) = (value(a.0, a.1), value(b.0, b.1));
+                    if (fa >= 0.0) != (fb >= 0.0) {
+                        let t = fa / (fa - fb);
+                        let (pa, pb) = (node(a.0, a.1), node(b.0, b.1));
+                        crossings.push((pa.0 + t * (pb.0 - pa.0), pa.1 + t * (pb.1 - pa.1)));
+                    }
+                }
+                match crossings[..] {
+                    [p, q] => segments.push([p, q]),
+                    [p, q, r, s] => {
+                        // Saddle: the centre decides which corners are joined
+                        let centre = node(i, j);
+                        let centre = (centre.0 + 0.5 * spacing, centre.1 + 0.5 * spacing);
+                        if (allowed(centre) >= 0.0) == (value(i, j) >= 0.0) {
+                            segments.push([p, s]);
+                            segments.push([q, r]);
+                        } else {
+                            segments.push([p, q]);
+                            segments.push([r, s]);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        segments
+    }
+}
+
+impl ForceField for RestrictedThreeBody {
+    fn acceleration(&self, body: &Body, _time: f64, g_constant: f32) -> (f32, f32) {
+        let (x, y) = body.position;
+        let omega = self.angular_velocity(g_constant);
+        let gravity = self.gravity(body.position, g_constant);
+        (gravity.0 + omega * omega * x, gravity.1 + omega * omega * y)
+    }
+
+    fn potential(&self, position: (f32, f32), _time: f64, g_constant: f32) -> f32 {
+        self.effective_potential(position, g_constant)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn earth_moon() -> RestrictedThreeBody {
+        RestrictedThreeBody {
+            primary_mass: 0.9878,
+            secondary_mass: 0.0122,
+            separation: 1.0,
+            softening: 0.0,
+            zero_velocity_curves: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_triangular_point_is_equilibrium() {
+        let system = earth_moon();
+        let mu = system.mass_ratio();
+        let l4 = Body::test_particle((0.5 - mu, 0.75f32.sqrt()), (0.0, 0.0)).unwrap();
+        let a = system.acceleration(&l4, 0.0, 1.0);
+        assert!(a.0.hypot(a.1) < 1e-5, "{a:?}");
+        // All five Lagrange points are equilibria of the rotating frame
+        for point in system.lagrange_points() {
+            let at_rest = Body::test_particle(point, (0.0, 0.0)).unwrap();
+            let a = system.acceleration(&at_rest, 0.0, 1.0);
+            assert!(a.0.hypot(a.1) < 1e-4, "{point:?} {a:?}");
+        }
+        // C = 3 - mu (1 - mu) in units of G, the total mass and the separation
+        let jacobi = system.jacobi_constant(&l4, 1.0);
+        assert!((jacobi - (3.0 - mu * (1.0 - mu))).abs() < 1e-5);
+
+        // The acceleration is minus the potential gradient
+        let moving = Body::test_particle((0.3, 0.4), (0.1, -0.2)).unwrap();
+        let h = 1e-3;
+        let phi = |dx: f32, dy: f32| system.potential((0.3 + dx, 0.4 + dy), 0.0, 1.0);
+        let gradient = (
+            (phi(h, 0.0) - phi(-h, 0.0)) / (2.0 * h),
+            (phi(0.0, h) - phi(0.0, -h)) / (2.0 * h),
+        );
+        let a = system.acceleration(&moving, 0.0, 1.0);
+        assert!((a.0 + gradient.0).abs() < 1e-2 && (a.1 + gradient.1).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_zero_velocity_curve_follows_level_set() {
+        let system = earth_moon();
+        let jacobi = 3.2;
+        let segments = system.zero_velocity_curve(jacobi, 1.0, 1.5, 150);
+        assert!(!segments.is_empty());
+        let spacing = 3.0 / 150.0;
+        for point in segments.iter().flatten() {
+            let c = -2.0 * system.effective_potential(*point, 1.0);
+            // Linear interpolation is good to the potential's change over a cell
+            let r = (point.0 + 0.0122)
+                .hypot(point.1)
+                .min((point.0 - 0.9878).hypot(point.1));
+            assert!(
+                (c - jacobi).abs() < 5.0 * spacing / (r * r).max(spacing),
+                "{point:?}"
+            );
+        }
+        // Below the constant at the triangular points every point of the plane is allowed
+        assert!(system.zero_velocity_curve(2.5, 1.0, 1.5, 50).is_empty());
+        assert!(
+            RestrictedThreeBody {
+                secondary_mass: 2.0,
+                ..earth_moon()
+            }
+            .validate()
+            .is_err()
+        );
+    }
+}
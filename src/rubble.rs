@@ -0,0 +1,291 @@
+use anyhow::{Result, bail};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use crate::body::Body;
+use crate::neighbours::pairs_within;
+
+/// Soft-sphere contact forces between touching bodies, treated as discs of their
+/// `radius`: a linear spring pushes overlapping bodies apart and a dashpot damps their
+/// approach and separation. Test particles have no surface and pass through.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SoftSphere {
+    /// Repulsive force per unit overlap.
+    pub stiffness: f32,
+    /// Force per unit normal relative speed, which dissipates energy in collisions.
+    #[serde(default)]
+    pub damping: f32,
+}
+
+impl SoftSphere {
+    /// Contact accelerations, added to `accelerations`.
+    pub fn add_accelerations(&self, bodies: &[Body], accelerations: &mut [(f32, f32)]) {
+        for (i, j) in touching_pairs(bodies, 0.0) {
+            let (a, b) = (&bodies[i], &bodies[j]);
+            let dx = b.position.0 - a.position.0;
+            let dy = b.position.1 - a.position.1;
+            let distance = dx.hypot(dy);
+            if distance == 0.0 {
+                continue;
+            }
+            let (nx, ny) = (dx / distance, dy / distance);
+            let overlap = a.radius + b.radius - distance;
+            let closing_speed =
+                (a.velocity.0 - b.velocity.0) * nx + (a.velocity.1 - b.velocity.1) * ny;
+            // Force on b, along the normal from a to b
+            let force = self.stiffness * overlap + self.damping * closing_speed;
+            accelerations[i].0 -= force * nx / a.mass;
+            accelerations[i].1 -= force * ny / a.mass;
+            accelerations[j].0 += force * nx / b.mass;
+            accelerations[j].1 += force * ny / b.mass;
+        }
+    }
+
+    /// Elastic energy stored in the overlaps.
+    pub fn potential_energy(&self, bodies: &[Body]) -> f32 {
+        touching_pairs(bodies, 0.0)
+            .into_iter()
+            .map(|(i, j)| {
+                let overlap =
+                    bodies[i].radius + bodies[j].radius - bodies[i].distance_to(&bodies[j]);
+                0.5 * self.stiffness * overlap * overlap
+            })
+            .sum()
+    }
+}
+
+/// Pairs `(i, j)`, `i < j`, of massive bodies closer than `1 + margin` times the sum
+/// of their radii.
+fn touching_pairs(bodies: &[Body], margin: f32) -> Vec<(usize, usize)> {
+    let reach = 1.0 + margin;
+    let massive: Vec<usize> = (0..bodies.len())
+        .filter(|&i| !bodies[i].is_test_particle())
+        .collect();
+    let points: Vec<(f32, f32)> = massive.iter().map(|&i| bodies[i].position).collect();
+    let largest = massive.iter().fold(0.0f32, |r, &i| r.max(bodies[i].radius));
+    pairs_within(&points, 2.0 * largest * reach)
+        .into_iter()
+        .map(|(a, b)| (massive[a], massive[b]))
+        .filter(|&(i, j)| {
+            bodies[i].distance_to(&bodies[j]) < (bodies[i].radius + bodies[j].radius) * reach
+        })
+        .collect()
+}
+
+/// Replaces `parent` by a rubble pile of `count` equal discs, hexagonally packed to
+/// fill its radius and just touching. The sub-particles share the parent's mass and
+/// charge, with their centre of mass at its position and velocity, and turn together
+/// to carry its spin.
+pub fn rubble_pile(parent: &Body, count: usize) -> Result<Vec<Body>> {
+    if count == 0 {
+        bail!("a rubble pile needs at least one particle");
+    }
+    if parent.radius <= 0.0 {
+        bail!("a rubble pile needs a positive radius");
+    }
+    // Lattice spacing at which `count` hexagonal cells cover the parent's area
+    let spacing = parent.radius * (2.0 * PI / (3.0f32.sqrt() * count as f32)).sqrt();
+    let extent = (parent.radius / spacing).ceil() as i32 + 2;
+    let mut sites: Vec<(f32, f32)> = Vec::new();
+    for row in -extent..=extent {
+        for column in -extent..=extent {
+            let x = (column as f32 + 0.5 * row as f32) * spacing;
+            let y = row as f32 * 0.5 * 3.0f32.sqrt() * spacing;
+            sites.push((x, y));
+        }
+    }
+    // Closest to the centre first, ties broken by angle so the pile is reproducible
+    let key = |&(x, y): &(f32, f32)| (x.hypot(y), y.atan2(x));
+    sites.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+    sites.truncate(count);
+
+    let n = count as f32;
+    let centre = sites
+        .iter()
+        .fold((0.0, 0.0), |(cx, cy), (x, y)| (cx + x / n, cy + y / n));
+    let mass = parent.mass / n;
+    let inertia: f32 = sites
+        .iter()
+        .map(|(x, y)| mass * ((x - centre.0).powi(2) + (y - centre.1).powi(2)))
+        .sum();
+    let angular_velocity = if inertia > 0.0 {
+        parent.spin / inertia
+    } else {
+        0.0
+    };
+
+    sites
+        .iter()
+        .map(|(x, y)| {
+            let (rx, ry) = (x - centre.0, y - centre.1);
+            let mut particle = Body::new(
+                mass,
+                (parent.position.0 + rx, parent.position.1 + ry),
+                (
+                    parent.velocity.0 - angular_velocity * ry,
+                    parent.velocity.1 + angular_velocity * rx,
+                ),
+            )?;
+            particle.radius = 0.5 * spacing;
+            particle.charge = parent.charge / n;
+            Ok(particle)
+        })
+        .collect()
+}
+
+/// The sub-particles of one rubble pile, by index into the simulation's bodies.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Aggregate {
+    pub name: String,
+    pub members: Vec<usize>,
+}
+
+impl Aggregate {
+    /// Groups of members still held together: chains of particles within 10% of
+    /// touching. Largest mass first.
+    pub fn fragments(&self, bodies: &[Body]) -> Vec<Vec<usize>> {
+        let members: Vec<Body> = self.members.iter().map(|&i| bodies[i]).collect();
+        // Union-find over the members
+        let mut parent: Vec<usize> = (0..members.len()).collect();
+        fn root(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        for (i, j) in touching_pairs(&members, 0.1) {
+            let (ri, rj) = (root(&mut parent, i), root(&mut parent, j));
+            parent[ri] = rj;
+        }
+
+        let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+        for (i, &index) in self.members.iter().enumerate() {
+            groups.entry(root(&mut parent, i)).or_default().push(index);
+        }
+        let mass = |group: &[usize]| group.iter().map(|&i| bodies[i].mass).sum::<f32>();
+        let mut fragments: Vec<Vec<usize>> = groups.into_values().collect();
+        fragments.sort_by(|a, b| mass(b).total_cmp(&mass(a)).then(a[0].cmp(&b[0])));
+        fragments
+    }
+
+    /// Fraction of the aggregate's mass in its largest fragment: 1 while it is intact.
+    pub fn largest_fragment_fraction(&self, bodies: &[Body]) -> f32 {
+        let mass = |group: &[usize]| group.iter().map(|&i| bodies[i].mass).sum::<f32>();
+        let total = mass(&self.members);
+        match self.fragments(bodies).first() {
+            Some(largest) if total > 0.0 => mass(largest) / total,
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parent() -> Body {
+        let mut parent = Body::new(2.0, (1.0, -0.5), (0.3, 0.4)).unwrap();
+        parent.radius = 0.1;
+        parent
+    }
+
+    #[test]
+    fn test_rubble_pile_conserves_parent() {
+        let mut parent = parent();
+        parent.spin = 0.01;
+        let particles = rubble_pile(&parent, 37).unwrap();
+        assert_eq!(particles.len(), 37);
+
+        let mass: f32 = particles.iter().map(|p| p.mass).sum();
+        assert!((mass - parent.mass).abs() < 1e-5);
+        let (mut x, mut y, mut px, mut py, mut spin) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for p in &particles {
+            x += p.mass * p.position.0 / mass;
+            y += p.mass * p.position.1 / mass;
+            px += p.mass * p.velocity.0;
+            py += p.mass * p.velocity.1;
+        }
+        for p in &particles {
+            let (rx, ry) = (p.position.0 - x, p.position.1 - y);
+            let (vx, vy) = (p.velocity.0 - 0.3, p.velocity.1 - 0.4);
+            spin += p.mass * (rx * vy - ry * vx);
+        }
+        assert!((x - 1.0).abs() < 1e-5 && (y + 0.5).abs() < 1e-5);
+        assert!((px - 0.6).abs() < 1e-5 && (py - 0.8).abs() < 1e-5);
+        assert!((spin - 0.01).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_rubble_pile_is_packed_without_overlap() {
+        let particles = rubble_pile(&parent(), 50).unwrap();
+        let diameter = 2.0 * particles[0].radius;
+        for (i, a) in particles.iter().enumerate() {
+            let nearest = particles
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .map(|(_, b)| a.distance_to(b))
+                .fold(f32::INFINITY, f32::min);
+            assert!((nearest - diameter).abs() < 1e-4 * diameter);
+            // The pile fills the parent's disc
+            let offset = (a.position.0 - 1.0).hypot(a.position.1 + 0.5);
+            assert!(offset < 0.1 + diameter);
+        }
+        assert!(rubble_pile(&parent(), 0).is_err());
+    }
+
+    #[test]
+    fn test_contact_forces_balance_and_damp() {
+        let mut a = Body::new(1.0, (0.0, 0.0), (1.0, 0.0)).unwrap();
+        let mut b = Body::new(4.0, (0.375, 0.0), (-1.0, 0.0)).unwrap();
+        a.radius = 0.25;
+        b.radius = 0.25;
+        let elastic = SoftSphere {
+            stiffness: 100.0,
+            damping: 0.0,
+        };
+        let mut accelerations = vec![(0.0, 0.0); 2];
+        elastic.add_accelerations(&[a, b], &mut accelerations);
+        assert_eq!(accelerations, vec![(-12.5, 0.0), (3.125, 0.0)]);
+        assert_eq!(elastic.potential_energy(&[a, b]), 0.78125);
+
+        // Damping resists the approach
+        let damped = SoftSphere {
+            damping: 1.0,
+            ..elastic
+        };
+        let mut accelerations = vec![(0.0, 0.0); 2];
+        damped.add_accelerations(&[a, b], &mut accelerations);
+        assert_eq!(accelerations[0], (-14.5, 0.0));
+
+        // Apart, or massless, there is no contact
+        b.position.0 = 0.6;
+        assert!(touching_pairs(&[a, b], 0.0).is_empty());
+        b.position.0 = 0.375;
+        a.mass = 0.0;
+        assert!(touching_pairs(&[a, b], 0.0).is_empty());
+    }
+
+    #[test]
+    fn test_fragments() {
+        let mut particles = rubble_pile(&parent(), 20).unwrap();
+        let aggregate = Aggregate {
+            name: "moon".to_string(),
+            members: (0..20).collect(),
+        };
+        assert_eq!(aggregate.fragments(&particles).len(), 1);
+        assert_eq!(aggregate.largest_fragment_fraction(&particles), 1.0);
+
+        // Pull the pile apart along x
+        for p in particles.iter_mut().filter(|p| p.position.0 > 1.01) {
+            p.position.0 += 1.0;
+        }
+        let fragments = aggregate.fragments(&particles);
+        assert_eq!(fragments.len(), 2);
+        assert!(fragments[0].len() >= fragments[1].len());
+        assert!(aggregate.largest_fragment_fraction(&particles) < 1.0);
+    }
+}
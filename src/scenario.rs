@@ -0,0 +1,977 @@
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::path::{Path, PathBuf};
+
+use crate::body::Body;
+use crate::boundary::Boundary;
+use crate::cosmology::{Cosmology, Zeldovich};
+use crate::detector::{Detector, DetectorKind};
+use crate::field::ExternalField;
+use crate::force_law::ForceLaw;
+use crate::kepler::{OrbitalElements, body_on_orbit};
+use crate::lagrange;
+use crate::link::{Link, LinkKind};
+use crate::mass_flow::{MassSchedule, Thrust};
+use crate::post_newtonian::PostNewtonian;
+use crate::regularization::Regularization;
+use crate::restricted::RestrictedThreeBody;
+use crate::rubble::{Aggregate, SoftSphere, rubble_pile};
+use crate::simulation::Integrator;
+use crate::solver::GravitySolver;
+use crate::sph::{GasParticle, Sph};
+
+/// Initial conditions and simulation parameters, loaded from a TOML scenario file.
+///
+/// ```toml
+/// g_constant = 1.0
+///
+/// [[bodies]]
+/// name = "star"
+/// mass = 100.0
+/// position = [0.0, 0.0]
+/// velocity = [0.0, 0.0]
+///
+/// [[bodies]]
+/// name = "planet"
+/// mass = 1.0
+/// orbit = { primary = "star", semi_major_axis = 0.5, eccentricity = 0.2 }
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(default = "default_g_constant")]
+    pub g_constant: f32,
+    #[serde(default = "default_time_step")]
+    pub time_step: f32,
+    #[serde(default = "default_eps2")]
+    pub eps2: f32,
+    #[serde(default)]
+    pub integrator: Integrator,
+    #[serde(default)]
+    pub solver: GravitySolver,
+    /// Pair interaction between the bodies.
+    #[serde(default)]
+    pub force_law: ForceLaw,
+    /// Relativistic corrections to Newtonian gravity.
+    #[serde(default)]
+    pub post_newtonian: Option<PostNewtonian>,
+    /// CSV file the post-Newtonian observer's strain is written to after every step.
+    #[serde(default)]
+    pub strain_output: Option<PathBuf>,
+    /// Regularization of close encounters between pairs of massive bodies.
+    #[serde(default)]
+    pub regularization: Option<Regularization>,
+    #[serde(default)]
+    pub boundary: Boundary,
+    /// Expanding background for a comoving run in a periodic box.
+    #[serde(default)]
+    pub cosmology: Option<Cosmology>,
+    /// Circular restricted three-body problem: the bodies are massless particles in
+    /// the frame co-rotating with two primaries.
+    #[serde(default)]
+    pub restricted_three_body: Option<RestrictedThreeBody>,
+    /// External force fields acting on every body, in addition to their gravity.
+    #[serde(default)]
+    pub fields: Vec<ExternalField>,
+    /// Soft-sphere contact forces between touching bodies.
+    #[serde(default)]
+    pub contacts: Option<SoftSphere>,
+    /// Hydrodynamics of the gas bodies.
+    #[serde(default)]
+    pub sph: Option<Sph>,
+    /// Shift the bodies into the frame where the centre of mass is at rest at the
+    /// origin after loading.
+    #[serde(default)]
+    pub zero_momentum_frame: bool,
+    #[serde(default)]
+    pub bodies: Vec<BodySpec>,
+    /// Generated rings of bodies, added after `bodies`.
+    #[serde(default)]
+    pub rings: Vec<RingSpec>,
+    /// Perturbed lattice filling the periodic box of a cosmological run, added last.
+    #[serde(default)]
+    pub zeldovich: Option<Zeldovich>,
+    /// Springs, tethers and rods between named bodies.
+    #[serde(default)]
+    pub links: Vec<LinkSpec>,
+    /// Pairs of named bodies whose Lagrange points and Hill sphere are marked.
+    #[serde(default)]
+    pub lagrange_points: Vec<LagrangeSpec>,
+    /// Events to detect during every step.
+    #[serde(default)]
+    pub detectors: Vec<DetectorSpec>,
+}
+
+/// A body given either by its position and velocity or by an orbit around a body
+/// declared earlier in the file. A body without a mass is a massless test particle.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BodySpec {
+    pub name: Option<String>,
+    #[serde(default)]
+    pub mass: f32,
+    /// Signed charge for the Coulomb force law.
+    #[serde(default)]
+    pub charge: f32,
+    /// Equatorial radius, which sets the reach of `j2`.
+    pub radius: Option<f32>,
+    /// Spin angular momentum, positive anticlockwise.
+    #[serde(default)]
+    pub spin: f32,
+    /// Oblateness, as the J2 zonal harmonic coefficient.
+    #[serde(default)]
+    pub j2: f32,
+    pub position: Option<[f32; 2]>,
+    pub velocity: Option<[f32; 2]>,
+    pub orbit: Option<OrbitSpec>,
+    /// Time-dependent mass, such as a stellar wind or accretion.
+    pub mass_schedule: Option<MassSchedule>,
+    /// Engine burn, such as a spacecraft's.
+    pub thrust: Option<Thrust>,
+    /// Break the body up into a rubble pile of sub-particles filling its `radius`.
+    pub rubble_pile: Option<RubblePileSpec>,
+    /// Specific internal energy, which makes the body a gas particle.
+    pub internal_energy: Option<f32>,
+}
+
+/// A link between two named bodies. Its `length` defaults to their initial distance;
+/// a rod must start at its length.
+///
+/// ```toml
+/// [[links]]
+/// bodies = ["station", "counterweight"]
+/// kind = "tether"
+/// stiffness = 50.0
+/// damping = 0.1
+/// length = 0.3
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+pub struct LinkSpec {
+    pub bodies: [String; 2],
+    pub length: Option<f32>,
+    // Unknown keys reach the kind, which rejects them
+    #[serde(flatten)]
+    pub kind: LinkKind,
+}
+
+/// The Lagrange points of the second named body's orbit about the first, marked on
+/// screen, with test particles spawned at rest in the pair's turning frame at the
+/// points numbered in `spawn`.
+///
+/// ```toml
+/// [[lagrange_points]]
+/// bodies = ["sun", "jupiter"]
+/// spawn = [4, 5]
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LagrangeSpec {
+    pub bodies: [String; 2],
+    #[serde(default)]
+    pub spawn: Vec<usize>,
+}
+
+/// An event detector on named bodies: two for the kinds between a pair, the primary
+/// first for periapsis and apoapsis, or one for an escape. Without `bodies` it watches
+/// every pair or body.
+///
+/// ```toml
+/// [[detectors]]
+/// kind = "periapsis"
+/// bodies = ["sun", "comet"]
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+pub struct DetectorSpec {
+    #[serde(default)]
+    pub bodies: Vec<String>,
+    // Unknown keys reach the kind, which rejects them
+    #[serde(flatten)]
+    pub kind: DetectorKind,
+}
+
+/// Sub-particles making up a rubble-pile body.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RubblePileSpec {
+    pub count: usize,
+}
+
+/// Keplerian orbit relative to a named primary. The size is given by exactly one of
+/// `semi_major_axis` (negative for hyperbolic orbits) or `periapsis`, and the phase by
+/// at most one of `true_anomaly` or `mean_anomaly`. Angles are in radians.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OrbitSpec {
+    pub primary: String,
+    pub semi_major_axis: Option<f64>,
+    pub periapsis: Option<f64>,
+    #[serde(default)]
+    pub eccentricity: f64,
+    #[serde(default)]
+    pub argument_of_periapsis: f64,
+    pub true_anomaly: Option<f64>,
+    pub mean_anomaly: Option<f64>,
+    #[serde(default)]
+    pub retrograde: bool,
+}
+
+/// `count` bodies on circular orbits around a named primary, filling the annulus
+/// between `inner_radius` and `outer_radius` with even area density. Phases step by
+/// the golden angle so the ring has no clumps or spokes. Each body has mass `mass`,
+/// which defaults to zero for a ring of test particles.
+///
+/// ```toml
+/// [[rings]]
+/// primary = "planet"
+/// count = 2000
+/// inner_radius = 0.05
+/// outer_radius = 0.08
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RingSpec {
+    pub primary: String,
+    pub count: usize,
+    pub inner_radius: f64,
+    pub outer_radius: f64,
+    #[serde(default)]
+    pub mass: f32,
+    #[serde(default)]
+    pub retrograde: bool,
+    /// Specific internal energy, which makes the ring gas.
+    pub internal_energy: Option<f32>,
+}
+
+fn default_g_constant() -> f32 {
+    1.0
+}
+
+fn default_time_step() -> f32 {
+    0.0001
+}
+
+fn default_eps2() -> f32 {
+    0.004
+}
+
+impl Scenario {
+    pub fn from_toml(src: &str) -> Result<Self> {
+        Ok(toml::from_str(src)?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let src = std::fs::read_to_string(path)
+            .with_context(|| format!("reading scenario {}", path.display()))?;
+        Self::from_toml(&src).with_context(|| format!("parsing scenario {}", path.display()))
+    }
+
+    /// Resolves every body spec into a `Body`, in file order.
+    pub fn build_bodies(&self) -> Result<Vec<Body>> {
+        Ok(self.build_system()?.0)
+    }
+
+    /// The gas bodies, with their internal energies: those specs and rings given one.
+    pub fn gas_particles(&self) -> Result<Vec<GasParticle>> {
+        let mut gas = Vec::new();
+        for (index, spec) in self.bodies.iter().enumerate() {
+            if let Some(internal_energy) = spec.internal_energy {
+                let label = spec.name.clone().unwrap_or_else(|| format!("#{index}"));
+                if spec.mass <= 0.0 || spec.rubble_pile.is_some() || internal_energy < 0.0 {
+                    bail!(
+                        "body {label}: gas needs a mass and a non-negative internal energy, and \
+                         can't be a rubble pile"
+                    );
+                }
+                gas.push(GasParticle {
+                    index,
+                    internal_energy,
+                });
+            }
+        }
+        let mut start = self.bodies.len();
+        for (number, ring) in self.rings.iter().enumerate() {
+            if let Some(internal_energy) = ring.internal_energy {
+                if ring.mass <= 0.0 || internal_energy < 0.0 {
+                    bail!("ring #{number}: gas needs a mass and a non-negative internal energy");
+                }
+                gas.extend((start..start + ring.count).map(|index| GasParticle {
+                    index,
+                    internal_energy,
+                }));
+            }
+            start += ring.count;
+        }
+        Ok(gas)
+    }
+
+    /// Index of the body named `name`, which `what` refers to. It can't be a rubble
+    /// pile and, if `needs_mass`, must be massive.
+    fn body_index(&self, what: &str, name: &str, needs_mass: bool) -> Result<usize> {
+        let Some(index) = self
+            .bodies
+            .iter()
+            .position(|spec| spec.name.as_deref() == Some(name))
+        else {
+            bail!("{what}: unknown body {name:?}");
+        };
+        let spec = &self.bodies[index];
+        if spec.rubble_pile.is_some() {
+            bail!("{what}: body {name:?} is a rubble pile");
+        }
+        if needs_mass && spec.mass <= 0.0 {
+            bail!("{what}: body {name:?} needs a mass");
+        }
+        Ok(index)
+    }
+
+    /// The links between the named bodies, given the bodies built from this scenario.
+    pub fn links(&self, bodies: &[Body]) -> Result<Vec<Link>> {
+        let index = |name: &str| self.body_index("link", name, true);
+        self.links
+            .iter()
+            .map(|spec| {
+                let [first, second] = &spec.bodies;
+                let (i, j) = (index(first)?, index(second)?);
+                if i == j {
+                    bail!("link: body {first:?} can't be linked to itself");
+                }
+                let distance = bodies[i].distance_to(&bodies[j]);
+                let length = spec.length.unwrap_or(distance);
+                if length < 0.0 {
+                    bail!("link {first:?}-{second:?}: length can't be negative");
+                }
+                if spec.kind == (LinkKind::Rod {})
+                    && (length <= 0.0 || (distance - length).abs() > 1e-3 * length)
+                {
+                    bail!("link {first:?}-{second:?}: a rod must start at its positive length");
+                }
+                Ok(Link {
+                    bodies: (i, j),
+                    length,
+                    kind: spec.kind,
+                })
+            })
+            .collect()
+    }
+
+    /// The primary and secondary of each of the `lagrange_points` pairs, by index.
+    pub fn lagrange_pairs(&self) -> Result<Vec<(usize, usize)>> {
+        let index = |name: &str| self.body_index("lagrange points", name, true);
+        self.lagrange_points
+            .iter()
+            .map(|spec| {
+                let [primary, secondary] = &spec.bodies;
+                let (i, j) = (index(primary)?, index(secondary)?);
+                if i == j {
+                    bail!("lagrange points: body {primary:?} can't orbit itself");
+                }
+                Ok((i, j))
+            })
+            .collect()
+    }
+
+    /// The event detectors, with their named bodies resolved to indices.
+    pub fn detectors(&self) -> Result<Vec<Detector>> {
+        self.detectors
+            .iter()
+            .map(|spec| {
+                let bodies = spec
+                    .bodies
+                    .iter()
+                    .map(|name| self.body_index("detector", name, false))
+                    .collect::<Result<_>>()?;
+                Detector::new(spec.kind, bodies)
+            })
+            .collect()
+    }
+
+    /// Resolves the bodies, and breaks up the rubble piles. The first particle of a
+    /// pile takes its parent's place and the rest follow the rings, so every other
+    /// body keeps the index of its spec. Particles spawned at Lagrange points follow,
+    /// and a Zel'dovich lattice comes last.
+    pub fn build_system(&self) -> Result<(Vec<Body>, Vec<Aggregate>)> {
+        let mut bodies: Vec<Body> = Vec::with_capacity(self.bodies.len());
+        let mut names: HashMap<&str, usize> = HashMap::new();
+
+        for (index, spec) in self.bodies.iter().enumerate() {
+            let label = spec.name.clone().unwrap_or_else(|| format!("#{index}"));
+            if spec.mass.is_nan() || spec.mass < 0.0 {
+                bail!(
+                    "body {label}: the mass can't be negative, got {}",
+                    spec.mass
+                );
+            }
+            let mut body = match (&spec.orbit, spec.position, spec.velocity) {
+                (None, Some(position), velocity) => {
+                    let velocity = velocity.unwrap_or([0.0, 0.0]);
+                    Body::new(
+                        spec.mass,
+                        (position[0], position[1]),
+                        (velocity[0], velocity[1]),
+                    )?
+                }
+                (Some(orbit), None, None) => {
+                    let primary = names.get(orbit.primary.as_str()).with_context(|| {
+                        format!(
+                            "body {label}: primary {:?} must be declared before it",
+                            orbit.primary
+                        )
+                    })?;
+                    let elements = orbit
+                        .elements()
+                        .with_context(|| format!("body {label}: invalid orbit"))?;
+                    body_on_orbit(spec.mass, &bodies[*primary], &elements, self.g_constant)
+                        .with_context(|| format!("body {label}: invalid orbit"))?
+                }
+                (Some(_), _, _) => {
+                    bail!("body {label}: an orbit can't be combined with a position or velocity")
+                }
+                (None, None, _) => bail!("body {label}: needs either a position or an orbit"),
+            };
+
+            if let Some(name) = &spec.name
+                && names.insert(name, index).is_some()
+            {
+                bail!("body name {name:?} is used more than once");
+            }
+            body.charge = spec.charge;
+            body.spin = spec.spin;
+            body.j2 = spec.j2;
+            match spec.radius {
+                Some(radius) if radius > 0.0 => body.radius = radius,
+                Some(_) => bail!("body {label}: radius must be positive"),
+                None if spec.j2 != 0.0 => bail!("body {label}: j2 needs a radius"),
+                None => {}
+            }
+            bodies.push(body);
+        }
+
+        for (index, ring) in self.rings.iter().enumerate() {
+            let primary = *names
+                .get(ring.primary.as_str())
+                .with_context(|| format!("ring #{index}: unknown primary {:?}", ring.primary))?;
+            let primary = bodies[primary];
+            for elements in ring
+                .orbits()
+                .with_context(|| format!("ring #{index}: invalid ring"))?
+            {
+                bodies.push(body_on_orbit(
+                    ring.mass,
+                    &primary,
+                    &elements,
+                    self.g_constant,
+                )?);
+            }
+        }
+
+        let mut aggregates = Vec::new();
+        for (index, spec) in self.bodies.iter().enumerate() {
+            let Some(pile) = &spec.rubble_pile else {
+                continue;
+            };
+            let label = spec.name.clone().unwrap_or_else(|| format!("#{index}"));
+            if self.contacts.is_none() {
+                bail!("body {label}: a rubble pile needs contacts to hold it together");
+            }
+            if spec.radius.is_none() {
+                bail!("body {label}: a rubble pile needs a radius");
+            }
+            if spec.j2 != 0.0 || spec.mass_schedule.is_some() || spec.thrust.is_some() {
+                bail!("body {label}: a rubble pile can't be oblate, lose mass or thrust");
+            }
+            let mut particles = rubble_pile(&bodies[index], pile.count)
+                .with_context(|| format!("body {label}: invalid rubble pile"))?
+                .into_iter();
+            bodies[index] = particles.next().expect("rubble piles aren't empty");
+            let first = bodies.len();
+            bodies.extend(particles);
+            aggregates.push(Aggregate {
+                name: label,
+                members: std::iter::once(index).chain(first..bodies.len()).collect(),
+            });
+        }
+
+        for (spec, (i, j)) in self.lagrange_points.iter().zip(self.lagrange_pairs()?) {
+            let points = lagrange::lagrange_points(&bodies[i], &bodies[j])?;
+            for &number in &spec.spawn {
+                let Some(&position) = number.checked_sub(1).and_then(|k| points.get(k)) else {
+                    bail!("lagrange points: there is no L{number}");
+                };
+                let velocity = lagrange::corotating_velocity(&bodies[i], &bodies[j], position)?;
+                bodies.push(Body::test_particle(position, velocity)?);
+            }
+        }
+
+        if let Some(zeldovich) = &self.zeldovich {
+            let (Some(cosmology), Boundary::Periodic { size, .. }) =
+                (&self.cosmology, self.boundary)
+            else {
+                bail!("Zel'dovich initial conditions need a cosmology and a periodic boundary");
+            };
+            cosmology.validate()?;
+            bodies.extend(zeldovich.bodies(cosmology, size)?);
+        }
+        Ok((bodies, aggregates))
+    }
+}
+
+impl Default for Scenario {
+    fn default() -> Self {
+        Self::from_toml(include_str!("../scenarios/default.toml"))
+            .expect("bundled default scenario is valid")
+    }
+}
+
+impl OrbitSpec {
+    pub fn elements(&self) -> Result<OrbitalElements> {
+        let true_anomaly = match (self.true_anomaly, self.mean_anomaly) {
+            (Some(_), Some(_)) => bail!("give either a true or a mean anomaly, not both"),
+            (true_anomaly, _) => true_anomaly.unwrap_or(0.0),
+        };
+        let mut elements = match (self.semi_major_axis, self.periapsis) {
+            (Some(a), None) => OrbitalElements::from_semi_major_axis(
+                a,
+                self.eccentricity,
+                self.argument_of_periapsis,
+                true_anomaly,
+            )?,
+            (None, Some(q)) => OrbitalElements::from_periapsis(
+                q,
+                self.eccentricity,
+                self.argument_of_periapsis,
+                true_anomaly,
+            )?,
+            _ => bail!("give exactly one of semi_major_axis or periapsis"),
+        };
+        if let Some(mean_anomaly) = self.mean_anomaly {
+            elements = elements.with_mean_anomaly(mean_anomaly);
+        }
+        if self.retrograde {
+            elements = elements.with_orientation(PI, 0.0);
+        }
+        Ok(elements)
+    }
+}
+
+impl RingSpec {
+    /// Orbital elements of every body in the ring, innermost first.
+    pub fn orbits(&self) -> Result<Vec<OrbitalElements>> {
+        if !(0.0 < self.inner_radius && self.inner_radius <= self.outer_radius) {
+            bail!(
+                "radii must satisfy 0 < inner_radius <= outer_radius, got {} and {}",
+                self.inner_radius,
+                self.outer_radius
+            );
+        }
+        let golden_angle = PI * (3.0 - 5f64.sqrt());
+        let (inner2, outer2) = (self.inner_radius.powi(2), self.outer_radius.powi(2));
+        (0..self.count)
+            .map(|k| {
+                let fraction = (k as f64 + 0.5) / self.count as f64;
+                let radius = (inner2 + fraction * (outer2 - inner2)).sqrt();
+                let elements = OrbitalElements::from_semi_major_axis(
+                    radius,
+                    0.0,
+                    0.0,
+                    k as f64 * golden_angle,
+                )?;
+                Ok(if self.retrograde {
+                    elements.with_orientation(PI, 0.0)
+                } else {
+                    elements
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boundary::PeriodicForces;
+    use crate::kepler::elements_of;
+    use crate::mass_flow::ThrustDirection;
+    use crate::solver::MeshBoundary;
+
+    #[test]
+    fn test_default_scenario_builds() {
+        let scenario = Scenario::default();
+        let bodies = scenario.build_bodies().unwrap();
+        assert_eq!(bodies.len(), scenario.bodies.len());
+        assert!(!bodies.is_empty());
+    }
+
+    #[test]
+    fn test_orbit_around_named_primary() {
+        let scenario = Scenario::from_toml(
+            r#"
+            g_constant = 2.0
+
+            [[bodies]]
+            name = "star"
+            mass = 50.0
+            position = [0.1, 0.0]
+            velocity = [0.0, 0.3]
+
+            [[bodies]]
+            name = "planet"
+            mass = 1.0
+            orbit = { primary = "star", semi_major_axis = 0.4, eccentricity = 0.25, argument_of_periapsis = 1.0, mean_anomaly = 2.0 }
+            "#,
+        )
+        .unwrap();
+        let bodies = scenario.build_bodies().unwrap();
+        let elements = elements_of(&bodies[1], &bodies[0], scenario.g_constant).unwrap();
+
+        assert!((elements.semi_major_axis() - 0.4).abs() < 1e-4);
+        assert!((elements.eccentricity - 0.25).abs() < 1e-4);
+        assert!((elements.argument_of_periapsis - 1.0).abs() < 1e-4);
+        assert!((elements.mean_anomaly() - 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_retrograde_orbit() {
+        let scenario = Scenario::from_toml(
+            r#"
+            [[bodies]]
+            name = "star"
+            mass = 10.0
+            position = [0.0, 0.0]
+
+            [[bodies]]
+            mass = 0.1
+            orbit = { primary = "star", periapsis = 0.5, retrograde = true }
+            "#,
+        )
+        .unwrap();
+        let bodies = scenario.build_bodies().unwrap();
+        assert!(bodies[1].get_angular_momentum() < 0.0);
+    }
+
+    #[test]
+    fn test_ring_of_test_particles() {
+        let scenario = Scenario::from_toml(
+            r#"
+            [[bodies]]
+            name = "planet"
+            mass = 5.0
+            position = [0.2, -0.1]
+            velocity = [0.0, 1.0]
+
+            [[bodies]]
+            position = [0.5, 0.5]
+
+            [[rings]]
+            primary = "planet"
+            count = 100
+            inner_radius = 0.1
+            outer_radius = 0.2
+            "#,
+        )
+        .unwrap();
+        let bodies = scenario.build_bodies().unwrap();
+
+        assert_eq!(bodies.len(), 102);
+        assert!(bodies[1].is_test_particle());
+        for body in &bodies[2..] {
+            assert!(body.is_test_particle());
+            let elements = elements_of(body, &bodies[0], scenario.g_constant).unwrap();
+            let radius = elements.semi_major_axis();
+            assert!((0.1 - 1e-4..=0.2 + 1e-4).contains(&radius));
+            assert!(elements.eccentricity < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_solver_settings() {
+        let scenario = Scenario::from_toml(
+            r#"
+            solver = { kind = "parallel", threads = 8 }
+            bodies = []
+            "#,
+        )
+        .unwrap();
+        assert_eq!(scenario.solver, GravitySolver::Parallel { threads: 8 });
+        assert_eq!(
+            Scenario::from_toml("bodies = []").unwrap().solver,
+            GravitySolver::DirectSum
+        );
+        let scenario = Scenario::from_toml(
+            r#"
+            solver = { kind = "particle_mesh", boundary = "periodic" }
+            bodies = []
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            scenario.solver,
+            GravitySolver::ParticleMesh {
+                grid: 128,
+                boundary: MeshBoundary::Periodic,
+                box_size: 2.0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_post_newtonian_settings() {
+        assert_eq!(
+            Scenario::from_toml("bodies = []").unwrap().post_newtonian,
+            None
+        );
+        let scenario = Scenario::from_toml(
+            r#"
+            post_newtonian = { speed_of_light = 50.0 }
+            bodies = []
+            "#,
+        )
+        .unwrap();
+        assert_eq!(scenario.post_newtonian, Some(PostNewtonian::new(50.0)));
+    }
+
+    #[test]
+    fn test_boundary_settings() {
+        assert_eq!(
+            Scenario::from_toml("bodies = []").unwrap().boundary,
+            Boundary::Open
+        );
+        let scenario = Scenario::from_toml(
+            r#"
+            boundary = { kind = "periodic", size = 4.0, forces = "minimum_image" }
+            bodies = []
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            scenario.boundary,
+            Boundary::Periodic {
+                size: 4.0,
+                forces: PeriodicForces::MinimumImage,
+            }
+        );
+        let scenario = Scenario::from_toml(
+            r#"
+            boundary = { kind = "reflecting" }
+            bodies = []
+            "#,
+        )
+        .unwrap();
+        assert_eq!(scenario.boundary, Boundary::Reflecting { size: 2.0 });
+        let scenario = Scenario::from_toml(
+            r#"
+            boundary = { kind = "escape", r_cut = 20.0 }
+            bodies = []
+            "#,
+        )
+        .unwrap();
+        assert_eq!(scenario.boundary, Boundary::Escape { r_cut: 20.0 });
+    }
+
+    #[test]
+    fn test_mass_flow_settings() {
+        let scenario = Scenario::from_toml(
+            r#"
+            [[bodies]]
+            mass = 1.0
+            position = [0.0, 0.0]
+            mass_schedule = { kind = "table", points = [[0.0, 1.0], [10.0, 0.5]] }
+
+            [[bodies]]
+            mass = 0.01
+            position = [1.0, 0.0]
+            velocity = [0.0, 1.0]
+            thrust = { acceleration = 0.1, direction = { kind = "fixed", angle = 1.5 }, start = 2.0 }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            scenario.bodies[0].mass_schedule,
+            Some(MassSchedule::Table {
+                points: vec![[0.0, 1.0], [10.0, 0.5]],
+            })
+        );
+        assert_eq!(
+            scenario.bodies[1].thrust,
+            Some(Thrust {
+                acceleration: 0.1,
+                direction: ThrustDirection::Fixed { angle: 1.5 },
+                start: 2.0,
+                duration: None,
+                exhaust_velocity: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_oblate_spinning_body() {
+        let scenario = Scenario::from_toml(
+            r#"
+            [[bodies]]
+            name = "planet"
+            mass = 1.0
+            position = [0.0, 0.0]
+            radius = 0.1
+            spin = -0.5
+            j2 = 0.01
+            "#,
+        )
+        .unwrap();
+        let planet = scenario.build_bodies().unwrap()[0];
+        assert_eq!((planet.radius, planet.spin, planet.j2), (0.1, -0.5, 0.01));
+
+        let without_radius = r#"
+            [[bodies]]
+            mass = 1.0
+            position = [0.0, 0.0]
+            j2 = 0.01
+            "#;
+        let error = Scenario::from_toml(without_radius)
+            .unwrap()
+            .build_bodies()
+            .unwrap_err();
+        assert!(error.to_string().contains("j2 needs a radius"));
+    }
+
+    #[test]
+    fn test_links_between_named_bodies() {
+        let source = r#"
+            [[bodies]]
+            name = "station"
+            mass = 1.0
+            position = [0.0, 0.0]
+
+            [[bodies]]
+            name = "weight"
+            mass = 0.5
+            position = [0.3, 0.4]
+
+            [[links]]
+            bodies = ["station", "weight"]
+            kind = "spring"
+            stiffness = 20.0
+
+            [[links]]
+            bodies = ["weight", "station"]
+            kind = "rod"
+            "#;
+        let scenario = Scenario::from_toml(source).unwrap();
+        let links = scenario.links(&scenario.build_bodies().unwrap()).unwrap();
+        assert_eq!(
+            links,
+            vec![
+                Link {
+                    bodies: (0, 1),
+                    length: 0.5,
+                    kind: LinkKind::Spring {
+                        stiffness: 20.0,
+                        damping: 0.0,
+                    },
+                },
+                Link {
+                    bodies: (1, 0),
+                    length: 0.5,
+                    kind: LinkKind::Rod {},
+                },
+            ]
+        );
+
+        for (from, to) in [
+            ("kind = \"rod\"", "kind = \"rod\"\nlength = 0.4"),
+            ("\"weight\", \"station\"", "\"weight\", \"nowhere\""),
+            ("kind = \"rod\"", "kind = \"rod\"\nstiffness = 1.0"),
+        ] {
+            let broken = Scenario::from_toml(&source.replace(from, to))
+                .and_then(|scenario| scenario.links(&scenario.build_bodies()?));
+            assert!(broken.is_err(), "{to}");
+        }
+    }
+
+    #[test]
+    fn test_rubble_pile_replaces_its_body() {
+        let source = r#"
+            contacts = { stiffness = 100.0, damping = 0.1 }
+
+            [[bodies]]
+            name = "planet"
+            mass = 1.0
+            position = [0.0, 0.0]
+
+            [[bodies]]
+            name = "moon"
+            mass = 0.01
+            radius = 0.05
+            orbit = { primary = "planet", semi_major_axis = 1.0 }
+            rubble_pile = { count = 10 }
+
+            [[bodies]]
+            mass = 0.001
+            position = [2.0, 0.0]
+            "#;
+        let scenario = Scenario::from_toml(source).unwrap();
+        let (bodies, aggregates) = scenario.build_system().unwrap();
+        assert_eq!(bodies.len(), 12);
+        assert_eq!(bodies[2].position, (2.0, 0.0));
+        assert_eq!(
+            aggregates,
+            vec![Aggregate {
+                name: "moon".to_string(),
+                members: vec![1, 3, 4, 5, 6, 7, 8, 9, 10, 11],
+            }]
+        );
+        let mass: f32 = aggregates[0].members.iter().map(|&i| bodies[i].mass).sum();
+        assert!((mass - 0.01).abs() < 1e-7);
+
+        let loose = source.replace("contacts = { stiffness = 100.0, damping = 0.1 }", "");
+        let error = Scenario::from_toml(&loose)
+            .unwrap()
+            .build_system()
+            .unwrap_err();
+        assert!(error.to_string().contains("needs contacts"));
+    }
+
+    #[test]
+    fn test_invalid_bodies_are_rejected() {
+        let undeclared_primary = r#"
+            [[bodies]]
+            mass = 1.0
+            orbit = { primary = "star", semi_major_axis = 1.0 }
+        "#;
+        let position_and_orbit = r#"
+            [[bodies]]
+            name = "star"
+            mass = 1.0
+            position = [0.0, 0.0]
+
+            [[bodies]]
+            mass = 1.0
+            position = [1.0, 0.0]
+            orbit = { primary = "star", semi_major_axis = 1.0 }
+        "#;
+        let no_position = r#"
+            [[bodies]]
+            mass = 1.0
+        "#;
+        for src in [undeclared_primary, position_and_orbit, no_position] {
+            let scenario = Scenario::from_toml(src).unwrap();
+            assert!(scenario.build_bodies().is_err());
+        }
+        let negative_mass = r#"
+            [[bodies]]
+            name = "ghost"
+            mass = -1.0
+            position = [0.0, 0.0]
+        "#;
+        let error = Scenario::from_toml(negative_mass)
+            .unwrap()
+            .build_bodies()
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "body ghost: the mass can't be negative, got -1"
+        );
+        assert!(Scenario::from_toml("bodies = []\nunknown = 1").is_err());
+    }
+}
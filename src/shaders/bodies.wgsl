@@ -1,19 +1,25 @@
 struct VsIn {
     @location(0) local_pos: vec2f,     // quad vertex [-1,1]
     @location(1) instance_pos: vec2f,  // body center in clip space
+    @location(2) style: vec2f,         // x: 0 body, 1 massless tracer, 2 gas; y: radius or 0
 };
 struct VsOut {
     @builtin(position) pos: vec4f,
     @location(0) uv: vec2f,            // pass local_pos to fragment
+    @location(1) kind: f32,
 };
 
 @vertex
 fn vs(in: VsIn) -> VsOut {
     var out: VsOut;
-    let radius = 0.02; // tweak size
+    // Tracers and gas are drawn as small dots so dense rings and clouds don't hide the
+    // massive bodies, and bodies with a physical size at that size
+    let marker = select(0.02, 0.006, in.style.x > 0.5);
+    let radius = select(marker, in.style.y, in.style.y > 0.0);
     let pos = in.instance_pos + in.local_pos * radius;
     out.pos = vec4f(pos, 0.0, 1.0);
     out.uv = in.local_pos;
+    out.kind = in.style.x;
     return out;
 }
 
@@ -22,5 +28,9 @@ fn fs(in: VsOut) -> @location(0) vec4f {
     // circle mask in the quad
     let r = length(in.uv);
     if (r > 1.0) { discard; }
-    return vec4f(0.9, 0.9, 0.9, 1.0);
+    let body_colour = vec4f(0.9, 0.9, 0.9, 1.0);
+    let tracer_colour = vec4f(0.45, 0.65, 1.0, 0.8);
+    let gas_colour = vec4f(1.0, 0.55, 0.3, 0.8);
+    if (in.kind > 1.5) { return gas_colour; }
+    return mix(body_colour, tracer_colour, in.kind);
 }
\ No newline at end of file
@@ -0,0 +1,15 @@
+// Links between bodies and static overlays, drawn as coloured lines
+struct VsOut {
+    @builtin(position) position: vec4f,
+    @location(0) colour: vec4f,
+};
+
+@vertex
+fn vs(@location(0) pos: vec2f, @location(1) colour: vec4f) -> VsOut {
+    return VsOut(vec4f(pos, 0.0, 1.0), colour);
+}
+
+@fragment
+fn fs(in: VsOut) -> @location(0) vec4f {
+    return in.colour;
+}
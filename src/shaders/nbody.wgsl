@@ -0,0 +1,75 @@
+struct Params {
+    num_bodies: u32,
+    g_constant: f32,
+    eps2: f32,
+    dt: f32,
+};
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read_write> positions: array<vec2f>;
+@group(0) @binding(2) var<storage, read_write> velocities: array<vec2f>;
+@group(0) @binding(3) var<storage, read> masses: array<f32>;
+@group(0) @binding(4) var<storage, read_write> accelerations: array<vec2f>;
+
+const TILE: u32 = 64u;
+
+// One tile of source bodies (x, y, mass) shared by the whole workgroup
+var<workgroup> tile: array<vec3f, TILE>;
+
+// v(t + dt/2) = v(t) + a(t) dt/2, then x(t + dt) = x(t) + v(t + dt/2) dt
+@compute @workgroup_size(TILE)
+fn kick_drift(@builtin(global_invocation_id) id: vec3u) {
+    let i = id.x;
+    if (i >= params.num_bodies) { return; }
+    let v = velocities[i] + 0.5 * params.dt * accelerations[i];
+    velocities[i] = v;
+    positions[i] = positions[i] + params.dt * v;
+}
+
+// a(t + dt) by direct summation, staging the source bodies through workgroup memory
+// one tile at a time
+@compute @workgroup_size(TILE)
+fn accelerate(
+    @builtin(global_invocation_id) id: vec3u,
+    @builtin(local_invocation_index) local: u32,
+) {
+    let i = id.x;
+    // Out-of-range invocations still help load tiles and reach every barrier
+    let in_range = i < params.num_bodies;
+    var p = vec2f(0.0, 0.0);
+    if (in_range) { p = positions[i]; }
+
+    var a = vec2f(0.0, 0.0);
+    let num_tiles = (params.num_bodies + TILE - 1u) / TILE;
+    for (var t = 0u; t < num_tiles; t++) {
+        let j = t * TILE + local;
+        if (j < params.num_bodies) {
+            tile[local] = vec3f(positions[j], masses[j]);
+        } else {
+            tile[local] = vec3f(0.0, 0.0, 0.0);
+        }
+        workgroupBarrier();
+
+        for (var k = 0u; k < TILE; k++) {
+            let source = tile[k];
+            let d = source.xy - p;
+            let r2 = dot(d, d) + params.eps2;
+            // Skips the body itself and the zero-mass padding past the end
+            if (t * TILE + k != i && source.z != 0.0) {
+                let inv_r = inverseSqrt(r2);
+                a += source.z * inv_r * inv_r * inv_r * d;
+            }
+        }
+        workgroupBarrier();
+    }
+
+    if (in_range) { accelerations[i] = params.g_constant * a; }
+}
+
+// v(t + dt) = v(t + dt/2) + a(t + dt) dt/2
+@compute @workgroup_size(TILE)
+fn kick(@builtin(global_invocation_id) id: vec3u) {
+    let i = id.x;
+    if (i >= params.num_bodies) { return; }
+    velocities[i] = velocities[i] + 0.5 * params.dt * accelerations[i];
+}
@@ -0,0 +1,2531 @@
+use anyhow::{Result, bail};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use crate::body::{Body, BodyStore};
+use crate::boundary::{Boundary, BoundaryEvent};
+use crate::cosmology::Cosmology;
+use crate::detector::{Detection, Detector, DetectorKind, StepRecord};
+use crate::field::ForceField;
+use crate::force_law::ForceLaw;
+use crate::link::{self, Link};
+use crate::mass_flow::{MassSchedule, Thrust};
+use crate::oblateness;
+use crate::post_newtonian::{PostNewtonian, STRAIN_CSV_HEADER, StrainSample};
+use crate::regularization::{self, Regularization};
+use crate::restricted::RestrictedThreeBody;
+use crate::rubble::{Aggregate, SoftSphere};
+use crate::scenario::Scenario;
+use crate::solver::{self, GravitySolver, simd};
+use crate::sph::{GasParticle, Sph};
+
+/// Strain samples kept in memory; older ones live only in `strain_output`.
+pub const MAX_STRAIN_SAMPLES: usize = 1 << 16;
+
+/// Time integration scheme used by [`Simulation::step`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Integrator {
+    /// Second-order, symplectic kick-drift-kick scheme.
+    #[default]
+    VelocityVerlet,
+    /// Fourth-order symplectic composition of three velocity Verlet substeps.
+    Yoshida4,
+}
+
+impl Integrator {
+    /// Fractions of the time step taken by each velocity Verlet substep.
+    pub(crate) fn substeps(self) -> &'static [f32] {
+        // w1 = 1 / (2 - 2^(1/3)), w0 = 1 - 2 w1
+        const YOSHIDA_W1: f32 = 1.351_207_2;
+        const YOSHIDA_W0: f32 = -1.702_414_4;
+        match self {
+            Integrator::VelocityVerlet => &[1.0],
+            Integrator::Yoshida4 => &[YOSHIDA_W1, YOSHIDA_W0, YOSHIDA_W1],
+        }
+    }
+}
+
+/// The gravitational N-body system, independent of any rendering resources.
+pub struct Simulation {
+    pub bodies: Vec<Body>,
+    pub g_constant: f32,
+    pub time_step: f32,
+    pub eps2: f32,
+    pub integrator: Integrator,
+    pub solver: GravitySolver,
+    /// Pair interaction between the bodies; only Newtonian gravity uses the solver.
+    pub force_law: ForceLaw,
+    /// Relativistic corrections added to Newtonian gravity in open space.
+    pub post_newtonian: Option<PostNewtonian>,
+    /// Regularization of close encounters between pairs of massive bodies.
+    pub regularization: Option<Regularization>,
+    /// Pairs whose relative orbits were drifted in regularized coordinates in the last
+    /// step, by index into `bodies`.
+    pub regularized_pairs: Vec<(usize, usize)>,
+    pub boundary: Boundary,
+    /// Expanding background: positions are comoving, velocities peculiar, and `step`
+    /// integrates in comoving coordinates. Energy isn't conserved.
+    pub cosmology: Option<Cosmology>,
+    /// Scale factor of the comoving coordinates, 1 without a cosmology.
+    pub scale_factor: f64,
+    /// Primaries of a restricted three-body run. Their gravity and the rotating
+    /// frame's inertial forces are the last of `fields`.
+    pub restricted_three_body: Option<RestrictedThreeBody>,
+    /// External fields stacked onto the mutual gravity of the bodies.
+    pub fields: Vec<Box<dyn ForceField>>,
+    /// Bodies whose mass follows a schedule, by index into `bodies`.
+    pub mass_schedules: Vec<(usize, MassSchedule)>,
+    /// Bodies with engines, by index into `bodies`.
+    pub thrusters: Vec<(usize, Thrust)>,
+    /// Soft-sphere contact forces between touching bodies.
+    pub contacts: Option<SoftSphere>,
+    /// Springs, tethers and rods between pairs of bodies.
+    pub links: Vec<Link>,
+    /// Primaries and secondaries, by index into `bodies`, whose Lagrange points and
+    /// Hill spheres are marked.
+    pub lagrange_pairs: Vec<(usize, usize)>,
+    /// Rubble piles, tracked so their break-up can be followed.
+    pub aggregates: Vec<Aggregate>,
+    /// Hydrodynamics of the gas particles.
+    pub sph: Option<Sph>,
+    /// Bodies that are gas rather than collisionless, with their internal energies.
+    pub gas: Vec<GasParticle>,
+    /// Mass carried off the bodies by winds and exhaust, negative when accreted.
+    pub expelled_mass: f32,
+    /// Momentum carried off by winds and exhaust. The bodies' momentum plus this is
+    /// conserved.
+    pub expelled_momentum: (f32, f32),
+    /// Simulated time elapsed since the initial conditions.
+    pub time: f64,
+    /// Bodies removed by the boundary so far, oldest first.
+    pub events: Vec<BoundaryEvent>,
+    /// Detectors checked during every step.
+    pub detectors: Vec<Detector>,
+    /// Events the detectors have found, oldest first. Take them to clear the queue;
+    /// the app logs and drains it after every step.
+    pub detections: Vec<Detection>,
+    /// Conserved energy when the energy-error detectors were first checked, which the
+    /// errors are relative to.
+    pub reference_energy: Option<f32>,
+    /// Gravitational-wave strain after every step, when the post-Newtonian settings
+    /// have an observer. Only the latest [`MAX_STRAIN_SAMPLES`] are kept.
+    pub strain: VecDeque<StrainSample>,
+    /// CSV file every strain sample is appended to as the run goes. It is created on
+    /// the first sample, and [`Simulation::flush_strain`] makes sure it is complete.
+    pub strain_output: Option<PathBuf>,
+    /// The open `strain_output`.
+    strain_writer: Option<BufWriter<File>>,
+    /// The bodies in structure-of-arrays form while the SIMD solver steps them,
+    /// kept between steps so its arrays are only reallocated when the bodies grow.
+    store: BodyStore,
+}
+
+impl Simulation {
+    pub fn new(bodies: Vec<Body>, g_constant: f32, time_step: f32, eps2: f32) -> Self {
+        Self {
+            bodies,
+            g_constant,
+            time_step,
+            eps2,
+            integrator: Integrator::default(),
+            solver: GravitySolver::default(),
+            force_law: ForceLaw::default(),
+            post_newtonian: None,
+            regularization: None,
+            regularized_pairs: Vec::new(),
+            boundary: Boundary::default(),
+            cosmology: None,
+            scale_factor: 1.0,
+            restricted_three_body: None,
+            fields: Vec::new(),
+            mass_schedules: Vec::new(),
+            thrusters: Vec::new(),
+            contacts: None,
+            links: Vec::new(),
+            lagrange_pairs: Vec::new(),
+            aggregates: Vec::new(),
+            sph: None,
+            gas: Vec::new(),
+            expelled_mass: 0.0,
+            expelled_momentum: (0.0, 0.0),
+            time: 0.0,
+            events: Vec::new(),
+            detectors: Vec::new(),
+            detections: Vec::new(),
+            reference_energy: None,
+            strain: VecDeque::new(),
+            strain_output: None,
+            strain_writer: None,
+            store: BodyStore::default(),
+        }
+    }
+
+    pub fn from_scenario(scenario: &Scenario) -> Result<Self> {
+        let (bodies, aggregates) = scenario.build_system()?;
+        let mut simulation = Self::new(
+            bodies,
+            scenario.g_constant,
+            scenario.time_step,
+            scenario.eps2,
+        );
+        simulation.integrator = scenario.integrator;
+        simulation.solver = scenario.solver.clone();
+        simulation.solver.validate()?;
+        simulation.force_law = scenario.force_law;
+        simulation.force_law.validate()?;
+        simulation.post_newtonian = scenario.post_newtonian;
+        simulation.strain_output = scenario.strain_output.clone();
+        simulation.boundary = scenario.boundary;
+        simulation.boundary.validate()?;
+        simulation.contacts = scenario.contacts;
+        simulation.sph = scenario.sph;
+        if let Some(sph) = &simulation.sph {
+            sph.validate()?;
+        }
+        simulation.aggregates = aggregates;
+        let newtonian = simulation.force_law == ForceLaw::Newtonian;
+        if matches!(simulation.boundary, Boundary::Periodic { .. })
+            && !(newtonian && simulation.post_newtonian.is_none())
+        {
+            bail!("periodic boundaries only support Newtonian gravity");
+        }
+        if let Boundary::Periodic { size, .. } = simulation.boundary {
+            match simulation.solver.periodic_box() {
+                Some(box_size) if box_size != size => bail!(
+                    "the periodic mesh box_size {box_size} doesn't match the boundary size {size}"
+                ),
+                None if simulation.solver != GravitySolver::DirectSum => bail!(
+                    "periodic boundaries sum their own forces, or use a periodic particle \
+                     mesh, not {:?}",
+                    simulation.solver
+                ),
+                _ => {}
+            }
+        }
+        if !newtonian && simulation.solver != GravitySolver::DirectSum {
+            bail!(
+                "{:?} is summed over every pair directly and can't use the {:?} solver",
+                simulation.force_law,
+                simulation.solver
+            );
+        }
+        if let Some(post_newtonian) = simulation.post_newtonian {
+            post_newtonian.validate()?;
+        }
+        if simulation.post_newtonian.is_some() && !newtonian {
+            bail!("post-Newtonian corrections only apply to Newtonian gravity");
+        }
+        if simulation.strain_output.is_some()
+            && simulation
+                .post_newtonian
+                .is_none_or(|post_newtonian| post_newtonian.observer.is_none())
+        {
+            bail!("strain_output needs a post-Newtonian observer");
+        }
+        if oblateness::any_oblate(&simulation.bodies)
+            && (!newtonian || matches!(simulation.boundary, Boundary::Periodic { .. }))
+        {
+            bail!("oblate bodies need Newtonian gravity without periodic boundaries");
+        }
+        if simulation.contacts.is_some() && matches!(simulation.boundary, Boundary::Periodic { .. })
+        {
+            bail!("contacts aren't supported with periodic boundaries");
+        }
+        if let Some(cosmology) = scenario.cosmology {
+            cosmology.validate()?;
+            if !matches!(simulation.boundary, Boundary::Periodic { .. }) {
+                bail!("a cosmology needs a periodic boundary");
+            }
+            if simulation.integrator != Integrator::VelocityVerlet {
+                bail!(
+                    "a cosmology uses its own leapfrog, not {:?}",
+                    simulation.integrator
+                );
+            }
+            if !scenario.fields.is_empty()
+                || scenario
+                    .bodies
+                    .iter()
+                    .any(|spec| spec.mass_schedule.is_some() || spec.thrust.is_some())
+            {
+                bail!("a cosmology can't be combined with fields, mass schedules or thrust");
+            }
+            simulation.cosmology = Some(cosmology);
+            simulation.scale_factor = cosmology.initial_scale_factor;
+        }
+        simulation.links = scenario.links(&simulation.bodies)?;
+        if !simulation.links.is_empty() {
+            if matches!(simulation.boundary, Boundary::Periodic { .. }) {
+                bail!("links aren't supported with periodic boundaries");
+            }
+            link::constrain_velocities(&simulation.links, &mut simulation.bodies);
+        }
+        simulation.lagrange_pairs = scenario.lagrange_pairs()?;
+        if let Some(regularization) = scenario.regularization {
+            regularization.validate()?;
+            if !newtonian
+                || simulation.post_newtonian.is_some()
+                || matches!(simulation.boundary, Boundary::Periodic { .. })
+            {
+                bail!("regularization needs Newtonian gravity without periodic boundaries");
+            }
+            if simulation.eps2 != 0.0 {
+                bail!("regularized pairs attract unsoftened, so regularization needs eps2 = 0");
+            }
+            if !simulation.links.is_empty()
+                || simulation.contacts.is_some()
+                || simulation.sph.is_some()
+                || scenario.restricted_three_body.is_some()
+            {
+                bail!(
+                    "regularization can't be combined with links, contacts, gas or a \
+                     restricted three-body problem"
+                );
+            }
+            simulation.regularization = Some(regularization);
+        }
+        simulation.gas = scenario.gas_particles()?;
+        if !simulation.gas.is_empty() {
+            if simulation.sph.is_none() {
+                bail!("gas particles need sph settings");
+            }
+            if matches!(simulation.boundary, Boundary::Periodic { .. }) {
+                bail!("gas isn't supported with periodic boundaries");
+            }
+        }
+        simulation.fields = scenario
+            .fields
+            .iter()
+            .map(|&field| Box::new(field) as Box<dyn ForceField>)
+            .collect();
+        if let Some(restricted) = &scenario.restricted_three_body {
+            restricted.validate()?;
+            if simulation.bodies.iter().any(|b| b.mass != 0.0) {
+                bail!("the bodies of a restricted three-body run must be massless");
+            }
+            if matches!(simulation.boundary, Boundary::Periodic { .. }) {
+                bail!("a restricted three-body run can't have a periodic boundary");
+            }
+            simulation.fields.push(Box::new(restricted.clone()));
+            simulation.restricted_three_body = Some(restricted.clone());
+        }
+        for (index, spec) in scenario.bodies.iter().enumerate() {
+            if let Some(schedule) = &spec.mass_schedule {
+                simulation.mass_schedules.push((index, schedule.clone()));
+            }
+            if let Some(thrust) = spec.thrust {
+                simulation.thrusters.push((index, thrust));
+            }
+        }
+        simulation.detectors = scenario.detectors()?;
+        if !simulation.detectors.is_empty()
+            && matches!(simulation.boundary, Boundary::Periodic { .. })
+        {
+            bail!("event detectors can't follow bodies across periodic boundaries");
+        }
+        // A field's potential has no zero at infinity to be unbound against
+        if !simulation.fields.is_empty()
+            && simulation
+                .detectors
+                .iter()
+                .any(|d| d.kind == DetectorKind::Escape {})
+        {
+            bail!("escape detectors can't be combined with external fields");
+        }
+        if scenario.zero_momentum_frame {
+            simulation.to_barycentric_frame();
+        }
+        Ok(simulation)
+    }
+
+    /// Acceleration of every body due to the external fields and the (softened)
+    /// pairwise gravity of the others, at the current time.
+    pub fn accelerations(&self) -> Vec<(f32, f32)> {
+        self.accelerations_at(self.time)
+    }
+
+    fn accelerations_at(&self, time: f64) -> Vec<(f32, f32)> {
+        self.rates_at(time).0
+    }
+
+    /// Accelerations at `time`, and the heating rates of the gas particles in the
+    /// order of `gas`.
+    fn rates_at(&self, time: f64) -> (Vec<(f32, f32)>, Vec<f32>) {
+        self.rates_with_gravity(self.gravity_accelerations(), time)
+    }
+
+    /// `rates_at` given the accelerations due to the pair interactions.
+    fn rates_with_gravity(
+        &self,
+        mut accelerations: Vec<(f32, f32)>,
+        time: f64,
+    ) -> (Vec<(f32, f32)>, Vec<f32>) {
+        let mut heating = Vec::new();
+        if let Some(sph) = &self.sph
+            && !self.gas.is_empty()
+        {
+            let hydrodynamics;
+            (hydrodynamics, heating) = sph.rates(&self.bodies, &self.gas);
+            for (particle, a) in self.gas.iter().zip(hydrodynamics) {
+                accelerations[particle.index].0 += a.0;
+                accelerations[particle.index].1 += a.1;
+            }
+        }
+        if oblateness::any_oblate(&self.bodies) {
+            oblateness::add_accelerations(
+                &self.bodies,
+                self.g_constant,
+                self.eps2,
+                &mut accelerations,
+            );
+        }
+        if let Some(contacts) = &self.contacts {
+            contacts.add_accelerations(&self.bodies, &mut accelerations);
+        }
+        link::add_accelerations(&self.links, &self.bodies, &mut accelerations);
+        for (index, thrust) in &self.thrusters {
+            let a = thrust.acceleration(&self.bodies[*index], time);
+            accelerations[*index].0 += a.0;
+            accelerations[*index].1 += a.1;
+        }
+        if let Some(post_newtonian) = &self.post_newtonian {
+            let corrections =
+                post_newtonian.accelerations(&self.bodies, self.g_constant, self.eps2);
+            for (a, correction) in accelerations.iter_mut().zip(corrections) {
+                a.0 += correction.0;
+                a.1 += correction.1;
+            }
+        }
+        for field in &self.fields {
+            for (a, body) in accelerations.iter_mut().zip(&self.bodies) {
+                let field_a = field.acceleration(body, time, self.g_constant);
+                a.0 += field_a.0;
+                a.1 += field_a.1;
+            }
+        }
+        (accelerations, heating)
+    }
+
+    /// Accelerations due to the pair interactions between the bodies.
+    ///
+    /// Test particles only receive gravity: in open space the solver sees the massive
+    /// bodies alone and the tracers are summed directly against them. Periodic image
+    /// sums and meshes take the zero masses in their stride.
+    fn gravity_accelerations(&self) -> Vec<(f32, f32)> {
+        if let Boundary::Periodic { size, forces } = self.boundary {
+            return if self.solver.periodic_box().is_some() {
+                self.solver
+                    .accelerations(&self.bodies, self.g_constant, self.eps2)
+            } else {
+                forces.accelerations(&self.bodies, self.g_constant, self.eps2, size)
+            };
+        }
+        if self.force_law != ForceLaw::Newtonian {
+            return self
+                .force_law
+                .accelerations(&self.bodies, self.g_constant, self.eps2);
+        }
+        if !self.bodies.iter().any(Body::is_test_particle) {
+            return self
+                .solver
+                .accelerations(&self.bodies, self.g_constant, self.eps2);
+        }
+
+        let (tracers, massive): (Vec<usize>, Vec<usize>) =
+            (0..self.bodies.len()).partition(|&i| self.bodies[i].is_test_particle());
+        let select =
+            |indices: &[usize]| -> Vec<Body> { indices.iter().map(|&i| self.bodies[i]).collect() };
+        let (sources, receivers) = (select(&massive), select(&tracers));
+
+        let mut accelerations = vec![(0.0, 0.0); self.bodies.len()];
+        let massive_accelerations = self
+            .solver
+            .accelerations(&sources, self.g_constant, self.eps2);
+        for (&i, a) in massive.iter().zip(massive_accelerations) {
+            accelerations[i] = a;
+        }
+        let tracer_accelerations =
+            solver::test_particle_accelerations(&sources, &receivers, self.g_constant, self.eps2);
+        for (&i, a) in tracers.iter().zip(tracer_accelerations) {
+            accelerations[i] = a;
+        }
+        accelerations
+    }
+
+    pub fn step(&mut self) {
+        let before = (!self.detectors.is_empty()).then(|| self.bodies.clone());
+        let energy_before = self.watches_energy().then(|| self.conserved_energy());
+        if let Some(cosmology) = self.cosmology {
+            self.comoving_step(&cosmology);
+        } else if self.solver == GravitySolver::Simd && self.only_mutual_gravity() {
+            self.store_step();
+        } else {
+            let spin = self
+                .restricted_three_body
+                .as_ref()
+                .map(|restricted| restricted.angular_velocity(self.g_constant));
+            if let Some(regularization) = &self.regularization {
+                self.regularized_pairs = regularization.pairs(&self.bodies);
+            }
+            let mut time = self.time;
+            for &weight in self.integrator.substeps() {
+                let dt = weight * self.time_step;
+                match spin {
+                    Some(omega) => self.rotating_frame_verlet(dt, time, omega),
+                    None => self.velocity_verlet(dt, time),
+                }
+                time += dt as f64;
+            }
+        }
+        self.change_masses(self.time, self.time_step as f64);
+        if let Some(before) = before {
+            self.detect(&before, energy_before);
+        }
+        self.time += self.time_step as f64;
+        let mut events = self.boundary.remove(
+            &mut self.bodies,
+            &self.force_law,
+            self.g_constant,
+            self.eps2,
+            self.time,
+        );
+        for event in &mut events {
+            event.energy += event.body.mass * self.external_potential(event.body.position);
+            if let Some(particle) = self.gas.iter().find(|p| p.index == event.index) {
+                event.energy += event.body.mass * particle.internal_energy;
+            }
+            self.forget_body(event.index);
+        }
+        self.events.extend(events);
+
+        if let Some(post_newtonian) = self.post_newtonian
+            && let Some(observer) = post_newtonian.observer
+        {
+            let accelerations = self.gravity_accelerations();
+            let (plus, cross) =
+                post_newtonian.strain(&observer, &self.bodies, &accelerations, self.g_constant);
+            self.record_strain(StrainSample {
+                time: self.time,
+                plus,
+                cross,
+            });
+        }
+    }
+
+    /// Keeps `sample` and appends it to `strain_output`. Recording to the file stops
+    /// if it can't be written.
+    fn record_strain(&mut self, sample: StrainSample) {
+        if self.strain.len() == MAX_STRAIN_SAMPLES {
+            self.strain.pop_front();
+        }
+        self.strain.push_back(sample);
+        if let Err(e) = self.write_strain_sample(&sample)
+            && let Some(path) = self.strain_output.take()
+        {
+            log::error!("Couldn't write the strain to {}: {e}", path.display());
+            self.strain_writer = None;
+        }
+    }
+
+    fn write_strain_sample(&mut self, sample: &StrainSample) -> io::Result<()> {
+        if self.strain_writer.is_none()
+            && let Some(path) = &self.strain_output
+        {
+            let mut out = BufWriter::new(File::create(path)?);
+            writeln!(out, "{STRAIN_CSV_HEADER}")?;
+            self.strain_writer = Some(out);
+        }
+        match &mut self.strain_writer {
+            Some(out) => sample.write_csv(out),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the bodies feel nothing but their mutual Newtonian gravity in open space,
+    /// so the solver alone can step them.
+    pub fn only_mutual_gravity(&self) -> bool {
+        self.boundary == Boundary::Open
+            && self.fields.is_empty()
+            && self.force_law == ForceLaw::Newtonian
+            && self.post_newtonian.is_none()
+            && self.mass_schedules.is_empty()
+            && self.thrusters.is_empty()
+            && !oblateness::any_oblate(&self.bodies)
+            && self.contacts.is_none()
+            && self.gas.is_empty()
+            && self.links.is_empty()
+            && self.regularization.is_none()
+    }
+
+    /// One step of the integrator on the bodies in structure-of-arrays form, with the
+    /// SIMD kernel for every kick. The bodies are copied in and out once per step
+    /// rather than for every force evaluation, and each substep's closing
+    /// accelerations open the next.
+    fn store_step(&mut self) {
+        let mut store = std::mem::take(&mut self.store);
+        store.fill(&self.bodies);
+        let mut accelerations = simd::accelerations(&store, self.g_constant, self.eps2);
+        for &weight in self.integrator.substeps() {
+            let dt = weight * self.time_step;
+            // x(t+dt) = x(t) + v(t) dt + 0.5 a(t) dt^2
+            for (((x, y), (vx, vy)), a) in store
+                .x
+                .iter_mut()
+                .zip(&mut store.y)
+                .zip(store.vx.iter().zip(&store.vy))
+                .zip(&accelerations)
+            {
+                *x += vx * dt + 0.5 * a.0 * dt * dt;
+                *y += vy * dt + 0.5 * a.1 * dt * dt;
+            }
+            // v(t+dt) = v(t) + 0.5 (a(t) + a(t+dt)) dt
+            let accelerations_new = simd::accelerations(&store, self.g_constant, self.eps2);
+            for (((vx, vy), a), a_new) in store
+                .vx
+                .iter_mut()
+                .zip(&mut store.vy)
+                .zip(&accelerations)
+                .zip(&accelerations_new)
+            {
+                *vx += 0.5 * (a.0 + a_new.0) * dt;
+                *vy += 0.5 * (a.1 + a_new.1) * dt;
+            }
+            accelerations = accelerations_new;
+        }
+        store.write_to(&mut self.bodies);
+        self.store = store;
+    }
+
+    /// Applies the mass schedules and then the propellant burn over the step from
+    /// `time`, so a body with both burns from its scheduled mass. The material leaves
+    /// or arrives with the body's velocity.
+    fn change_masses(&mut self, time: f64, dt: f64) {
+        let Self {
+            bodies,
+            mass_schedules,
+            thrusters,
+            expelled_mass,
+            expelled_momentum,
+            ..
+        } = self;
+        let mut set_mass = |body: &mut Body, mass: f32| {
+            let dm = mass - body.mass;
+            body.mass = mass;
+            *expelled_mass -= dm;
+            expelled_momentum.0 -= dm * body.velocity.0;
+            expelled_momentum.1 -= dm * body.velocity.1;
+        };
+        for (index, schedule) in mass_schedules.iter() {
+            let body = &mut bodies[*index];
+            set_mass(body, schedule.mass_after(body.mass, time, dt));
+        }
+        for (index, thrust) in thrusters.iter() {
+            let body = &mut bodies[*index];
+            set_mass(body, thrust.mass_after(body.mass, time, dt));
+        }
+    }
+
+    fn watches_energy(&self) -> bool {
+        self.detectors
+            .iter()
+            .any(|d| matches!(d.kind, DetectorKind::EnergyError { .. }))
+    }
+
+    /// Total energy plus what removed bodies carried off, which stays conserved.
+    fn conserved_energy(&self) -> f32 {
+        self.total_energy() + self.removed_energy()
+    }
+
+    /// Runs the detectors over the step just taken from the bodies `before`, queueing
+    /// what they find. It runs before the boundary removes any bodies, so that the
+    /// detections carry the indices the bodies had during the step.
+    fn detect(&mut self, before: &[Body], energy_before: Option<f32>) {
+        let energy_errors = energy_before.map(|start| {
+            let reference = *self.reference_energy.get_or_insert(start);
+            let error = |energy: f32| ((energy - reference) / reference).abs();
+            (error(start), error(self.conserved_energy()))
+        });
+        let step = StepRecord {
+            before,
+            after: &self.bodies,
+            time: self.time,
+            dt: self.time_step as f64,
+            g_constant: self.g_constant,
+            eps2: self.eps2,
+            force_law: self.force_law,
+            regularized_pairs: &self.regularized_pairs,
+            energy_errors,
+        };
+        let detections = step.detect(&self.detectors);
+        self.detections.extend(detections);
+    }
+
+    /// Total thrust force on the bodies at `time`.
+    fn thrust_force(&self, time: f64) -> (f32, f32) {
+        self.thrusters
+            .iter()
+            .fold((0.0, 0.0), |(fx, fy), (index, thrust)| {
+                let body = &self.bodies[*index];
+                let a = thrust.acceleration(body, time);
+                (fx + body.mass * a.0, fy + body.mass * a.1)
+            })
+    }
+
+    /// Drops the per-body settings of a body the boundary has removed, and shifts the
+    /// indices of those after it.
+    fn forget_body(&mut self, index: usize) {
+        self.mass_schedules.retain(|(i, _)| *i != index);
+        self.thrusters.retain(|(i, _)| *i != index);
+        for aggregate in &mut self.aggregates {
+            aggregate.members.retain(|i| *i != index);
+        }
+        self.gas.retain(|p| p.index != index);
+        self.links
+            .retain(|link| link.bodies.0 != index && link.bodies.1 != index);
+        self.lagrange_pairs
+            .retain(|pair| pair.0 != index && pair.1 != index);
+        self.regularized_pairs
+            .retain(|pair| pair.0 != index && pair.1 != index);
+        self.detectors.retain(|d| !d.bodies.contains(&index));
+        for i in self
+            .mass_schedules
+            .iter_mut()
+            .map(|(i, _)| i)
+            .chain(self.thrusters.iter_mut().map(|(i, _)| i))
+            .chain(
+                self.aggregates
+                    .iter_mut()
+                    .flat_map(|a| a.members.iter_mut()),
+            )
+            .chain(self.gas.iter_mut().map(|p| &mut p.index))
+            .chain(
+                self.links
+                    .iter_mut()
+                    .flat_map(|link| [&mut link.bodies.0, &mut link.bodies.1]),
+            )
+            .chain(
+                self.lagrange_pairs
+                    .iter_mut()
+                    .chain(&mut self.regularized_pairs)
+                    .flat_map(|pair| [&mut pair.0, &mut pair.1]),
+            )
+            .chain(self.detectors.iter_mut().flat_map(|d| d.bodies.iter_mut()))
+        {
+            if *i > index {
+                *i -= 1;
+            }
+        }
+    }
+
+    fn velocity_verlet(&mut self, dt: f32, time: f64) {
+        // a(t)
+        let (accelerations, heating) = self.kick_rates_at(time);
+        let thrust = self.thrust_force(time);
+        let previous: Vec<(f32, f32)> = if self.links.iter().any(Link::is_rod) {
+            self.bodies.iter().map(|b| b.position).collect()
+        } else {
+            Vec::new()
+        };
+
+        let pairs: Vec<_> = self
+            .regularized_pairs
+            .iter()
+            .map(|&(i, j)| self.pair_drift(i, j, &accelerations, dt))
+            .collect();
+        // x(t+dt) = x(t) + v(t) dt + 0.5 a(t) dt^2
+        for (body, a) in self.bodies.iter_mut().zip(&accelerations) {
+            body.position.0 += body.velocity.0 * dt + 0.5 * a.0 * dt * dt;
+            body.position.1 += body.velocity.1 * dt + 0.5 * a.1 * dt * dt;
+        }
+        for (&(i, j), drifted) in self.regularized_pairs.iter().zip(pairs) {
+            for (k, (position, velocity)) in [i, j].into_iter().zip(drifted) {
+                self.bodies[k].position = position;
+                self.bodies[k].velocity = velocity;
+            }
+        }
+        // Rods pull the drift back to their lengths, RATTLE-style
+        link::constrain_positions(&self.links, &mut self.bodies, &previous, dt);
+        self.boundary.apply(&mut self.bodies);
+
+        // a(t+dt), with velocity-dependent fields still seeing v(t)
+        let (accelerations_new, heating_new) = self.kick_rates_at(time + dt as f64);
+        let thrust_new = self.thrust_force(time + dt as f64);
+        // The exhaust takes the opposite of the impulse the engines give in the kick
+        self.expelled_momentum.0 -= 0.5 * (thrust.0 + thrust_new.0) * dt;
+        self.expelled_momentum.1 -= 0.5 * (thrust.1 + thrust_new.1) * dt;
+
+        // v(t+dt) = v(t) + 0.5 (a(t) + a(t+dt)) dt
+        for ((body, a), a_new) in self
+            .bodies
+            .iter_mut()
+            .zip(&accelerations)
+            .zip(&accelerations_new)
+        {
+            body.velocity.0 += 0.5 * (a.0 + a_new.0) * dt;
+            body.velocity.1 += 0.5 * (a.1 + a_new.1) * dt;
+        }
+        link::constrain_velocities(&self.links, &mut self.bodies);
+        for ((particle, du), du_new) in self.gas.iter_mut().zip(heating).zip(heating_new) {
+            particle.internal_energy =
+                (particle.internal_energy + 0.5 * (du + du_new) * dt).max(0.0);
+        }
+    }
+
+    /// Rates for the kicks of `velocity_verlet`, which leave out the attraction
+    /// within each regularized pair since its drift follows the pair's orbit.
+    fn kick_rates_at(&self, time: f64) -> (Vec<(f32, f32)>, Vec<f32>) {
+        let mut gravity = self.gravity_accelerations();
+        for &(i, j) in &self.regularized_pairs {
+            // Recomputed rather than subtracted, which would cancel catastrophically
+            // at close approach
+            gravity[i] = self.gravity_on(i, j);
+            gravity[j] = self.gravity_on(j, i);
+        }
+        self.rates_with_gravity(gravity, time)
+    }
+
+    /// Newtonian gravity on body `i` from the massive bodies other than its partner,
+    /// summed directly.
+    fn gravity_on(&self, i: usize, partner: usize) -> (f32, f32) {
+        let body = &self.bodies[i];
+        self.bodies
+            .iter()
+            .enumerate()
+            .filter(|&(j, other)| j != i && j != partner && !other.is_test_particle())
+            .fold((0.0, 0.0), |(ax, ay), (_, other)| {
+                let (dx, dy) = (
+                    other.position.0 - body.position.0,
+                    other.position.1 - body.position.1,
+                );
+                let d2 = dx * dx + dy * dy + self.eps2;
+                let a = self.g_constant * other.mass / (d2 * d2.sqrt());
+                (ax + a * dx, ay + a * dy)
+            })
+    }
+
+    /// Positions and velocities of the regularized pair `(i, j)` after a drift over
+    /// `dt` from the half kick by `accelerations`: the centre of mass coasts and the
+    /// relative orbit follows its Kepler orbit. The velocities are less the half kick,
+    /// which the closing kick of `velocity_verlet` adds back.
+    fn pair_drift(
+        &self,
+        i: usize,
+        j: usize,
+        accelerations: &[(f32, f32)],
+        dt: f32,
+    ) -> [((f32, f32), (f32, f32)); 2] {
+        let dt = dt as f64;
+        let state = |k: usize| {
+            let body = &self.bodies[k];
+            let a = accelerations[k];
+            (
+                body.mass as f64,
+                (body.position.0 as f64, body.position.1 as f64),
+                (
+                    body.velocity.0 as f64 + 0.5 * a.0 as f64 * dt,
+                    body.velocity.1 as f64 + 0.5 * a.1 as f64 * dt,
+                ),
+            )
+        };
+        let ((m1, x1, v1), (m2, x2, v2)) = (state(i), state(j));
+        let mass = m1 + m2;
+        let centre = |a: (f64, f64), b: (f64, f64)| {
+            ((m1 * a.0 + m2 * b.0) / mass, (m1 * a.1 + m2 * b.1) / mass)
+        };
+        let (position, velocity) = (centre(x1, x2), centre(v1, v2));
+        let position = (position.0 + velocity.0 * dt, position.1 + velocity.1 * dt);
+        let (r, v) = regularization::kepler_drift(
+            (x2.0 - x1.0, x2.1 - x1.1),
+            (v2.0 - v1.0, v2.1 - v1.1),
+            self.g_constant as f64 * mass,
+            dt,
+        );
+        [(i, -m2 / mass), (j, m1 / mass)].map(|(k, share)| {
+            let a = accelerations[k];
+            (
+                (
+                    (position.0 + share * r.0) as f32,
+                    (position.1 + share * r.1) as f32,
+                ),
+                (
+                    (velocity.0 + share * v.0 - 0.5 * a.0 as f64 * dt) as f32,
+                    (velocity.1 + share * v.1 - 0.5 * a.1 as f64 * dt) as f32,
+                ),
+            )
+        })
+    }
+
+    /// Kick-drift-kick leapfrog in a frame turning at `omega`, for the massless
+    /// particles of a restricted three-body run. The Coriolis acceleration only turns
+    /// the velocities, so it is integrated exactly as a rotation either side of the
+    /// drift, as in the Boris scheme, which keeps the Jacobi constants from drifting.
+    fn rotating_frame_verlet(&mut self, dt: f32, time: f64, omega: f32) {
+        // Over half a step the Coriolis term turns v clockwise by omega dt. Boris's
+        // two shears make the turn as increments, so rounding can't steadily grow or
+        // shrink the speed.
+        let t = (-0.5 * omega * dt).tan();
+        let s = 2.0 * t / (1.0 + t * t);
+        let turn = |(vx, vy): (f32, f32)| {
+            let (hx, hy) = (vx - t * vy, vy + t * vx);
+            (vx - s * hy, vy + s * hx)
+        };
+
+        let (accelerations, _) = self.rates_at(time);
+        for (body, a) in self.bodies.iter_mut().zip(&accelerations) {
+            let v = (
+                body.velocity.0 + 0.5 * a.0 * dt,
+                body.velocity.1 + 0.5 * a.1 * dt,
+            );
+            body.velocity = turn(v);
+            body.position.0 += body.velocity.0 * dt;
+            body.position.1 += body.velocity.1 * dt;
+            body.velocity = turn(body.velocity);
+        }
+        self.boundary.apply(&mut self.bodies);
+
+        let (accelerations, _) = self.rates_at(time + dt as f64);
+        for (body, a) in self.bodies.iter_mut().zip(&accelerations) {
+            body.velocity.0 += 0.5 * a.0 * dt;
+            body.velocity.1 += 0.5 * a.1 * dt;
+        }
+    }
+
+    /// Kick-drift-kick leapfrog in comoving coordinates `x` with momenta `p = a v` per
+    /// unit mass, for which `dx/dt = p / a^2` and `dp/dt = g / a`, where `g` is the
+    /// gravity of the perturbations at the comoving positions. The drift and kick
+    /// factors are integrated over the expansion, so the scheme stays symplectic.
+    fn comoving_step(&mut self, cosmology: &Cosmology) {
+        let half = 0.5 * self.time_step as f64;
+        let first = cosmology.advance(self.scale_factor, half);
+        let second = cosmology.advance(first.scale_factor, half);
+
+        let a = self.scale_factor as f32;
+        let kick = first.kick as f32;
+        let mut momenta: Vec<(f32, f32)> = self
+            .bodies
+            .iter()
+            .zip(self.accelerations())
+            .map(|(body, g)| {
+                (
+                    a * body.velocity.0 + g.0 * kick,
+                    a * body.velocity.1 + g.1 * kick,
+                )
+            })
+            .collect();
+        let drift = (first.drift + second.drift) as f32;
+        for (body, p) in self.bodies.iter_mut().zip(&momenta) {
+            body.position.0 += p.0 * drift;
+            body.position.1 += p.1 * drift;
+        }
+        self.boundary.apply(&mut self.bodies);
+
+        self.scale_factor = second.scale_factor;
+        let a = self.scale_factor as f32;
+        let kick = second.kick as f32;
+        let accelerations = self.accelerations_at(self.time + self.time_step as f64);
+        for ((body, p), g) in self.bodies.iter_mut().zip(&mut momenta).zip(accelerations) {
+            p.0 += g.0 * kick;
+            p.1 += g.1 * kick;
+            body.velocity = (p.0 / a, p.1 / a);
+        }
+    }
+
+    /// Cosmological redshift `1 / a - 1` of the current scale factor.
+    pub fn redshift(&self) -> f64 {
+        1.0 / self.scale_factor - 1.0
+    }
+
+    pub fn total_kinetic_energy(&self) -> f32 {
+        self.bodies
+            .iter()
+            .fold(0.0, |s, x| s + x.get_kinetic_energy())
+    }
+
+    /// Potential energy matching the softened forces used by `step`, including the
+    /// bodies' energy in the external fields and the elastic energy of their contacts
+    /// and links.
+    pub fn total_potential_energy(&self) -> f32 {
+        let external: f32 = self
+            .bodies
+            .iter()
+            .map(|b| b.mass * self.external_potential(b.position))
+            .sum();
+        let elastic = self
+            .contacts
+            .map_or(0.0, |contacts| contacts.potential_energy(&self.bodies))
+            + link::potential_energy(&self.links, &self.bodies);
+        external + elastic + self.mutual_potential_energy()
+    }
+
+    /// Jacobi constant of every body in a restricted three-body run, empty otherwise.
+    pub fn jacobi_constants(&self) -> Vec<f32> {
+        self.restricted_three_body
+            .as_ref()
+            .map_or_else(Vec::new, |restricted| {
+                self.bodies
+                    .iter()
+                    .map(|body| restricted.jacobi_constant(body, self.g_constant))
+                    .collect()
+            })
+    }
+
+    /// Sum of the external fields' potentials per unit mass at `position`.
+    pub fn external_potential(&self, position: (f32, f32)) -> f32 {
+        self.fields
+            .iter()
+            .map(|f| f.potential(position, self.time, self.g_constant))
+            .sum()
+    }
+
+    fn mutual_potential_energy(&self) -> f32 {
+        match self.boundary {
+            Boundary::Periodic { .. } if self.solver.periodic_box().is_some() => self
+                .solver
+                .potential_energy(&self.bodies, self.g_constant, self.eps2),
+            Boundary::Periodic { size, forces } => {
+                forces.potential_energy(&self.bodies, self.g_constant, self.eps2, size)
+            }
+            _ if self.force_law != ForceLaw::Newtonian => {
+                self.force_law
+                    .total_potential_energy(&self.bodies, self.g_constant, self.eps2)
+            }
+            // Test particles carry no potential energy
+            _ => {
+                let massive: Vec<Body> = self
+                    .bodies
+                    .iter()
+                    .filter(|b| !b.is_test_particle())
+                    .copied()
+                    .collect();
+                self.solver
+                    .potential_energy(&massive, self.g_constant, self.eps2)
+                    + oblateness::potential_energy(&self.bodies, self.g_constant, self.eps2)
+            }
+        }
+    }
+
+    /// Internal energy of the gas.
+    pub fn total_thermal_energy(&self) -> f32 {
+        self.gas
+            .iter()
+            .map(|p| self.bodies[p.index].mass * p.internal_energy)
+            .sum()
+    }
+
+    pub fn total_energy(&self) -> f32 {
+        self.total_kinetic_energy() + self.total_potential_energy() + self.total_thermal_energy()
+    }
+
+    /// Mass carried out of the system by removed bodies.
+    pub fn removed_mass(&self) -> f32 {
+        self.events.iter().map(|e| e.body.mass).sum()
+    }
+
+    /// Energy carried out of the system by removed bodies; `total_energy` plus this
+    /// is what stays conserved.
+    pub fn removed_energy(&self) -> f32 {
+        self.events.iter().map(|e| e.energy).sum()
+    }
+
+    /// Flushes the strain written to `strain_output` so far.
+    pub fn flush_strain(&mut self) -> io::Result<()> {
+        match &mut self.strain_writer {
+            Some(out) => out.flush(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn total_mass(&self) -> f32 {
+        self.bodies.iter().map(|b| b.mass).sum()
+    }
+
+    pub fn total_linear_momentum(&self) -> (f32, f32) {
+        self.bodies.iter().fold((0.0, 0.0), |(px, py), b| {
+            let p = b.get_linear_momentum();
+            (px + p.0, py + p.1)
+        })
+    }
+
+    /// Total angular momentum about the origin, orbital and spin.
+    pub fn total_angular_momentum(&self) -> f32 {
+        self.bodies
+            .iter()
+            .map(|b| b.get_angular_momentum() + b.spin)
+            .sum()
+    }
+
+    /// Mass-weighted mean position, or the origin if the system has no mass.
+    pub fn centre_of_mass(&self) -> (f32, f32) {
+        let total_mass = self.total_mass();
+        if total_mass == 0.0 {
+            return (0.0, 0.0);
+        }
+        let (x, y) = self.bodies.iter().fold((0.0, 0.0), |(x, y), b| {
+            (x + b.mass * b.position.0, y + b.mass * b.position.1)
+        });
+        (x / total_mass, y / total_mass)
+    }
+
+    /// Velocity of the centre of mass (the barycentric frame), or zero if the system
+    /// has no mass.
+    pub fn centre_of_mass_velocity(&self) -> (f32, f32) {
+        let total_mass = self.total_mass();
+        if total_mass == 0.0 {
+            return (0.0, 0.0);
+        }
+        let (px, py) = self.total_linear_momentum();
+        (px / total_mass, py / total_mass)
+    }
+
+    /// Shifts every body into the frame where the centre of mass sits at rest at the
+    /// origin, so the total linear momentum is zero.
+    pub fn to_barycentric_frame(&mut self) {
+        let com = self.centre_of_mass();
+        let com_velocity = self.centre_of_mass_velocity();
+        for body in &mut self.bodies {
+            body.position.0 -= com.0;
+            body.position.1 -= com.1;
+            body.velocity.0 -= com_velocity.0;
+            body.velocity.1 -= com_velocity.1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{PI, TAU};
+
+    use super::*;
+    use crate::field::ExternalField;
+    use crate::kepler;
+    use crate::lagrange;
+    use crate::sph::GasParticle;
+
+    fn two_body_simulation() -> Simulation {
+        let bodies = vec![
+            Body::new(100.0, (-1.0, 0.0), (0.0, 1.0)).unwrap(),
+            Body::new(100.0, (1.0, 0.0), (0.0, -1.0)).unwrap(),
+        ];
+        Simulation::new(bodies, 1.0, 0.0001, 0.0)
+    }
+
+    fn total_momentum(simulation: &Simulation) -> f32 {
+        let (px, py) = simulation.total_linear_momentum();
+        (px * px + py * py).sqrt()
+    }
+
+    #[test]
+    fn test_energy_conservation_two_body() {
+        let mut simulation = two_body_simulation();
+        let initial_energy = simulation.total_energy();
+
+        for _ in 0..10 {
+            simulation.step();
+        }
+
+        let final_energy = simulation.total_energy();
+        println!("Initial total energy: {initial_energy}, Final total energy: {final_energy}");
+        println!(
+            "Total energy difference: {}",
+            (final_energy - initial_energy).abs()
+        );
+        assert!((final_energy - initial_energy).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_momentum_conservation_two_body() {
+        let mut simulation = two_body_simulation();
+        let p0 = total_momentum(&simulation);
+
+        for _ in 0..10 {
+            simulation.step();
+        }
+
+        let p1 = total_momentum(&simulation);
+        println!("Initial momentum: {p0}, Final momentum: {p1}");
+        assert!((p1 - p0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_softened_energy_is_conserved() {
+        let mut simulation = two_body_simulation();
+        simulation.eps2 = 0.004;
+        let initial_energy = simulation.total_energy();
+
+        for _ in 0..1000 {
+            simulation.step();
+        }
+
+        let relative_error = ((simulation.total_energy() - initial_energy) / initial_energy).abs();
+        assert!(
+            relative_error < 1e-4,
+            "relative energy error {relative_error}"
+        );
+    }
+
+    #[test]
+    fn test_step_advances_time() {
+        let mut simulation = two_body_simulation();
+        simulation.integrator = Integrator::Yoshida4;
+        for _ in 0..4 {
+            simulation.step();
+        }
+        assert!((simulation.time - 0.0004).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_centre_of_mass() {
+        let bodies = vec![
+            Body::new(1.0, (0.0, 0.0), (1.0, 0.0)).unwrap(),
+            Body::new(3.0, (4.0, 2.0), (0.0, -1.0)).unwrap(),
+        ];
+        let simulation = Simulation::new(bodies, 1.0, 0.001, 0.0);
+
+        assert_eq!(simulation.total_mass(), 4.0);
+        assert_eq!(simulation.centre_of_mass(), (3.0, 1.5));
+        assert_eq!(simulation.centre_of_mass_velocity(), (0.25, -0.75));
+        assert_eq!(simulation.total_linear_momentum(), (1.0, -3.0));
+    }
+
+    #[test]
+    fn test_massless_system_has_centre_at_origin() {
+        let simulation = Simulation::new(Vec::new(), 1.0, 0.001, 0.0);
+        assert_eq!(simulation.centre_of_mass(), (0.0, 0.0));
+        assert_eq!(simulation.centre_of_mass_velocity(), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_to_barycentric_frame() {
+        let bodies = vec![
+            Body::new(1.0, (1.0, 1.0), (0.5, 2.0)).unwrap(),
+            Body::new(2.0, (-1.0, 3.0), (1.0, -1.0)).unwrap(),
+            Body::new(3.0, (2.0, -2.0), (-0.5, 0.0)).unwrap(),
+        ];
+        let mut simulation = Simulation::new(bodies, 1.0, 0.001, 0.0);
+        let relative_velocity = |s: &Simulation| {
+            let (a, b) = (s.bodies[0].velocity, s.bodies[2].velocity);
+            (a.0 - b.0, a.1 - b.1)
+        };
+        let before = relative_velocity(&simulation);
+
+        simulation.to_barycentric_frame();
+
+        let com = simulation.centre_of_mass();
+        let (px, py) = simulation.total_linear_momentum();
+        assert!(com.0.abs() < 1e-6 && com.1.abs() < 1e-6);
+        assert!(px.abs() < 1e-6 && py.abs() < 1e-6);
+        assert_eq!(relative_velocity(&simulation), before);
+    }
+
+    #[test]
+    fn test_angular_momentum_is_conserved() {
+        let mut simulation = two_body_simulation();
+        simulation.eps2 = 0.004;
+        let initial = simulation.total_angular_momentum();
+
+        for _ in 0..1000 {
+            simulation.step();
+        }
+
+        let relative_error = ((simulation.total_angular_momentum() - initial) / initial).abs();
+        assert!(relative_error < 1e-5, "relative error {relative_error}");
+    }
+
+    #[test]
+    fn test_scenario_zero_momentum_frame() {
+        let scenario = Scenario::from_toml(
+            r#"
+            zero_momentum_frame = true
+
+            [[bodies]]
+            mass = 10.0
+            position = [0.5, 0.0]
+            velocity = [0.0, 1.0]
+
+            [[bodies]]
+            mass = 1.0
+            position = [-0.5, 0.0]
+            velocity = [0.0, 0.0]
+            "#,
+        )
+        .unwrap();
+        let simulation = Simulation::from_scenario(&scenario).unwrap();
+        let com = simulation.centre_of_mass();
+        let (px, py) = simulation.total_linear_momentum();
+
+        assert!(com.0.abs() < 1e-6 && com.1.abs() < 1e-6);
+        assert!(px.abs() < 1e-6 && py.abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_periodic_box_wraps_and_conserves() {
+        let scenario = Scenario::from_toml(
+            r#"
+            eps2 = 0.01
+            time_step = 0.001
+            boundary = { kind = "periodic", size = 2.0 }
+
+            [[bodies]]
+            mass = 1.0
+            position = [0.9, 0.0]
+            velocity = [3.0, 0.5]
+
+            [[bodies]]
+            mass = 2.0
+            position = [-0.4, 0.3]
+            velocity = [-1.5, -0.25]
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_scenario(&scenario).unwrap();
+        let initial_energy = simulation.total_energy();
+
+        for _ in 0..500 {
+            simulation.step();
+        }
+
+        for body in &simulation.bodies {
+            assert!(body.position.0.abs() <= 1.0 && body.position.1.abs() <= 1.0);
+        }
+        let (px, py) = simulation.total_linear_momentum();
+        assert!(px.abs() < 1e-4 && py.abs() < 1e-4);
+        let relative_error = ((simulation.total_energy() - initial_energy) / initial_energy).abs();
+        assert!(
+            relative_error < 1e-4,
+            "relative energy error {relative_error}"
+        );
+    }
+
+    #[test]
+    fn test_strain_is_written_to_the_output() {
+        let binary = |post_newtonian: &str| {
+            Scenario::from_toml(&format!(
+                r#"
+                post_newtonian = {post_newtonian}
+                strain_output = "strain.csv"
+                eps2 = 0.0
+
+                [[bodies]]
+                mass = 1.0
+                position = [-0.5, 0.0]
+                velocity = [0.0, -0.707]
+
+                [[bodies]]
+                mass = 1.0
+                position = [0.5, 0.0]
+                velocity = [0.0, 0.707]
+                "#
+            ))
+            .unwrap()
+        };
+        assert!(Simulation::from_scenario(&binary("{ speed_of_light = 100.0 }")).is_err());
+        for (post_newtonian, message) in [
+            (
+                "{ speed_of_light = 0.0, observer = { distance = 100.0 } }",
+                "the speed of light must be positive, got 0",
+            ),
+            (
+                "{ speed_of_light = 100.0, observer = { distance = -1.0 } }",
+                "the strain observer distance must be positive, got -1",
+            ),
+        ] {
+            let error = Simulation::from_scenario(&binary(post_newtonian))
+                .err()
+                .unwrap();
+            assert_eq!(error.to_string(), message);
+        }
+
+        let scenario = binary("{ speed_of_light = 100.0, observer = { distance = 100.0 } }");
+        let mut simulation = Simulation::from_scenario(&scenario).unwrap();
+        let path = std::env::temp_dir().join(format!("grav-sim-strain-{}.csv", std::process::id()));
+        simulation.strain_output = Some(path.clone());
+        for _ in 0..10 {
+            simulation.step();
+        }
+        simulation.flush_strain().unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 11);
+        assert_eq!(lines[0], "time,plus,cross");
+        let plus: f32 = lines[10].split(',').nth(1).unwrap().parse().unwrap();
+        assert_eq!(plus, simulation.strain[9].plus);
+
+        // Later samples are appended as the run goes
+        for _ in 0..5 {
+            simulation.step();
+        }
+        simulation.flush_strain().unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(csv.lines().count(), 16);
+    }
+
+    #[test]
+    fn test_strain_buffer_keeps_the_latest_samples() {
+        let mut simulation = two_body_simulation();
+        for i in 0..MAX_STRAIN_SAMPLES + 5 {
+            simulation.record_strain(StrainSample {
+                time: i as f64,
+                plus: 0.0,
+                cross: 0.0,
+            });
+        }
+        assert_eq!(simulation.strain.len(), MAX_STRAIN_SAMPLES);
+        assert_eq!(simulation.strain[0].time, 5.0);
+    }
+
+    #[test]
+    fn test_simd_solver_steps_the_store_like_the_direct_sum() {
+        let mut bodies = crate::solver::tests::random_bodies(60, 37);
+        bodies.push(Body::test_particle((0.1, 0.2), (0.3, -0.1)).unwrap());
+        for integrator in [Integrator::VelocityVerlet, Integrator::Yoshida4] {
+            let run = |solver: GravitySolver| {
+                let mut simulation = Simulation::new(bodies.clone(), 1.0, 0.001, 0.01);
+                simulation.integrator = integrator;
+                simulation.solver = solver;
+                for _ in 0..200 {
+                    simulation.step();
+                }
+                simulation.bodies
+            };
+            let (expected, actual) = (run(GravitySolver::DirectSum), run(GravitySolver::Simd));
+            for (e, a) in expected.iter().zip(&actual) {
+                let error = (e.position.0 - a.position.0).hypot(e.position.1 - a.position.1);
+                assert!(error < 1e-4, "{integrator:?}: {error}");
+                assert_eq!((a.radius, a.charge), (e.radius, e.charge));
+            }
+        }
+    }
+
+    #[test]
+    fn test_absorbed_energy_is_accounted_for() {
+        let scenario = Scenario::from_toml(
+            r#"
+            eps2 = 0.01
+            time_step = 0.001
+            boundary = { kind = "absorbing", size = 2.0 }
+
+            [[bodies]]
+            mass = 1.0
+            position = [0.0, 0.0]
+            velocity = [0.0, 0.0]
+
+            [[bodies]]
+            mass = 0.1
+            position = [0.5, 0.0]
+            velocity = [3.0, 0.0]
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_scenario(&scenario).unwrap();
+        let initial_energy = simulation.total_energy();
+        let initial_mass = simulation.total_mass();
+
+        for _ in 0..500 {
+            simulation.step();
+        }
+
+        assert_eq!(simulation.bodies.len(), 1);
+        assert_eq!(simulation.events.len(), 1);
+        assert!(simulation.events[0].time > 0.0);
+        assert_eq!(
+            simulation.total_mass() + simulation.removed_mass(),
+            initial_mass
+        );
+        let energy = simulation.total_energy() + simulation.removed_energy();
+        assert!(((energy - initial_energy) / initial_energy).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_absorbed_energy_follows_the_force_law() {
+        // Screened well short of the wall, so Newtonian bookkeeping would be far off
+        let scenario = Scenario::from_toml(
+            r#"
+            eps2 = 0.01
+            time_step = 0.001
+            boundary = { kind = "absorbing", size = 2.0 }
+            force_law = { kind = "yukawa", range = 0.5 }
+
+            [[bodies]]
+            mass = 1.0
+            position = [0.0, 0.0]
+            velocity = [0.0, 0.0]
+
+            [[bodies]]
+            mass = 0.1
+            position = [0.5, 0.0]
+            velocity = [3.0, 0.0]
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_scenario(&scenario).unwrap();
+        let initial_energy = simulation.total_energy();
+
+        // The conserved energy must not jump on the step that removes the body
+        let mut energy = initial_energy;
+        for _ in 0..500 {
+            simulation.step();
+            let next = simulation.total_energy() + simulation.removed_energy();
+            assert!(
+                ((next - energy) / initial_energy).abs() < 1e-4,
+                "{energy} -> {next}"
+            );
+            energy = next;
+        }
+
+        assert_eq!(simulation.events.len(), 1);
+        assert!(((energy - initial_energy) / initial_energy).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_reflecting_box_keeps_bodies_inside() {
+        let mut simulation = two_body_simulation();
+        simulation.boundary = Boundary::Reflecting { size: 2.0 };
+        simulation.bodies[0].velocity = (5.0, 3.0);
+        let initial_energy = simulation.total_energy();
+
+        for _ in 0..1000 {
+            simulation.step();
+        }
+
+        for body in &simulation.bodies {
+            assert!(body.position.0.abs() <= 1.0 && body.position.1.abs() <= 1.0);
+        }
+        assert!(simulation.events.is_empty());
+        assert!(((simulation.total_energy() - initial_energy) / initial_energy).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_test_particles_do_not_perturb_massive_bodies() {
+        let mut reference = two_body_simulation();
+        let mut simulation = two_body_simulation();
+        simulation.solver = GravitySolver::Fmm { order: 4 };
+        reference.solver = simulation.solver.clone();
+        simulation
+            .bodies
+            .push(Body::test_particle((0.0, 0.5), (3.0, 0.0)).unwrap());
+        simulation
+            .bodies
+            .push(Body::test_particle((0.0, -0.5), (-3.0, 0.0)).unwrap());
+
+        for _ in 0..200 {
+            reference.step();
+            simulation.step();
+        }
+
+        for (body, expected) in simulation.bodies.iter().zip(&reference.bodies) {
+            assert_eq!(body.position, expected.position);
+            assert_eq!(body.velocity, expected.velocity);
+        }
+        for tracer in &simulation.bodies[2..] {
+            assert!(tracer.position.0.is_finite() && tracer.position.1.is_finite());
+            assert!(tracer.velocity != (3.0, 0.0) && tracer.velocity != (-3.0, 0.0));
+        }
+        assert_eq!(simulation.total_energy(), reference.total_energy());
+    }
+
+    #[test]
+    fn test_periodic_test_particle_matches_light_body() {
+        let mut simulation = Simulation::new(
+            vec![
+                Body::new(1.0, (0.0, 0.0), (0.0, 0.0)).unwrap(),
+                Body::test_particle((0.9, 0.0), (0.0, 0.0)).unwrap(),
+            ],
+            1.0,
+            0.001,
+            0.01,
+        );
+        simulation.boundary = Boundary::Periodic {
+            size: 2.0,
+            forces: Default::default(),
+        };
+
+        let accelerations = simulation.accelerations();
+        simulation.bodies[1].mass = 1e-6;
+        let expected = simulation.accelerations();
+
+        assert_eq!(accelerations[0], (0.0, 0.0));
+        let error = (accelerations[1].0 - expected[1].0).hypot(accelerations[1].1 - expected[1].1);
+        assert!(error < 1e-4 * expected[1].0.hypot(expected[1].1));
+    }
+
+    #[test]
+    fn test_energy_in_static_fields_is_conserved() {
+        let scenario = Scenario::from_toml(
+            r#"
+            time_step = 0.0005
+            eps2 = 0.01
+            integrator = "yoshida4"
+            fields = [
+                { kind = "plummer_halo", mass = 20.0, scale_radius = 0.3 },
+                { kind = "logarithmic_halo", v0 = 1.0, core_radius = 0.1 },
+                { kind = "point_mass", mass = 5.0, position = [0.6, 0.0], softening = 0.1 },
+            ]
+
+            [[bodies]]
+            mass = 1.0
+            position = [-0.5, 0.0]
+            velocity = [0.0, 4.0]
+
+            [[bodies]]
+            mass = 0.5
+            position = [0.0, 0.4]
+            velocity = [-3.0, 0.0]
+
+            [[bodies]]
+            position = [0.2, -0.3]
+            velocity = [1.0, 2.0]
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_scenario(&scenario).unwrap();
+        assert_eq!(simulation.fields.len(), 3);
+        let initial_energy = simulation.total_energy();
+        let initial_bodies = simulation.bodies.clone();
+
+        for _ in 0..2000 {
+            simulation.step();
+        }
+
+        // The fields pull the bodies around without exchanging energy with them
+        assert!(simulation.bodies[0].position != initial_bodies[0].position);
+        let relative_error = ((simulation.total_energy() - initial_energy) / initial_energy).abs();
+        assert!(
+            relative_error < 1e-4,
+            "relative energy error {relative_error}"
+        );
+    }
+
+    #[test]
+    fn test_fields_stack_onto_gravity() {
+        let mut simulation = two_body_simulation();
+        let gravity = simulation.accelerations();
+        simulation.fields.push(Box::new(ExternalField::Uniform {
+            acceleration: (0.0, -1.0),
+        }));
+        simulation
+            .fields
+            .push(Box::new(ExternalField::Drag { coefficient: 2.0 }));
+
+        let accelerations = simulation.accelerations();
+
+        for ((a, g), body) in accelerations.iter().zip(&gravity).zip(&simulation.bodies) {
+            assert_eq!(a.0, g.0 - 2.0 * body.velocity.0);
+            assert_eq!(a.1, g.1 - 1.0 - 2.0 * body.velocity.1);
+        }
+    }
+
+    #[test]
+    fn test_energy_is_conserved_under_every_force_law() {
+        for force_law in [
+            r#"{ kind = "coulomb" }"#,
+            r#"{ kind = "yukawa", range = 0.3 }"#,
+            r#"{ kind = "mond", a0 = 2.0 }"#,
+            r#"{ kind = "power_law", exponent = 1.5 }"#,
+        ] {
+            let scenario = Scenario::from_toml(&format!(
+                r#"
+                time_step = 0.0002
+                eps2 = 0.01
+                force_law = {force_law}
+
+                [[bodies]]
+                mass = 3.0
+                charge = 2.0
+                position = [-0.3, 0.0]
+                velocity = [0.0, -0.5]
+
+                [[bodies]]
+                mass = 1.0
+                charge = -2.0
+                position = [0.4, 0.1]
+                velocity = [0.0, 1.5]
+
+                [[bodies]]
+                mass = 0.5
+                charge = 1.0
+                position = [0.0, 0.6]
+                velocity = [-1.0, 0.0]
+                "#
+            ))
+            .unwrap();
+            let mut simulation = Simulation::from_scenario(&scenario).unwrap();
+            let initial_energy = simulation.total_energy();
+            let initial_momentum = simulation.total_linear_momentum();
+
+            for _ in 0..2000 {
+                simulation.step();
+            }
+
+            let relative_error =
+                ((simulation.total_energy() - initial_energy) / initial_energy).abs();
+            assert!(relative_error < 1e-3, "{force_law}: {relative_error}");
+            let momentum = simulation.total_linear_momentum();
+            assert!(
+                (momentum.0 - initial_momentum.0).abs() < 1e-4,
+                "{force_law}"
+            );
+            assert!(
+                (momentum.1 - initial_momentum.1).abs() < 1e-4,
+                "{force_law}"
+            );
+        }
+    }
+
+    fn conserved_momentum(simulation: &Simulation) -> (f32, f32) {
+        let (px, py) = simulation.total_linear_momentum();
+        (
+            px + simulation.expelled_momentum.0,
+            py + simulation.expelled_momentum.1,
+        )
+    }
+
+    #[test]
+    fn test_rocket_follows_tsiolkovsky() {
+        let scenario = Scenario::from_toml(
+            r#"
+            g_constant = 0.0
+            time_step = 0.001
+
+            [[bodies]]
+            mass = 10.0
+            position = [0.0, 0.0]
+            velocity = [1.0, 0.0]
+            thrust = { acceleration = 2.0, duration = 1.0, exhaust_velocity = 4.0 }
+
+            [[bodies]]
+            mass = 1.0
+            position = [0.0, 1.0]
+            velocity = [0.0, 0.0]
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_scenario(&scenario).unwrap();
+        let initial_momentum = conserved_momentum(&simulation);
+
+        for _ in 0..1500 {
+            simulation.step();
+        }
+
+        // dv = v_e ln(m0 / m1)
+        let rocket = simulation.bodies[0];
+        let expected = 4.0 * (10.0 / rocket.mass).ln();
+        assert!(
+            (expected - 2.0).abs() < 1e-2,
+            "burnt down to {}",
+            rocket.mass
+        );
+        assert!((rocket.velocity.0 - 1.0 - expected).abs() < 1e-2);
+        assert!(rocket.velocity.1.abs() < 1e-6);
+        assert!((simulation.expelled_mass - (10.0 - rocket.mass)).abs() < 1e-5);
+
+        let momentum = conserved_momentum(&simulation);
+        assert!((momentum.0 - initial_momentum.0).abs() < 1e-3);
+        assert!((momentum.1 - initial_momentum.1).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_rocket_with_a_mass_schedule_burns_from_the_scheduled_mass() {
+        let scenario = Scenario::from_toml(
+            r#"
+            g_constant = 0.0
+            time_step = 0.001
+
+            [[bodies]]
+            mass = 10.0
+            position = [0.0, 0.0]
+            velocity = [1.0, 0.0]
+            mass_schedule = { kind = "constant", rate = -1.0 }
+            thrust = { acceleration = 2.0, duration = 1.0, exhaust_velocity = 4.0 }
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_scenario(&scenario).unwrap();
+        let initial_momentum = conserved_momentum(&simulation);
+
+        for _ in 0..1500 {
+            simulation.step();
+        }
+
+        // dm/dt = -1 - m / 2 during the burn gives m(1) = 12 / sqrt(e) - 2, and the
+        // schedule alone takes off another half afterwards
+        let rocket = simulation.bodies[0];
+        let expected = 12.0 * (-0.5f32).exp() - 2.5;
+        assert!((rocket.mass - expected).abs() < 1e-2, "{}", rocket.mass);
+        assert!((simulation.expelled_mass - (10.0 - rocket.mass)).abs() < 1e-5);
+
+        let momentum = conserved_momentum(&simulation);
+        assert!((momentum.0 - initial_momentum.0).abs() < 1e-3);
+        assert!((momentum.1 - initial_momentum.1).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_slow_mass_loss_widens_orbit() {
+        // Under adiabatic mass loss a M stays constant
+        let scenario = Scenario::from_toml(
+            r#"
+            time_step = 0.002
+
+            [[bodies]]
+            name = "star"
+            mass = 1.0
+            position = [0.0, 0.0]
+            mass_schedule = { kind = "constant", rate = -0.005 }
+
+            [[bodies]]
+            mass = 0.001
+            orbit = { primary = "star", semi_major_axis = 1.0 }
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_scenario(&scenario).unwrap();
+        assert_eq!(simulation.mass_schedules.len(), 1);
+        let initial_momentum = conserved_momentum(&simulation);
+
+        for _ in 0..20000 {
+            simulation.step();
+        }
+
+        let [star, planet] = [simulation.bodies[0], simulation.bodies[1]];
+        assert!((star.mass - 0.8).abs() < 1e-3);
+        let elements = kepler::elements_of(&planet, &star, simulation.g_constant).unwrap();
+        let semi_major_axis = elements.periapsis / (1.0 - elements.eccentricity);
+        let expected = 1.001 / (star.mass + planet.mass) as f64;
+        assert!(
+            (semi_major_axis - expected).abs() < 1e-2 * expected,
+            "{semi_major_axis} vs {expected}"
+        );
+
+        let momentum = conserved_momentum(&simulation);
+        assert!((momentum.0 - initial_momentum.0).abs() < 1e-5);
+        assert!((momentum.1 - initial_momentum.1).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_rubble_pile_breaks_up_inside_roche_limit() {
+        // A rigid rubble pile is pulled apart inside 1.26 R (M / m)^(1/3) = 0.63
+        let disrupted = |periapsis: f64| {
+            let scenario = Scenario::from_toml(&format!(
+                r#"
+                time_step = 0.0005
+                eps2 = 0.0
+                contacts = {{ stiffness = 5.0, damping = 0.01 }}
+
+                [[bodies]]
+                name = "planet"
+                mass = 1.0
+                position = [0.0, 0.0]
+
+                [[bodies]]
+                name = "moon"
+                mass = 0.001
+                radius = 0.05
+                orbit = {{ primary = "planet", periapsis = {periapsis}, eccentricity = 1.0, true_anomaly = -2.5 }}
+                rubble_pile = {{ count = 19 }}
+                "#
+            ))
+            .unwrap();
+            let mut simulation = Simulation::from_scenario(&scenario).unwrap();
+            let moon = simulation.aggregates[0].clone();
+            assert_eq!(moon.largest_fragment_fraction(&simulation.bodies), 1.0);
+            let initial_momentum = simulation.total_linear_momentum();
+            for _ in 0..8000 {
+                simulation.step();
+            }
+            let momentum = simulation.total_linear_momentum();
+            assert!((momentum.0 - initial_momentum.0).abs() < 1e-4);
+            assert!((momentum.1 - initial_momentum.1).abs() < 1e-4);
+            moon.largest_fragment_fraction(&simulation.bodies)
+        };
+
+        let close = disrupted(0.2);
+        assert!(close < 0.8, "largest fragment {close}");
+        let distant = disrupted(2.0);
+        assert_eq!(distant, 1.0);
+    }
+
+    /// Square gas clump of `side` x `side` particles centred on `centre`.
+    fn gas_clump(
+        simulation: &mut Simulation,
+        side: usize,
+        centre: (f32, f32),
+        velocity: (f32, f32),
+        internal_energy: f32,
+    ) {
+        let spacing = 0.02;
+        let offset = 0.5 * (side - 1) as f32 * spacing;
+        for k in 0..side * side {
+            let x = centre.0 + (k % side) as f32 * spacing - offset;
+            let y = centre.1 + (k / side) as f32 * spacing - offset;
+            simulation.gas.push(GasParticle {
+                index: simulation.bodies.len(),
+                internal_energy,
+            });
+            simulation
+                .bodies
+                .push(Body::new(1e-3, (x, y), velocity).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_colliding_gas_clouds_heat_up_and_conserve() {
+        let mut simulation = Simulation::new(Vec::new(), 0.0, 0.0002, 0.0);
+        simulation.sph = Some(Sph::new(0.025));
+        gas_clump(&mut simulation, 8, (-0.1, 0.0), (2.0, 0.0), 0.1);
+        gas_clump(&mut simulation, 8, (0.1, 0.01), (-2.0, 0.0), 0.1);
+        let initial_energy = simulation.total_energy();
+        let initial_thermal = simulation.total_thermal_energy();
+
+        for _ in 0..500 {
+            simulation.step();
+        }
+
+        // The shock turns much of the kinetic energy into heat
+        assert!(simulation.total_thermal_energy() > initial_thermal + 0.25 * initial_energy);
+        let relative_error = ((simulation.total_energy() - initial_energy) / initial_energy).abs();
+        assert!(
+            relative_error < 2e-2,
+            "relative energy error {relative_error}"
+        );
+        let momentum = simulation.total_linear_momentum();
+        assert!(momentum.0.abs() < 1e-5 && momentum.1.abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_gas_and_stars_evolve_together() {
+        let scenario = Scenario::from_toml(
+            r#"
+            time_step = 0.0002
+            eps2 = 0.001
+            sph = { smoothing_length = 0.03 }
+
+            [[bodies]]
+            name = "star"
+            mass = 1.0
+            position = [0.0, 0.0]
+
+            [[bodies]]
+            mass = 0.05
+            orbit = { primary = "star", semi_major_axis = 0.5, eccentricity = 0.2 }
+
+            [[rings]]
+            primary = "star"
+            count = 200
+            inner_radius = 0.2
+            outer_radius = 0.35
+            mass = 1e-4
+            internal_energy = 0.01
+
+            [[rings]]
+            primary = "star"
+            count = 50
+            inner_radius = 0.2
+            outer_radius = 0.35
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_scenario(&scenario).unwrap();
+        assert_eq!(simulation.gas.len(), 200);
+        assert_eq!(simulation.gas[0].index, 2);
+        let initial_energy = simulation.total_energy();
+        let initial_momentum = simulation.total_linear_momentum();
+        let initial_thermal = simulation.total_thermal_energy();
+
+        for _ in 0..500 {
+            simulation.step();
+        }
+
+        assert!(simulation.total_thermal_energy() != initial_thermal);
+        let relative_error = ((simulation.total_energy() - initial_energy) / initial_energy).abs();
+        assert!(
+            relative_error < 1e-3,
+            "relative energy error {relative_error}"
+        );
+        let momentum = simulation.total_linear_momentum();
+        assert!((momentum.0 - initial_momentum.0).abs() < 1e-4);
+        assert!((momentum.1 - initial_momentum.1).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_gas_needs_sph_settings() {
+        let scenario = Scenario::from_toml(
+            r#"
+            [[bodies]]
+            mass = 1.0
+            position = [0.0, 0.0]
+            internal_energy = 1.0
+            "#,
+        )
+        .unwrap();
+        assert!(Simulation::from_scenario(&scenario).is_err());
+
+        let scenario = Scenario::from_toml(
+            r#"
+            sph = { smoothing_length = 0.0 }
+
+            [[bodies]]
+            mass = 1.0
+            position = [0.0, 0.0]
+            internal_energy = 1.0
+            "#,
+        )
+        .unwrap();
+        let error = Simulation::from_scenario(&scenario).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "the sph smoothing_length must be positive, got 0"
+        );
+    }
+
+    #[test]
+    fn test_rod_dumbbell_orbits_and_conserves() {
+        let scenario = Scenario::from_toml(
+            r#"
+            eps2 = 0.0
+            time_step = 0.001
+
+            [[bodies]]
+            name = "star"
+            mass = 1.0
+            position = [0.0, 0.0]
+
+            [[bodies]]
+            name = "inner"
+            mass = 0.001
+            orbit = { primary = "star", semi_major_axis = 0.9 }
+
+            [[bodies]]
+            name = "outer"
+            mass = 0.002
+            orbit = { primary = "star", semi_major_axis = 1.1, true_anomaly = 0.2 }
+
+            [[links]]
+            bodies = ["inner", "outer"]
+            kind = "rod"
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_scenario(&scenario).unwrap();
+        let length = simulation.links[0].length;
+        let initial_energy = simulation.total_energy();
+        let initial_angular_momentum = simulation.total_angular_momentum();
+        for _ in 0..5000 {
+            simulation.step();
+        }
+        let (inner, outer) = (simulation.bodies[1], simulation.bodies[2]);
+        assert!((inner.distance_to(&outer) - length).abs() < 1e-5 * length);
+        let energy_error = (simulation.total_energy() - initial_energy) / initial_energy;
+        assert!(energy_error.abs() < 1e-4, "energy error {energy_error}");
+        let angular_momentum_error = (simulation.total_angular_momentum()
+            - initial_angular_momentum)
+            / initial_angular_momentum;
+        assert!(angular_momentum_error.abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_links_go_with_absorbed_bodies() {
+        let scenario = Scenario::from_toml(
+            r#"
+            time_step = 0.001
+            boundary = { kind = "absorbing", size = 2.0 }
+
+            [[bodies]]
+            name = "anchor"
+            mass = 1.0
+            position = [0.0, 0.0]
+
+            [[bodies]]
+            name = "escaping"
+            mass = 0.1
+            position = [0.5, 0.0]
+            velocity = [20.0, 0.0]
+
+            [[bodies]]
+            name = "weight"
+            mass = 0.1
+            position = [0.0, 0.2]
+
+            [[links]]
+            bodies = ["anchor", "escaping"]
+            kind = "tether"
+            stiffness = 1.0
+            length = 1.0
+
+            [[links]]
+            bodies = ["anchor", "weight"]
+            kind = "spring"
+            stiffness = 1.0
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_scenario(&scenario).unwrap();
+        for _ in 0..100 {
+            simulation.step();
+        }
+        assert_eq!(simulation.bodies.len(), 2);
+        assert_eq!(simulation.links.len(), 1);
+        assert_eq!(simulation.links[0].bodies, (0, 1));
+    }
+
+    #[test]
+    fn test_trojans_spawned_at_lagrange_points_keep_up() {
+        let toml = |spawn: &str| {
+            format!(
+                r#"
+                time_step = 0.005
+                eps2 = 0.0
+                zero_momentum_frame = true
+
+                [[bodies]]
+                name = "sun"
+                mass = 1.0
+                position = [0.0, 0.0]
+
+                [[bodies]]
+                name = "jupiter"
+                mass = 0.001
+                orbit = {{ primary = "sun", semi_major_axis = 1.0 }}
+
+                [[lagrange_points]]
+                bodies = ["sun", "jupiter"]
+                spawn = {spawn}
+                "#
+            )
+        };
+        let scenario = Scenario::from_toml(&toml("[4, 5]")).unwrap();
+        let mut simulation = Simulation::from_scenario(&scenario).unwrap();
+        assert_eq!(simulation.bodies.len(), 4);
+        assert_eq!(simulation.lagrange_pairs, vec![(0, 1)]);
+        for _ in 0..2500 {
+            simulation.step();
+        }
+        // Two orbits on, the Trojans are still at the points leading and trailing Jupiter
+        let points =
+            lagrange::lagrange_points(&simulation.bodies[0], &simulation.bodies[1]).unwrap();
+        for (trojan, point) in simulation.bodies[2..].iter().zip(&points[3..]) {
+            let offset = (trojan.position.0 - point.0).hypot(trojan.position.1 - point.1);
+            assert!(offset < 0.02, "{offset}");
+        }
+        assert!(
+            Scenario::from_toml(&toml("[6]"))
+                .and_then(|s| s.build_bodies())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_regularized_binary_survives_coarse_steps() {
+        // A hard binary of period 0.0063 released at its pericentre 1e-3 apart, with
+        // e = 0.9, and a third body circling it; the time step is a sixth of the period
+        let toml = |regularization: &str| {
+            format!(
+                r#"
+                time_step = 0.001
+                eps2 = 0.0
+                zero_momentum_frame = true
+                {regularization}
+
+                [[bodies]]
+                mass = 0.5
+                position = [-0.0005, 0.0]
+                velocity = [0.0, -21.794]
+
+                [[bodies]]
+                mass = 0.5
+                position = [0.0005, 0.0]
+                velocity = [0.0, 21.794]
+
+                [[bodies]]
+                mass = 0.1
+                position = [0.5, 0.0]
+                velocity = [0.0, 1.483]
+                "#
+            )
+        };
+        let run = |regularization: &str| {
+            let scenario = Scenario::from_toml(&toml(regularization)).unwrap();
+            let mut simulation = Simulation::from_scenario(&scenario).unwrap();
+            let initial = simulation.total_energy();
+            for _ in 0..2000 {
+                simulation.step();
+            }
+            (simulation, initial)
+        };
+        let (simulation, initial) = run("regularization = { radius = 0.05 }");
+        assert_eq!(simulation.regularized_pairs, vec![(0, 1)]);
+        let error = ((simulation.total_energy() - initial) / initial).abs();
+        assert!(error < 1e-3, "{error}");
+        let (plain, initial) = run("");
+        assert!(((plain.total_energy() - initial) / initial).abs() > 1e-2);
+
+        let softened = Scenario::from_toml(
+            r#"
+            eps2 = 0.001
+            regularization = { radius = 0.05 }
+            bodies = []
+            "#,
+        )
+        .unwrap();
+        assert!(Simulation::from_scenario(&softened).is_err());
+    }
+
+    #[test]
+    fn test_detectors_time_apsides_and_approaches() {
+        // A comet on an e = 0.8 orbit of period 2 pi, at mean anomaly 3 just short of
+        // apoapsis, so it passes apoapsis at pi - 3 and periapsis at 2 pi - 3, and again
+        // an orbit later
+        let toml = |detectors: &str| {
+            format!(
+                r#"
+                time_step = 0.001
+                eps2 = 0.0
+
+                [[bodies]]
+                name = "sun"
+                mass = 1.0
+                position = [0.0, 0.0]
+
+                [[bodies]]
+                name = "comet"
+                orbit = {{ primary = "sun", semi_major_axis = 1.0, eccentricity = 0.8, mean_anomaly = 3.0 }}
+
+                {detectors}
+                "#
+            )
+        };
+        let scenario = Scenario::from_toml(&toml(
+            r#"
+            [[detectors]]
+            kind = "periapsis"
+            bodies = ["sun", "comet"]
+
+            [[detectors]]
+            kind = "apoapsis"
+            bodies = ["sun", "comet"]
+
+            [[detectors]]
+            kind = "close_approach"
+            distance = 0.5
+            "#,
+        ))
+        .unwrap();
+        let mut simulation = Simulation::from_scenario(&scenario).unwrap();
+        while simulation.time < 3.5 * PI {
+            simulation.step();
+        }
+        let times = |detector: usize| -> Vec<f64> {
+            simulation
+                .detections
+                .iter()
+                .filter(|d| d.detector == detector)
+                .map(|d| d.time)
+                .collect()
+        };
+        let (periapsides, apoapsides, approaches) = (times(0), times(1), times(2));
+        let periapsis = [TAU - 3.0, 2.0 * TAU - 3.0];
+        assert_eq!(periapsides.len(), 2, "{periapsides:?}");
+        assert_eq!(apoapsides.len(), 2, "{apoapsides:?}");
+        for (time, expected) in periapsides
+            .iter()
+            .chain(&apoapsides)
+            .zip(periapsis.into_iter().chain([PI - 3.0, PI + TAU - 3.0]))
+        {
+            // Well within the step, to the integrator's own phase error
+            assert!((time - expected).abs() < 3e-4, "{time} {expected}");
+        }
+        // r = 0.5 at eccentric anomaly cos E = 5 / 8, before each periapsis
+        let anomaly = (5.0f64 / 8.0).acos();
+        let before_periapsis = anomaly - 0.8 * anomaly.sin();
+        assert_eq!(approaches.len(), 2, "{approaches:?}");
+        for (time, periapsis) in approaches.iter().zip(periapsis) {
+            assert!(
+                (time - (periapsis - before_periapsis)).abs() < 3e-4,
+                "{time}"
+            );
+        }
+        assert!(simulation.detections.iter().all(|d| d.bodies == vec![0, 1]));
+        assert!(simulation.detections.is_sorted_by(|a, b| a.time <= b.time));
+
+        for detectors in [
+            "[[detectors]]\nkind = \"periapsis\"",
+            "[[detectors]]\nkind = \"escape\"\nbodies = [\"moon\"]",
+            "[[detectors]]\nkind = \"collision\"\nbodies = [\"sun\"]",
+            "[[fields]]\nkind = \"plummer_halo\"\nmass = 1.0\nscale_radius = 0.3\n\n\
+             [[detectors]]\nkind = \"escape\"",
+        ] {
+            let scenario = Scenario::from_toml(&toml(detectors)).unwrap();
+            assert!(Simulation::from_scenario(&scenario).is_err(), "{detectors}");
+        }
+    }
+
+    #[test]
+    fn test_comoving_free_particle_keeps_its_momentum() {
+        // Einstein-de Sitter: a lone body feels no force, so a v is constant and it
+        // drifts by a v times the integral of dt / a^2
+        let scenario = Scenario::from_toml(
+            r#"
+            time_step = 0.01
+            boundary = { kind = "periodic", size = 2.0 }
+            cosmology = { omega_m = 1.0, omega_lambda = 0.0, hubble = 2.0, initial_scale_factor = 0.1 }
+
+            [[bodies]]
+            mass = 1.0
+            position = [0.1, 0.2]
+            velocity = [0.3, 0.0]
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_scenario(&scenario).unwrap();
+        for _ in 0..30 {
+            simulation.step();
+        }
+        let (a0, h0) = (0.1f64, 2.0);
+        let a = (a0.powf(1.5) + 1.5 * h0 * simulation.time).powf(2.0 / 3.0);
+        assert!((simulation.scale_factor - a).abs() < 1e-6);
+        assert!((simulation.redshift() - (1.0 / a - 1.0)).abs() < 1e-5);
+
+        let body = simulation.bodies[0];
+        let momentum = 0.3 * a0;
+        assert!((body.velocity.0 as f64 - momentum / a).abs() < 1e-4);
+        let drift = 2.0 * (a0.powf(-0.5) - a.powf(-0.5)) / h0;
+        assert!((body.position.0 as f64 - (0.1 + momentum * drift)).abs() < 1e-4);
+        assert!((body.position.1 - 0.2).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_zeldovich_box_evolves_comoving() {
+        let scenario = Scenario::from_toml(
+            r#"
+            time_step = 0.005
+            eps2 = 0.0004
+            boundary = { kind = "periodic", size = 2.0 }
+            cosmology = { omega_m = 0.3, omega_lambda = 0.7, hubble = 1.0, initial_scale_factor = 0.2 }
+            zeldovich = { grid = 12, mass = 1.0, seed = 3, power_spectrum = { kind = "power_law", amplitude = 1e-4, index = 0.0 } }
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_scenario(&scenario).unwrap();
+        assert_eq!(simulation.bodies.len(), 144);
+        // Offset of each body from its lattice site
+        let spacing = 2.0 / 12.0;
+        let rms_offset = |simulation: &Simulation| {
+            let sum: f32 = simulation
+                .bodies
+                .iter()
+                .enumerate()
+                .map(|(k, b)| {
+                    let site = (
+                        ((k % 12) as f32 + 0.5) * spacing - 1.0,
+                        ((k / 12) as f32 + 0.5) * spacing - 1.0,
+                    );
+                    let dx = crate::boundary::wrap(b.position.0 - site.0, 2.0);
+                    let dy = crate::boundary::wrap(b.position.1 - site.1, 2.0);
+                    dx * dx + dy * dy
+                })
+                .sum();
+            (sum / 144.0).sqrt()
+        };
+        let initial = rms_offset(&simulation);
+        assert!(initial > 0.0 && initial < 0.2 * spacing);
+        for _ in 0..100 {
+            simulation.step();
+        }
+        // The displacements keep growing with the expansion, without net momentum
+        assert!(simulation.scale_factor > 0.3);
+        assert!(rms_offset(&simulation) > 1.2 * initial);
+        let (px, py) = simulation.bodies.iter().fold((0.0, 0.0), |(x, y), b| {
+            (x + b.mass * b.velocity.0, y + b.mass * b.velocity.1)
+        });
+        assert!(px.hypot(py) < 1e-4);
+    }
+
+    #[test]
+    fn test_cosmology_needs_periodic_box() {
+        let open = Scenario::from_toml(
+            r#"
+            cosmology = { omega_m = 1.0, omega_lambda = 0.0, hubble = 1.0, initial_scale_factor = 0.1 }
+            bodies = []
+            "#,
+        )
+        .unwrap();
+        assert!(Simulation::from_scenario(&open).is_err());
+        let without_cosmology = Scenario::from_toml(
+            r#"
+            boundary = { kind = "periodic", size = 2.0 }
+            zeldovich = { grid = 4, mass = 1.0, power_spectrum = { kind = "power_law", amplitude = 1e-4, index = 0.0 } }
+            "#,
+        )
+        .unwrap();
+        assert!(Simulation::from_scenario(&without_cosmology).is_err());
+    }
+
+    #[test]
+    fn test_restricted_three_body_conserves_jacobi_constant() {
+        let scenario = Scenario::from_toml(
+            r#"
+            time_step = 0.002
+            restricted_three_body = { primary_mass = 0.99, secondary_mass = 0.01, zero_velocity_curves = [3.1] }
+
+            [[bodies]]
+            position = [0.5, 0.85]
+
+            [[bodies]]
+            position = [0.3, 0.0]
+            velocity = [0.0, 1.48]
+            "#,
+        )
+        .unwrap();
+        let mut simulation = Simulation::from_scenario(&scenario).unwrap();
+        assert_eq!(simulation.fields.len(), 1);
+        let initial = simulation.jacobi_constants();
+        for _ in 0..5000 {
+            simulation.step();
+        }
+        for (jacobi, start) in simulation.jacobi_constants().iter().zip(&initial) {
+            assert!(
+                (jacobi - start).abs() < 1e-3 * start.abs(),
+                "{jacobi} {start}"
+            );
+        }
+        // A Trojan librates about the triangular point without reaching the primaries
+        let (x, y) = simulation.bodies[0].position;
+        assert!(y > 0.4 && x.hypot(y) > 0.7 && x.hypot(y) < 1.3, "{x} {y}");
+
+        let massive = Scenario::from_toml(
+            r#"
+            restricted_three_body = { primary_mass = 1.0, secondary_mass = 0.01 }
+
+            [[bodies]]
+            mass = 0.1
+            position = [0.5, 0.5]
+            "#,
+        )
+        .unwrap();
+        assert!(Simulation::from_scenario(&massive).is_err());
+    }
+
+    #[test]
+    fn test_other_force_laws_reject_gravity_solvers() {
+        let scenario = |force_law: &str, solver: &str| {
+            Scenario::from_toml(&format!(
+                "force_law = {force_law}\nsolver = {solver}\nbodies = []"
+            ))
+            .unwrap()
+        };
+        let yukawa = r#"{ kind = "yukawa", range = 0.5 }"#;
+        assert!(Simulation::from_scenario(&scenario(yukawa, r#"{ kind = "direct_sum" }"#)).is_ok());
+        for solver in [
+            r#"{ kind = "fmm", order = 4 }"#,
+            r#"{ kind = "simd" }"#,
+            r#"{ kind = "gpu" }"#,
+            r#"{ kind = "particle_mesh", grid = 32 }"#,
+        ] {
+            let error = Simulation::from_scenario(&scenario(yukawa, solver))
+                .err()
+                .unwrap();
+            assert!(
+                error.to_string().contains("summed over every pair"),
+                "{error}"
+            );
+        }
+        let error = Simulation::from_scenario(&scenario(
+            r#"{ kind = "yukawa", range = 0.0 }"#,
+            r#"{ kind = "direct_sum" }"#,
+        ))
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "the yukawa range must be positive, got 0"
+        );
+    }
+
+    #[test]
+    fn test_periodic_boundary_rejects_other_force_laws() {
+        let scenario = Scenario::from_toml(
+            r#"
+            force_law = { kind = "yukawa", range = 0.5 }
+            boundary = { kind = "periodic", size = 2.0 }
+            bodies = []
+            "#,
+        )
+        .unwrap();
+        assert!(Simulation::from_scenario(&scenario).is_err());
+    }
+
+    #[test]
+    fn test_periodic_boundary_runs_on_a_matching_periodic_mesh() {
+        let boundary = |solver: &str| {
+            Scenario::from_toml(&format!(
+                r#"
+                eps2 = 0.01
+                boundary = {{ kind = "periodic", size = 2.0 }}
+                solver = {solver}
+                bodies = []
+                "#
+            ))
+            .unwrap()
+        };
+        let mesh =
+            r#"{ kind = "particle_mesh", grid = 256, boundary = "periodic", box_size = 2.0 }"#;
+        let mut simulation = Simulation::from_scenario(&boundary(mesh)).unwrap();
+        simulation.bodies = crate::solver::tests::random_bodies(200, 41);
+        let accelerations = simulation.accelerations();
+        let energy = simulation.total_potential_energy();
+        simulation.solver = GravitySolver::DirectSum;
+        let ewald = simulation.accelerations();
+        let ewald_energy = simulation.total_potential_energy();
+
+        let error = crate::solver::tests::max_relative_error(&accelerations, &ewald);
+        assert!(error < 0.1, "{error}");
+        assert!(
+            ((energy - ewald_energy) / ewald_energy).abs() < 1e-2,
+            "{energy} {ewald_energy}"
+        );
+
+        for solver in [
+            r#"{ kind = "particle_mesh", grid = 64, boundary = "periodic", box_size = 3.0 }"#,
+            r#"{ kind = "particle_mesh", grid = 64 }"#,
+            r#"{ kind = "fmm" }"#,
+        ] {
+            let error = Simulation::from_scenario(&boundary(solver)).err().unwrap();
+            assert!(error.to_string().contains("periodic"), "{error}");
+        }
+        let scenario =
+            Scenario::from_toml("boundary = { kind = \"periodic\", size = 0.0 }\nbodies = []")
+                .unwrap();
+        let error = Simulation::from_scenario(&scenario).err().unwrap();
+        assert!(error.to_string().contains("must be positive"), "{error}");
+    }
+
+    #[test]
+    fn test_boundary_parameters_validated() {
+        for (boundary, message) in [
+            (
+                r#"{ kind = "reflecting", size = -1.0 }"#,
+                "the boundary box size must be positive, got -1",
+            ),
+            (
+                r#"{ kind = "absorbing", size = 0.0 }"#,
+                "the boundary box size must be positive, got 0",
+            ),
+            (
+                r#"{ kind = "escape", r_cut = -2.0 }"#,
+                "the escape radius r_cut can't be negative, got -2",
+            ),
+        ] {
+            let scenario =
+                Scenario::from_toml(&format!("boundary = {boundary}\nbodies = []")).unwrap();
+            let error = Simulation::from_scenario(&scenario).err().unwrap();
+            assert_eq!(error.to_string(), message);
+        }
+        let scenario =
+            Scenario::from_toml("boundary = { kind = \"escape\", r_cut = 0.0 }\nbodies = []")
+                .unwrap();
+        assert!(Simulation::from_scenario(&scenario).is_ok());
+    }
+}
@@ -0,0 +1,524 @@
+use std::cell::RefCell;
+
+use anyhow::{Result, bail};
+use serde::Deserialize;
+
+use crate::body::{Body, BodyStore};
+
+pub(crate) mod ewald;
+mod fmm;
+#[cfg(not(target_arch = "wasm32"))]
+mod parallel;
+pub(crate) mod particle_mesh;
+pub(crate) mod simd;
+
+pub use particle_mesh::MeshBoundary;
+
+thread_local! {
+    /// Structure-of-arrays copy of the bodies for the SIMD kernels, kept from one call
+    /// to the next so its arrays are only reallocated when the bodies outgrow them.
+    static SIMD_STORE: RefCell<BodyStore> = RefCell::default();
+}
+
+/// Runs `kernel` on the bodies copied into this thread's SIMD store.
+fn with_store<T>(bodies: &[Body], kernel: impl FnOnce(&BodyStore) -> T) -> T {
+    SIMD_STORE.with_borrow_mut(|store| {
+        store.fill(bodies);
+        kernel(store)
+    })
+}
+
+/// Algorithm used to evaluate the mutual gravity between bodies.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum GravitySolver {
+    /// Single-threaded O(N^2) pairwise sum.
+    #[default]
+    DirectSum,
+    /// Pairwise sum with rows of the interaction matrix spread over a thread pool.
+    /// `threads = 0` uses one thread per core. Falls back to the serial sum on wasm.
+    Parallel {
+        #[serde(default)]
+        threads: usize,
+    },
+    /// Pairwise sum over a structure-of-arrays copy of the bodies, each pair visited
+    /// once and streamed through fixed-width chunks that vectorize. When the bodies
+    /// feel nothing but each other, [`Simulation`](crate::Simulation) keeps them in
+    /// that form through the whole step instead of copying them for every kick.
+    Simd,
+    /// Tiled direct sum and velocity Verlet integration in a compute shader, with the
+    /// bodies kept resident on the GPU. Only the windowed app runs it there; a
+    /// headless [`Simulation`](crate::Simulation) falls back to the serial sum.
+    Gpu,
+    /// Particle-mesh solver: masses are deposited on a `grid` x `grid` mesh and the
+    /// potential found by FFT convolution. O(N + grid^2 log grid), but forces are
+    /// smoothed over a couple of cells. `box_size` is the side of the periodic box
+    /// and is ignored for isolated boundaries, where the mesh follows the bodies.
+    ParticleMesh {
+        #[serde(default = "default_mesh_grid")]
+        grid: usize,
+        #[serde(default)]
+        boundary: MeshBoundary,
+        #[serde(default = "default_box_size")]
+        box_size: f32,
+    },
+    /// Fast multipole method on a uniform quadtree, deep enough that even clustered
+    /// bodies share leaves with only a few dozen others. `order` is the truncation order of
+    /// the expansions: the far-field error falls geometrically with it, at a cost
+    /// that grows as `order^3` per box pair.
+    Fmm {
+        #[serde(default = "default_fmm_order")]
+        order: usize,
+    },
+}
+
+fn default_mesh_grid() -> usize {
+    128
+}
+
+fn default_box_size() -> f32 {
+    2.0
+}
+
+fn default_fmm_order() -> usize {
+    10
+}
+
+impl GravitySolver {
+    pub fn validate(&self) -> Result<()> {
+        match *self {
+            GravitySolver::ParticleMesh { grid, box_size, .. } => {
+                if grid < 2 {
+                    bail!("the particle-mesh grid needs at least 2 nodes a side, got {grid}");
+                }
+                if box_size.is_nan() || box_size <= 0.0 {
+                    bail!("the particle-mesh box_size must be positive, got {box_size}");
+                }
+            }
+            GravitySolver::Fmm { order } if order < 1 => {
+                bail!("the multipole order must be at least 1");
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Side of the box a periodic particle mesh repeats, for other solvers `None`.
+    pub fn periodic_box(&self) -> Option<f32> {
+        match *self {
+            GravitySolver::ParticleMesh {
+                boundary: MeshBoundary::Periodic,
+                box_size,
+                ..
+            } => Some(box_size),
+            _ => None,
+        }
+    }
+
+    /// Acceleration of every body due to the softened gravity of all the others.
+    pub fn accelerations(&self, bodies: &[Body], g_constant: f32, eps2: f32) -> Vec<(f32, f32)> {
+        match self {
+            GravitySolver::DirectSum | GravitySolver::Gpu => direct_sum(bodies, g_constant, eps2),
+            #[cfg(not(target_arch = "wasm32"))]
+            GravitySolver::Parallel { threads } => {
+                parallel::direct_sum(bodies, g_constant, eps2, *threads)
+            }
+            #[cfg(target_arch = "wasm32")]
+            GravitySolver::Parallel { .. } => direct_sum(bodies, g_constant, eps2),
+            GravitySolver::Simd => {
+                with_store(bodies, |store| simd::accelerations(store, g_constant, eps2))
+            }
+            GravitySolver::ParticleMesh {
+                grid,
+                boundary,
+                box_size,
+            } => {
+                particle_mesh::accelerations(bodies, g_constant, eps2, *grid, *boundary, *box_size)
+            }
+            GravitySolver::Fmm { order } => fmm::accelerations(bodies, g_constant, eps2, *order),
+        }
+    }
+
+    /// Softened pairwise potential energy of the system.
+    pub fn potential_energy(&self, bodies: &[Body], g_constant: f32, eps2: f32) -> f32 {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            GravitySolver::Parallel { threads } => {
+                parallel::potential_energy(bodies, g_constant, eps2, *threads)
+            }
+            GravitySolver::Simd => with_store(bodies, |store| {
+                simd::potential_energy(store, g_constant, eps2)
+            }),
+            GravitySolver::ParticleMesh {
+                grid,
+                boundary,
+                box_size,
+            } => particle_mesh::potential_energy(
+                bodies, g_constant, eps2, *grid, *boundary, *box_size,
+            ),
+            GravitySolver::Fmm { order } => fmm::potential_energy(bodies, g_constant, eps2, *order),
+            _ => direct_potential_energy(bodies, g_constant, eps2),
+        }
+    }
+}
+
+/// Adds the interactions of body `i` with every later body to `accelerations`, using
+/// Newton's third law for the `j` side.
+fn accumulate_row(
+    bodies: &[Body],
+    i: usize,
+    g_constant: f32,
+    eps2: f32,
+    accelerations: &mut [(f32, f32)],
+) {
+    for j in (i + 1)..bodies.len() {
+        let f = bodies[i].gravitational_force_softened(&bodies[j], g_constant, eps2);
+        accelerations[i].0 += f.0 / bodies[i].mass;
+        accelerations[i].1 += f.1 / bodies[i].mass;
+        accelerations[j].0 -= f.0 / bodies[j].mass;
+        accelerations[j].1 -= f.1 / bodies[j].mass;
+    }
+}
+
+/// Potential energy of body `i` with every later body.
+fn row_potential_energy(bodies: &[Body], i: usize, g_constant: f32, eps2: f32) -> f32 {
+    let mut potential_energy = 0.0;
+    for j in (i + 1)..bodies.len() {
+        let distance = bodies[i].distance_to(&bodies[j]);
+        // U = -G * m1 * m2 / sqrt(r^2 + eps^2)
+        potential_energy -=
+            g_constant * bodies[i].mass * bodies[j].mass / (distance * distance + eps2).sqrt();
+    }
+    potential_energy
+}
+
+pub fn direct_sum(bodies: &[Body], g_constant: f32, eps2: f32) -> Vec<(f32, f32)> {
+    let mut accelerations = vec![(0.0, 0.0); bodies.len()];
+    for i in 0..bodies.len() {
+        accumulate_row(bodies, i, g_constant, eps2, &mut accelerations);
+    }
+    accelerations
+}
+
+/// Accelerations of massless `receivers` due to the softened gravity of `sources`,
+/// at O(N_sources x N_receivers) cost.
+pub fn test_particle_accelerations(
+    sources: &[Body],
+    receivers: &[Body],
+    g_constant: f32,
+    eps2: f32,
+) -> Vec<(f32, f32)> {
+    receivers
+        .iter()
+        .map(|receiver| {
+            sources.iter().fold((0.0, 0.0), |(ax, ay), source| {
+                let dx = source.position.0 - receiver.position.0;
+                let dy = source.position.1 - receiver.position.1;
+                let r2 = dx * dx + dy * dy + eps2;
+                if r2 == 0.0 {
+                    return (ax, ay);
+                }
+                let inv_r = r2.sqrt().recip();
+                let a = g_constant * source.mass * inv_r * inv_r * inv_r;
+                (ax + a * dx, ay + a * dy)
+            })
+        })
+        .collect()
+}
+
+pub fn direct_potential_energy(bodies: &[Body], g_constant: f32, eps2: f32) -> f32 {
+    (0..bodies.len())
+        .map(|i| row_potential_energy(bodies, i, g_constant, eps2))
+        .sum()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Deterministic pseudo-random cluster of bodies for comparing solvers.
+    pub(crate) fn random_bodies(n: usize, seed: u64) -> Vec<Body> {
+        let mut state = seed;
+        let mut next = || {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            ((state >> 40) as f32) / (1u64 << 24) as f32
+        };
+        (0..n)
+            .map(|_| {
+                let mass = 0.5 + next();
+                let position = (2.0 * next() - 1.0, 2.0 * next() - 1.0);
+                let velocity = (next() - 0.5, next() - 0.5);
+                Body::new(mass, position, velocity).unwrap()
+            })
+            .collect()
+    }
+
+    /// Largest acceleration error relative to the largest reference acceleration.
+    pub(crate) fn max_relative_error(actual: &[(f32, f32)], expected: &[(f32, f32)]) -> f32 {
+        let scale = expected.iter().map(|a| a.0.hypot(a.1)).fold(0.0, f32::max);
+        actual
+            .iter()
+            .zip(expected)
+            .map(|(a, e)| (a.0 - e.0).hypot(a.1 - e.1) / scale)
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn test_test_particles_match_light_bodies() {
+        let sources = random_bodies(20, 3);
+        let mut receivers = random_bodies(10, 4);
+        let accelerations = test_particle_accelerations(&sources, &receivers, 1.5, 0.01);
+
+        // A tracer moves like a body too light to disturb the others
+        for (k, receiver) in receivers.iter_mut().enumerate() {
+            receiver.mass = 1e-6;
+            let mut bodies = sources.clone();
+            bodies.push(*receiver);
+            let expected = direct_sum(&bodies, 1.5, 0.01)[sources.len()];
+            let a = accelerations[k];
+            assert!(
+                (a.0 - expected.0).hypot(a.1 - expected.1) < 1e-4 * expected.0.hypot(expected.1)
+            );
+        }
+    }
+
+    #[test]
+    fn test_direct_sum_matches_pairwise_forces() {
+        let bodies = random_bodies(5, 1);
+        let accelerations = direct_sum(&bodies, 2.0, 0.0);
+
+        for (i, body) in bodies.iter().enumerate() {
+            let mut expected = (0.0, 0.0);
+            for (j, other) in bodies.iter().enumerate() {
+                if i != j {
+                    let f = body.gravitational_force(other, 2.0);
+                    expected.0 += f.0 / body.mass;
+                    expected.1 += f.1 / body.mass;
+                }
+            }
+            assert!((accelerations[i].0 - expected.0).abs() < 1e-3 * expected.0.abs().max(1.0));
+            assert!((accelerations[i].1 - expected.1).abs() < 1e-3 * expected.1.abs().max(1.0));
+        }
+    }
+
+    #[test]
+    fn test_parallel_matches_direct_sum() {
+        let bodies = random_bodies(300, 7);
+        let expected = direct_sum(&bodies, 1.0, 0.01);
+        let expected_energy = direct_potential_energy(&bodies, 1.0, 0.01);
+
+        for threads in [0, 1, 3] {
+            let solver = GravitySolver::Parallel { threads };
+            let accelerations = solver.accelerations(&bodies, 1.0, 0.01);
+            let energy = solver.potential_energy(&bodies, 1.0, 0.01);
+
+            assert!(max_relative_error(&accelerations, &expected) < 1e-5);
+            assert!(((energy - expected_energy) / expected_energy).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_simd_matches_direct_sum() {
+        // Deliberately not a multiple of the lane width so the partial last chunk is exercised
+        let bodies = random_bodies(203, 11);
+        for eps2 in [0.0, 0.01] {
+            let expected = direct_sum(&bodies, 1.5, eps2);
+            let expected_energy = direct_potential_energy(&bodies, 1.5, eps2);
+            let accelerations = GravitySolver::Simd.accelerations(&bodies, 1.5, eps2);
+            let energy = GravitySolver::Simd.potential_energy(&bodies, 1.5, eps2);
+
+            assert!(max_relative_error(&accelerations, &expected) < 1e-4);
+            assert!(((energy - expected_energy) / expected_energy).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_simd_handles_single_body() {
+        let bodies = random_bodies(1, 3);
+        assert_eq!(
+            GravitySolver::Simd.accelerations(&bodies, 1.0, 0.0),
+            vec![(0.0, 0.0)]
+        );
+        assert_eq!(
+            GravitySolver::Simd.potential_energy(&bodies, 1.0, 0.01),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_particle_mesh_matches_direct_sum() {
+        let bodies = random_bodies(200, 13);
+        let expected = direct_sum(&bodies, 1.0, 0.01);
+        let expected_energy = direct_potential_energy(&bodies, 1.0, 0.01);
+
+        // Forces converge once the softening spans a few cells
+        for (grid, tolerance) in [(64, 0.1), (256, 0.01)] {
+            let solver = GravitySolver::ParticleMesh {
+                grid,
+                boundary: MeshBoundary::Isolated,
+                box_size: 2.0,
+            };
+            let accelerations = solver.accelerations(&bodies, 1.0, 0.01);
+            let energy = solver.potential_energy(&bodies, 1.0, 0.01);
+
+            assert!(max_relative_error(&accelerations, &expected) < tolerance);
+            assert!(((energy - expected_energy) / expected_energy).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn test_particle_mesh_handles_coincident_bodies() {
+        // The bodies span no distance, so the mesh is sized by the softening length
+        let bodies = vec![Body::new(1.0, (0.3, 0.3), (0.0, 0.0)).unwrap(); 2];
+        let solver = GravitySolver::ParticleMesh {
+            grid: 64,
+            boundary: MeshBoundary::Isolated,
+            box_size: 2.0,
+        };
+        let accelerations = solver.accelerations(&bodies, 1.0, 0.01);
+        assert!(
+            accelerations
+                .iter()
+                .all(|a| a.0.abs() < 1e-3 && a.1.abs() < 1e-3),
+            "{accelerations:?}"
+        );
+        let energy = solver.potential_energy(&bodies, 1.0, 0.01);
+        let expected = direct_potential_energy(&bodies, 1.0, 0.01);
+        assert!((energy - expected).abs() < 0.1 * expected.abs(), "{energy}");
+    }
+
+    #[test]
+    fn test_particle_mesh_settings_validated() {
+        let mesh = |grid, box_size| GravitySolver::ParticleMesh {
+            grid,
+            boundary: MeshBoundary::Periodic,
+            box_size,
+        };
+        assert!(mesh(64, 2.0).validate().is_ok());
+        assert!(mesh(1, 2.0).validate().is_err());
+        assert!(mesh(64, 0.0).validate().is_err());
+        assert!(mesh(64, -1.0).validate().is_err());
+    }
+
+    #[test]
+    fn test_periodic_mesh_close_pair() {
+        // Well inside the box, the nearest images dominate and Newton's third law holds
+        let bodies = vec![
+            Body::new(1.0, (0.03, 0.1), (0.0, 0.0)).unwrap(),
+            Body::new(2.0, (-0.07, 0.12), (0.0, 0.0)).unwrap(),
+        ];
+        let solver = GravitySolver::ParticleMesh {
+            grid: 256,
+            boundary: MeshBoundary::Periodic,
+            box_size: 2.0,
+        };
+        let accelerations = solver.accelerations(&bodies, 1.0, 0.0025);
+        let expected = direct_sum(&bodies, 1.0, 0.0025);
+
+        assert!(max_relative_error(&accelerations, &expected) < 0.02);
+        let (a, b) = (accelerations[0], accelerations[1]);
+        assert!((a.0 + 2.0 * b.0).abs() < 1e-3 * a.0.abs());
+        assert!((a.1 + 2.0 * b.1).abs() < 1e-3 * a.0.abs());
+    }
+
+    #[test]
+    fn test_periodic_mesh_single_body_feels_no_force() {
+        let bodies = vec![Body::new(1.0, (0.33, -0.71), (0.0, 0.0)).unwrap()];
+        let solver = GravitySolver::ParticleMesh {
+            grid: 64,
+            boundary: MeshBoundary::Periodic,
+            box_size: 2.0,
+        };
+        let a = solver.accelerations(&bodies, 1.0, 0.0)[0];
+        assert!(a.0.hypot(a.1) < 1e-3, "{a:?}");
+    }
+
+    #[test]
+    fn test_fmm_matches_pairwise_forces() {
+        let bodies = random_bodies(1500, 17);
+        let expected: Vec<(f32, f32)> = bodies
+            .iter()
+            .enumerate()
+            .map(|(i, body)| {
+                bodies.iter().enumerate().filter(|&(j, _)| j != i).fold(
+                    (0.0, 0.0),
+                    |a, (_, other)| {
+                        let f = body.gravitational_force(other, 1.0);
+                        (a.0 + f.0 / body.mass, a.1 + f.1 / body.mass)
+                    },
+                )
+            })
+            .collect();
+
+        // The error falls with the expansion order until it reaches f32 round-off
+        let errors: Vec<f32> = [2, 4, 8]
+            .iter()
+            .map(|&order| {
+                let accelerations = GravitySolver::Fmm { order }.accelerations(&bodies, 1.0, 0.0);
+                max_relative_error(&accelerations, &expected)
+            })
+            .collect();
+        assert!(errors[0] < 1e-3, "{errors:?}");
+        assert!(errors[1] < errors[0] && errors[2] < errors[1], "{errors:?}");
+        assert!(errors[2] < 5e-6, "{errors:?}");
+    }
+
+    #[test]
+    fn test_fmm_matches_softened_direct_sum() {
+        let bodies = random_bodies(1500, 19);
+        let expected = direct_sum(&bodies, 1.5, 0.004);
+        let expected_energy = direct_potential_energy(&bodies, 1.5, 0.004);
+
+        for (order, tolerance) in [(4, 1e-2), (12, 1e-5)] {
+            let solver = GravitySolver::Fmm { order };
+            let accelerations = solver.accelerations(&bodies, 1.5, 0.004);
+            let energy = solver.potential_energy(&bodies, 1.5, 0.004);
+
+            assert!(max_relative_error(&accelerations, &expected) < tolerance);
+            assert!(((energy - expected_energy) / expected_energy).abs() < 1e-4);
+        }
+    }
+
+    /// Bodies from `random_bodies`, all but the first `halo` squeezed into a small
+    /// square about `(0.3, -0.2)`.
+    pub(crate) fn clustered_bodies(n: usize, halo: usize, seed: u64) -> Vec<Body> {
+        let mut bodies = random_bodies(n, seed);
+        for body in &mut bodies[halo..] {
+            body.position = (
+                0.3 + 0.025 * body.position.0,
+                -0.2 + 0.025 * body.position.1,
+            );
+        }
+        bodies
+    }
+
+    #[test]
+    fn test_fmm_matches_pairwise_forces_when_clustered() {
+        let bodies = clustered_bodies(1500, 100, 29);
+        let expected = direct_sum(&bodies, 1.0, 0.0);
+        let expected_energy = direct_potential_energy(&bodies, 1.0, 0.0);
+
+        let solver = GravitySolver::Fmm { order: 8 };
+        let accelerations = solver.accelerations(&bodies, 1.0, 0.0);
+        let energy = solver.potential_energy(&bodies, 1.0, 0.0);
+
+        assert!(max_relative_error(&accelerations, &expected) < 1e-4);
+        assert!(((energy - expected_energy) / expected_energy).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_fmm_order_validated() {
+        assert!(GravitySolver::Fmm { order: 1 }.validate().is_ok());
+        assert!(GravitySolver::Fmm { order: 0 }.validate().is_err());
+    }
+
+    #[test]
+    fn test_fmm_small_systems_use_near_field() {
+        // Too few bodies for a tree with far boxes, so every pair is summed directly
+        let bodies = random_bodies(40, 23);
+        let accelerations = GravitySolver::Fmm { order: 2 }.accelerations(&bodies, 1.0, 0.01);
+        assert!(max_relative_error(&accelerations, &direct_sum(&bodies, 1.0, 0.01)) < 1e-5);
+    }
+}
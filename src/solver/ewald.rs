@@ -0,0 +1,322 @@
+use std::f64::consts::PI;
+
+use crate::body::Body;
+use crate::boundary::wrap;
+
+/// Splitting parameter in units of `1 / size`. Real-space images beyond the nearest
+/// nine are at least `1.5 size` away, where `erfc(3.75) ~ 1e-7`.
+const ALPHA: f64 = 2.5;
+
+/// Reciprocal lattice vectors are summed over `[-K_MAX, K_MAX]^2`; the largest
+/// damping factor left out is `erfc(2 pi (K_MAX + 1) / (2 ALPHA))`, about `1e-8`.
+const K_MAX: i32 = 5;
+
+/// Complementary error function, with a fractional error below `1.2e-7`
+/// (Numerical Recipes' Chebyshev fit).
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let poly = -z * z - 1.265_512_23
+        + t * (1.000_023_68
+            + t * (0.374_091_96
+                + t * (0.096_784_18
+                    + t * (-0.186_288_06
+                        + t * (0.278_868_07
+                            + t * (-1.135_203_98
+                                + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77))))))));
+    let r = t * poly.exp();
+    if x >= 0.0 { r } else { 2.0 - r }
+}
+
+/// Integral of `1 / sqrt(r^2 + eps2) - 1 / r` over the periodic cell, which is
+/// close to `-2 pi eps` for softening much smaller than the box.
+fn softening_integral(eps2: f64, length: f64) -> f64 {
+    if eps2 == 0.0 {
+        return 0.0;
+    }
+    // In polar coordinates the radial integral is exact, out to the cell edge
+    // `R = L / (2 cos theta)`; the eight symmetric wedges are then summed by Simpson's
+    // rule in theta
+    let eps = eps2.sqrt();
+    let radial = |theta: f64| {
+        let r = 0.5 * length / theta.cos();
+        (r * r + eps2).sqrt() - eps - r
+    };
+    let steps = 64;
+    let h = 0.25 * PI / steps as f64;
+    let sum: f64 = (0..=steps)
+        .map(|i| {
+            let weight = if i == 0 || i == steps {
+                1.0
+            } else if i % 2 == 1 {
+                4.0
+            } else {
+                2.0
+            };
+            weight * radial(i as f64 * h)
+        })
+        .sum();
+    8.0 * sum * h / 3.0
+}
+
+/// Nearest periodic image of `x_j - x_i`.
+fn minimum_image(from: &Body, to: &Body, size: f32) -> (f32, f32) {
+    (
+        wrap(to.position.0 - from.position.0, size),
+        wrap(to.position.1 - from.position.1, size),
+    )
+}
+
+/// Per-body `sum m_j / r` over every periodic image of every body (less a uniform
+/// background of the same total mass), and its gradient.
+///
+/// The kernel is split as `erfc(a r) / r + erf(a r) / r`. The first part is summed
+/// over nearby images in real space; the second is smooth, and is summed over
+/// reciprocal lattice vectors using its 2D transform `2 pi erfc(k / 2a) / k`. The
+/// `k = 0` term is dropped, which is what the neutralising background amounts to.
+/// Softening is applied to the nearest image only, where it matters, so the result
+/// agrees with the periodic particle-mesh solver.
+fn ewald(bodies: &[Body], eps2: f32, size: f32, alpha: f64) -> Vec<(f64, (f64, f64))> {
+    let length = size as f64;
+    let area = length * length;
+    let alpha = alpha / length;
+    let eps2 = eps2 as f64;
+    let total_mass: f64 = bodies.iter().map(|b| b.mass as f64).sum();
+    let two_alpha_over_sqrt_pi = 2.0 * alpha / PI.sqrt();
+
+    // Self-image and background terms are the same for every body
+    let self_images: f64 = (-1..=1)
+        .flat_map(|nx| (-1..=1).map(move |ny| (nx, ny)))
+        .filter(|&n| n != (0, 0))
+        .map(|(nx, ny)| {
+            let r = (nx as f64).hypot(ny as f64) * length;
+            erfc(alpha * r) / r
+        })
+        .sum();
+    // The background matches the mean of the kernel actually summed, so it includes
+    // the softening applied to the nearest image
+    let background =
+        (2.0 * PI.sqrt() / alpha + softening_integral(eps2, length)) / area * total_mass;
+
+    let mut fields: Vec<(f64, (f64, f64))> = bodies
+        .iter()
+        .map(|body| {
+            let m = body.mass as f64;
+            let mut potential = m * (self_images - two_alpha_over_sqrt_pi) - background;
+            let mut gradient = (0.0, 0.0);
+            for other in bodies {
+                if std::ptr::eq(body, other) {
+                    continue;
+                }
+                let mj = other.mass as f64;
+                let d0 = minimum_image(body, other, size);
+                for nx in -1..=1 {
+                    for ny in -1..=1 {
+                        let dx = d0.0 as f64 + nx as f64 * length;
+                        let dy = d0.1 as f64 + ny as f64 * length;
+                        let r2 = dx * dx + dy * dy;
+                        let nearest = nx == 0 && ny == 0;
+                        if r2 == 0.0 {
+                            // Coincident bodies: only the softened kernel less the
+                            // smooth part survives, and it has no gradient
+                            if eps2 > 0.0 {
+                                potential += mj * (1.0 / eps2.sqrt() - two_alpha_over_sqrt_pi);
+                            }
+                            continue;
+                        }
+                        let r = r2.sqrt();
+                        let short = erfc(alpha * r) / r;
+                        let mut phi = short;
+                        let mut force =
+                            (short + two_alpha_over_sqrt_pi * (-alpha * alpha * r2).exp()) / r2;
+                        if nearest {
+                            let soft2 = r2 + eps2;
+                            phi += 1.0 / soft2.sqrt() - 1.0 / r;
+                            force += 1.0 / (soft2 * soft2.sqrt()) - 1.0 / (r2 * r);
+                        }
+                        potential += mj * phi;
+                        gradient.0 += mj * force * dx;
+                        gradient.1 += mj * force * dy;
+                    }
+                }
+            }
+            (potential, gradient)
+        })
+        .collect();
+
+    let k_unit = 2.0 * PI / length;
+    for kx in -K_MAX..=K_MAX {
+        for ky in -K_MAX..=K_MAX {
+            if (kx, ky) == (0, 0) {
+                continue;
+            }
+            let k = (kx as f64 * k_unit, ky as f64 * k_unit);
+            let k_norm = k.0.hypot(k.1);
+            let weight = 2.0 * PI / k_norm * erfc(k_norm / (2.0 * alpha)) / area;
+            let phase = |b: &Body| k.0 * b.position.0 as f64 + k.1 * b.position.1 as f64;
+
+            // Structure factor S(k) = sum m_j exp(-i k.x_j)
+            let (s_re, s_im) = bodies.iter().fold((0.0, 0.0), |(re, im), b| {
+                let (sin, cos) = phase(b).sin_cos();
+                (re + b.mass as f64 * cos, im - b.mass as f64 * sin)
+            });
+            for ((potential, gradient), body) in fields.iter_mut().zip(bodies) {
+                // c = S(k) exp(i k.x_i)
+                let (sin, cos) = phase(body).sin_cos();
+                let c_re = s_re * cos - s_im * sin;
+                let c_im = s_re * sin + s_im * cos;
+                *potential += weight * c_re;
+                gradient.0 -= weight * k.0 * c_im;
+                gradient.1 -= weight * k.1 * c_im;
+            }
+        }
+    }
+    fields
+}
+
+pub fn accelerations(bodies: &[Body], g_constant: f32, eps2: f32, size: f32) -> Vec<(f32, f32)> {
+    ewald(bodies, eps2, size, ALPHA)
+        .into_iter()
+        .map(|(_, gradient)| {
+            let g = g_constant as f64;
+            ((g * gradient.0) as f32, (g * gradient.1) as f32)
+        })
+        .collect()
+}
+
+/// Potential energy per periodic cell, relative to the neutralising background.
+pub fn potential_energy(bodies: &[Body], g_constant: f32, eps2: f32, size: f32) -> f32 {
+    let total: f64 = ewald(bodies, eps2, size, ALPHA)
+        .iter()
+        .zip(bodies)
+        .map(|((potential, _), body)| body.mass as f64 * potential)
+        .sum();
+    (-0.5 * g_constant as f64 * total) as f32
+}
+
+/// Softened pairwise sum over the nearest image of each other body.
+pub fn minimum_image_accelerations(
+    bodies: &[Body],
+    g_constant: f32,
+    eps2: f32,
+    size: f32,
+) -> Vec<(f32, f32)> {
+    bodies
+        .iter()
+        .enumerate()
+        .map(|(i, body)| {
+            bodies
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| j != i)
+                .fold((0.0, 0.0), |a, (_, other)| {
+                    let d = minimum_image(body, other, size);
+                    let r2 = d.0 * d.0 + d.1 * d.1 + eps2;
+                    if r2 == 0.0 {
+                        return a;
+                    }
+                    let s = g_constant * other.mass / (r2 * r2.sqrt());
+                    (a.0 + s * d.0, a.1 + s * d.1)
+                })
+        })
+        .collect()
+}
+
+pub fn minimum_image_potential_energy(
+    bodies: &[Body],
+    g_constant: f32,
+    eps2: f32,
+    size: f32,
+) -> f32 {
+    let mut potential_energy = 0.0;
+    for (i, body) in bodies.iter().enumerate() {
+        for other in &bodies[i + 1..] {
+            let d = minimum_image(body, other, size);
+            let r2 = d.0 * d.0 + d.1 * d.1 + eps2;
+            if r2 > 0.0 {
+                potential_energy -= g_constant * body.mass * other.mass / r2.sqrt();
+            }
+        }
+    }
+    potential_energy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::tests::{max_relative_error, random_bodies};
+    use crate::solver::{GravitySolver, MeshBoundary, direct_sum};
+
+    #[test]
+    fn test_erfc() {
+        assert!((erfc(0.0) - 1.0).abs() < 1e-7);
+        assert!((erfc(0.5) - 0.479_500_122_186_953_5).abs() < 1e-7);
+        assert!((erfc(-1.0) - 1.842_700_792_949_715).abs() < 1e-7);
+        assert!((erfc(3.0) - 2.209_049_699_858_544e-5).abs() < 1e-11);
+    }
+
+    #[test]
+    fn test_ewald_is_independent_of_splitting() {
+        // The split between real and reciprocal space is arbitrary, so moving it must
+        // leave both the potential and the forces unchanged
+        let bodies = random_bodies(30, 29);
+        let a = ewald(&bodies, 0.004, 2.0, ALPHA);
+        let b = ewald(&bodies, 0.004, 2.0, 3.5);
+        for ((pa, ga), (pb, gb)) in a.iter().zip(&b) {
+            assert!((pa - pb).abs() < 1e-5 * pa.abs(), "{pa} {pb}");
+            assert!((ga.0 - gb.0).hypot(ga.1 - gb.1) < 1e-6 * ga.0.hypot(ga.1).max(1.0));
+        }
+    }
+
+    #[test]
+    fn test_ewald_lone_body_feels_no_force() {
+        let bodies = vec![Body::new(2.0, (0.4, -0.3), (0.0, 0.0)).unwrap()];
+        let a = accelerations(&bodies, 1.0, 0.0, 2.0)[0];
+        assert!(a.0.hypot(a.1) < 1e-6, "{a:?}");
+    }
+
+    #[test]
+    fn test_ewald_close_pair_matches_direct_sum() {
+        let bodies = vec![
+            Body::new(1.0, (0.03, 0.1), (0.0, 0.0)).unwrap(),
+            Body::new(2.0, (-0.07, 0.12), (0.0, 0.0)).unwrap(),
+        ];
+        let accelerations = accelerations(&bodies, 1.0, 0.0025, 2.0);
+        assert!(max_relative_error(&accelerations, &direct_sum(&bodies, 1.0, 0.0025)) < 1e-2);
+    }
+
+    #[test]
+    fn test_ewald_matches_periodic_particle_mesh() {
+        let bodies = random_bodies(20, 31);
+        let expected = GravitySolver::ParticleMesh {
+            grid: 256,
+            boundary: MeshBoundary::Periodic,
+            box_size: 2.0,
+        };
+        let accelerations = accelerations(&bodies, 1.0, 0.01, 2.0);
+        let energy = potential_energy(&bodies, 1.0, 0.01, 2.0);
+        let expected_energy = expected.potential_energy(&bodies, 1.0, 0.01);
+
+        assert!(
+            max_relative_error(&accelerations, &expected.accelerations(&bodies, 1.0, 0.01)) < 1e-2
+        );
+        assert!(
+            ((energy - expected_energy) / expected_energy).abs() < 1e-2,
+            "{energy} {expected_energy}"
+        );
+    }
+
+    #[test]
+    fn test_minimum_image_attracts_across_the_boundary() {
+        let bodies = vec![
+            Body::new(1.0, (0.95, 0.0), (0.0, 0.0)).unwrap(),
+            Body::new(1.0, (-0.95, 0.0), (0.0, 0.0)).unwrap(),
+        ];
+        let a = minimum_image_accelerations(&bodies, 1.0, 0.0, 2.0);
+
+        // 0.1 apart through the edge, so the right body is pulled further right
+        assert!((a[0].0 - 100.0).abs() < 1e-2, "{a:?}");
+        assert!((a[1].0 + 100.0).abs() < 1e-2, "{a:?}");
+        assert!((minimum_image_potential_energy(&bodies, 1.0, 0.0, 2.0) + 10.0).abs() < 1e-3);
+    }
+}
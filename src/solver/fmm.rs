@@ -0,0 +1,496 @@
+use rustfft::num_complex::Complex;
+
+use crate::body::Body;
+
+type C64 = Complex<f64>;
+
+/// Target number of bodies per leaf box.
+const LEAF_SIZE: usize = 32;
+
+/// Leaf occupancy past which the tree deepens regardless of the body count. Evenly
+/// spread bodies stay under it at the depth `LEAF_SIZE` picks.
+const DENSE_LEAF: usize = 4 * LEAF_SIZE;
+
+/// Most leaf boxes per body the tree may deepen to when chasing a dense cluster.
+const BOX_BUDGET: usize = 64;
+
+/// Generalised binomial coefficient `binom(a, k)` for real `a`.
+fn binomial(a: f64, k: usize) -> f64 {
+    (0..k).fold(1.0, |c, i| c * (a - i as f64) / (i + 1) as f64)
+}
+
+/// Square `(order + 1)`-sized matrix of expansion coefficients, indexed `[k][l]` for
+/// the term in `z^k conj(z)^l`.
+#[derive(Clone)]
+struct Expansion {
+    size: usize,
+    data: Vec<C64>,
+}
+
+impl Expansion {
+    fn zeros(size: usize) -> Self {
+        Self {
+            size,
+            data: vec![C64::default(); size * size],
+        }
+    }
+
+    fn at(&self, k: usize, l: usize) -> C64 {
+        self.data[k * self.size + l]
+    }
+
+    fn at_mut(&mut self, k: usize, l: usize) -> &mut C64 {
+        &mut self.data[k * self.size + l]
+    }
+
+    /// `self += a^T m conj(b)` when `transpose` is set, otherwise `self += a m b^H`.
+    fn add_sandwich(&mut self, a: &Expansion, m: &Expansion, b: &Expansion, transpose: bool) {
+        let n = self.size;
+        let mut left = Expansion::zeros(n);
+        for i in 0..n {
+            for k in 0..n {
+                let aik = if transpose { a.at(k, i) } else { a.at(i, k) };
+                if aik == C64::default() {
+                    continue;
+                }
+                for l in 0..n {
+                    *left.at_mut(i, l) += aik * m.at(k, l);
+                }
+            }
+        }
+        for i in 0..n {
+            for j in 0..n {
+                let mut sum = C64::default();
+                for l in 0..n {
+                    let blj = if transpose { b.at(l, j) } else { b.at(j, l) };
+                    sum += left.at(i, l) * blj.conj();
+                }
+                *self.at_mut(i, j) += sum;
+            }
+        }
+    }
+}
+
+/// `B[k][a] = binom(k, a) s^(k - a)`, which shifts `(w + s)^k` into powers of `w`.
+fn shift_matrix(size: usize, s: C64) -> Expansion {
+    let mut b = Expansion::zeros(size);
+    for k in 0..size {
+        for a in 0..=k {
+            *b.at_mut(k, a) = binomial(k as f64, a) * s.powi((k - a) as i32);
+        }
+    }
+    b
+}
+
+/// Precomputed translation constants for one evaluation.
+///
+/// In the plane the kernel is `1 / sqrt(r^2 + eps2)`, not the logarithm of the
+/// classical 2D FMM. It is expanded as `sum_n binom(-1/2, n) eps2^n |u|^(-2n-1)`, and
+/// each `|u|^(-2n-1) = u^(-n-1/2) conj(u^(-n-1/2))` separates into analytic and
+/// anti-analytic factors, so expansions carry a double series in `z` and `conj(z)`.
+struct Operators {
+    size: usize,
+    /// Weight of each softening term, `binom(-1/2, n) eps2^n`.
+    softening: Vec<f64>,
+    /// `C[n][k] = binom(-n-1/2, k) (-1)^k`, the multipole coefficients of term `n`.
+    multipole: Vec<Vec<f64>>,
+}
+
+impl Operators {
+    fn new(order: usize, eps2: f64) -> Self {
+        let size = order + 1;
+        // Far boxes are at least two softening lengths apart, so each term is at most a
+        // quarter of the last and this many keep pace with the expansion order
+        let terms = if eps2 > 0.0 { order / 2 + 1 } else { 1 };
+        let softening = (0..terms)
+            .map(|n| binomial(-0.5, n) * eps2.powi(n as i32))
+            .collect();
+        let multipole = (0..terms)
+            .map(|n| {
+                (0..size)
+                    .map(|k| binomial(-(n as f64) - 0.5, k) * if k % 2 == 0 { 1.0 } else { -1.0 })
+                    .collect()
+            })
+            .collect();
+        Self {
+            size,
+            softening,
+            multipole,
+        }
+    }
+
+    /// Multipole moments `M[k][l] = sum m d^k conj(d)^l` about `centre`.
+    fn particle_to_multipole(&self, bodies: &[Body], members: &[usize], centre: C64) -> Expansion {
+        let mut m = Expansion::zeros(self.size);
+        for &i in members {
+            let d = position(&bodies[i]) - centre;
+            let mut dk = C64::new(bodies[i].mass as f64, 0.0);
+            for k in 0..self.size {
+                let mut dl = dk;
+                for l in 0..self.size {
+                    *m.at_mut(k, l) += dl;
+                    dl *= d.conj();
+                }
+                dk *= d;
+            }
+        }
+        m
+    }
+
+    /// Matrices converting multipoles into a local expansion about a centre `u0` away,
+    /// one `(w C A, C A)` pair per softening term, where
+    /// `A[k][j] = binom(-(n+k+1/2), j) u0^(-(n+k+j)-1/2)`.
+    fn translation(&self, u0: C64) -> Vec<(Expansion, Expansion)> {
+        let inv_sqrt = u0.sqrt().inv();
+        let inv = u0.inv();
+        self.softening
+            .iter()
+            .zip(&self.multipole)
+            .enumerate()
+            .map(|(n, (&weight, c))| {
+                let mut a = Expansion::zeros(self.size);
+                for (k, &ck) in c.iter().enumerate() {
+                    let mut power = inv_sqrt * inv.powi((n + k) as i32);
+                    for j in 0..self.size {
+                        *a.at_mut(k, j) = ck * binomial(-((n + k) as f64) - 0.5, j) * power;
+                        power *= inv;
+                    }
+                }
+                let mut weighted = a.clone();
+                weighted.data.iter_mut().for_each(|x| *x *= weight);
+                (weighted, a)
+            })
+            .collect()
+    }
+
+    /// `L += sum_n (w C A)^T M conj(C A)`.
+    fn multipole_to_local(
+        m: &Expansion,
+        translation: &[(Expansion, Expansion)],
+        local: &mut Expansion,
+    ) {
+        for (weighted, a) in translation {
+            local.add_sandwich(weighted, m, a, true);
+        }
+    }
+
+    /// Potential sum `m / r` and its gradient (as `dx + i dy`) at offset `t`.
+    fn local_to_particle(&self, local: &Expansion, t: C64) -> (f64, C64) {
+        let mut potential = C64::default();
+        let mut gradient = C64::default();
+        let mut tk = C64::new(1.0, 0.0);
+        for k in 0..self.size {
+            let mut tl = C64::new(1.0, 0.0);
+            for l in 0..self.size {
+                let coefficient = local.at(k, l) * tk;
+                potential += coefficient * tl;
+                if l + 1 < self.size {
+                    // d/dx + i d/dy = 2 d/d(conj z)
+                    gradient += 2.0 * (l + 1) as f64 * local.at(k, l + 1) * tk * tl;
+                }
+                tl *= t.conj();
+            }
+            tk *= t;
+        }
+        (potential.re, gradient)
+    }
+}
+
+fn position(body: &Body) -> C64 {
+    C64::new(body.position.0 as f64, body.position.1 as f64)
+}
+
+/// Uniform quadtree over the bounding square of the bodies.
+///
+/// The depth follows the densest leaf rather than the body count alone, so clustered
+/// systems (discs, collapsing clouds) still keep their near-field sums short. Boxes
+/// with no bodies are skipped by both passes, so the extra levels only cost the
+/// bookkeeping of their empty slots. That is capped at `BOX_BUDGET` leaves per body,
+/// so a cluster much denser than its surroundings (or one smaller than the softening
+/// length) can still leave crowded leaves, whose direct sums then dominate.
+struct Tree {
+    levels: usize,
+    origin: C64,
+    side: f64,
+    /// Bodies in each leaf box, row-major.
+    leaves: Vec<Vec<usize>>,
+}
+
+impl Tree {
+    fn new(bodies: &[Body], eps2: f64) -> Self {
+        let (min, max) = bodies.iter().fold(
+            (
+                C64::new(f64::INFINITY, f64::INFINITY),
+                C64::new(f64::NEG_INFINITY, f64::NEG_INFINITY),
+            ),
+            |(min, max), b| {
+                let p = position(b);
+                (
+                    C64::new(min.re.min(p.re), min.im.min(p.im)),
+                    C64::new(max.re.max(p.re), max.im.max(p.im)),
+                )
+            },
+        );
+        let side = (max.re - min.re)
+            .max(max.im - min.im)
+            .max(f64::MIN_POSITIVE)
+            * 1.001;
+        let centre = 0.5 * (min + max);
+
+        let mut tree = Self {
+            levels: 0,
+            origin: centre - C64::new(0.5 * side, 0.5 * side),
+            side,
+            leaves: Vec::new(),
+        };
+
+        // Deepen for about LEAF_SIZE bodies per leaf, and further while any leaf is
+        // crowded past DENSE_LEAF, but keep leaves no smaller than two softening lengths
+        // so the softening series converges for far boxes, and stop at BOX_BUDGET
+        while bodies.len() >> (2 * (tree.levels + 1)) >= LEAF_SIZE
+            || (tree.densest_leaf(bodies) > DENSE_LEAF
+                && 1 << (2 * (tree.levels + 1)) <= BOX_BUDGET * bodies.len())
+        {
+            if eps2 > 0.0 && tree.box_side(tree.levels + 1) < 2.0 * eps2.sqrt() {
+                break;
+            }
+            tree.levels += 1;
+        }
+
+        let width = tree.width(tree.levels);
+        tree.leaves = vec![Vec::new(); width * width];
+        for (i, body) in bodies.iter().enumerate() {
+            let (ix, iy) = tree.leaf_of(position(body));
+            tree.leaves[iy * width + ix].push(i);
+        }
+        tree
+    }
+
+    /// Most bodies sharing one leaf at the current depth.
+    fn densest_leaf(&self, bodies: &[Body]) -> usize {
+        let width = self.width(self.levels);
+        let mut counts = vec![0; width * width];
+        for body in bodies {
+            let (ix, iy) = self.leaf_of(position(body));
+            counts[iy * width + ix] += 1;
+        }
+        counts.into_iter().max().unwrap_or(0)
+    }
+
+    fn width(&self, level: usize) -> usize {
+        1 << level
+    }
+
+    fn box_side(&self, level: usize) -> f64 {
+        self.side / self.width(level) as f64
+    }
+
+    fn leaf_of(&self, p: C64) -> (usize, usize) {
+        let width = self.width(self.levels);
+        let cell =
+            |x: f64| ((x / self.box_side(self.levels)).floor().max(0.0) as usize).min(width - 1);
+        let d = p - self.origin;
+        (cell(d.re), cell(d.im))
+    }
+
+    fn centre(&self, level: usize, ix: usize, iy: usize) -> C64 {
+        let s = self.box_side(level);
+        self.origin + C64::new((ix as f64 + 0.5) * s, (iy as f64 + 0.5) * s)
+    }
+}
+
+/// Boxes adjacent to `(ix, iy)` on a `width`-wide level, including itself.
+fn neighbours(ix: usize, iy: usize, width: usize) -> impl Iterator<Item = (usize, usize)> {
+    let range = move |i: usize| i.saturating_sub(1)..=(i + 1).min(width - 1);
+    range(iy).flat_map(move |y| range(ix).map(move |x| (x, y)))
+}
+
+/// Offsets of the four children of a box, as `(x, y)` steps.
+const CHILDREN: [(usize, usize); 4] = [(0, 0), (1, 0), (0, 1), (1, 1)];
+
+/// Per-body `sum m_j / r` (softened) and its gradient, over every other body.
+fn evaluate(bodies: &[Body], eps2: f32, order: usize) -> Vec<(f64, C64)> {
+    let eps2 = eps2 as f64;
+    let tree = Tree::new(bodies, eps2);
+    let mut fields = vec![(0.0, C64::default()); bodies.len()];
+
+    // Interaction lists only exist from level 2 down; shallower trees are all near field
+    if tree.levels >= 2 {
+        let operators = Operators::new(order.max(1), eps2);
+        let levels = tree.levels;
+
+        // Upward pass
+        let mut multipoles: Vec<Vec<Option<Expansion>>> =
+            (0..=levels).map(|l| vec![None; 1 << (2 * l)]).collect();
+        let leaf_width = tree.width(levels);
+        for (index, members) in tree.leaves.iter().enumerate() {
+            if !members.is_empty() {
+                let centre = tree.centre(levels, index % leaf_width, index / leaf_width);
+                multipoles[levels][index] =
+                    Some(operators.particle_to_multipole(bodies, members, centre));
+            }
+        }
+        // A child sits a quarter of its parent's side off the parent's centre
+        let child_shifts = |level: usize| {
+            let q = 0.25 * tree.box_side(level);
+            CHILDREN.map(|(cx, cy)| {
+                let shift = C64::new(q * (2.0 * cx as f64 - 1.0), q * (2.0 * cy as f64 - 1.0));
+                shift_matrix(operators.size, shift)
+            })
+        };
+        for level in (2..levels).rev() {
+            let width = tree.width(level);
+            let shifts = child_shifts(level);
+            for index in 0..width * width {
+                let (ix, iy) = (index % width, index / width);
+                let mut parent: Option<Expansion> = None;
+                for (&(cx, cy), b) in CHILDREN.iter().zip(&shifts) {
+                    let (x, y) = (2 * ix + cx, 2 * iy + cy);
+                    if let Some(child) = &multipoles[level + 1][y * 2 * width + x] {
+                        // M += B M' B^H
+                        parent
+                            .get_or_insert_with(|| Expansion::zeros(operators.size))
+                            .add_sandwich(b, child, b, false);
+                    }
+                }
+                multipoles[level][index] = parent;
+            }
+        }
+
+        // Downward pass: each box hears from the children of its parent's neighbours
+        // that are not its own neighbours, plus whatever its parent already heard. On a
+        // uniform grid those lie at most three boxes away, so each level needs only a
+        // handful of distinct translations
+        let mut locals: Vec<Option<Expansion>> = Vec::new();
+        for (level, level_multipoles) in multipoles.iter().enumerate().skip(2) {
+            let width = tree.width(level);
+            let side = tree.box_side(level);
+            let translations: Vec<Vec<(Expansion, Expansion)>> = (0..49)
+                .map(|offset: usize| {
+                    let (dx, dy) = (offset % 7, offset / 7);
+                    let u0 = C64::new(dx as f64 - 3.0, dy as f64 - 3.0) * side;
+                    if dx.abs_diff(3) <= 1 && dy.abs_diff(3) <= 1 {
+                        Vec::new()
+                    } else {
+                        operators.translation(u0)
+                    }
+                })
+                .collect();
+            let shifts = child_shifts(level - 1);
+
+            // Only boxes holding bodies need a local expansion
+            let mut next: Vec<Option<Expansion>> = vec![None; width * width];
+            for (index, slot) in next.iter_mut().enumerate() {
+                if level_multipoles[index].is_none() {
+                    continue;
+                }
+                let local = slot.insert(Expansion::zeros(operators.size));
+                let (ix, iy) = (index % width, index / width);
+                if level > 2 {
+                    // L' = B^T L conj(B)
+                    let parent = locals[(iy / 2) * (width / 2) + ix / 2]
+                        .as_ref()
+                        .expect("a box with bodies has a parent with bodies");
+                    let b = &shifts[(iy % 2) * 2 + ix % 2];
+                    local.add_sandwich(b, parent, b, true);
+                }
+                for (px, py) in neighbours(ix / 2, iy / 2, width / 2) {
+                    for (cx, cy) in CHILDREN {
+                        let (x, y) = (2 * px + cx, 2 * py + cy);
+                        if x.abs_diff(ix) <= 1 && y.abs_diff(iy) <= 1 {
+                            continue;
+                        }
+                        if let Some(m) = &level_multipoles[y * width + x] {
+                            let offset = (iy + 3 - y) * 7 + (ix + 3 - x);
+                            Operators::multipole_to_local(m, &translations[offset], local);
+                        }
+                    }
+                }
+            }
+            locals = next;
+        }
+
+        for (index, members) in tree.leaves.iter().enumerate() {
+            let centre = tree.centre(levels, index % leaf_width, index / leaf_width);
+            if let Some(local) = &locals[index] {
+                for &i in members {
+                    fields[i] = operators.local_to_particle(local, position(&bodies[i]) - centre);
+                }
+            }
+        }
+    }
+
+    // Near field: direct sum over each leaf and its neighbours
+    let leaf_width = tree.width(tree.levels);
+    for (index, members) in tree.leaves.iter().enumerate() {
+        let (ix, iy) = (index % leaf_width, index / leaf_width);
+        for (x, y) in neighbours(ix, iy, leaf_width) {
+            let sources = &tree.leaves[y * leaf_width + x];
+            for &i in members {
+                let p = position(&bodies[i]);
+                let (potential, gradient) = &mut fields[i];
+                for &j in sources {
+                    if i == j {
+                        continue;
+                    }
+                    let d = position(&bodies[j]) - p;
+                    let r2 = d.norm_sqr() + eps2;
+                    if r2 > 0.0 {
+                        let inv_r = r2.sqrt().recip();
+                        let m = bodies[j].mass as f64;
+                        *potential += m * inv_r;
+                        // grad_z (m / |z - w|) points towards the source
+                        *gradient += m * inv_r * inv_r * inv_r * d;
+                    }
+                }
+            }
+        }
+    }
+    fields
+}
+
+/// Accelerations from a fast multipole evaluation of order `order`.
+pub fn accelerations(bodies: &[Body], g_constant: f32, eps2: f32, order: usize) -> Vec<(f32, f32)> {
+    if bodies.is_empty() {
+        return Vec::new();
+    }
+    evaluate(bodies, eps2, order)
+        .into_iter()
+        .map(|(_, gradient)| {
+            let a = gradient * g_constant as f64;
+            (a.re as f32, a.im as f32)
+        })
+        .collect()
+}
+
+pub fn potential_energy(bodies: &[Body], g_constant: f32, eps2: f32, order: usize) -> f32 {
+    if bodies.is_empty() {
+        return 0.0;
+    }
+    let total: f64 = evaluate(bodies, eps2, order)
+        .iter()
+        .zip(bodies)
+        .map(|((potential, _), body)| body.mass as f64 * potential)
+        .sum();
+    (-0.5 * g_constant as f64 * total) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::tests::{clustered_bodies, random_bodies};
+
+    #[test]
+    fn test_tree_deepens_for_clustered_bodies() {
+        // Spread evenly, 1500 bodies need two levels; packed into a square a fortieth of
+        // the width, the tree keeps going until the cluster is split up too
+        let uniform = Tree::new(&random_bodies(1500, 31), 0.0);
+        assert_eq!(uniform.levels, 2);
+
+        let bodies = clustered_bodies(1500, 100, 31);
+        let tree = Tree::new(&bodies, 0.0);
+        assert!(tree.levels > uniform.levels, "{}", tree.levels);
+        let densest = tree.leaves.iter().map(Vec::len).max().unwrap();
+        assert!(densest <= DENSE_LEAF, "{densest}");
+    }
+}
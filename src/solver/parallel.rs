@@ -0,0 +1,68 @@
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use super::{accumulate_row, row_potential_energy};
+use crate::body::Body;
+
+/// Runs `f` on a pool with the requested number of threads, or on rayon's global
+/// pool (one thread per core) when `threads` is zero. Pools are built once per
+/// thread count and reused across steps.
+fn install<R: Send>(threads: usize, f: impl FnOnce() -> R + Send) -> R {
+    if threads == 0 {
+        return f();
+    }
+    static POOLS: OnceLock<Mutex<HashMap<usize, Arc<rayon::ThreadPool>>>> = OnceLock::new();
+    let pool = POOLS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap()
+        .entry(threads)
+        .or_insert_with(|| {
+            Arc::new(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .expect("build gravity thread pool"),
+            )
+        })
+        .clone();
+    pool.install(f)
+}
+
+/// Row-parallel direct sum. Each worker accumulates the rows it is handed into its
+/// own buffer, including the Newton's-third-law updates to later bodies, and the
+/// buffers are summed at the end, so no two threads ever write the same entry.
+pub fn direct_sum(bodies: &[Body], g_constant: f32, eps2: f32, threads: usize) -> Vec<(f32, f32)> {
+    let n = bodies.len();
+    install(threads, || {
+        (0..n)
+            .into_par_iter()
+            .fold(
+                || vec![(0.0, 0.0); n],
+                |mut accelerations, i| {
+                    accumulate_row(bodies, i, g_constant, eps2, &mut accelerations);
+                    accelerations
+                },
+            )
+            .reduce(
+                || vec![(0.0, 0.0); n],
+                |mut total, partial| {
+                    for (t, p) in total.iter_mut().zip(&partial) {
+                        t.0 += p.0;
+                        t.1 += p.1;
+                    }
+                    total
+                },
+            )
+    })
+}
+
+pub fn potential_energy(bodies: &[Body], g_constant: f32, eps2: f32, threads: usize) -> f32 {
+    install(threads, || {
+        (0..bodies.len())
+            .into_par_iter()
+            .map(|i| row_potential_energy(bodies, i, g_constant, eps2))
+            .sum()
+    })
+}
@@ -0,0 +1,301 @@
+use rustfft::FftPlanner;
+use rustfft::num_complex::Complex;
+use serde::Deserialize;
+use std::f32::consts::PI;
+
+use crate::body::Body;
+use crate::solver::{direct_potential_energy, direct_sum};
+
+/// Boundary condition of the particle-mesh grid.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MeshBoundary {
+    /// Open space: the grid is fitted to the bodies each evaluation and zero-padded
+    /// to twice its size so the FFT convolution has no periodic images. It spans at
+    /// least four softening lengths, and bodies all at one point without softening
+    /// are summed directly.
+    #[default]
+    Isolated,
+    /// Periodic square box of side `box_size` centred on the origin: every body feels
+    /// all the images of the others. Only the forces are periodic; bodies are wrapped
+    /// into the box by a periodic [`Boundary`](crate::Boundary).
+    Periodic,
+}
+
+/// Mesh fitted over the bodies or the periodic box, with cloud-in-cell weights.
+struct Mesh {
+    /// Nodes per side.
+    n: usize,
+    /// Node spacing.
+    h: f32,
+    /// Position of node (0, 0).
+    origin: (f32, f32),
+    periodic: bool,
+}
+
+impl Mesh {
+    /// `None` when the bodies of an isolated mesh span no distance at all.
+    fn new(
+        bodies: &[Body],
+        n: usize,
+        boundary: MeshBoundary,
+        box_size: f32,
+        eps2: f32,
+    ) -> Option<Self> {
+        match boundary {
+            MeshBoundary::Periodic => Some(Self {
+                n,
+                h: box_size / n as f32,
+                origin: (-0.5 * box_size, -0.5 * box_size),
+                periodic: true,
+            }),
+            MeshBoundary::Isolated => {
+                let (min, max) = bodies.iter().fold(
+                    (
+                        (f32::INFINITY, f32::INFINITY),
+                        (f32::NEG_INFINITY, f32::NEG_INFINITY),
+                    ),
+                    |(min, max), b| {
+                        (
+                            (min.0.min(b.position.0), min.1.min(b.position.1)),
+                            (max.0.max(b.position.0), max.1.max(b.position.1)),
+                        )
+                    },
+                );
+                // No finer than the softening resolves, and a small margin keeps the
+                // extreme bodies strictly inside the last cell
+                let side = (max.0 - min.0).max(max.1 - min.1).max(4.0 * eps2.sqrt()) * 1.01;
+                if side == 0.0 {
+                    return None;
+                }
+                let centre = (0.5 * (min.0 + max.0), 0.5 * (min.1 + max.1));
+                Some(Self {
+                    n,
+                    h: side / (n - 1) as f32,
+                    origin: (centre.0 - 0.5 * side, centre.1 - 0.5 * side),
+                    periodic: false,
+                })
+            }
+        }
+    }
+
+    /// The four nodes around `position` and their cloud-in-cell weights, as indices
+    /// into a row-major grid `stride` nodes wide.
+    fn stencil(&self, position: (f32, f32), stride: usize) -> [(usize, f32); 4] {
+        let axis = |x: f32, origin: f32| {
+            let u = (x - origin) / self.h;
+            if self.periodic {
+                let u = u.rem_euclid(self.n as f32);
+                let i0 = (u.floor() as usize).min(self.n - 1);
+                (i0, (i0 + 1) % self.n, u - i0 as f32)
+            } else {
+                let i0 = (u.floor().max(0.0) as usize).min(self.n - 2);
+                (i0, i0 + 1, u - i0 as f32)
+            }
+        };
+        let (x0, x1, fx) = axis(position.0, self.origin.0);
+        let (y0, y1, fy) = axis(position.1, self.origin.1);
+        [
+            (y0 * stride + x0, (1.0 - fx) * (1.0 - fy)),
+            (y0 * stride + x1, fx * (1.0 - fy)),
+            (y1 * stride + x0, (1.0 - fx) * fy),
+            (y1 * stride + x1, fx * fy),
+        ]
+    }
+}
+
+/// In-place forward or inverse 2D FFT of a square row-major grid, unnormalised.
+pub(crate) fn fft2(
+    grid: &mut [Complex<f32>],
+    n: usize,
+    planner: &mut FftPlanner<f32>,
+    inverse: bool,
+) {
+    let fft = if inverse {
+        planner.plan_fft_inverse(n)
+    } else {
+        planner.plan_fft_forward(n)
+    };
+    fft.process(grid);
+    transpose(grid, n);
+    fft.process(grid);
+    transpose(grid, n);
+}
+
+fn transpose(grid: &mut [Complex<f32>], n: usize) {
+    for row in 0..n {
+        for col in (row + 1)..n {
+            grid.swap(row * n + col, col * n + row);
+        }
+    }
+}
+
+/// Gravitational potential (including `-G`) at every node of the mesh.
+///
+/// Bodies attract with the softened `1 / r^2` law in the plane, so the mesh is
+/// convolved with the kernel `1 / sqrt(r^2 + eps2)` rather than solving the 2D
+/// Poisson equation, whose logarithmic potential would give a `1 / r` force.
+fn potential_grid(bodies: &[Body], mesh: &Mesh, g_constant: f32, eps2: f32) -> Vec<Complex<f32>> {
+    let mut planner = FftPlanner::new();
+    if mesh.periodic {
+        let n = mesh.n;
+        let mut mass = vec![Complex::default(); n * n];
+        for body in bodies {
+            for (index, weight) in mesh.stencil(body.position, n) {
+                mass[index].re += weight * body.mass;
+            }
+        }
+        fft2(&mut mass, n, &mut planner, false);
+
+        // The kernel's 2D transform is 2 pi exp(-k eps) / k; the k = 0 mode is dropped,
+        // which amounts to a uniform neutralising background
+        let box_size = mesh.h * n as f32;
+        let eps = eps2.sqrt();
+        let wavenumber = |i: usize| {
+            let signed = if i <= n / 2 {
+                i as f32
+            } else {
+                i as f32 - n as f32
+            };
+            2.0 * PI * signed / box_size
+        };
+        for (index, m) in mass.iter_mut().enumerate() {
+            let k = wavenumber(index % n).hypot(wavenumber(index / n));
+            let green = if k > 0.0 {
+                2.0 * PI * (-k * eps).exp() / k
+            } else {
+                0.0
+            };
+            *m *= -g_constant * green / (box_size * box_size);
+        }
+        fft2(&mut mass, n, &mut planner, true);
+        mass
+    } else {
+        // Zero-padding to 2n leaves room for every separation up to n cells without
+        // wrapping onto another body
+        let padded = 2 * mesh.n;
+        let mut mass = vec![Complex::default(); padded * padded];
+        for body in bodies {
+            for (index, weight) in mesh.stencil(body.position, padded) {
+                mass[index].re += weight * body.mass;
+            }
+        }
+        let mut green = vec![Complex::default(); padded * padded];
+        for (index, g) in green.iter_mut().enumerate() {
+            let (i, j) = (index % padded, index / padded);
+            let dx = i.min(padded - i) as f32 * mesh.h;
+            let dy = j.min(padded - j) as f32 * mesh.h;
+            let r2 = dx * dx + dy * dy + eps2;
+            // The node's own cell carries no self-interaction when unsoftened
+            if r2 > 0.0 {
+                g.re = -g_constant / r2.sqrt();
+            }
+        }
+        fft2(&mut mass, padded, &mut planner, false);
+        fft2(&mut green, padded, &mut planner, false);
+        let norm = 1.0 / (padded * padded) as f32;
+        for (m, g) in mass.iter_mut().zip(&green) {
+            *m *= g * norm;
+        }
+        fft2(&mut mass, padded, &mut planner, true);
+        mass
+    }
+}
+
+/// Row-major width of the grid returned by [`potential_grid`].
+fn stride(mesh: &Mesh) -> usize {
+    if mesh.periodic { mesh.n } else { 2 * mesh.n }
+}
+
+/// Particle-mesh accelerations: cloud-in-cell deposition, FFT convolution with the
+/// gravitational kernel, central-difference gradient on the mesh and cloud-in-cell
+/// interpolation back to the bodies. The same weights are used both ways, so the
+/// mesh forces conserve momentum.
+pub fn accelerations(
+    bodies: &[Body],
+    g_constant: f32,
+    eps2: f32,
+    grid: usize,
+    boundary: MeshBoundary,
+    box_size: f32,
+) -> Vec<(f32, f32)> {
+    // A lone body has no mesh to fit in open space, and feels no force anyway
+    if bodies.len() < 2 && boundary == MeshBoundary::Isolated {
+        return vec![(0.0, 0.0); bodies.len()];
+    }
+    let Some(mesh) = Mesh::new(bodies, grid.max(2), boundary, box_size, eps2) else {
+        return direct_sum(bodies, g_constant, eps2);
+    };
+    let phi = potential_grid(bodies, &mesh, g_constant, eps2);
+    let stride = stride(&mesh);
+
+    // a = -grad(phi). Periodic grids wrap; the padded isolated grid is also exact one
+    // node either side of the occupied region, since the neighbour at index -1 (the
+    // last padded node) is still within n cells of every body
+    let node = |x: usize, y: usize, dx: isize, dy: isize| {
+        let wrap = |i: usize, d: isize| (i as isize + d).rem_euclid(stride as isize) as usize;
+        phi[wrap(y, dy) * stride + wrap(x, dx)].re
+    };
+    let inv_2h = 0.5 / mesh.h;
+    bodies
+        .iter()
+        .map(|body| {
+            mesh.stencil(body.position, stride).iter().fold(
+                (0.0, 0.0),
+                |(ax, ay), &(index, weight)| {
+                    let (x, y) = (index % stride, index / stride);
+                    (
+                        ax - weight * (node(x, y, 1, 0) - node(x, y, -1, 0)) * inv_2h,
+                        ay - weight * (node(x, y, 0, 1) - node(x, y, 0, -1)) * inv_2h,
+                    )
+                },
+            )
+        })
+        .collect()
+}
+
+/// Half the mass-weighted mesh potential at the bodies, less the energy of each
+/// body's cloud with itself.
+pub fn potential_energy(
+    bodies: &[Body],
+    g_constant: f32,
+    eps2: f32,
+    grid: usize,
+    boundary: MeshBoundary,
+    box_size: f32,
+) -> f32 {
+    if bodies.len() < 2 && boundary == MeshBoundary::Isolated {
+        return 0.0;
+    }
+    let Some(mesh) = Mesh::new(bodies, grid.max(2), boundary, box_size, eps2) else {
+        return direct_potential_energy(bodies, g_constant, eps2);
+    };
+    let phi = potential_grid(bodies, &mesh, g_constant, eps2);
+    let stride = stride(&mesh);
+    let kernel = |r2: f32| {
+        let r2 = r2 + eps2;
+        if r2 > 0.0 {
+            -g_constant / r2.sqrt()
+        } else {
+            0.0
+        }
+    };
+    let (k0, k1, k2) = (
+        kernel(0.0),
+        kernel(mesh.h * mesh.h),
+        kernel(2.0 * mesh.h * mesh.h),
+    );
+    0.5 * bodies
+        .iter()
+        .map(|body| {
+            let stencil = mesh.stencil(body.position, stride);
+            let potential: f32 = stencil.iter().map(|&(i, w)| w * phi[i].re).sum();
+            // The stencil nodes are the corners of one cell: [00, 10, 01, 11]
+            let w = stencil.map(|(_, w)| w);
+            let self_potential = w.iter().map(|w| w * w).sum::<f32>() * k0
+                + 2.0 * (w[0] * w[1] + w[2] * w[3] + w[0] * w[2] + w[1] * w[3]) * k1
+                + 2.0 * (w[0] * w[3] + w[1] * w[2]) * k2;
+            body.mass * (potential - body.mass * self_potential)
+        })
+        .sum::<f32>()
+}
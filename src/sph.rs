@@ -0,0 +1,296 @@
+use anyhow::{Result, bail};
+use serde::Deserialize;
+use std::f32::consts::PI;
+
+use crate::body::Body;
+use crate::neighbours::pairs_within;
+
+/// Smoothed-particle hydrodynamics for the gas particles: densities from a cubic
+/// spline kernel, an ideal-gas equation of state `P = (gamma - 1) rho u` and Monaghan's
+/// artificial viscosity to capture shocks. Gas feels gravity like every other body;
+/// pressure and viscosity only act between gas particles.
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sph {
+    /// Kernel smoothing length `h`; particles interact within `2 h`.
+    pub smoothing_length: f32,
+    /// Adiabatic index of the gas.
+    #[serde(default = "default_gamma")]
+    pub gamma: f32,
+    /// Linear, bulk viscosity coefficient.
+    #[serde(default = "default_alpha")]
+    pub alpha: f32,
+    /// Quadratic, von Neumann-Richtmyer viscosity coefficient, which stops particles
+    /// passing through each other in strong shocks.
+    #[serde(default = "default_beta")]
+    pub beta: f32,
+}
+
+fn default_gamma() -> f32 {
+    5.0 / 3.0
+}
+
+fn default_alpha() -> f32 {
+    1.0
+}
+
+fn default_beta() -> f32 {
+    2.0
+}
+
+/// A gas particle: the body at `index` and its specific internal energy.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GasParticle {
+    pub index: usize,
+    pub internal_energy: f32,
+}
+
+impl Sph {
+    pub fn new(smoothing_length: f32) -> Self {
+        Self {
+            smoothing_length,
+            gamma: default_gamma(),
+            alpha: default_alpha(),
+            beta: default_beta(),
+        }
+    }
+
+    /// Rejects a smoothing length that isn't positive, which leaves the kernel
+    /// undefined.
+    pub fn validate(&self) -> Result<()> {
+        let h = self.smoothing_length;
+        if h.is_nan() || h <= 0.0 {
+            bail!("the sph smoothing_length must be positive, got {h}");
+        }
+        Ok(())
+    }
+
+    /// 2D cubic spline kernel at distance `r`, normalised to unit integral over the
+    /// plane.
+    pub fn kernel(&self, r: f32) -> f32 {
+        let h = self.smoothing_length;
+        let sigma = 10.0 / (7.0 * PI * h * h);
+        let q = r / h;
+        if q < 1.0 {
+            sigma * (1.0 - 1.5 * q * q + 0.75 * q * q * q)
+        } else if q < 2.0 {
+            sigma * 0.25 * (2.0 - q).powi(3)
+        } else {
+            0.0
+        }
+    }
+
+    /// `dW/dr / r`, so that the kernel gradient is this times the separation vector.
+    fn kernel_gradient(&self, r: f32) -> f32 {
+        let h = self.smoothing_length;
+        let sigma = 10.0 / (7.0 * PI * h * h);
+        let q = r / h;
+        let derivative = if q < 1.0 {
+            sigma / h * (-3.0 * q + 2.25 * q * q)
+        } else if q < 2.0 {
+            -sigma / h * 0.75 * (2.0 - q).powi(2)
+        } else {
+            0.0
+        };
+        if r > 0.0 { derivative / r } else { 0.0 }
+    }
+
+    pub fn pressure(&self, density: f32, internal_energy: f32) -> f32 {
+        (self.gamma - 1.0) * density * internal_energy
+    }
+
+    pub fn sound_speed(&self, internal_energy: f32) -> f32 {
+        (self.gamma * (self.gamma - 1.0) * internal_energy)
+            .max(0.0)
+            .sqrt()
+    }
+
+    /// Kernel-estimated surface density of each gas particle, in the order of `gas`.
+    pub fn densities(&self, bodies: &[Body], gas: &[GasParticle]) -> Vec<f32> {
+        let points: Vec<(f32, f32)> = gas.iter().map(|p| bodies[p.index].position).collect();
+        self.densities_with(bodies, gas, &self.neighbours(&points))
+    }
+
+    fn neighbours(&self, points: &[(f32, f32)]) -> Vec<(usize, usize)> {
+        pairs_within(points, 2.0 * self.smoothing_length)
+    }
+
+    fn densities_with(
+        &self,
+        bodies: &[Body],
+        gas: &[GasParticle],
+        pairs: &[(usize, usize)],
+    ) -> Vec<f32> {
+        let mut densities: Vec<f32> = gas
+            .iter()
+            .map(|p| bodies[p.index].mass * self.kernel(0.0))
+            .collect();
+        for &(i, j) in pairs {
+            let (a, b) = (&bodies[gas[i].index], &bodies[gas[j].index]);
+            let w = self.kernel(a.distance_to(b));
+            densities[i] += b.mass * w;
+            densities[j] += a.mass * w;
+        }
+        densities
+    }
+
+    /// Pressure and viscous accelerations of the gas particles, and the rates at which
+    /// their specific internal energies change, both in the order of `gas`. The pair
+    /// terms are antisymmetric, so momentum is conserved, and the work they do goes
+    /// into internal energy, so total energy is too.
+    pub fn rates(&self, bodies: &[Body], gas: &[GasParticle]) -> (Vec<(f32, f32)>, Vec<f32>) {
+        let points: Vec<(f32, f32)> = gas.iter().map(|p| bodies[p.index].position).collect();
+        let pairs = self.neighbours(&points);
+        let densities = self.densities_with(bodies, gas, &pairs);
+        let pressure_terms: Vec<f32> = gas
+            .iter()
+            .zip(&densities)
+            .map(|(p, &rho)| self.pressure(rho, p.internal_energy) / (rho * rho))
+            .collect();
+
+        let h = self.smoothing_length;
+        let mut accelerations = vec![(0.0, 0.0); gas.len()];
+        let mut heating = vec![0.0; gas.len()];
+        for (i, j) in pairs {
+            let (a, b) = (&bodies[gas[i].index], &bodies[gas[j].index]);
+            let (dx, dy) = (a.position.0 - b.position.0, a.position.1 - b.position.1);
+            let (dvx, dvy) = (a.velocity.0 - b.velocity.0, a.velocity.1 - b.velocity.1);
+            let f = self.kernel_gradient(dx.hypot(dy));
+            let (gx, gy) = (f * dx, f * dy);
+
+            // Monaghan viscosity, acting only while the pair approaches
+            let approach = dvx * dx + dvy * dy;
+            let viscosity = if approach < 0.0 {
+                let mu = h * approach / (dx * dx + dy * dy + 0.01 * h * h);
+                let sound_speed = 0.5
+                    * (self.sound_speed(gas[i].internal_energy)
+                        + self.sound_speed(gas[j].internal_energy));
+                let density = 0.5 * (densities[i] + densities[j]);
+                (-self.alpha * sound_speed * mu + self.beta * mu * mu) / density
+            } else {
+                0.0
+            };
+
+            let force = pressure_terms[i] + pressure_terms[j] + viscosity;
+            accelerations[i].0 -= b.mass * force * gx;
+            accelerations[i].1 -= b.mass * force * gy;
+            accelerations[j].0 += a.mass * force * gx;
+            accelerations[j].1 += a.mass * force * gy;
+            let work = dvx * gx + dvy * gy;
+            heating[i] += b.mass * (pressure_terms[i] + 0.5 * viscosity) * work;
+            heating[j] += a.mass * (pressure_terms[j] + 0.5 * viscosity) * work;
+        }
+        (accelerations, heating)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Unit-mass particles on a square lattice of spacing `spacing`, centred on the
+    /// origin.
+    fn lattice(side: usize, spacing: f32) -> (Vec<Body>, Vec<GasParticle>) {
+        let offset = 0.5 * (side - 1) as f32 * spacing;
+        let bodies: Vec<Body> = (0..side * side)
+            .map(|k| {
+                let (x, y) = ((k % side) as f32, (k / side) as f32);
+                Body::new(
+                    1.0,
+                    (x * spacing - offset, y * spacing - offset),
+                    (0.0, 0.0),
+                )
+                .unwrap()
+            })
+            .collect();
+        let gas = (0..bodies.len())
+            .map(|index| GasParticle {
+                index,
+                internal_energy: 1.0,
+            })
+            .collect();
+        (bodies, gas)
+    }
+
+    #[test]
+    fn test_kernel_is_normalised_and_gradient_matches() {
+        let sph = Sph::new(0.3);
+        // Integrate over rings out to the 2h support
+        let dr = 1e-4;
+        let integral: f32 = (0..6000)
+            .map(|k| {
+                let r = (k as f32 + 0.5) * dr;
+                2.0 * PI * r * sph.kernel(r) * dr
+            })
+            .sum();
+        assert!((integral - 1.0).abs() < 1e-3, "integral {integral}");
+
+        for r in [0.1, 0.3, 0.45] {
+            let h = 1e-3;
+            let derivative = (sph.kernel(r + h) - sph.kernel(r - h)) / (2.0 * h);
+            assert!((sph.kernel_gradient(r) * r - derivative).abs() < 1e-2 * derivative.abs());
+        }
+        assert_eq!(sph.kernel(0.6), 0.0);
+    }
+
+    #[test]
+    fn test_density_of_uniform_lattice() {
+        let (bodies, gas) = lattice(15, 0.1);
+        let sph = Sph::new(0.15);
+        let densities = sph.densities(&bodies, &gas);
+        // Away from the edges the density is one particle per cell
+        let centre = densities[7 * 15 + 7];
+        assert!((centre - 100.0).abs() < 2.0, "density {centre}");
+        // and falls off towards the edges and corners
+        assert!(densities[0] < densities[7] && densities[7] < 0.8 * centre);
+    }
+
+    #[test]
+    fn test_pressure_pushes_outwards_and_conserves() {
+        let (mut bodies, gas) = lattice(7, 0.1);
+        bodies[24].velocity = (0.5, -0.2);
+        let sph = Sph::new(0.12);
+        let (accelerations, heating) = sph.rates(&bodies, &gas);
+
+        // The edge of a uniform blob is pushed out, and the interior nearly balanced
+        assert!(accelerations[3].1 < 0.0 && accelerations[45].1 > 0.0);
+        let total = accelerations
+            .iter()
+            .fold((0.0, 0.0), |(x, y), a| (x + a.0, y + a.1));
+        assert!(total.0.abs() < 1e-3 && total.1.abs() < 1e-3);
+
+        // Pressure and viscous work balances the heating
+        let work: f32 = bodies
+            .iter()
+            .zip(&accelerations)
+            .map(|(b, a)| b.mass * (b.velocity.0 * a.0 + b.velocity.1 * a.1))
+            .sum();
+        let heat: f32 = heating.iter().sum();
+        assert!((work + heat).abs() < 1e-3 * heat.abs(), "{work} {heat}");
+    }
+
+    #[test]
+    fn test_viscosity_only_resists_approach() {
+        let sph = Sph {
+            alpha: 1.0,
+            beta: 2.0,
+            ..Sph::new(0.2)
+        };
+        let mut bodies = vec![
+            Body::new(1.0, (0.0, 0.0), (1.0, 0.0)).unwrap(),
+            Body::new(1.0, (0.2, 0.0), (-1.0, 0.0)).unwrap(),
+        ];
+        let gas = [0, 1].map(|index| GasParticle {
+            index,
+            internal_energy: 0.0,
+        });
+        let (accelerations, heating) = sph.rates(&bodies, &gas);
+        assert!(accelerations[0].0 < 0.0 && heating[0] > 0.0);
+
+        bodies[0].velocity = (-1.0, 0.0);
+        bodies[1].velocity = (1.0, 0.0);
+        let (accelerations, heating) = sph.rates(&bodies, &gas);
+        assert_eq!(accelerations[0], (0.0, 0.0));
+        assert_eq!(heating, vec![0.0, 0.0]);
+    }
+}
@@ -4,9 +4,21 @@ use std::sync::Arc;
 use wgpu::{Device, Instance, Queue, Surface, util::DeviceExt};
 use winit::window::Window;
 
+use crate::gpu::GpuSimulation;
+use crate::lagrange;
+use crate::scenario::Scenario;
+use crate::simulation::Simulation;
+use crate::solver::GravitySolver;
+
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
+const PRIMARY_COLOUR: [f32; 4] = [0.95, 0.75, 0.35, 0.9];
+const LAGRANGE_COLOUR: [f32; 4] = [0.9, 0.5, 0.8, 0.8];
+const HILL_COLOUR: [f32; 4] = [0.5, 0.7, 0.9, 0.4];
+/// Sides of the polygons drawn for circles.
+const CIRCLE_SEGMENTS: usize = 48;
+
 pub struct State<'a> {
     // Window
     pub window: Arc<Window>,
@@ -22,17 +34,26 @@ pub struct State<'a> {
     pub quad_vertex_buffer: wgpu::Buffer,
     pub quad_index_buffer: wgpu::Buffer,
     pub instance_buffer: wgpu::Buffer,
+    /// Per-body kind and drawn radius, alongside the positions.
+    pub style_buffer: wgpu::Buffer,
     pub num_instances: u32,
+    pub line_pipeline: wgpu::RenderPipeline,
+    /// Coloured end points of the links and overlays, two vertices per line.
+    pub line_buffer: wgpu::Buffer,
+    pub num_line_vertices: u32,
+    /// Lines fixed for the whole run, such as zero-velocity curves, drawn before the
+    /// links.
+    pub overlay_vertices: Vec<[f32; 6]>,
 
     // Gravity Sim
-    pub bodies: Vec<Body>,
-    pub g_constant: f32,
-    pub time_step: f32,
-    pub eps2: f32,
+    pub simulation: Simulation,
+    /// GPU-resident copy of the bodies when the scenario selects the GPU solver. Its
+    /// position buffer replaces `instance_buffer` for drawing.
+    pub gpu_simulation: Option<GpuSimulation>,
 }
 
 impl<'a> State<'a> {
-    pub async fn new(window: Arc<Window>) -> Result<Self> {
+    pub async fn new(window: Arc<Window>, scenario: &Scenario) -> Result<Self> {
         // Create an instance to interact with the GPU
         let instance_descriptor = wgpu::InstanceDescriptor {
             backends: wgpu::Backends::all(),
@@ -100,13 +121,27 @@ impl<'a> State<'a> {
 
         surface.configure(&device, &config);
 
-        // TODO: Replace with initial config loaded from file (or let the user provide them?)
-        let bodies = vec![
-            Body::new(200.0, (-0.8, 0.0), (0.0, 1.0)).unwrap(),
-            Body::new(100.0, (0.8, 0.0), (0.0, 1.0)).unwrap(),
-            Body::new(100.0, (0.2, 0.0), (0.0, -1.0)).unwrap(),
-            Body::new(50.0, (-0.3, 0.0), (0.0, 1.0)).unwrap(),
-        ];
+        let simulation = Simulation::from_scenario(scenario)?;
+        // The compute shader only knows mutual gravity in open space
+        let gpu_simulation = if simulation.solver == GravitySolver::Gpu
+            && simulation.only_mutual_gravity()
+            // The Lagrange markers and detectors follow the bodies on the CPU
+            && simulation.lagrange_pairs.is_empty()
+            && simulation.detectors.is_empty()
+        {
+            if adapter
+                .get_downlevel_capabilities()
+                .flags
+                .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+            {
+                Some(GpuSimulation::new(&device, &queue, &simulation))
+            } else {
+                log::warn!("Adapter has no compute shaders, stepping the GPU solver on the CPU");
+                None
+            }
+        } else {
+            None
+        };
 
         let quad_vertices: [[f32; 2]; 4] = [[-1.0, -1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]];
         let quad_indices: [u16; 6] = [0, 1, 2, 0, 2, 3];
@@ -123,7 +158,8 @@ impl<'a> State<'a> {
             usage: wgpu::BufferUsages::INDEX,
         });
 
-        let instance_data: Vec<[f32; 2]> = bodies
+        let instance_data: Vec<[f32; 2]> = simulation
+            .bodies
             .iter()
             .map(|b| [b.position.0, b.position.1])
             .collect();
@@ -132,6 +168,11 @@ impl<'a> State<'a> {
             contents: bytemuck::cast_slice(&instance_data),
             usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
         });
+        let style_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Style Buffer"),
+            contents: bytemuck::cast_slice(&body_styles(&simulation)),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
         let num_instances = instance_data.len() as u32;
 
         // RENDER
@@ -168,6 +209,15 @@ impl<'a> State<'a> {
                     format: wgpu::VertexFormat::Float32x2,
                 }],
             },
+            wgpu::VertexBufferLayout {
+                array_stride: std::mem::size_of::<[f32; 2]>() as u64,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &[wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x2,
+                }],
+            },
         ];
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -207,6 +257,73 @@ impl<'a> State<'a> {
             cache: None,
         });
 
+        #[cfg(not(target_arch = "wasm32"))]
+        let line_shader_src =
+            &std::fs::read_to_string("src/shaders/lines.wgsl").expect("read lines.wgsl");
+        #[cfg(target_arch = "wasm32")]
+        let line_shader_src = include_str!("shaders/lines.wgsl");
+
+        let line_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Lines Shader"),
+            source: wgpu::ShaderSource::Wgsl(line_shader_src.into()),
+        });
+        let line_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Lines Render Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &line_shader,
+                entry_point: Some("vs"),
+                buffers: &[wgpu::VertexBufferLayout {
+                    array_stride: std::mem::size_of::<[f32; 6]>() as u64,
+                    step_mode: wgpu::VertexStepMode::Vertex,
+                    attributes: &[
+                        wgpu::VertexAttribute {
+                            offset: 0,
+                            shader_location: 0,
+                            format: wgpu::VertexFormat::Float32x2,
+                        },
+                        wgpu::VertexAttribute {
+                            offset: std::mem::size_of::<[f32; 2]>() as u64,
+                            shader_location: 1,
+                            format: wgpu::VertexFormat::Float32x4,
+                        },
+                    ],
+                }],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &line_shader,
+                entry_point: Some("fs"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+        let overlay_vertices = overlay_vertices(&simulation);
+        let line_vertices = line_vertices(&simulation, &overlay_vertices);
+        let line_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Line Buffer"),
+            // Never empty, so there is always a buffer to bind
+            contents: bytemuck::cast_slice(if line_vertices.is_empty() {
+                &[[0.0f32; 6]; 2]
+            } else {
+                line_vertices.as_slice()
+            }),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        let num_line_vertices = line_vertices.len() as u32;
+
         Ok(Self {
             window,
             instance,
@@ -221,13 +338,16 @@ impl<'a> State<'a> {
             quad_vertex_buffer,
             quad_index_buffer,
             instance_buffer,
+            style_buffer,
             num_instances,
+            line_pipeline,
+            line_buffer,
+            num_line_vertices,
+            overlay_vertices,
 
             // Grav Sim
-            bodies,
-            g_constant: 1.0,
-            time_step: 0.0001, // Small time step for accuracy
-            eps2: 0.004,
+            simulation,
+            gpu_simulation,
         })
     }
 
@@ -259,17 +379,46 @@ impl<'a> State<'a> {
             }
         };
 
-        let instance_data: Vec<[f32; 2]> = self
-            .bodies
-            .iter()
-            .map(|b| [b.position.0, b.position.1])
-            .collect();
-        self.queue.write_buffer(
-            &self.instance_buffer,
-            0,
-            bytemuck::cast_slice(&instance_data),
-        );
-        self.num_instances = instance_data.len() as u32;
+        // GPU-resident bodies are drawn straight from their position buffer
+        if self.gpu_simulation.is_none() {
+            let instance_data: Vec<[f32; 2]> = self
+                .simulation
+                .bodies
+                .iter()
+                .map(|b| [b.position.0, b.position.1])
+                .collect();
+            self.queue.write_buffer(
+                &self.instance_buffer,
+                0,
+                bytemuck::cast_slice(&instance_data),
+            );
+            // Bodies may have been removed by the boundary
+            self.queue.write_buffer(
+                &self.style_buffer,
+                0,
+                bytemuck::cast_slice(&body_styles(&self.simulation)),
+            );
+            self.num_instances = instance_data.len() as u32;
+            let line_vertices = line_vertices(&self.simulation, &self.overlay_vertices);
+            let contents: &[u8] = bytemuck::cast_slice(&line_vertices);
+            // Links only go, but a pair's Lagrange points can appear if they had none
+            if contents.len() as u64 > self.line_buffer.size() {
+                self.line_buffer =
+                    self.device
+                        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                            label: Some("Line Buffer"),
+                            contents,
+                            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                        });
+            } else {
+                self.queue.write_buffer(&self.line_buffer, 0, contents);
+            }
+            self.num_line_vertices = line_vertices.len() as u32;
+        }
+        let instance_buffer = match &self.gpu_simulation {
+            Some(gpu) => &gpu.positions,
+            None => &self.instance_buffer,
+        };
 
         let view = frame
             .texture
@@ -301,9 +450,17 @@ impl<'a> State<'a> {
             timestamp_writes: None,
         });
 
+        // Overlays and links go under the bodies
+        if self.num_line_vertices > 0 {
+            rpass.set_pipeline(&self.line_pipeline);
+            rpass.set_vertex_buffer(0, self.line_buffer.slice(..));
+            rpass.draw(0..self.num_line_vertices, 0..1);
+        }
+
         rpass.set_pipeline(&self.render_pipeline);
         rpass.set_vertex_buffer(0, self.quad_vertex_buffer.slice(..));
-        rpass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        rpass.set_vertex_buffer(1, instance_buffer.slice(..));
+        rpass.set_vertex_buffer(2, self.style_buffer.slice(..));
         rpass.set_index_buffer(self.quad_index_buffer.slice(..), wgpu::IndexFormat::Uint16);
 
         rpass.draw_indexed(0..6, 0, 0..self.num_instances);
@@ -315,377 +472,130 @@ impl<'a> State<'a> {
         self.window.request_redraw();
     }
 
-    pub fn total_kinetic_energy(&self) -> f32 {
-        self.bodies
-            .iter()
-            .fold(0.0, |s, x| s + x.get_kinetic_energy())
-    }
-
-    pub fn total_potential_energy(&self) -> f32 {
-        let mut potential_energy = 0.0;
-        for i in 0..self.bodies.len() {
-            for j in (i + 1)..self.bodies.len() {
-                let distance = self.bodies[i].distance_to(&self.bodies[j]);
-                // U = -G * m1 * m2 / r
-                potential_energy -=
-                    self.g_constant * self.bodies[i].mass * self.bodies[j].mass / distance;
-            }
-        }
-        potential_energy
-    }
-
-    pub fn total_energy(&self) -> f32 {
-        self.total_kinetic_energy() + self.total_potential_energy()
-    }
-
     pub fn step(&mut self) {
-        // 1. Pairwise forces
-        let n = self.bodies.len();
-        let mut forces = vec![(0.0, 0.0); n];
-
-        // a(t)
-        for i in 0..n {
-            for j in (i + 1)..n {
-                let f = self.bodies[i].gravitational_force(&self.bodies[j], self.g_constant);
-
-                forces[i].0 += f.0 / self.bodies[i].mass;
-                forces[i].1 += f.1 / self.bodies[i].mass;
-                forces[j].0 -= f.0 / self.bodies[j].mass;
-                forces[j].1 -= f.1 / self.bodies[j].mass;
+        match &self.gpu_simulation {
+            Some(gpu) => {
+                gpu.step(&self.device, &self.queue);
+                self.simulation.time += self.simulation.time_step as f64;
             }
+            None => self.simulation.step(),
         }
-
-        // x(t+dt) = x(t) + v(t) dt + 0.5 a(t) dt^2
-        for i in 0..n {
-            self.bodies[i].position.0 += self.bodies[i].velocity.0 * self.time_step
-                + 0.5 * forces[i].0 * self.time_step * self.time_step;
-            self.bodies[i].position.1 += self.bodies[i].velocity.1 * self.time_step
-                + 0.5 * forces[i].1 * self.time_step * self.time_step;
-        }
-
-        // a(t+dt)
-        let mut forces_new = vec![(0.0, 0.0); n];
-        for i in 0..n {
-            for j in (i + 1)..n {
-                let f = self.bodies[i].gravitational_force_softened(
-                    &self.bodies[j],
-                    self.g_constant,
-                    self.eps2,
-                );
-                forces_new[i].0 += f.0 / self.bodies[i].mass;
-                forces_new[i].1 += f.1 / self.bodies[i].mass;
-                forces_new[j].0 -= f.0 / self.bodies[j].mass;
-                forces_new[j].1 -= f.1 / self.bodies[j].mass;
-            }
-        }
-
-        // v(t+dt) = v(t) + 0.5 (a(t) + a(t+dt)) dt
-        for i in 0..n {
-            self.bodies[i].velocity.0 += 0.5 * (forces[i].0 + forces_new[i].0) * self.time_step;
-            self.bodies[i].velocity.1 += 0.5 * (forces[i].1 + forces_new[i].1) * self.time_step;
+        for detection in std::mem::take(&mut self.simulation.detections) {
+            log::info!(
+                "detector {} found {:?} at t = {} for bodies {:?}",
+                detection.detector,
+                detection.kind,
+                detection.time,
+                detection.bodies
+            );
         }
     }
 }
 
-#[derive(Copy, Clone)]
-pub struct Body {
-    pub mass: f32,
-    pub position: (f32, f32),
-    pub velocity: (f32, f32),
-    pub radius: f32,
-}
-
-impl Body {
-    pub fn new(mass: f32, position: (f32, f32), velocity: (f32, f32)) -> Result<Self> {
-        let radius: f32 = 0.02;
-        Ok(Self {
-            mass,
-            position,
-            velocity,
-            radius,
+/// Per-instance style for the bodies shader: the kind of body (0.0 for a massive body,
+/// 1.0 for a test particle, 2.0 for gas), and the radius to draw at. Bodies only have a
+/// size when they touch, otherwise it is left at zero for the default marker.
+fn body_styles(simulation: &Simulation) -> Vec<[f32; 2]> {
+    let sized = simulation.contacts.is_some();
+    let mut styles: Vec<[f32; 2]> = simulation
+        .bodies
+        .iter()
+        .map(|b| {
+            let kind = if b.is_test_particle() { 1.0 } else { 0.0 };
+            [kind, if sized { b.radius } else { 0.0 }]
         })
+        .collect();
+    for particle in &simulation.gas {
+        styles[particle.index][0] = 2.0;
     }
-
-    pub fn gravitational_force(&self, other: &Body, gravity: f32) -> (f32, f32) {
-        let dx = other.position.0 - self.position.0;
-        let dy = other.position.1 - self.position.1;
-        let r2 = dx * dx + dy * dy;
-        let inv_r = r2.sqrt().recip();
-        let inv_r3 = inv_r * inv_r * inv_r;
-        let direct_force = gravity * self.mass * other.mass * inv_r3;
-        (direct_force * dx, direct_force * dy)
-    }
-
-    pub fn distance_to(&self, other: &Body) -> f32 {
-        let dx = self.position.0 - other.position.0;
-        let dy = self.position.1 - other.position.1;
-        (dx * dx + dy * dy).sqrt()
-    }
-
-    pub fn gravitational_force_softened(
-        &self,
-        other: &Body,
-        gravity: f32,
-        eps2: f32,
-    ) -> (f32, f32) {
-        let dx = other.position.0 - self.position.0;
-        let dy = other.position.1 - self.position.1;
-        let r2 = dx * dx + dy * dy + eps2;
-        let inv_r = r2.sqrt().recip();
-        let inv_r3 = inv_r * inv_r * inv_r;
-        let direct_force = gravity * self.mass * other.mass * inv_r3;
-        (direct_force * dx, direct_force * dy)
-    }
-
-    pub fn update(&mut self, acceleration: (f32, f32), dt: f32) {
-        self.velocity.0 += acceleration.0 * dt;
-        self.velocity.1 += acceleration.1 * dt;
-        self.position.0 += self.velocity.0 * dt;
-        self.position.1 += self.velocity.1 * dt;
-    }
-
-    pub fn get_kinetic_energy(&self) -> f32 {
-        0.5 * self.mass * (self.velocity.0 * self.velocity.0 + self.velocity.1 * self.velocity.1)
-    }
-
-    pub fn get_linear_momentum(&self) -> (f32, f32) {
-        (self.mass * self.velocity.0, self.mass * self.velocity.1)
-    }
+    styles
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_body_creation() {
-        let body = Body::new(100.0, (0.0, 0.0), (1.0, 0.0)).unwrap();
-
-        assert_eq!(body.mass, 100.0);
-        assert_eq!(body.position, (0.0, 0.0));
-        assert_eq!(body.velocity, (1.0, 0.0));
-        // Test that radius was calculated (adjust based on your formula)
-        assert!(body.radius > 0.0);
+/// Zero-velocity curves of a restricted three-body run, and crosses marking its
+/// primaries and Lagrange points, in clip space like the body positions.
+fn overlay_vertices(simulation: &Simulation) -> Vec<[f32; 6]> {
+    let Some(restricted) = &simulation.restricted_three_body else {
+        return Vec::new();
+    };
+    let curve_colour = [0.45, 0.55, 0.9, 0.6];
+    let mut vertices: Vec<[f32; 6]> = restricted
+        .zero_velocity_curves
+        .iter()
+        .flat_map(|&jacobi| restricted.zero_velocity_curve(jacobi, simulation.g_constant, 1.0, 400))
+        .flatten()
+        .map(|p| with_colour(p, curve_colour))
+        .collect();
+    for position in restricted.primary_positions() {
+        vertices.extend(cross(position, false, PRIMARY_COLOUR));
     }
-
-    #[test]
-    fn test_distance_calculation() {
-        let body1 = Body::new(1.0, (0.0, 0.0), (0.0, 0.0)).unwrap();
-        let body2 = Body::new(1.0, (3.0, 4.0), (0.0, 0.0)).unwrap();
-
-        let distance = body1.distance_to(&body2);
-        assert!((distance - 5.0).abs() < 1e-6); // 3-4-5 triangle (using f32 precision)
-    }
-
-    #[test]
-    fn test_gravitational_force_symmetry() {
-        let body1 = Body::new(100.0, (0.0, 0.0), (0.0, 0.0)).unwrap();
-        let body2 = Body::new(200.0, (1.0, 0.0), (0.0, 0.0)).unwrap();
-        let g = 1.0; // Simplified constant
-
-        let force_12 = body1.gravitational_force(&body2, g);
-        let force_21 = body2.gravitational_force(&body1, g);
-
-        // Forces should be equal and opposite (Newton's third law)
-        assert!((force_12.0 + force_21.0).abs() < 1e-10);
-        assert!((force_12.1 + force_21.1).abs() < 1e-10);
-    }
-
-    #[test]
-    fn test_gravitational_force_magnitude() {
-        let body1 = Body::new(100.0, (0.0, 0.0), (0.0, 0.0)).unwrap();
-        let body2 = Body::new(200.0, (2.0, 0.0), (0.0, 0.0)).unwrap();
-        let g = 1.0;
-
-        let force = body1.gravitational_force(&body2, g);
-
-        // F = G * m1 * m2 / r^2 = 1 * 100 * 200 / 4 = 5000
-        let expected_magnitude = 5000.0;
-        let actual_magnitude = (force.0 * force.0 + force.1 * force.1).sqrt();
-
-        assert!((actual_magnitude - expected_magnitude).abs() < 1e-10);
-
-        // Force should point in positive x direction (toward body2)
-        assert!(force.0 > 0.0);
-        assert!(force.1.abs() < 1e-10);
-    }
-
-    #[test]
-    fn test_body_update() {
-        let mut body = Body::new(1.0, (0.0, 0.0), (0.0, 0.0)).unwrap();
-        let acceleration = (2.0, 1.0);
-        let dt = 1.0;
-
-        body.update(acceleration, dt);
-
-        // After 1 second with acceleration (2, 1):
-        // velocity = (0, 0) + (2, 1) * 1 = (2, 1)
-        // position = (0, 0) + (2, 1) * 1 = (2, 1)
-        assert_eq!(body.velocity, (2.0, 1.0));
-        assert_eq!(body.position, (2.0, 1.0));
-    }
-
-    #[test]
-    fn test_get_momentum() {
-        let body = Body::new(1.0, (0.0, 0.0), (1.0, 0.0)).unwrap();
-        assert_eq!(body.get_linear_momentum(), (1.0, 0.0));
+    for point in restricted.lagrange_points() {
+        vertices.extend(cross(point, true, LAGRANGE_COLOUR));
     }
+    vertices
+}
 
-    #[test]
-    fn test_energy_conservation_two_body() {
-        // Create a minimal state for testing (no GPU resources needed)
-        let mut bodies = vec![
-            Body::new(100.0, (-1.0, 0.0), (0.0, 1.0)).unwrap(),
-            Body::new(100.0, (1.0, 0.0), (0.0, -1.0)).unwrap(),
-        ];
-        let g_constant = 1.0;
-        let time_step = 0.0001;
-
-        // Calculate total energy (kinetic + potential)
-        let calculate_total_energy = |bodies: &[Body]| -> f32 {
-            let kinetic: f32 = bodies.iter().map(|b| b.get_kinetic_energy()).sum();
-            let mut potential = 0.0;
-            for i in 0..bodies.len() {
-                for j in (i + 1)..bodies.len() {
-                    let distance = bodies[i].distance_to(&bodies[j]);
-                    potential -= g_constant * bodies[i].mass * bodies[j].mass / distance;
-                }
-            }
-            kinetic + potential
+/// The overlay, the Lagrange points and Hill spheres of the marked pairs where they
+/// are now, and the end points of every link.
+fn line_vertices(simulation: &Simulation, overlay: &[[f32; 6]]) -> Vec<[f32; 6]> {
+    let mut vertices = overlay.to_vec();
+    for &(i, j) in &simulation.lagrange_pairs {
+        let (primary, secondary) = (&simulation.bodies[i], &simulation.bodies[j]);
+        // A pair whose masses have run out has no points
+        let (Ok(points), Ok(hill)) = (
+            lagrange::lagrange_points(primary, secondary),
+            lagrange::hill_radius(primary, secondary),
+        ) else {
+            continue;
         };
-
-        let initial_energy = calculate_total_energy(&bodies);
-
-        // Run a few simulation steps
-        for _ in 0..10 {
-            // 1. Pairwise forces
-            let n = bodies.len();
-            let mut forces = vec![(0.0, 0.0); n];
-
-            // a(t)
-            for i in 0..n {
-                for j in (i + 1)..n {
-                    let f = bodies[i].gravitational_force(&bodies[j], g_constant);
-
-                    forces[i].0 += f.0 / bodies[i].mass;
-                    forces[i].1 += f.1 / bodies[i].mass;
-                    forces[j].0 -= f.0 / bodies[j].mass;
-                    forces[j].1 -= f.1 / bodies[j].mass;
-                }
-            }
-
-            // x(t+dt) = x(t) + v(t) dt + 0.5 a(t) dt^2
-            for i in 0..n {
-                bodies[i].position.0 +=
-                    bodies[i].velocity.0 * time_step + 0.5 * forces[i].0 * time_step * time_step;
-                bodies[i].position.1 +=
-                    bodies[i].velocity.1 * time_step + 0.5 * forces[i].1 * time_step * time_step;
-            }
-
-            // a(t+dt)
-            let mut forces_new = vec![(0.0, 0.0); n];
-            for i in 0..n {
-                for j in (i + 1)..n {
-                    let f = bodies[i].gravitational_force(&bodies[j], g_constant);
-                    forces_new[i].0 += f.0 / bodies[i].mass;
-                    forces_new[i].1 += f.1 / bodies[i].mass;
-                    forces_new[j].0 -= f.0 / bodies[j].mass;
-                    forces_new[j].1 -= f.1 / bodies[j].mass;
-                }
-            }
-
-            // v(t+dt) = v(t) + 0.5 (a(t) + a(t+dt)) dt
-            for i in 0..n {
-                bodies[i].velocity.0 += 0.5 * (forces[i].0 + forces_new[i].0) * time_step;
-                bodies[i].velocity.1 += 0.5 * (forces[i].1 + forces_new[i].1) * time_step;
-            }
+        for point in points {
+            vertices.extend(cross(point, true, LAGRANGE_COLOUR));
         }
-
-        let final_energy = calculate_total_energy(&bodies);
-        let final_kinetic: f32 = bodies.iter().map(|b| b.get_kinetic_energy()).sum();
-
-        // Total energy should be conserved (within numerical error)
-        println!("Initial total energy: {initial_energy}, Final total energy: {final_energy}");
-        println!("Final kinetic energy: {final_kinetic}");
-        println!(
-            "Total energy difference: {}",
-            (final_energy - initial_energy).abs()
-        );
-        assert!((final_energy - initial_energy).abs() < 0.01);
+        vertices.extend(circle(secondary.position, hill, HILL_COLOUR));
     }
+    let link_colour = [0.55, 0.8, 0.55, 0.7];
+    vertices.extend(
+        simulation
+            .links
+            .iter()
+            .flat_map(|link| [link.bodies.0, link.bodies.1])
+            .map(|i| with_colour(simulation.bodies[i].position, link_colour)),
+    );
+    vertices
+}
 
-    #[test]
-    fn test_momentum_conservation_two_body() {
-        // Create a minimal state for testing (no GPU resources needed)
-        let mut bodies = vec![
-            Body::new(100.0, (-1.0, 0.0), (0.0, 1.0)).unwrap(),
-            Body::new(100.0, (1.0, 0.0), (0.0, -1.0)).unwrap(),
-        ];
-        let g_constant = 1.0;
-        let time_step = 0.0001;
-
-        let (px0, py0) = bodies.iter().fold((0.0, 0.0), |(sx, sy), b| {
-            let (px, py) = b.get_linear_momentum();
-            (sx + px, sy + py)
-        });
-
-        let p0 = (px0 * px0 + py0 * py0).sqrt();
-
-        // Run a few simulation steps
-        for _ in 0..10 {
-            // 1. Pairwise forces
-            let n = bodies.len();
-            let mut forces = vec![(0.0, 0.0); n];
-
-            // a(t)
-            for i in 0..n {
-                for j in (i + 1)..n {
-                    let f = bodies[i].gravitational_force(&bodies[j], g_constant);
-
-                    forces[i].0 += f.0 / bodies[i].mass;
-                    forces[i].1 += f.1 / bodies[i].mass;
-                    forces[j].0 -= f.0 / bodies[j].mass;
-                    forces[j].1 -= f.1 / bodies[j].mass;
-                }
-            }
-
-            // x(t+dt) = x(t) + v(t) dt + 0.5 a(t) dt^2
-            for i in 0..n {
-                bodies[i].position.0 +=
-                    bodies[i].velocity.0 * time_step + 0.5 * forces[i].0 * time_step * time_step;
-                bodies[i].position.1 +=
-                    bodies[i].velocity.1 * time_step + 0.5 * forces[i].1 * time_step * time_step;
-            }
-
-            // a(t+dt)
-            let mut forces_new = vec![(0.0, 0.0); n];
-            for i in 0..n {
-                for j in (i + 1)..n {
-                    let f = bodies[i].gravitational_force(&bodies[j], g_constant);
-                    forces_new[i].0 += f.0 / bodies[i].mass;
-                    forces_new[i].1 += f.1 / bodies[i].mass;
-                    forces_new[j].0 -= f.0 / bodies[j].mass;
-                    forces_new[j].1 -= f.1 / bodies[j].mass;
-                }
-            }
-
-            // v(t+dt) = v(t) + 0.5 (a(t) + a(t+dt)) dt
-            for i in 0..n {
-                bodies[i].velocity.0 += 0.5 * (forces[i].0 + forces_new[i].0) * time_step;
-                bodies[i].velocity.1 += 0.5 * (forces[i].1 + forces_new[i].1) * time_step;
-            }
-        }
-
-        let (px1, py1) = bodies.iter().fold((0.0, 0.0), |(sx, sy), b| {
-            let (px, py) = b.get_linear_momentum();
-            (sx + px, sy + py)
-        });
+/// A small plus, or a diagonal cross, centred on `centre`, as two lines.
+fn cross(centre: (f32, f32), diagonal: bool, colour: [f32; 4]) -> [[f32; 6]; 4] {
+    let arm = 0.02;
+    let (x, y) = centre;
+    let ends = if diagonal {
+        [
+            (x - arm, y - arm),
+            (x + arm, y + arm),
+            (x - arm, y + arm),
+            (x + arm, y - arm),
+        ]
+    } else {
+        [(x - arm, y), (x + arm, y), (x, y - arm), (x, y + arm)]
+    };
+    ends.map(|end| with_colour(end, colour))
+}
 
-        let p1: f32 = (px1 * px1 + py1 * py1).sqrt();
+/// A circle of `radius` about `centre`, as a polygon of lines.
+fn circle(centre: (f32, f32), radius: f32, colour: [f32; 4]) -> Vec<[f32; 6]> {
+    let vertex = |k: usize| {
+        let angle = std::f32::consts::TAU * k as f32 / CIRCLE_SEGMENTS as f32;
+        let point = (
+            centre.0 + radius * angle.cos(),
+            centre.1 + radius * angle.sin(),
+        );
+        with_colour(point, colour)
+    };
+    (0..CIRCLE_SEGMENTS)
+        .flat_map(|k| [vertex(k), vertex(k + 1)])
+        .collect()
+}
 
-        // Total energy should be conserved (within numerical error)
-        println!("Initial momentum: {p0}, Final momentum: {p1}");
-        println!("Total energy difference: {}", (p1 - p0).abs());
-        assert!((p1 - p0).abs() < 0.01);
-    }
+fn with_colour(position: (f32, f32), colour: [f32; 4]) -> [f32; 6] {
+    let [r, g, b, a] = colour;
+    [position.0, position.1, r, g, b, a]
 }
@@ -0,0 +1,773 @@
+//! Regression tests that drive the real integrators against problems with known
+//! solutions and bound the period, phase and energy errors over many orbits.
+
+use std::f64::consts::{PI, TAU};
+
+use grav_sim::kepler::{OrbitalElements, Vec3};
+use grav_sim::{
+    Body, Detector, DetectorKind, Integrator, Link, LinkKind, PostNewtonian, Regularization,
+    RestrictedThreeBody, Simulation, StrainObserver,
+};
+
+const INTEGRATORS: [Integrator; 2] = [Integrator::VelocityVerlet, Integrator::Yoshida4];
+
+/// Two bodies on the given relative orbit with their centre of mass at rest at the origin.
+fn kepler_pair(m1: f32, m2: f32, elements: &OrbitalElements, time_step: f32) -> Simulation {
+    let mu = (m1 + m2) as f64;
+    let (r, v) = elements.to_state_vectors(mu);
+    let f1 = -(m2 / (m1 + m2)) as f64;
+    let f2 = (m1 / (m1 + m2)) as f64;
+    let body = |f: f64, mass: f32| {
+        Body::new(
+            mass,
+            ((f * r[0]) as f32, (f * r[1]) as f32),
+            ((f * v[0]) as f32, (f * v[1]) as f32),
+        )
+        .unwrap()
+    };
+    Simulation::new(vec![body(f1, m1), body(f2, m2)], 1.0, time_step, 0.0)
+}
+
+fn relative_state(simulation: &Simulation) -> (Vec3, Vec3) {
+    let [a, b] = [simulation.bodies[0], simulation.bodies[1]];
+    (
+        [
+            (b.position.0 - a.position.0) as f64,
+            (b.position.1 - a.position.1) as f64,
+            0.0,
+        ],
+        [
+            (b.velocity.0 - a.velocity.0) as f64,
+            (b.velocity.1 - a.velocity.1) as f64,
+            0.0,
+        ],
+    )
+}
+
+fn relative_energy_error(simulation: &Simulation, initial_energy: f32) -> f32 {
+    ((simulation.total_energy() - initial_energy) / initial_energy).abs()
+}
+
+fn max_position_error(simulation: &Simulation, expected: &[(f32, f32)]) -> f32 {
+    simulation
+        .bodies
+        .iter()
+        .zip(expected)
+        .map(|(b, e)| ((b.position.0 - e.0).powi(2) + (b.position.1 - e.1).powi(2)).sqrt())
+        .fold(0.0, f32::max)
+}
+
+fn angle_difference(a: f64, b: f64) -> f64 {
+    (a - b + TAU / 2.0).rem_euclid(TAU) - TAU / 2.0
+}
+
+#[test]
+fn test_circular_kepler_orbit_period_and_phase() {
+    let elements = OrbitalElements::from_semi_major_axis(1.0, 0.0, 0.0, 0.0).unwrap();
+    let (m1, m2) = (1.0, 0.001);
+    let mu = (m1 + m2) as f64;
+    let period = elements.period(mu).unwrap();
+    let orbits = 20;
+    let steps_per_orbit = 2000;
+
+    for integrator in INTEGRATORS {
+        let mut simulation =
+            kepler_pair(m1, m2, &elements, (period / steps_per_orbit as f64) as f32);
+        simulation.integrator = integrator;
+        let initial_energy = simulation.total_energy();
+
+        // Record the times at which the companion crosses the +x axis going upwards
+        let mut crossings = Vec::new();
+        let mut max_radius_error: f64 = 0.0;
+        let mut previous = relative_state(&simulation).0;
+        for _ in 0..orbits * steps_per_orbit {
+            let t0 = simulation.time;
+            simulation.step();
+            let (r, _) = relative_state(&simulation);
+            if previous[1] < 0.0 && r[1] >= 0.0 && r[0] > 0.0 {
+                let fraction = -previous[1] / (r[1] - previous[1]);
+                crossings.push(t0 + fraction * (simulation.time - t0));
+            }
+            max_radius_error = max_radius_error.max(((r[0].hypot(r[1])) - 1.0).abs());
+            previous = r;
+        }
+
+        let measured_period =
+            (crossings[crossings.len() - 1] - crossings[0]) / (crossings.len() - 1) as f64;
+        let period_error = ((measured_period - period) / period).abs();
+        let (r, _) = relative_state(&simulation);
+        let expected = elements.propagate(mu, simulation.time).true_anomaly;
+        let phase_error = angle_difference(r[1].atan2(r[0]), expected).abs();
+        let energy_error = relative_energy_error(&simulation, initial_energy);
+
+        assert!(
+            crossings.len() >= orbits as usize - 1,
+            "{integrator:?}: {} crossings",
+            crossings.len()
+        );
+        assert!(
+            period_error < 5e-5,
+            "{integrator:?}: period error {period_error}"
+        );
+        assert!(
+            phase_error < 5e-3,
+            "{integrator:?}: phase error {phase_error}"
+        );
+        assert!(
+            max_radius_error < 1e-4,
+            "{integrator:?}: radius error {max_radius_error}"
+        );
+        assert!(
+            energy_error < 5e-5,
+            "{integrator:?}: energy error {energy_error}"
+        );
+    }
+}
+
+#[test]
+fn test_eccentric_kepler_orbit_matches_analytic_propagation() {
+    let elements = OrbitalElements::from_semi_major_axis(1.0, 0.6, 0.7, 0.0).unwrap();
+    let (m1, m2) = (1.0, 0.001);
+    let mu = (m1 + m2) as f64;
+    let period = elements.period(mu).unwrap();
+    let orbits = 10;
+    let steps_per_orbit = 5000;
+
+    // Both schemes are limited by f32 round-off at this step size; the extra substeps
+    // of the 4th order scheme accumulate slightly more of it
+    for (integrator, phase_tolerance, energy_tolerance) in [
+        (Integrator::VelocityVerlet, 5e-3, 5e-5),
+        (Integrator::Yoshida4, 5e-3, 1e-4),
+    ] {
+        let mut simulation =
+            kepler_pair(m1, m2, &elements, (period / steps_per_orbit as f64) as f32);
+        simulation.integrator = integrator;
+        let initial_energy = simulation.total_energy();
+
+        let mut max_energy_error: f32 = 0.0;
+        for orbit in 1..=orbits {
+            for _ in 0..steps_per_orbit {
+                simulation.step();
+                max_energy_error =
+                    max_energy_error.max(relative_energy_error(&simulation, initial_energy));
+            }
+
+            // Compare positions against the analytic solution after every full orbit
+            let (r, _) = relative_state(&simulation);
+            let (expected, _) = elements.propagate(mu, simulation.time).to_state_vectors(mu);
+            let error = (r[0] - expected[0]).hypot(r[1] - expected[1]);
+            assert!(
+                error < phase_tolerance,
+                "{integrator:?}: position error {error} after orbit {orbit}"
+            );
+        }
+
+        let (r, v) = relative_state(&simulation);
+        let recovered = OrbitalElements::from_state_vectors(r, v, mu).unwrap();
+        assert!(
+            (recovered.semi_major_axis() - 1.0).abs() < 1e-3,
+            "{integrator:?}: {recovered:?}"
+        );
+        assert!(
+            (recovered.eccentricity - 0.6).abs() < 1e-3,
+            "{integrator:?}: {recovered:?}"
+        );
+        assert!(
+            max_energy_error < energy_tolerance,
+            "{integrator:?}: {max_energy_error}"
+        );
+    }
+}
+
+/// Period of the Chenciner & Montgomery (2000) figure-eight orbit with G = m = 1.
+const FIGURE_EIGHT_PERIOD: f64 = 6.325_913_98;
+
+fn figure_eight_bodies() -> Vec<Body> {
+    const X1: (f64, f64) = (0.970_004_36, -0.243_087_53);
+    const V3: (f64, f64) = (-0.932_407_37, -0.864_731_46);
+    let positions = [X1, (-X1.0, -X1.1), (0.0, 0.0)];
+    let velocities = [(-0.5 * V3.0, -0.5 * V3.1), (-0.5 * V3.0, -0.5 * V3.1), V3];
+    positions
+        .iter()
+        .zip(&velocities)
+        .map(|(x, v)| Body::new(1.0, (x.0 as f32, x.1 as f32), (v.0 as f32, v.1 as f32)).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_figure_eight_choreography() {
+    let orbits = 5;
+    let steps_per_period = 4000;
+    let time_step = (FIGURE_EIGHT_PERIOD / steps_per_period as f64) as f32;
+
+    for integrator in INTEGRATORS {
+        let mut simulation = Simulation::new(figure_eight_bodies(), 1.0, time_step, 0.0);
+        simulation.integrator = integrator;
+        let initial_positions: Vec<(f32, f32)> =
+            simulation.bodies.iter().map(|b| b.position).collect();
+        let initial_energy = simulation.total_energy();
+
+        for _ in 0..orbits * steps_per_period {
+            simulation.step();
+        }
+
+        let error = max_position_error(&simulation, &initial_positions);
+        let energy_error = relative_energy_error(&simulation, initial_energy);
+        assert!(error < 1e-3, "{integrator:?}: position error {error}");
+        assert!(
+            energy_error < 5e-5,
+            "{integrator:?}: energy error {energy_error}"
+        );
+    }
+}
+
+#[test]
+fn test_figure_eight_cycles_bodies_every_third_of_a_period() {
+    let steps = 3000;
+    let time_step = (FIGURE_EIGHT_PERIOD / 3.0 / steps as f64) as f32;
+    let mut simulation = Simulation::new(figure_eight_bodies(), 1.0, time_step, 0.0);
+    simulation.integrator = Integrator::Yoshida4;
+    let positions: Vec<(f32, f32)> = simulation.bodies.iter().map(|b| b.position).collect();
+
+    for _ in 0..steps {
+        simulation.step();
+    }
+
+    // After T/3 each body sits where the next one along the eight started
+    let expected = [positions[2], positions[0], positions[1]];
+    let error = max_position_error(&simulation, &expected);
+    assert!(error < 1e-4, "position error {error}");
+}
+
+#[test]
+fn test_lagrange_triangle_rotates_rigidly() {
+    // Masses chosen to satisfy Routh's stability criterion so errors don't grow
+    let masses = [1.0_f64, 0.01, 0.001];
+    let total_mass: f64 = masses.iter().sum();
+    let side = 0.8_f64;
+    let omega = (total_mass / side.powi(3)).sqrt();
+    let period = TAU / omega;
+
+    let vertices = [
+        (0.0, 0.0),
+        (side, 0.0),
+        (0.5 * side, 0.5 * 3.0_f64.sqrt() * side),
+    ];
+    let com = vertices
+        .iter()
+        .zip(&masses)
+        .fold((0.0, 0.0), |(x, y), (v, m)| {
+            (x + m * v.0 / total_mass, y + m * v.1 / total_mass)
+        });
+    let offsets: Vec<(f64, f64)> = vertices
+        .iter()
+        .map(|v| (v.0 - com.0, v.1 - com.1))
+        .collect();
+    let bodies = offsets
+        .iter()
+        .zip(&masses)
+        .map(|(r, &m)| {
+            Body::new(
+                m as f32,
+                (r.0 as f32, r.1 as f32),
+                ((-omega * r.1) as f32, (omega * r.0) as f32),
+            )
+            .unwrap()
+        })
+        .collect();
+
+    let orbits = 10;
+    let steps_per_orbit = 2000;
+    let mut simulation =
+        Simulation::new(bodies, 1.0, (period / steps_per_orbit as f64) as f32, 0.0);
+    simulation.integrator = Integrator::Yoshida4;
+    let initial_energy = simulation.total_energy();
+
+    for _ in 0..orbits * steps_per_orbit {
+        simulation.step();
+    }
+
+    // The triangle keeps its shape...
+    for (i, j) in [(0, 1), (1, 2), (2, 0)] {
+        let distance = simulation.bodies[i].distance_to(&simulation.bodies[j]) as f64;
+        assert!(
+            ((distance - side) / side).abs() < 1e-3,
+            "side {i}-{j} is {distance}"
+        );
+    }
+    // ...and every body keeps rotating about the barycentre at the analytic rate
+    for (body, offset) in simulation.bodies.iter().zip(&offsets) {
+        let expected = offset.1.atan2(offset.0) + omega * simulation.time;
+        let actual = (body.position.1 as f64).atan2(body.position.0 as f64);
+        let phase_error = angle_difference(actual, expected).abs();
+        assert!(phase_error < 5e-3, "phase error {phase_error}");
+    }
+    assert!(relative_energy_error(&simulation, initial_energy) < 1e-5);
+}
+
+fn pythagorean_simulation(time_step: f32) -> Simulation {
+    // Burrau's problem: masses 3, 4 and 5 at rest on the vertices of a 3-4-5 triangle
+    let bodies = vec![
+        Body::new(3.0, (1.0, 3.0), (0.0, 0.0)).unwrap(),
+        Body::new(4.0, (-2.0, -1.0), (0.0, 0.0)).unwrap(),
+        Body::new(5.0, (1.0, -1.0), (0.0, 0.0)).unwrap(),
+    ];
+    let mut simulation = Simulation::new(bodies, 1.0, time_step, 0.0);
+    simulation.integrator = Integrator::Yoshida4;
+    simulation
+}
+
+#[test]
+fn test_pythagorean_three_body_first_close_encounter() {
+    // Reference values from an f64 Yoshida integration converged at dt = 2e-6
+    const ENCOUNTER_TIME: f64 = 1.879_34;
+    const ENCOUNTER_DISTANCE: f32 = 0.009_700_1;
+
+    let mut simulation = pythagorean_simulation(1e-5);
+    let initial_energy = simulation.total_energy();
+
+    // E = -(3*4/5 + 3*5/4 + 4*5/3) = -769/60
+    assert!((initial_energy - (-769.0 / 60.0)).abs() < 1e-5);
+
+    let mut closest = (f32::INFINITY, 0.0);
+    while simulation.time < 2.5 {
+        simulation.step();
+        let distance = simulation.bodies[1].distance_to(&simulation.bodies[2]);
+        if distance < closest.0 {
+            closest = (distance, simulation.time);
+        }
+    }
+
+    let (px, py) = simulation.total_linear_momentum();
+    let angular_momentum = simulation.total_angular_momentum();
+    let energy_error = relative_energy_error(&simulation, initial_energy);
+
+    assert!(
+        (closest.1 - ENCOUNTER_TIME).abs() < 1e-3,
+        "encounter at {}",
+        closest.1
+    );
+    assert!(
+        ((closest.0 - ENCOUNTER_DISTANCE) / ENCOUNTER_DISTANCE).abs() < 0.02,
+        "encounter distance {}",
+        closest.0
+    );
+    assert!(energy_error < 5e-3, "energy error {energy_error}");
+    assert!(px.abs() < 1e-3 && py.abs() < 1e-3, "momentum ({px}, {py})");
+    assert!(
+        angular_momentum.abs() < 1e-3,
+        "angular momentum {angular_momentum}"
+    );
+}
+
+#[test]
+fn test_pythagorean_three_body_is_time_reversible() {
+    let mut simulation = pythagorean_simulation(1e-4);
+    let initial_positions: Vec<(f32, f32)> = simulation.bodies.iter().map(|b| b.position).collect();
+    let steps = 10_000;
+
+    for _ in 0..steps {
+        simulation.step();
+    }
+    for body in &mut simulation.bodies {
+        body.velocity = (-body.velocity.0, -body.velocity.1);
+    }
+    for _ in 0..steps {
+        simulation.step();
+    }
+
+    let error = max_position_error(&simulation, &initial_positions);
+    assert!(error < 1e-3, "position error {error}");
+}
+
+/// Longitude of periapsis at every periapsis passage, located by fitting a parabola
+/// through the separations of the three steps around each minimum.
+fn periapsis_longitudes(simulation: &mut Simulation, steps: usize) -> Vec<f64> {
+    let radius = |s: &Simulation| {
+        let (r, _) = relative_state(s);
+        (r[0].hypot(r[1]), r[1].atan2(r[0]))
+    };
+    let mut longitudes = Vec::new();
+    let mut history = [radius(simulation); 3];
+    for step in 0..steps {
+        simulation.step();
+        history = [history[1], history[2], radius(simulation)];
+        let [(r0, _), (r1, angle1), (r2, angle2)] = history;
+        if step >= 2 && r1 < r0 && r1 <= r2 {
+            // Offset of the minimum from the middle step, in steps
+            let offset = 0.5 * (r0 - r2) / (r0 - 2.0 * r1 + r2);
+            let angle0 = history[0].1;
+            let rate = 0.5 * angle_difference(angle2, angle0);
+            longitudes.push(angle1 + offset * rate);
+        }
+    }
+    longitudes
+}
+
+#[test]
+fn test_post_newtonian_periapsis_precession() {
+    let (a, e) = (1.0, 0.3);
+    let elements = OrbitalElements::from_semi_major_axis(a, e, 0.0, 0.0).unwrap();
+    let (m1, m2) = (1.0, 0.001);
+    let mu = (m1 + m2) as f64;
+    let period = elements.period(mu).unwrap();
+    let orbits = 10;
+    let steps_per_orbit = 4000;
+    let speed_of_light = 30.0;
+    let expected = 3.0 * TAU * mu / (speed_of_light * speed_of_light * a * (1.0 - e * e));
+
+    for relativistic in [false, true] {
+        let mut simulation =
+            kepler_pair(m1, m2, &elements, (period / steps_per_orbit as f64) as f32);
+        simulation.integrator = Integrator::Yoshida4;
+        if relativistic {
+            simulation.post_newtonian = Some(PostNewtonian::new(speed_of_light as f32));
+        }
+
+        let longitudes = periapsis_longitudes(&mut simulation, orbits * steps_per_orbit);
+        let precession = longitudes
+            .windows(2)
+            .map(|w| angle_difference(w[1], w[0]))
+            .sum::<f64>()
+            / (longitudes.len() - 1) as f64;
+
+        assert!(
+            longitudes.len() >= orbits - 1,
+            "relativistic {relativistic}: {} periapsides",
+            longitudes.len()
+        );
+        if relativistic {
+            assert!(
+                ((precession - expected) / expected).abs() < 2e-2,
+                "precession {precession} per orbit, expected {expected}"
+            );
+        } else {
+            assert!(
+                precession.abs() < 1e-2 * expected,
+                "precession {precession}"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_j2_apsidal_precession() {
+    let (a, e) = (1.0, 0.3);
+    let elements = OrbitalElements::from_semi_major_axis(a, e, 0.0, 0.0).unwrap();
+    let (m1, m2) = (1.0, 0.001);
+    let mu = (m1 + m2) as f64;
+    let period = elements.period(mu).unwrap();
+    let (j2, radius) = (0.01, 0.3);
+    let orbits = 10;
+    let steps_per_orbit = 4000;
+    // In the equatorial plane the longitude of periapsis moves at the sum of both rates
+    let (node_rate, periapsis_rate) = elements.j2_rates(mu, j2, radius);
+    let expected = (node_rate + periapsis_rate) * period;
+
+    let mut simulation = kepler_pair(m1, m2, &elements, (period / steps_per_orbit as f64) as f32);
+    simulation.integrator = Integrator::Yoshida4;
+    simulation.bodies[0].j2 = j2 as f32;
+    simulation.bodies[0].radius = radius as f32;
+    let initial_energy = simulation.total_energy();
+
+    let longitudes = periapsis_longitudes(&mut simulation, orbits * steps_per_orbit);
+    let precession = longitudes
+        .windows(2)
+        .map(|w| angle_difference(w[1], w[0]))
+        .sum::<f64>()
+        / (longitudes.len() - 1) as f64;
+
+    assert!(
+        longitudes.len() >= orbits - 1,
+        "{} periapsides",
+        longitudes.len()
+    );
+    assert!(
+        ((precession - expected) / expected).abs() < 2e-2,
+        "precession {precession} per orbit, expected {expected}"
+    );
+    // The J2 potential is part of the energy
+    let relative_error = ((simulation.total_energy() - initial_energy) / initial_energy).abs();
+    assert!(
+        relative_error < 1e-4,
+        "relative energy error {relative_error}"
+    );
+}
+
+/// Two bodies with gravity off, `separation` apart along x and moving apart at
+/// `speed`, joined by `kind` at `length`.
+fn linked_pair(
+    (m1, m2): (f32, f32),
+    separation: f32,
+    velocity: (f32, f32),
+    length: f32,
+    kind: LinkKind,
+    time_step: f32,
+) -> Simulation {
+    let f1 = -m2 / (m1 + m2);
+    let f2 = m1 / (m1 + m2);
+    let body = |f: f32, mass: f32| {
+        Body::new(
+            mass,
+            (f * separation, 0.0),
+            (f * velocity.0, f * velocity.1),
+        )
+        .unwrap()
+    };
+    let mut simulation = Simulation::new(vec![body(f1, m1), body(f2, m2)], 0.0, time_step, 0.0);
+    simulation.links.push(Link {
+        bodies: (0, 1),
+        length,
+        kind,
+    });
+    simulation
+}
+
+#[test]
+fn test_spring_oscillation_period_and_phase() {
+    // Separation L + A cos(w t), with w^2 = k / mu for the reduced mass mu
+    let (m1, m2, stiffness): (f32, f32, f32) = (1.0, 3.0, 12.0);
+    let omega = (stiffness / (m1 * m2 / (m1 + m2))).sqrt() as f64;
+    let (length, amplitude) = (0.5, 0.1);
+    let period = TAU / omega;
+    let steps_per_period = 400;
+    for integrator in INTEGRATORS {
+        let mut simulation = linked_pair(
+            (m1, m2),
+            length + amplitude,
+            (0.0, 0.0),
+            length,
+            LinkKind::Spring {
+                stiffness,
+                damping: 0.0,
+            },
+            (period / steps_per_period as f64) as f32,
+        );
+        simulation.integrator = integrator;
+        let initial_energy = simulation.total_energy();
+        let mut max_error: f64 = 0.0;
+        for _ in 0..10 * steps_per_period {
+            simulation.step();
+            let [a, b] = [simulation.bodies[0], simulation.bodies[1]];
+            let expected = length as f64 + amplitude as f64 * (omega * simulation.time).cos();
+            max_error = max_error.max((a.distance_to(&b) as f64 - expected).abs());
+        }
+        assert!(
+            max_error < 1e-3 * amplitude as f64,
+            "{integrator:?}: separation error {max_error}"
+        );
+        assert!(relative_energy_error(&simulation, initial_energy) < 1e-4);
+    }
+}
+
+#[test]
+fn test_rod_rotor_turns_rigidly() {
+    // Free bodies on a rod turn about their centre of mass at a constant rate
+    let (m1, m2, length, speed) = (2.0, 1.0, 0.5, 1.5);
+    let omega = (speed / length) as f64;
+    let period = TAU / omega;
+    let steps_per_period = 1000;
+    for integrator in INTEGRATORS {
+        let mut simulation = linked_pair(
+            (m1, m2),
+            length,
+            (0.0, speed),
+            length,
+            LinkKind::Rod {},
+            (period / steps_per_period as f64) as f32,
+        );
+        simulation.integrator = integrator;
+        let initial_energy = simulation.total_energy();
+        for _ in 0..5 * steps_per_period {
+            simulation.step();
+        }
+        let (r, v) = relative_state(&simulation);
+        let separation = r[0].hypot(r[1]);
+        assert!((separation - length as f64).abs() < 1e-5 * length as f64);
+        // Back where it started after whole turns, up to the integrator's phase error
+        let angle = angle_difference(r[1].atan2(r[0]), 0.0);
+        assert!(angle.abs() < 1e-3, "{integrator:?}: phase error {angle}");
+        assert!((r[0] * v[0] + r[1] * v[1]).abs() < 1e-5);
+        assert!(relative_energy_error(&simulation, initial_energy) < 1e-4);
+    }
+}
+
+#[test]
+fn test_distant_orbit_in_rotating_frame() {
+    // Far from a nearly single primary, a circular orbit at the Kepler rate w turns
+    // at w - Omega in the frame of the primaries
+    let restricted = RestrictedThreeBody {
+        primary_mass: 0.999,
+        secondary_mass: 0.001,
+        separation: 1.0,
+        softening: 0.0,
+        zero_velocity_curves: Vec::new(),
+    };
+    let (radius, omega) = (4.0f32, 1.0f64);
+    let kepler_rate = (1.0 / radius.powi(3) as f64).sqrt();
+    let rate = kepler_rate - omega;
+    let duration = TAU / kepler_rate;
+    let steps = 10_000;
+    for integrator in INTEGRATORS {
+        let particle =
+            Body::test_particle((radius, 0.0), (0.0, (rate * radius as f64) as f32)).unwrap();
+        let mut simulation =
+            Simulation::new(vec![particle], 1.0, (duration / steps as f64) as f32, 0.0);
+        simulation.integrator = integrator;
+        simulation.fields.push(Box::new(restricted.clone()));
+        simulation.restricted_three_body = Some(restricted.clone());
+        let initial_jacobi = simulation.jacobi_constants()[0];
+        for _ in 0..steps {
+            simulation.step();
+        }
+        let (x, y) = simulation.bodies[0].position;
+        assert!((x.hypot(y) - radius).abs() < 1e-3 * radius);
+        // The frame has lapped the particle
+        let angle = angle_difference((y as f64).atan2(x as f64), rate * simulation.time);
+        assert!(angle.abs() < 3e-3, "{integrator:?}: phase error {angle}");
+        let jacobi = simulation.jacobi_constants()[0];
+        assert!((jacobi - initial_jacobi).abs() < 1e-4 * initial_jacobi.abs());
+    }
+}
+
+#[test]
+fn test_regularized_pair_through_collision() {
+    let regularization = Some(Regularization { radius: 2.0 });
+    for integrator in INTEGRATORS {
+        // Released from rest, the pair falls together, passes through a head-on
+        // collision and is back at rest where it started one period later. An odd
+        // number of steps keeps the collision itself between them, where f32 couldn't
+        // hold the state
+        let period = TAU * 0.5f64.powf(1.5);
+        let bodies = vec![
+            Body::new(0.5, (-0.5, 0.0), (0.0, 0.0)).unwrap(),
+            Body::new(0.5, (0.5, 0.0), (0.0, 0.0)).unwrap(),
+        ];
+        let mut simulation = Simulation::new(bodies, 1.0, (period / 51.0) as f32, 0.0);
+        simulation.integrator = integrator;
+        simulation.regularization = regularization;
+        for _ in 0..51 {
+            simulation.step();
+        }
+        let (r, v) = relative_state(&simulation);
+        assert!(
+            (r[0] - 1.0).abs() < 1e-4 && r[1].abs() < 1e-4,
+            "{integrator:?}: after a period {r:?}"
+        );
+        assert!(
+            v[0].hypot(v[1]) < 1e-3,
+            "{integrator:?}: after a period {v:?}"
+        );
+
+        // A very eccentric orbit from apocentre stays on the analytic one at 21 steps
+        // per orbit, again with pericentre between steps
+        let elements = OrbitalElements::from_semi_major_axis(1.0, 0.999, 0.3, PI).unwrap();
+        let period = elements.period(1.0).unwrap();
+        let mut simulation = kepler_pair(0.5, 0.5, &elements, (period / 21.0) as f32);
+        simulation.integrator = integrator;
+        simulation.regularization = regularization;
+        let initial_energy = simulation.total_energy();
+        for _ in 0..210 {
+            simulation.step();
+        }
+        let (r, _) = relative_state(&simulation);
+        let (expected, _) = elements
+            .propagate(1.0, simulation.time)
+            .to_state_vectors(1.0);
+        let error = (r[0] - expected[0]).hypot(r[1] - expected[1]);
+        let energy_error = relative_energy_error(&simulation, initial_energy);
+        assert!(error < 1e-4, "{integrator:?}: {error}");
+        assert!(energy_error < 1e-5, "{integrator:?}: {energy_error}");
+    }
+}
+
+#[test]
+fn test_periapsis_passages_detected_within_coarse_steps() {
+    // The regularized orbit of e = 0.999 at 21 steps per orbit, from apocentre: each
+    // periapsis passage falls mid-step, half a period on from an apocentre
+    let elements = OrbitalElements::from_semi_major_axis(1.0, 0.999, 0.3, PI).unwrap();
+    let period = elements.period(1.0).unwrap();
+    for integrator in INTEGRATORS {
+        let mut simulation = kepler_pair(0.5, 0.5, &elements, (period / 21.0) as f32);
+        simulation.integrator = integrator;
+        simulation.regularization = Some(Regularization { radius: 2.0 });
+        simulation.detectors = vec![
+            Detector::new(DetectorKind::Periapsis {}, vec![0, 1]).unwrap(),
+            Detector::new(DetectorKind::Collision {}, vec![0, 1]).unwrap(),
+        ];
+        for _ in 0..105 {
+            simulation.step();
+        }
+        // Bodies 0.02 across touch within 0.04, from eccentric anomaly E before periapsis
+        let anomaly = ((1.0 - 0.04) / 0.999f64).acos();
+        let touching = (anomaly - 0.999 * anomaly.sin()) / TAU;
+        assert_eq!(simulation.detections.len(), 10, "{integrator:?}");
+        for (orbit, pair) in simulation.detections.chunks(2).enumerate() {
+            let periapsis = (orbit as f64 + 0.5) * period;
+            assert_eq!(pair[0].kind, DetectorKind::Collision {});
+            assert_eq!(pair[1].kind, DetectorKind::Periapsis {});
+            let error = (pair[1].time - periapsis).abs() / period;
+            let lead = (periapsis - pair[0].time) / period;
+            assert!(error < 1e-5, "{integrator:?}: {error}");
+            assert!((lead - touching).abs() < 1e-5, "{integrator:?}: {lead}");
+        }
+    }
+}
+
+#[test]
+fn test_radiation_reaction_inspiral_and_chirp() {
+    let elements = OrbitalElements::from_semi_major_axis(1.0, 0.0, 0.0, 0.0).unwrap();
+    let (m1, m2) = (0.5, 0.5);
+    let total_mass = (m1 + m2) as f64;
+    let speed_of_light: f64 = 5.0;
+    let period = elements.period(total_mass).unwrap();
+    let mut simulation = kepler_pair(m1, m2, &elements, (period / 2000.0) as f32);
+    simulation.integrator = Integrator::Yoshida4;
+    simulation.post_newtonian = Some(PostNewtonian {
+        first_order: false,
+        radiation_reaction: true,
+        observer: Some(StrainObserver {
+            distance: 100.0,
+            inclination: 0.0,
+        }),
+        ..PostNewtonian::new(speed_of_light as f32)
+    });
+
+    // Peters-Mathews: a^4 = a0^4 - 4 beta t for a circular orbit, with
+    // beta = 64/5 G^3 m1 m2 M / c^5
+    let beta = 12.8 * (m1 * m2) as f64 * total_mass / speed_of_light.powi(5);
+    let end = 150.0;
+    let mut max_error: f64 = 0.0;
+    while simulation.time < end {
+        simulation.step();
+        let (r, _) = relative_state(&simulation);
+        let expected = (1.0 - 4.0 * beta * simulation.time).powf(0.25);
+        max_error = max_error.max((r[0].hypot(r[1]) - expected).abs() / expected);
+    }
+    let (r, _) = relative_state(&simulation);
+    assert!(r[0].hypot(r[1]) < 0.8, "separation {}", r[0].hypot(r[1]));
+    assert!(max_error < 5e-3, "separation error {max_error}");
+
+    // The strain chirps: both its amplitude and frequency grow as the orbit shrinks
+    let window = |from: f64, to: f64| {
+        let samples: Vec<_> = simulation
+            .strain
+            .iter()
+            .filter(|s| (from..to).contains(&s.time))
+            .collect();
+        let amplitude = samples.iter().map(|s| s.plus.abs()).fold(0.0, f32::max);
+        let crossings = samples
+            .windows(2)
+            .filter(|w| (w[0].plus < 0.0) != (w[1].plus < 0.0))
+            .count();
+        (amplitude, crossings)
+    };
+    let (early_amplitude, early_crossings) = window(0.0, 20.0);
+    let (late_amplitude, late_crossings) = window(end - 20.0, end);
+    assert_eq!(
+        simulation.strain.len(),
+        1 + (end / simulation.time_step as f64) as usize
+    );
+    assert!(late_amplitude > 1.2 * early_amplitude);
+    assert!(late_crossings as f32 > 1.3 * early_crossings as f32);
+}